@@ -81,8 +81,9 @@ install -m 755 target/release/frame-manager %{buildroot}/usr/local/bin/
 install -m 644 packaging/systemd/frame-manager.service %{buildroot}/etc/systemd/system/
 
 # Install configuration
-install -m 644 packaging/config/frame.conf %{buildroot}/etc/frame/
+install -m 600 packaging/config/frame.conf %{buildroot}/etc/frame/
 install -m 644 packaging/config/limits.conf %{buildroot}/etc/frame/
+install -m 644 packaging/config/groups.conf %{buildroot}/etc/frame/
 
 # Install hooks
 install -m 755 src/hooks/postwwwacct %{buildroot}/usr/local/cpanel/scripts/postwwwacct/frame
@@ -97,6 +98,7 @@ install -m 644 src/whm/templates/*.tmpl %{buildroot}/usr/local/cpanel/whostmgr/d
 install -m 644 src/whm/assets/css/*.css %{buildroot}/usr/local/cpanel/whostmgr/docroot/cgi/frame/assets/css/
 install -m 644 src/whm/assets/js/*.js %{buildroot}/usr/local/cpanel/whostmgr/docroot/cgi/frame/assets/js/
 install -m 644 src/api/whm/Frame.pm %{buildroot}/usr/local/cpanel/Whostmgr/API/1/
+install -m 644 src/api/common/FrameAuth.pm %{buildroot}/usr/local/cpanel/
 install -m 644 src/whm/plugin/icons/frame-icon.svg %{buildroot}/usr/local/cpanel/whostmgr/docroot/themes/x/icons/frame.svg
 
 # Install cPanel interface
@@ -130,8 +132,9 @@ install -m 755 src/apache/scripts/frame-apache-ctl.sh %{buildroot}/usr/local/bin
 /etc/systemd/system/frame-manager.service
 
 # Configuration (marked as config to preserve on upgrade)
-%config(noreplace) /etc/frame/frame.conf
+%config(noreplace) %attr(600,root,root) /etc/frame/frame.conf
 %config(noreplace) /etc/frame/limits.conf
+%config(noreplace) /etc/frame/groups.conf
 
 # Data directories
 %dir /var/frame
@@ -153,6 +156,7 @@ install -m 755 src/apache/scripts/frame-apache-ctl.sh %{buildroot}/usr/local/bin
 /usr/local/cpanel/whostmgr/docroot/cgi/frame/*
 /usr/local/cpanel/whostmgr/docroot/themes/x/icons/frame.svg
 /usr/local/cpanel/Whostmgr/API/1/Frame.pm
+/usr/local/cpanel/FrameAuth.pm
 
 # cPanel interface
 /usr/local/cpanel/base/frontend/jupiter/frame/*
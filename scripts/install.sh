@@ -192,9 +192,11 @@ install_config() {
 
     # Only install if not exists (preserve user settings)
     if [ ! -f "$FRAME_ETC/frame.conf" ]; then
-        cp "$PROJECT_DIR/packaging/config/frame.conf" "$FRAME_ETC/"
+        install -m 600 "$PROJECT_DIR/packaging/config/frame.conf" "$FRAME_ETC/"
         log_info "Installed: $FRAME_ETC/frame.conf"
     else
+        # May hold the API token: keep it root-only
+        chmod 600 "$FRAME_ETC/frame.conf"
         log_warn "Configuration exists, skipping: $FRAME_ETC/frame.conf"
     fi
 
@@ -243,6 +245,7 @@ install_whm() {
     # WHM API
     mkdir -p "$CPANEL_BASE/Whostmgr/API/1"
     cp "$PROJECT_DIR/src/api/whm/Frame.pm" "$CPANEL_BASE/Whostmgr/API/1/"
+    cp "$PROJECT_DIR/src/api/common/FrameAuth.pm" "$CPANEL_BASE/"
 
     # Icon
     cp "$PROJECT_DIR/src/whm/plugin/icons/frame-icon.svg" "$WHM_DOCROOT/themes/x/icons/frame.svg"
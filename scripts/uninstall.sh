@@ -141,6 +141,7 @@ remove_whm() {
     # Remove WHM API
     if [ -f "$CPANEL_BASE/Whostmgr/API/1/Frame.pm" ]; then
         rm -f "$CPANEL_BASE/Whostmgr/API/1/Frame.pm"
+        rm -f "$CPANEL_BASE/FrameAuth.pm"
         log_info "Removed: $CPANEL_BASE/Whostmgr/API/1/Frame.pm"
     fi
 
@@ -193,6 +193,56 @@ status() {
     fi
 }
 
+# Point a user's proxy configuration at a new instance port
+switch_port() {
+    local user="$1"
+    local old_port="$2"
+    local new_port="$3"
+
+    if [ -z "$user" ] || [ -z "$old_port" ] || [ -z "$new_port" ]; then
+        log_error "Usage: $0 switch-port USER OLD_PORT NEW_PORT"
+        return 1
+    fi
+
+    # Collect the user's path configs and domain configs generated for them
+    local files=()
+    for conf in "$FRAME_CONF_DIR/$user/"*.conf; do
+        [ -f "$conf" ] && files+=("$conf")
+    done
+    for conf in "$FRAME_CONF_DIR/domains/"*.conf; do
+        [ -f "$conf" ] && grep -q "^# User: $user\$" "$conf" && files+=("$conf")
+    done
+
+    if [ ${#files[@]} -eq 0 ]; then
+        log_warn "No proxy configuration found for $user, nothing to switch"
+        return 0
+    fi
+
+    # Rewrite ports, keeping backups so a bad config can be rolled back
+    for conf in "${files[@]}"; do
+        cp -p "$conf" "$conf.bak"
+        sed -i \
+            -e "s/127\.0\.0\.1:${old_port}\([^0-9]\|\$\)/127.0.0.1:${new_port}\1/g" \
+            -e "s/^# Port: ${old_port}\$/# Port: ${new_port}/" \
+            "$conf"
+    done
+
+    if ! test_config; then
+        log_error "Restoring previous configuration for $user"
+        for conf in "${files[@]}"; do
+            mv -f "$conf.bak" "$conf"
+        done
+        return 1
+    fi
+
+    for conf in "${files[@]}"; do
+        rm -f "$conf.bak"
+    done
+
+    reload
+    log_info "Switched $user from port $old_port to $new_port"
+}
+
 # Show usage
 usage() {
     cat <<EOF
@@ -207,6 +257,8 @@ Commands:
   reload    Reload Apache
   list      List all Frame configurations
   status    Show configuration status
+  switch-port USER OLD NEW
+            Point a user's proxy configuration at a new port
   help      Show this help message
 
 Examples:
@@ -237,6 +289,9 @@ case "${1:-}" in
     status)
         status
         ;;
+    switch-port)
+        switch_port "${2:-}" "${3:-}" "${4:-}"
+        ;;
     help|--help|-h)
         usage
         ;;
@@ -0,0 +1,37 @@
+package FrameAuth;
+
+# Frame cPanel Plugin - Manager API credentials
+# Shared by the WHM and cPanel modules that talk to the manager daemon
+
+use strict;
+use warnings;
+
+our $CONFIG_FILE = '/etc/frame/frame.conf';
+
+# Bearer token for the manager API ([security] api_token in frame.conf), if set
+# and readable. frame.conf is root-only, so unprivileged callers get nothing.
+sub api_token {
+    open(my $fh, '<', $CONFIG_FILE) or return;
+    my $section = '';
+    while (my $line = <$fh>) {
+        if ($line =~ /^\s*\[(\w+)\]/) {
+            $section = $1;
+        } elsif ($section eq 'security' && $line =~ /^\s*api_token\s*=\s*(\S+)/) {
+            close($fh);
+            return $1;
+        }
+    }
+    close($fh);
+    return;
+}
+
+# Add the Authorization header to an HTTP::Tiny headers hash when a token is set
+sub add_auth_header {
+    my ($headers) = @_;
+    if (my $token = api_token()) {
+        $headers->{'Authorization'} = "Bearer $token";
+    }
+    return $headers;
+}
+
+1;
@@ -9,6 +9,7 @@ use warnings;
 use JSON;
 use HTTP::Tiny;
 use Cpanel::PwCache ();
+use FrameAuth ();
 
 # Manager daemon API endpoint
 our $MANAGER_API = 'http://127.0.0.1:30000';
@@ -28,6 +29,7 @@ sub _manager_request {
     my $options = {
         headers => { 'Content-Type' => 'application/json' },
     };
+    FrameAuth::add_auth_header($options->{headers});
 
     if ($data && ($method eq 'POST' || $method eq 'PUT')) {
         $options->{content} = encode_json($data);
@@ -10,6 +10,8 @@ use JSON;
 use HTTP::Tiny;
 use File::Path qw(make_path);
 use File::Copy;
+use lib '/usr/local/cpanel';
+use FrameAuth ();
 
 our $MANAGER_API = 'http://127.0.0.1:30000';
 
@@ -35,6 +37,7 @@ sub _request {
     my $options = {
         headers => { 'Content-Type' => 'application/json' },
     };
+    FrameAuth::add_auth_header($options->{headers});
 
     if ($data && ($method eq 'POST' || $method eq 'PUT')) {
         $options->{content} = encode_json($data);
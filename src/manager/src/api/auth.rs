@@ -0,0 +1,200 @@
+//! API Authentication
+//!
+//! The API only listens on 127.0.0.1, but on a shared server that still lets
+//! every local user reach it. With `[security] api_token` set, requests must
+//! carry `Authorization: Bearer <token>`; reads can stay public. Health
+//! endpoints are always open so load balancers and systemd probes work.
+//! Instances report request metrics with a token of their own instead, which
+//! the handler checks whether or not an API token is set.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+
+use super::handlers::ApiResponse;
+use crate::manager::FrameManager;
+
+/// Endpoints answered without a token, for probes that can't present one
+const UNAUTHENTICATED_PATHS: &[&str] = &["/health", "/health/live", "/health/ready"];
+
+/// Token requirements for API requests
+#[derive(Debug, Clone, Default)]
+pub struct ApiAuth {
+    /// Required bearer token (no authentication if unset)
+    pub token: Option<String>,
+    /// Let GET and HEAD requests through without a token
+    pub public_reads: bool,
+}
+
+impl ApiAuth {
+    /// Check a request, returning the error to report if it's rejected
+    pub fn check(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+    ) -> Result<(), &'static str> {
+        let Some(token) = &self.token else {
+            return Ok(());
+        };
+        if UNAUTHENTICATED_PATHS.contains(&path)
+            || (method == Method::POST && is_metrics_report(path))
+        {
+            return Ok(());
+        }
+        if self.public_reads && (method == Method::GET || method == Method::HEAD) {
+            return Ok(());
+        }
+
+        let presented = bearer_token(headers).ok_or("Missing bearer token")?;
+        if !constant_time_eq(presented.as_bytes(), token.as_bytes()) {
+            return Err("Invalid bearer token");
+        }
+        Ok(())
+    }
+}
+
+/// The token of an `Authorization: Bearer <token>` header
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Whether a path is an instance's `/frame/instances/:username/metrics`
+fn is_metrics_report(path: &str) -> bool {
+    path.strip_prefix("/frame/instances/")
+        .and_then(|rest| rest.strip_suffix("/metrics"))
+        .is_some_and(|username| !username.is_empty() && !username.contains('/'))
+}
+
+/// Generate a new random API token (64 hex digits)
+pub fn generate_token() -> std::io::Result<String> {
+    use std::io::Read;
+
+    let mut bytes = [0u8; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Compare without returning early, so timing doesn't reveal the token
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Middleware rejecting requests that don't satisfy the configured `ApiAuth`
+pub async fn require_token(
+    State(manager): State<Arc<FrameManager>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let auth = manager.api_auth().await;
+    match auth.check(request.method(), request.uri().path(), request.headers()) {
+        Ok(()) => next.run(request).await,
+        Err(message) => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            Json(ApiResponse::<()>::error(message)),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_bearer_token() {
+        let auth = ApiAuth {
+            token: Some("s3cret".to_string()),
+            public_reads: true,
+        };
+        let with_header = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_static(value));
+            headers
+        };
+
+        // Missing token
+        assert_eq!(
+            auth.check(&Method::POST, "/frame/instances", &HeaderMap::new()),
+            Err("Missing bearer token")
+        );
+        assert_eq!(
+            auth.check(
+                &Method::POST,
+                "/frame/instances",
+                &with_header("Basic s3cret")
+            ),
+            Err("Missing bearer token")
+        );
+
+        // Wrong token
+        assert_eq!(
+            auth.check(
+                &Method::POST,
+                "/frame/instances",
+                &with_header("Bearer s3cre")
+            ),
+            Err("Invalid bearer token")
+        );
+
+        // Correct token
+        assert_eq!(
+            auth.check(
+                &Method::PUT,
+                "/frame/instances",
+                &with_header("Bearer s3cret")
+            ),
+            Ok(())
+        );
+
+        // Reads are public unless configured otherwise
+        assert_eq!(
+            auth.check(&Method::GET, "/frame/instances", &HeaderMap::new()),
+            Ok(())
+        );
+        let private = ApiAuth {
+            public_reads: false,
+            ..auth
+        };
+        assert!(private
+            .check(&Method::GET, "/frame/instances", &HeaderMap::new())
+            .is_err());
+
+        // Health probes never need the token
+        for path in ["/health", "/health/live", "/health/ready"] {
+            assert_eq!(private.check(&Method::GET, path, &HeaderMap::new()), Ok(()));
+        }
+
+        // Metrics reports are left to the handler's instance token check
+        let report = "/frame/instances/alice/metrics";
+        assert_eq!(
+            private.check(&Method::POST, report, &HeaderMap::new()),
+            Ok(())
+        );
+        for path in [
+            "/frame/instances/alice/metrics/x",
+            "/frame/instances//metrics",
+        ] {
+            assert!(private
+                .check(&Method::POST, path, &HeaderMap::new())
+                .is_err());
+        }
+
+        // No token configured, no authentication
+        assert_eq!(
+            ApiAuth::default().check(&Method::POST, "/frame/instances", &HeaderMap::new()),
+            Ok(())
+        );
+    }
+}
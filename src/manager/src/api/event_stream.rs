@@ -0,0 +1,152 @@
+//! Live Event Stream
+//!
+//! `GET /frame/events/ws` upgrades to a WebSocket and forwards every emitted
+//! event as a JSON text frame, for dashboards that update in real time. A
+//! client too slow to keep up is sent a resync marker instead of being
+//! disconnected, and can catch up from `GET /frame/events`.
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::Response,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::events::EventEnvelope;
+use crate::manager::FrameManager;
+
+/// Query parameters for the event stream
+#[derive(Debug, Default, Deserialize)]
+pub struct EventStreamQuery {
+    /// Only events about this user
+    pub username: Option<String>,
+}
+
+/// Stream events over a WebSocket
+pub async fn stream_events(
+    State(manager): State<Arc<FrameManager>>,
+    Query(query): Query<EventStreamQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let rx = manager.subscribe_events();
+    upgrade.on_upgrade(move |socket| forward_events(socket, rx, query.username))
+}
+
+/// Send events to a client until either side goes away
+async fn forward_events(
+    mut socket: WebSocket,
+    mut rx: broadcast::Receiver<EventEnvelope>,
+    username: Option<String>,
+) {
+    loop {
+        let frame = tokio::select! {
+            received = rx.recv() => match received {
+                Ok(envelope) => {
+                    if username
+                        .as_deref()
+                        .is_some_and(|username| envelope.event.username() != Some(username))
+                    {
+                        continue;
+                    }
+                    match serde_json::to_string(&envelope) {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            tracing::warn!("Failed to serialize event: {}", e);
+                            continue;
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => json!({"resync": true, "missed": missed}).to_string(),
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                // Clients only listen; anything but a close is ignored
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        if socket.send(Message::Text(frame)).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{Event, EventEmitter};
+    use axum::routing::get;
+    use axum::Router;
+    use futures::StreamExt;
+    use std::sync::Mutex;
+    use tokio_tungstenite::tungstenite;
+
+    /// Next frame from the client side, parsed as JSON
+    async fn next<S>(client: &mut S) -> serde_json::Value
+    where
+        S: futures::Stream<Item = Result<tungstenite::Message, tungstenite::Error>> + Unpin,
+    {
+        let message = client.next().await.unwrap().unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_filters_and_resyncs() {
+        let dir = tempfile::tempdir().unwrap();
+        let events = EventEmitter::new(dir.path().to_path_buf());
+        let rx = events.subscribe();
+        let stopped = |username: &str| Event::InstanceStopped {
+            username: username.to_string(),
+        };
+
+        // Overflow the channel (100 rounded up to 128 by tokio) before the
+        // client reads anything: the 50 oldest events are lost, leaving bob's
+        // and 127 of alice's
+        for _ in 0..50 {
+            events.emit(stopped("alice")).await;
+        }
+        events.emit(stopped("bob")).await;
+        for _ in 0..127 {
+            events.emit(stopped("alice")).await;
+        }
+
+        let rx = Arc::new(Mutex::new(Some(rx)));
+        let app = Router::new().route(
+            "/frame/events/ws",
+            get(
+                move |Query(query): Query<EventStreamQuery>, upgrade: WebSocketUpgrade| {
+                    let rx = rx.lock().unwrap().take().unwrap();
+                    async move {
+                        upgrade.on_upgrade(move |socket| forward_events(socket, rx, query.username))
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let url = format!("ws://127.0.0.1:{}/frame/events/ws?username=alice", port);
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        assert_eq!(
+            next(&mut client).await,
+            json!({"resync": true, "missed": 50})
+        );
+        for _ in 0..127 {
+            let event = next(&mut client).await;
+            assert_eq!(event["event"]["username"], "alice");
+        }
+
+        // Events emitted while connected arrive too
+        events.emit(stopped("bob")).await;
+        events.emit(stopped("alice")).await;
+        let event = next(&mut client).await;
+        assert_eq!(event["event"]["username"], "alice");
+    }
+}
@@ -1,14 +1,34 @@
 //! API Request Handlers
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, Uri},
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
     Json,
 };
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::sync::Arc;
 
+use crate::api::auth::bearer_token;
+use crate::api::listing::{InstanceList, InstanceListQuery};
+use crate::config::{
+    deserialize_memory_mb, ConfigValidation, EffectiveConfig, GroupError, PackageConfig,
+};
+use crate::deploy::{DeployError, DeployResult};
+use crate::events::{EventEnvelope, EventQuery, HookInfo, HookTestResult};
+use crate::instance::{
+    CreateError, ForceKillReport, Instance, LimitsApplied, RemoveOptions, RemoveReport,
+    ResourceLimits,
+};
+use crate::logs::{LogFilter, LogLevel};
 use crate::manager::FrameManager;
+use crate::metrics::{ManagerUsage, RequestReport};
+use crate::operations::{Operation, OperationKind};
+use crate::port::PortError;
 
 /// Standard API response wrapper
 #[derive(Serialize)]
@@ -16,6 +36,9 @@ pub struct ApiResponse<T> {
     pub status: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<T>,
+    /// Machine-readable error code for failures clients should handle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub errors: Vec<String>,
 }
@@ -25,6 +48,7 @@ impl<T> ApiResponse<T> {
         Self {
             status: 1,
             data: Some(data),
+            code: None,
             errors: Vec::new(),
         }
     }
@@ -33,11 +57,27 @@ impl<T> ApiResponse<T> {
         ApiResponse {
             status: 0,
             data: None,
+            code: None,
             errors: vec![message.to_string()],
         }
     }
 }
 
+/// Self-description served at `/`
+#[derive(Serialize)]
+pub struct ApiIndex {
+    pub service: &'static str,
+    pub version: &'static str,
+    pub endpoints: Vec<EndpointInfo>,
+}
+
+/// One route in the API index
+#[derive(Serialize)]
+pub struct EndpointInfo {
+    pub method: &'static str,
+    pub path: &'static str,
+}
+
 /// Service status response
 #[derive(Serialize)]
 pub struct ServiceStatus {
@@ -46,6 +86,19 @@ pub struct ServiceStatus {
     pub instances_total: usize,
     pub memory_usage_mb: u64,
     pub port_range: String,
+    /// Resource usage of the manager process itself
+    pub manager: ManagerUsage,
+}
+
+/// Result of updating one settings section
+#[derive(Debug, Serialize)]
+pub struct SettingsSectionUpdate {
+    /// The section as now configured
+    pub settings: serde_json::Value,
+    /// Some of the changes only take effect once the service restarts
+    pub restart_required: bool,
+    /// The changed keys waiting for that restart
+    pub restart_keys: Vec<String>,
 }
 
 /// Instance status response
@@ -53,10 +106,94 @@ pub struct ServiceStatus {
 pub struct InstanceStatusResponse {
     pub username: String,
     pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_detail: Option<String>,
     pub port: u16,
     pub memory_usage_mb: u64,
     pub cpu_usage: f32,
     pub app_count: u32,
+    /// Instance keeps dropping out of Running (see `flap_threshold`)
+    pub flapping: bool,
+    /// Times the instance has been restarted
+    pub restart_count: u32,
+    /// Why the process last died on its own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_exit_reason: Option<String>,
+    /// When the running process was started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    /// Seconds since `started_at`, while the instance is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime_seconds: Option<u64>,
+    /// When the health monitor last checked the instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_health_check: Option<DateTime<Utc>>,
+}
+
+impl InstanceStatusResponse {
+    /// Status of an instance as of now
+    pub fn new(
+        instance: &Instance,
+        flapping: bool,
+        last_health_check: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self {
+            username: instance.username.clone(),
+            status: instance.status.to_string(),
+            status_detail: instance.status_detail.clone(),
+            port: instance.port,
+            memory_usage_mb: instance.memory_usage / 1024 / 1024,
+            cpu_usage: instance.cpu_usage,
+            app_count: instance.app_count,
+            flapping,
+            restart_count: instance.restart_count,
+            last_exit_reason: instance.last_exit_reason.clone(),
+            started_at: instance.started_at,
+            uptime_seconds: instance.uptime(Utc::now()),
+            last_health_check,
+        }
+    }
+}
+
+/// Instance creation request
+#[derive(Deserialize)]
+pub struct CreateInstanceRequest {
+    pub username: String,
+    /// Limits for the instance (configured defaults if omitted)
+    pub limits: Option<ResourceLimits>,
+    /// Start the instance once it's created
+    pub auto_start: Option<bool>,
+}
+
+/// Effective resource limits for an instance
+#[derive(Serialize)]
+pub struct InstanceLimitsResponse {
+    pub username: String,
+    pub limits: ResourceLimits,
+}
+
+/// Combined log stream query parameters
+#[derive(Deserialize)]
+pub struct LogStreamQuery {
+    /// Comma-separated usernames to include
+    pub users: Option<String>,
+    /// Minimum log level to include
+    pub level: Option<String>,
+}
+
+/// Single instance log stream query parameters
+#[derive(Deserialize)]
+pub struct LogFollowQuery {
+    /// Existing lines to send before following (capped at 1000)
+    #[serde(default)]
+    pub lines: usize,
+}
+
+/// App deployment request
+#[derive(Deserialize)]
+pub struct DeployRequest {
+    /// Path to the new app version (file or directory) inside the user's instance directory
+    pub artifact: String,
 }
 
 /// Settings update request
@@ -67,9 +204,78 @@ pub struct SettingsUpdate {
     pub health_check_interval: Option<u64>,
 }
 
+/// New API token returned by a rotation
+#[derive(Debug, Serialize)]
+pub struct TokenRotation {
+    pub token: String,
+}
+
+/// Query parameters for instance start/stop/restart requests
+#[derive(Deserialize)]
+pub struct OperationQuery {
+    /// Return the queued operation's id immediately instead of waiting
+    #[serde(default, rename = "async")]
+    pub is_async: bool,
+}
+
+/// Instance log level update request
+#[derive(Deserialize)]
+pub struct LogLevelUpdate {
+    /// trace, debug, info, warn or error; `null` restores the server default
+    pub log_level: Option<String>,
+}
+
+/// Operation applied to several instances at once
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchAction {
+    Start,
+    Stop,
+    Restart,
+}
+
+/// Batch operation request: `usernames`, or every instance with `all`
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    pub action: BatchAction,
+    #[serde(default)]
+    pub usernames: Vec<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// Outcome of a group operation for a single member
+#[derive(Serialize)]
+pub struct GroupMemberResult {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Global service kill-switch state
+#[derive(Serialize)]
+pub struct ServiceStateResponse {
+    pub enabled: bool,
+    /// Instances stopped because the service was disabled
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub stopped: BTreeMap<String, GroupMemberResult>,
+}
+
+/// Outcome of applying package limits to a single member
+#[derive(Serialize)]
+pub struct PackageMemberResult {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub applied: Option<LimitsApplied>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 /// Package update request
 #[derive(Deserialize)]
 pub struct PackageUpdate {
+    /// Megabytes, or a string with a unit suffix (e.g. "512M", "2G")
+    #[serde(default, deserialize_with = "deserialize_memory_mb")]
     pub memory_limit: Option<u64>,
     pub cpu_limit: Option<u8>,
     pub max_apps: Option<u32>,
@@ -87,6 +293,7 @@ pub async fn get_status(
         Err(e) => Json(ApiResponse {
             status: 0,
             data: None,
+            code: None,
             errors: vec![e.to_string()],
         }),
     }
@@ -107,21 +314,95 @@ pub async fn restart_service(
             Json(ApiResponse {
                 status: 0,
                 data: None,
+                code: None,
                 errors: vec![e.to_string()],
             }),
         ),
     }
 }
 
-/// List all instances
+/// Get whether the service is enabled
+pub async fn get_service_state(
+    State(manager): State<Arc<FrameManager>>,
+) -> Json<ApiResponse<ServiceStateResponse>> {
+    Json(ApiResponse::success(ServiceStateResponse {
+        enabled: manager.is_enabled().await,
+        stopped: BTreeMap::new(),
+    }))
+}
+
+/// Enable the service, allowing instances to start again
+pub async fn enable_service(
+    State(manager): State<Arc<FrameManager>>,
+) -> (StatusCode, Json<ApiResponse<ServiceStateResponse>>) {
+    service_state_response(manager.set_enabled(true).await, true)
+}
+
+/// Disable the service and stop every instance
+pub async fn disable_service(
+    State(manager): State<Arc<FrameManager>>,
+) -> (StatusCode, Json<ApiResponse<ServiceStateResponse>>) {
+    service_state_response(manager.set_enabled(false).await, false)
+}
+
+fn service_state_response(
+    result: anyhow::Result<BTreeMap<String, GroupMemberResult>>,
+    enabled: bool,
+) -> (StatusCode, Json<ApiResponse<ServiceStateResponse>>) {
+    match result {
+        Ok(stopped) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(ServiceStateResponse {
+                enabled,
+                stopped,
+            })),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![format!("{:#}", e)],
+            }),
+        ),
+    }
+}
+
+/// List instances
+///
+/// Instances are ordered by username unless `sort` picks another order, so
+/// pages line up between requests. `status`, `sort`, `limit` and `offset`
+/// filter and page the list; with any of them the response is an envelope
+/// with the total count.
 pub async fn list_instances(
     State(manager): State<Arc<FrameManager>>,
-) -> Json<ApiResponse<Vec<InstanceStatusResponse>>> {
-    match manager.list_instances().await {
-        Ok(instances) => Json(ApiResponse::success(instances)),
+    Query(query): Query<InstanceListQuery>,
+) -> Json<ApiResponse<InstanceList>> {
+    match manager.list_instances(&query).await {
+        Ok(page) if query.is_empty() => {
+            Json(ApiResponse::success(InstanceList::All(page.instances)))
+        }
+        Ok(page) => Json(ApiResponse::success(InstanceList::Page(page))),
         Err(e) => Json(ApiResponse {
             status: 0,
             data: None,
+            code: None,
+            errors: vec![e.to_string()],
+        }),
+    }
+}
+
+/// List effective resource limits for all instances
+pub async fn list_instance_limits(
+    State(manager): State<Arc<FrameManager>>,
+) -> Json<ApiResponse<Vec<InstanceLimitsResponse>>> {
+    match manager.all_effective_limits().await {
+        Ok(limits) => Json(ApiResponse::success(limits)),
+        Err(e) => Json(ApiResponse {
+            status: 0,
+            data: None,
+            code: None,
             errors: vec![e.to_string()],
         }),
     }
@@ -131,65 +412,453 @@ pub async fn list_instances(
 pub async fn start_instance(
     State(manager): State<Arc<FrameManager>>,
     Path(username): Path<String>,
+    Query(query): Query<OperationQuery>,
 ) -> (StatusCode, Json<ApiResponse<String>>) {
-    match manager.start_instance(&username).await {
-        Ok(_) => (
+    let message = format!("Instance started for {}", username);
+    run_operation(&manager, OperationKind::Start, &username, query, message).await
+}
+
+/// Stop a user instance
+pub async fn stop_instance(
+    State(manager): State<Arc<FrameManager>>,
+    Path(username): Path<String>,
+    Query(query): Query<OperationQuery>,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    let message = format!("Instance stopped for {}", username);
+    run_operation(&manager, OperationKind::Stop, &username, query, message).await
+}
+
+/// Restart a user instance
+pub async fn restart_instance(
+    State(manager): State<Arc<FrameManager>>,
+    Path(username): Path<String>,
+    Query(query): Query<OperationQuery>,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    let message = format!("Instance restarted for {}", username);
+    run_operation(&manager, OperationKind::Restart, &username, query, message).await
+}
+
+/// Create a user instance, e.g. when a cPanel account is created
+///
+/// Answers 409 Conflict if the user already has an instance.
+pub async fn create_instance(
+    State(manager): State<Arc<FrameManager>>,
+    Json(request): Json<CreateInstanceRequest>,
+) -> (StatusCode, Json<ApiResponse<InstanceStatusResponse>>) {
+    if let Some(Err(e)) = request.limits.as_ref().map(ResourceLimits::validate) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e],
+            }),
+        );
+    }
+
+    let auto_start = request.auto_start.unwrap_or(false);
+    match manager
+        .create_instance(&request.username, request.limits, auto_start)
+        .await
+    {
+        Ok(instance) => (StatusCode::CREATED, Json(ApiResponse::success(instance))),
+        Err(e) => {
+            let status = match e.downcast_ref::<CreateError>() {
+                Some(CreateError::Exists(_)) => StatusCode::CONFLICT,
+                Some(CreateError::InvalidUser(_)) => StatusCode::BAD_REQUEST,
+                None => return operation_error(&e),
+            };
+            (
+                status,
+                Json(ApiResponse {
+                    status: 0,
+                    data: None,
+                    code: None,
+                    errors: vec![e.to_string()],
+                }),
+            )
+        }
+    }
+}
+
+/// Response for a failed instance operation
+///
+/// Running out of ports is expected on a full server rather than a fault, so
+/// it is reported as 503 with an error code WHM can branch on.
+fn operation_error<T>(e: &anyhow::Error) -> (StatusCode, Json<ApiResponse<T>>) {
+    let (status, code) = match e.downcast_ref::<PortError>() {
+        Some(port_error) => (StatusCode::SERVICE_UNAVAILABLE, Some(port_error.code())),
+        None => (StatusCode::INTERNAL_SERVER_ERROR, None),
+    };
+    (
+        status,
+        Json(ApiResponse {
+            status: 0,
+            data: None,
+            code,
+            errors: vec![e.to_string()],
+        }),
+    )
+}
+
+/// Remove a user instance, e.g. after the cPanel account is terminated
+///
+/// Apps and data are kept unless `purge=true`; `archive=true` backs the
+/// instance directory up first. Removing a missing instance succeeds.
+pub async fn delete_instance(
+    State(manager): State<Arc<FrameManager>>,
+    Path(username): Path<String>,
+    Query(options): Query<RemoveOptions>,
+) -> (StatusCode, Json<ApiResponse<RemoveReport>>) {
+    match manager.remove_instance(&username, options).await {
+        Ok(report) => (StatusCode::OK, Json(ApiResponse::success(report))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        ),
+    }
+}
+
+/// Queue an instance operation and wait for it, or return its id when async
+///
+/// Async requests get 202 Accepted with the operation id as data; a full
+/// queue is reported as 503 so callers can back off.
+async fn run_operation(
+    manager: &FrameManager,
+    kind: OperationKind,
+    username: &str,
+    query: OperationQuery,
+    done_message: String,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    let handle = match manager.submit_operation(kind, username).await {
+        Ok(handle) => handle,
+        Err(e) => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ApiResponse {
+                    status: 0,
+                    data: None,
+                    code: None,
+                    errors: vec![e.to_string()],
+                }),
+            )
+        }
+    };
+
+    if query.is_async {
+        return (
+            StatusCode::ACCEPTED,
+            Json(ApiResponse::success(handle.id.to_string())),
+        );
+    }
+
+    match handle.wait().await {
+        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(done_message))),
+        Err(e) => operation_error(&e),
+    }
+}
+
+/// List recent queued instance operations
+pub async fn list_operations(
+    State(manager): State<Arc<FrameManager>>,
+) -> Json<ApiResponse<Vec<Operation>>> {
+    Json(ApiResponse::success(manager.list_operations().await))
+}
+
+/// Get the status of a queued instance operation
+pub async fn get_operation(
+    State(manager): State<Arc<FrameManager>>,
+    Path(id): Path<String>,
+) -> (StatusCode, Json<ApiResponse<Operation>>) {
+    match manager.get_operation(&id).await {
+        Ok(operation) => (StatusCode::OK, Json(ApiResponse::success(operation))),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        ),
+    }
+}
+
+/// Start all instances in a group
+pub async fn start_group(
+    State(manager): State<Arc<FrameManager>>,
+    Path(name): Path<String>,
+) -> (
+    StatusCode,
+    Json<ApiResponse<BTreeMap<String, GroupMemberResult>>>,
+) {
+    group_response(manager.start_group(&name).await)
+}
+
+/// Stop all instances in a group
+pub async fn stop_group(
+    State(manager): State<Arc<FrameManager>>,
+    Path(name): Path<String>,
+) -> (
+    StatusCode,
+    Json<ApiResponse<BTreeMap<String, GroupMemberResult>>>,
+) {
+    group_response(manager.stop_group(&name).await)
+}
+
+/// Restart all instances in a group
+pub async fn restart_group(
+    State(manager): State<Arc<FrameManager>>,
+    Path(name): Path<String>,
+) -> (
+    StatusCode,
+    Json<ApiResponse<BTreeMap<String, GroupMemberResult>>>,
+) {
+    group_response(manager.restart_group(&name).await)
+}
+
+fn group_response(
+    result: anyhow::Result<BTreeMap<String, GroupMemberResult>>,
+) -> (
+    StatusCode,
+    Json<ApiResponse<BTreeMap<String, GroupMemberResult>>>,
+) {
+    match result {
+        Ok(results) => (StatusCode::OK, Json(ApiResponse::success(results))),
+        Err(e) => (
+            match e.downcast_ref::<GroupError>() {
+                Some(GroupError::Unknown(_)) => StatusCode::NOT_FOUND,
+                None => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        ),
+    }
+}
+
+/// Start, stop or restart several instances at once
+///
+/// Always answers 200 with a result per user once every operation has
+/// finished; one user failing doesn't stop the others.
+pub async fn batch_instances(
+    State(manager): State<Arc<FrameManager>>,
+    Json(request): Json<BatchRequest>,
+) -> (
+    StatusCode,
+    Json<ApiResponse<BTreeMap<String, GroupMemberResult>>>,
+) {
+    let usernames = match (request.all, request.usernames.is_empty()) {
+        (true, true) => None,
+        (false, false) => Some(request.usernames),
+        (true, false) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse {
+                    status: 0,
+                    data: None,
+                    code: None,
+                    errors: vec!["Give either usernames or all, not both".to_string()],
+                }),
+            )
+        }
+        (false, true) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse {
+                    status: 0,
+                    data: None,
+                    code: None,
+                    errors: vec!["No usernames given".to_string()],
+                }),
+            )
+        }
+    };
+
+    let results = manager.run_batch(request.action, usernames).await;
+    (StatusCode::OK, Json(ApiResponse::success(results)))
+}
+
+/// Reload an instance via signal, or restart it if reloads aren't supported
+pub async fn reload_instance(
+    State(manager): State<Arc<FrameManager>>,
+    Path(username): Path<String>,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    match manager.reload_instance(&username).await {
+        Ok(method) => (
             StatusCode::OK,
-            Json(ApiResponse::success(format!("Instance started for {}", username))),
+            Json(ApiResponse::success(format!(
+                "Instance reloaded for {} ({})",
+                username, method
+            ))),
         ),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse {
                 status: 0,
                 data: None,
+                code: None,
                 errors: vec![e.to_string()],
             }),
         ),
     }
 }
 
-/// Stop a user instance
-pub async fn stop_instance(
+/// Force-kill a stuck instance and mark it failed
+///
+/// Its port is released once nothing listens on it any more.
+pub async fn force_kill_instance(
+    State(manager): State<Arc<FrameManager>>,
+    Path(username): Path<String>,
+) -> (StatusCode, Json<ApiResponse<ForceKillReport>>) {
+    match manager.force_kill_instance(&username).await {
+        Ok(report) => (StatusCode::OK, Json(ApiResponse::success(report))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        ),
+    }
+}
+
+/// Freeze an instance's processes for debugging
+pub async fn freeze_instance(
     State(manager): State<Arc<FrameManager>>,
     Path(username): Path<String>,
 ) -> (StatusCode, Json<ApiResponse<String>>) {
-    match manager.stop_instance(&username).await {
-        Ok(_) => (
+    match manager.freeze_instance(&username).await {
+        Ok(()) => (
             StatusCode::OK,
-            Json(ApiResponse::success(format!("Instance stopped for {}", username))),
+            Json(ApiResponse::success(format!(
+                "Instance frozen for {}",
+                username
+            ))),
         ),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse {
                 status: 0,
                 data: None,
+                code: None,
                 errors: vec![e.to_string()],
             }),
         ),
     }
 }
 
-/// Restart a user instance
-pub async fn restart_instance(
+/// Thaw a frozen instance
+pub async fn thaw_instance(
+    State(manager): State<Arc<FrameManager>>,
+    Path(username): Path<String>,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    match manager.thaw_instance(&username).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(format!(
+                "Instance thawed for {}",
+                username
+            ))),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        ),
+    }
+}
+
+/// Set an instance's log level (restarts it if running)
+pub async fn set_instance_log_level(
     State(manager): State<Arc<FrameManager>>,
     Path(username): Path<String>,
+    Json(update): Json<LogLevelUpdate>,
 ) -> (StatusCode, Json<ApiResponse<String>>) {
-    match manager.restart_instance(&username).await {
+    if let Some(level) = update.log_level.as_deref() {
+        if LogLevel::parse(level).is_none() {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse {
+                    status: 0,
+                    data: None,
+                    code: None,
+                    errors: vec![format!(
+                        "Unknown log level: {} (expected one of: {})",
+                        level,
+                        LogLevel::NAMES.join(", ")
+                    )],
+                }),
+            );
+        }
+    }
+
+    match manager
+        .set_instance_log_level(&username, update.log_level.as_deref())
+        .await
+    {
         Ok(_) => (
             StatusCode::OK,
-            Json(ApiResponse::success(format!("Instance restarted for {}", username))),
+            Json(ApiResponse::success(format!(
+                "Log level updated for {}",
+                username
+            ))),
         ),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse {
                 status: 0,
                 data: None,
+                code: None,
                 errors: vec![e.to_string()],
             }),
         ),
     }
 }
 
+/// Deploy a new version of an app (blue/green)
+pub async fn deploy_app(
+    State(manager): State<Arc<FrameManager>>,
+    Path((username, app_name)): Path<(String, String)>,
+    Json(request): Json<DeployRequest>,
+) -> (StatusCode, Json<ApiResponse<DeployResult>>) {
+    let artifact = std::path::PathBuf::from(request.artifact);
+    match manager.deploy_app(&username, &app_name, &artifact).await {
+        Ok(result) => (StatusCode::OK, Json(ApiResponse::success(result))),
+        Err(e) => {
+            let status = match e.downcast_ref::<DeployError>() {
+                Some(DeployError::Invalid(_)) => StatusCode::BAD_REQUEST,
+                Some(DeployError::NotFound(_)) => StatusCode::NOT_FOUND,
+                None => return operation_error(&e),
+            };
+            (
+                status,
+                Json(ApiResponse {
+                    status: 0,
+                    data: None,
+                    code: None,
+                    errors: vec![e.to_string()],
+                }),
+            )
+        }
+    }
+}
+
 /// Get instance logs
 pub async fn get_instance_logs(
     State(manager): State<Arc<FrameManager>>,
@@ -202,12 +871,89 @@ pub async fn get_instance_logs(
             Json(ApiResponse {
                 status: 0,
                 data: None,
+                code: None,
                 errors: vec![e.to_string()],
             }),
         ),
     }
 }
 
+/// SSE event carrying one log line
+///
+/// SSE has no escape for carriage returns (the builder panics on them), so
+/// any left in the line, e.g. from progress output, are dropped.
+pub(crate) fn log_event(line: &str) -> SseEvent {
+    SseEvent::default().data(line.replace('\r', ""))
+}
+
+/// Follow one instance's log (Server-Sent Events)
+///
+/// Sends the last `lines` lines, then each line as it's appended. Following
+/// stops when the client disconnects.
+pub async fn stream_instance_logs(
+    State(manager): State<Arc<FrameManager>>,
+    Path(username): Path<String>,
+    Query(query): Query<LogFollowQuery>,
+) -> Result<
+    Sse<impl Stream<Item = Result<SseEvent, Infallible>>>,
+    (StatusCode, Json<ApiResponse<()>>),
+> {
+    let rx = match manager.follow_logs(&username, query.lines).await {
+        Ok(rx) => rx,
+        Err(e) => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error(&e.to_string())),
+            ))
+        }
+    };
+    let stream = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (Ok(log_event(&line)), rx))
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Stream combined logs from all instances (Server-Sent Events)
+pub async fn stream_logs(
+    State(manager): State<Arc<FrameManager>>,
+    Query(query): Query<LogStreamQuery>,
+) -> Result<
+    Sse<impl Stream<Item = Result<SseEvent, Infallible>>>,
+    (StatusCode, Json<ApiResponse<()>>),
+> {
+    let min_level = match query.level.as_deref() {
+        Some(level) => match LogLevel::parse(level) {
+            Some(level) => Some(level),
+            None => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::<()>::error(&format!(
+                        "Unknown log level: {}",
+                        level
+                    ))),
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let users = query.users.map(|users| {
+        users
+            .split(',')
+            .map(|u| u.trim().to_string())
+            .filter(|u| !u.is_empty())
+            .collect()
+    });
+
+    let rx = manager.stream_logs(LogFilter { users, min_level }).await;
+    let stream = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (Ok(log_event(&line)), rx))
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
 /// Get instance status
 pub async fn get_instance_status(
     State(manager): State<Arc<FrameManager>>,
@@ -220,6 +966,7 @@ pub async fn get_instance_status(
             Json(ApiResponse {
                 status: 0,
                 data: None,
+                code: None,
                 errors: vec![e.to_string()],
             }),
         ),
@@ -235,17 +982,169 @@ pub async fn get_settings(
         Err(e) => Json(ApiResponse {
             status: 0,
             data: None,
+            code: None,
             errors: vec![e.to_string()],
         }),
     }
 }
 
+/// Get every setting's effective value and its source (file or default)
+pub async fn get_effective_config(
+    State(manager): State<Arc<FrameManager>>,
+) -> (StatusCode, Json<ApiResponse<EffectiveConfig>>) {
+    match manager.effective_config().await {
+        Ok(config) => (StatusCode::OK, Json(ApiResponse::success(config))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        ),
+    }
+}
+
+/// Check the configuration file a reload would apply, without applying it
+///
+/// Answers 422 with every problem found if the file is invalid.
+pub async fn validate_config(
+    State(manager): State<Arc<FrameManager>>,
+) -> (StatusCode, Json<ApiResponse<ConfigValidation>>) {
+    let validation = manager.validate_config_file(manager.config_path()).await;
+    if validation.valid {
+        return (StatusCode::OK, Json(ApiResponse::success(validation)));
+    }
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(ApiResponse {
+            status: 0,
+            errors: validation.problems.clone(),
+            data: Some(validation),
+            code: None,
+        }),
+    )
+}
+
+/// Reload the configuration file, returning the settings now in effect
+///
+/// Answers 422 and keeps the current configuration if the file is invalid.
+pub async fn reload_config(
+    State(manager): State<Arc<FrameManager>>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    let error = |status: StatusCode, e: anyhow::Error| {
+        (
+            status,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        )
+    };
+    match manager.reload_config().await {
+        Ok(config) => match serde_json::to_value(&config) {
+            Ok(settings) => (StatusCode::OK, Json(ApiResponse::success(settings))),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.into()),
+        },
+        Err(e) => error(StatusCode::UNPROCESSABLE_ENTITY, e),
+    }
+}
+
+/// Get one settings section (service, defaults, logging, security or proxy)
+pub async fn get_settings_section(
+    State(manager): State<Arc<FrameManager>>,
+    Path(section): Path<String>,
+) -> (StatusCode, Json<ApiResponse<serde_json::Value>>) {
+    match manager.get_settings_section(&section).await {
+        Ok(Some(settings)) => (StatusCode::OK, Json(ApiResponse::success(settings))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![format!("Unknown settings section: {}", section)],
+            }),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        ),
+    }
+}
+
+/// Refuse settings writes while no API token is configured
+///
+/// Without a token every local user can reach the API, and the settings
+/// steer the root daemon.
+async fn settings_writes_allowed<T>(
+    manager: &FrameManager,
+) -> Result<(), (StatusCode, Json<ApiResponse<T>>)> {
+    if manager.api_auth().await.token.is_some() {
+        return Ok(());
+    }
+    Err((
+        StatusCode::FORBIDDEN,
+        Json(ApiResponse {
+            status: 0,
+            data: None,
+            code: None,
+            errors: vec!["Settings can't be changed until [security] api_token is set".to_string()],
+        }),
+    ))
+}
+
+/// Update some keys of one settings section, leaving the rest of the file alone
+///
+/// Answers 403 while no API token is configured.
+pub async fn update_settings_section(
+    State(manager): State<Arc<FrameManager>>,
+    Path(section): Path<String>,
+    Json(values): Json<serde_json::Map<String, serde_json::Value>>,
+) -> (StatusCode, Json<ApiResponse<SettingsSectionUpdate>>) {
+    if let Err(rejection) = settings_writes_allowed(&manager).await {
+        return rejection;
+    }
+    match manager.update_settings_section(&section, values).await {
+        Ok(update) => (StatusCode::OK, Json(ApiResponse::success(update))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![format!("{:#}", e)],
+            }),
+        ),
+    }
+}
+
 /// Update settings
+///
+/// Answers 403 while no API token is configured.
 pub async fn update_settings(
     State(manager): State<Arc<FrameManager>>,
     Json(update): Json<SettingsUpdate>,
 ) -> (StatusCode, Json<ApiResponse<String>>) {
+    if let Err(rejection) = settings_writes_allowed(&manager).await {
+        return rejection;
+    }
     match manager.update_settings(update).await {
+        Ok(update) if update.restart_required => (
+            StatusCode::OK,
+            Json(ApiResponse::success(format!(
+                "Settings updated; {} take effect when the service restarts",
+                update.restart_keys.join(", ")
+            ))),
+        ),
         Ok(_) => (
             StatusCode::OK,
             Json(ApiResponse::success("Settings updated".to_string())),
@@ -255,12 +1154,48 @@ pub async fn update_settings(
             Json(ApiResponse {
                 status: 0,
                 data: None,
+                code: None,
                 errors: vec![e.to_string()],
             }),
         ),
     }
 }
 
+/// Replace the API token, returning the new one
+///
+/// The token is only shown in this response. Answers 409 if no token is
+/// configured.
+pub async fn rotate_api_token(
+    State(manager): State<Arc<FrameManager>>,
+) -> (StatusCode, Json<ApiResponse<TokenRotation>>) {
+    let error = |status: StatusCode, e: String| {
+        (
+            status,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e],
+            }),
+        )
+    };
+
+    if manager.api_auth().await.token.is_none() {
+        return error(
+            StatusCode::CONFLICT,
+            "No API token is configured to rotate".to_string(),
+        );
+    }
+
+    match manager.rotate_api_token().await {
+        Ok(token) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(TokenRotation { token })),
+        ),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)),
+    }
+}
+
 /// List packages
 pub async fn list_packages(
     State(manager): State<Arc<FrameManager>>,
@@ -270,6 +1205,7 @@ pub async fn list_packages(
         Err(e) => Json(ApiResponse {
             status: 0,
             data: None,
+            code: None,
             errors: vec![e.to_string()],
         }),
     }
@@ -281,6 +1217,18 @@ pub async fn update_package(
     Path(name): Path<String>,
     Json(update): Json<PackageUpdate>,
 ) -> (StatusCode, Json<ApiResponse<String>>) {
+    if let Err(e) = PackageConfig::validate_name(&name) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        );
+    }
+
     match manager.update_package(&name, update).await {
         Ok(_) => (
             StatusCode::OK,
@@ -291,6 +1239,41 @@ pub async fn update_package(
             Json(ApiResponse {
                 status: 0,
                 data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        ),
+    }
+}
+
+/// Apply a package's current limits to every instance assigned to it
+pub async fn apply_package(
+    State(manager): State<Arc<FrameManager>>,
+    Path(name): Path<String>,
+) -> (
+    StatusCode,
+    Json<ApiResponse<BTreeMap<String, PackageMemberResult>>>,
+) {
+    if let Err(e) = PackageConfig::validate_name(&name) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        );
+    }
+
+    match manager.apply_package_to_members(&name).await {
+        Ok(results) => (StatusCode::OK, Json(ApiResponse::success(results))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
                 errors: vec![e.to_string()],
             }),
         ),
@@ -306,6 +1289,7 @@ pub async fn list_ports(
         Err(e) => Json(ApiResponse {
             status: 0,
             data: None,
+            code: None,
             errors: vec![e.to_string()],
         }),
     }
@@ -319,7 +1303,144 @@ pub async fn get_metrics(State(manager): State<Arc<FrameManager>>) -> String {
     }
 }
 
-/// Health check endpoint
+/// Record request statistics reported by an instance (see [`RequestReport`])
+///
+/// The instance authenticates with the `FRAME_METRICS_TOKEN` it was started
+/// with, so one user can't report for another.
+pub async fn report_instance_requests(
+    State(manager): State<Arc<FrameManager>>,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+    Json(report): Json<RequestReport>,
+) -> (StatusCode, Json<ApiResponse<String>>) {
+    let error = |status: StatusCode, e: anyhow::Error| {
+        (
+            status,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        )
+    };
+    let authorized = match bearer_token(&headers) {
+        Some(token) => manager.metrics_token_matches(&username, token).await,
+        None => false,
+    };
+    if !authorized {
+        return error(
+            StatusCode::UNAUTHORIZED,
+            anyhow::anyhow!("Missing or invalid instance metrics token"),
+        );
+    }
+    if let Err(e) = report.validate() {
+        return error(StatusCode::BAD_REQUEST, e);
+    }
+    match manager.record_requests(&username, &report).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(format!(
+                "Request metrics recorded for {}",
+                username
+            ))),
+        ),
+        Err(e) => error(StatusCode::NOT_FOUND, e),
+    }
+}
+
+/// Recent events, filtered by `username`, `since` and `type`
+pub async fn list_events(
+    State(manager): State<Arc<FrameManager>>,
+    Query(query): Query<EventQuery>,
+) -> (StatusCode, Json<ApiResponse<Vec<EventEnvelope>>>) {
+    match manager.recent_events(&query) {
+        Ok(events) => (StatusCode::OK, Json(ApiResponse::success(events))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        ),
+    }
+}
+
+/// List hook scripts and whether they're executable
+pub async fn list_hooks(
+    State(manager): State<Arc<FrameManager>>,
+) -> Json<ApiResponse<Vec<HookInfo>>> {
+    Json(ApiResponse::success(manager.list_hooks()))
+}
+
+/// Run the hooks for a synthetic event, optionally overriding sample fields
+pub async fn test_hook(
+    State(manager): State<Arc<FrameManager>>,
+    Path(event): Path<String>,
+    fields: Option<Json<serde_json::Map<String, serde_json::Value>>>,
+) -> (StatusCode, Json<ApiResponse<Vec<HookTestResult>>>) {
+    let fields = fields.map(|Json(f)| f).unwrap_or_default();
+    match manager.test_hook(&event, fields).await {
+        Ok(results) => (StatusCode::OK, Json(ApiResponse::success(results))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                status: 0,
+                data: None,
+                code: None,
+                errors: vec![e.to_string()],
+            }),
+        ),
+    }
+}
+
+/// Inject a synthetic instance for integration tests (`testing` feature)
+#[cfg(feature = "testing")]
+pub async fn inject_test_instance(
+    State(manager): State<Arc<FrameManager>>,
+    Json(spec): Json<crate::instance::SyntheticInstance>,
+) -> (StatusCode, Json<ApiResponse<InstanceStatusResponse>>) {
+    let instance = manager.inject_instance(spec).await;
+    (StatusCode::CREATED, Json(ApiResponse::success(instance)))
+}
+
+/// Liveness check: the process is up and answering
 pub async fn health_check() -> (StatusCode, &'static str) {
     (StatusCode::OK, "OK")
 }
+
+/// Readiness check: 503 until startup has finished and while stopping
+pub async fn readiness_check(
+    State(manager): State<Arc<FrameManager>>,
+) -> (StatusCode, &'static str) {
+    if manager.is_ready() {
+        (StatusCode::OK, "READY")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "NOT READY")
+    }
+}
+
+/// List the service name, version and endpoints
+pub async fn api_index() -> Json<ApiResponse<ApiIndex>> {
+    Json(ApiResponse::success(ApiIndex {
+        service: "frame-manager",
+        version: env!("CARGO_PKG_VERSION"),
+        endpoints: super::routes::ENDPOINTS
+            .iter()
+            .map(|&(method, path)| EndpointInfo { method, path })
+            .collect(),
+    }))
+}
+
+/// JSON 404 for paths without a route
+pub async fn not_found(uri: Uri) -> (StatusCode, Json<ApiResponse<()>>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(&format!(
+            "not found: {}",
+            uri.path()
+        ))),
+    )
+}
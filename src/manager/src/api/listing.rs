@@ -0,0 +1,177 @@
+//! Instance Listing
+//!
+//! Filtering, sorting and paging for `GET /frame/instances`, so a reseller
+//! server with thousands of accounts doesn't have to send them all at once.
+
+use serde::{Deserialize, Serialize};
+
+use super::handlers::InstanceStatusResponse;
+use crate::instance::InstanceStatus;
+
+/// Most instances returned in one page
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// Order of listed instances
+///
+/// Usage and restarts sort highest first; ties are broken by username.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstanceSort {
+    #[default]
+    Username,
+    Port,
+    Memory,
+    Cpu,
+    Restarts,
+}
+
+/// Query parameters for listing instances
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct InstanceListQuery {
+    /// Only instances in this status
+    pub status: Option<InstanceStatus>,
+    pub sort: Option<InstanceSort>,
+    /// Page size, capped at [`MAX_PAGE_SIZE`]; all instances if unset
+    pub limit: Option<usize>,
+    /// Matching instances to skip
+    pub offset: Option<usize>,
+}
+
+/// One page of listed instances
+#[derive(Serialize)]
+pub struct InstancePage {
+    pub instances: Vec<InstanceStatusResponse>,
+    /// Instances matching the filter, across all pages
+    pub total: usize,
+    pub offset: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// Listed instances as returned by the API
+///
+/// Requests without query parameters get the plain array older clients
+/// expect, in username order; any parameter switches to the paged envelope.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum InstanceList {
+    All(Vec<InstanceStatusResponse>),
+    Page(InstancePage),
+}
+
+impl InstanceListQuery {
+    /// Whether no parameter was given
+    pub fn is_empty(&self) -> bool {
+        self.status.is_none()
+            && self.sort.is_none()
+            && self.limit.is_none()
+            && self.offset.is_none()
+    }
+
+    /// Filter, sort and page a list of instances
+    pub fn apply(&self, mut instances: Vec<InstanceStatusResponse>) -> InstancePage {
+        if let Some(status) = self.status {
+            let status = status.to_string();
+            instances.retain(|i| i.status == status);
+        }
+
+        instances.sort_by(|a, b| {
+            match self.sort.unwrap_or_default() {
+                InstanceSort::Username => std::cmp::Ordering::Equal,
+                InstanceSort::Port => a.port.cmp(&b.port),
+                InstanceSort::Memory => b.memory_usage_mb.cmp(&a.memory_usage_mb),
+                InstanceSort::Cpu => b.cpu_usage.total_cmp(&a.cpu_usage),
+                InstanceSort::Restarts => b.restart_count.cmp(&a.restart_count),
+            }
+            .then_with(|| a.username.cmp(&b.username))
+        });
+
+        let total = instances.len();
+        let offset = self.offset.unwrap_or(0);
+        let limit = self.limit.map(|limit| limit.min(MAX_PAGE_SIZE));
+        let instances = instances
+            .into_iter()
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .collect();
+
+        InstancePage {
+            instances,
+            total,
+            offset,
+            limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(
+        username: &str,
+        status: InstanceStatus,
+        memory_usage_mb: u64,
+    ) -> InstanceStatusResponse {
+        InstanceStatusResponse {
+            username: username.to_string(),
+            status: status.to_string(),
+            status_detail: None,
+            port: 30001,
+            memory_usage_mb,
+            cpu_usage: 0.0,
+            app_count: 0,
+            flapping: false,
+            restart_count: 0,
+            last_exit_reason: None,
+            started_at: None,
+            uptime_seconds: None,
+            last_health_check: None,
+        }
+    }
+
+    fn usernames(page: &InstancePage) -> Vec<&str> {
+        page.instances.iter().map(|i| i.username.as_str()).collect()
+    }
+
+    #[test]
+    fn test_filter_sort_and_page() {
+        let instances = || {
+            vec![
+                instance("carol", InstanceStatus::Running, 300),
+                instance("alice", InstanceStatus::Running, 100),
+                instance("dave", InstanceStatus::Stopped, 0),
+                instance("bob", InstanceStatus::Running, 200),
+            ]
+        };
+
+        // Without parameters every instance is listed, by username
+        let query = InstanceListQuery::default();
+        assert!(query.is_empty());
+        let page = query.apply(instances());
+        assert_eq!(usernames(&page), ["alice", "bob", "carol", "dave"]);
+
+        let query = InstanceListQuery {
+            status: Some(InstanceStatus::Running),
+            sort: Some(InstanceSort::Memory),
+            limit: Some(2),
+            offset: Some(1),
+        };
+        let page = query.apply(instances());
+        assert_eq!(page.total, 3);
+        assert_eq!(usernames(&page), ["bob", "alice"]);
+
+        // Offsets past the end give an empty page, limits are capped
+        let query = InstanceListQuery {
+            limit: Some(MAX_PAGE_SIZE + 1),
+            offset: Some(10),
+            ..InstanceListQuery::default()
+        };
+        let page = query.apply(instances());
+        assert!(page.instances.is_empty());
+        assert_eq!(
+            (page.total, page.offset, page.limit),
+            (4, 10, Some(MAX_PAGE_SIZE))
+        );
+    }
+}
@@ -2,25 +2,88 @@
 //!
 //! Internal HTTP API for WHM/cPanel integration.
 
+pub mod auth;
+pub mod event_stream;
 pub mod handlers;
+pub mod listing;
 pub mod routes;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::Router;
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{watch, RwLock};
 
 pub use handlers::*;
 pub use routes::*;
 
 use crate::manager::FrameManager;
 
+/// How long a stopping API server waits for in-flight requests to finish
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TCP options for the API listening socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerOptions {
+    /// Pending connections queued before new ones are refused
+    pub backlog: u32,
+    /// Idle time before keepalive probes start (`None` disables keepalive)
+    pub keepalive: Option<Duration>,
+    /// Time between unanswered keepalive probes
+    pub keepalive_interval: Duration,
+    /// Unanswered probes before the connection is dropped
+    pub keepalive_retries: u32,
+}
+
+impl Default for ListenerOptions {
+    fn default() -> Self {
+        Self {
+            backlog: 1024,
+            keepalive: Some(Duration::from_secs(60)),
+            keepalive_interval: Duration::from_secs(10),
+            keepalive_retries: 5,
+        }
+    }
+}
+
+impl ListenerOptions {
+    /// Bind a listener with these options
+    ///
+    /// Keepalive is set on the listening socket; Linux copies it to every
+    /// accepted connection.
+    pub fn bind(&self, addr: SocketAddr) -> Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        if let Some(idle) = self.keepalive {
+            let keepalive = TcpKeepalive::new()
+                .with_time(idle)
+                .with_interval(self.keepalive_interval)
+                .with_retries(self.keepalive_retries);
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        socket
+            .bind(&addr.into())
+            .with_context(|| format!("Failed to bind API listener on {}", addr))?;
+        socket.listen(i32::try_from(self.backlog).unwrap_or(i32::MAX))?;
+
+        Ok(TcpListener::from_std(socket.into())?)
+    }
+}
+
 /// API server
 pub struct ApiServer {
     port: u16,
     manager: Arc<FrameManager>,
+    listener: ListenerOptions,
     running: Arc<RwLock<bool>>,
+    /// Set to stop accepting connections and drain the open ones
+    shutdown: watch::Sender<bool>,
+    /// Set once the server has drained and `start` is returning
+    finished: watch::Sender<bool>,
 }
 
 impl ApiServer {
@@ -29,17 +92,31 @@ impl ApiServer {
         Self {
             port,
             manager,
+            listener: ListenerOptions::default(),
             running: Arc::new(RwLock::new(false)),
+            shutdown: watch::Sender::new(false),
+            finished: watch::Sender::new(false),
         }
     }
 
+    /// Bind the listener with `options` instead of the defaults
+    pub fn with_listener_options(mut self, options: ListenerOptions) -> Self {
+        self.listener = options;
+        self
+    }
+
     /// Start the API server
+    ///
+    /// Serves until [`stop`](Self::stop) is called, then returns once the
+    /// open requests have been answered.
     pub async fn start(&self) -> Result<()> {
         let mut running = self.running.write().await;
         if *running {
             return Ok(());
         }
         *running = true;
+        self.shutdown.send_replace(false);
+        self.finished.send_replace(false);
         drop(running);
 
         let app = create_router(Arc::clone(&self.manager));
@@ -47,16 +124,56 @@ impl ApiServer {
 
         tracing::info!("API server listening on http://{}", addr);
 
-        let listener = tokio::net::TcpListener::bind(addr).await?;
-        axum::serve(listener, app).await?;
+        let result = match self.listener.bind(addr) {
+            Ok(listener) => {
+                self.manager.set_ready(true);
+                let result = self.serve(listener, app).await;
+                self.manager.set_ready(false);
+                result
+            }
+            Err(e) => Err(e),
+        };
 
-        Ok(())
+        *self.running.write().await = false;
+        self.finished.send_replace(true);
+        result
     }
 
-    /// Stop the API server (graceful shutdown would need more work)
+    /// Serve until shut down, then drain for at most `DRAIN_TIMEOUT`
+    ///
+    /// Connections still open after that (e.g. log streams) are dropped.
+    async fn serve(&self, listener: TcpListener, app: Router) -> Result<()> {
+        let mut graceful = self.shutdown.subscribe();
+        let mut forced = self.shutdown.subscribe();
+
+        let serve = axum::serve(listener, app).with_graceful_shutdown(async move {
+            let _ = graceful.wait_for(|stop| *stop).await;
+        });
+        tokio::select! {
+            result = serve => Ok(result?),
+            _ = async {
+                let _ = forced.wait_for(|stop| *stop).await;
+                tokio::time::sleep(DRAIN_TIMEOUT).await;
+            } => {
+                tracing::warn!(
+                    "API server still had open connections after {}s, closing them",
+                    DRAIN_TIMEOUT.as_secs()
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Stop accepting connections and wait for in-flight requests to finish
     pub async fn stop(&self) {
-        let mut running = self.running.write().await;
-        *running = false;
+        if !*self.running.read().await {
+            return;
+        }
+
+        tracing::info!("API server draining in-flight requests");
+        let mut finished = self.finished.subscribe();
+        self.shutdown.send_replace(true);
+        let _ = finished.wait_for(|done| *done).await;
         tracing::info!("API server stopped");
     }
 }
@@ -65,3 +182,608 @@ impl ApiServer {
 fn create_router(manager: Arc<FrameManager>) -> Router {
     routes::create_routes(manager)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, State};
+    use axum::http::{header, HeaderMap, StatusCode};
+    use axum::Json;
+
+    use crate::metrics::RequestReport;
+
+    /// Configuration keeping all of the manager's files under `dir`
+    fn test_config(dir: &std::path::Path) -> crate::config::Config {
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let mut config = crate::config::Config::default();
+        config.paths.instances_dir = path("instances");
+        config.paths.registry_path = path("ports.json");
+        config.paths.hooks_dir = path("hooks");
+        config.paths.packages_dir = path("packages");
+        config.paths.state_dir = path("state");
+        config
+    }
+
+    #[tokio::test]
+    async fn test_listener_options() {
+        let options = ListenerOptions {
+            backlog: 16,
+            keepalive: Some(Duration::from_secs(30)),
+            keepalive_interval: Duration::from_secs(5),
+            keepalive_retries: 3,
+        };
+        let listener = options.bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let socket = socket2::SockRef::from(&listener);
+        assert!(socket.keepalive().unwrap());
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(
+                socket.tcp_keepalive_time().unwrap(),
+                Duration::from_secs(30)
+            );
+            assert_eq!(socket.tcp_keepalive_retries().unwrap(), 3);
+        }
+
+        let listener = ListenerOptions {
+            keepalive: None,
+            ..options
+        }
+        .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .unwrap();
+        assert!(!socket2::SockRef::from(&listener).keepalive().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stop_ends_serving() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FrameManager::new(test_config(dir.path())).await.unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let server = Arc::new(ApiServer::new(port, manager));
+        let serving = tokio::spawn({
+            let server = Arc::clone(&server);
+            async move { server.start().await }
+        });
+
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Ok(mut stream) = tokio::net::TcpStream::connect(("127.0.0.1", port)).await {
+                    stream
+                        .write_all(
+                            b"GET /health HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+                        )
+                        .await
+                        .unwrap();
+                    stream.read_to_string(&mut response).await.unwrap();
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("server never accepted a connection");
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        // stop returns only once the serve future has completed
+        server.stop().await;
+        tokio::time::timeout(Duration::from_secs(5), serving)
+            .await
+            .expect("server kept serving after stop")
+            .unwrap()
+            .unwrap();
+        assert!(tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.service.manager_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let manager = FrameManager::new(config).await.unwrap();
+        let ready = |manager: &Arc<FrameManager>| readiness_check(State(Arc::clone(manager)));
+        assert_eq!(health_check().await.0, StatusCode::OK);
+        assert_eq!(ready(&manager).await.0, StatusCode::SERVICE_UNAVAILABLE);
+
+        let run = tokio::spawn({
+            let manager = Arc::clone(&manager);
+            async move { manager.run().await }
+        });
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !manager.is_ready() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("manager never became ready");
+        assert_eq!(ready(&manager).await.0, StatusCode::OK);
+
+        manager.stop().await.unwrap();
+        assert_eq!(ready(&manager).await.0, StatusCode::SERVICE_UNAVAILABLE);
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_request_metrics() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stands in for frame-server: keeps the token it was started with
+        let dir = tempfile::tempdir().unwrap();
+        let server = dir.path().join("frame-server");
+        let token_file = dir.path().join("token");
+        std::fs::write(
+            &server,
+            format!(
+                "#!/bin/sh\necho \"$FRAME_METRICS_TOKEN\" > {}\nexec sleep 30\n",
+                token_file.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut config = test_config(dir.path());
+        config.paths.frame_server_path = server.to_string_lossy().into_owned();
+        let manager = FrameManager::running_as_manager(config).await.unwrap();
+        manager.create_instance("alice", None, true).await.unwrap();
+        manager.create_instance("bob", None, false).await.unwrap();
+        let mut token = String::new();
+        for _ in 0..50 {
+            token = std::fs::read_to_string(&token_file).unwrap_or_default();
+            if token.ends_with('\n') {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let token = token.trim().to_string();
+        assert_eq!(token.len(), 64);
+
+        let report_as = |username: &str, token: &str, report: serde_json::Value| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::AUTHORIZATION,
+                format!("Bearer {}", token).parse().unwrap(),
+            );
+            handlers::report_instance_requests(
+                State(Arc::clone(&manager)),
+                Path(username.to_string()),
+                headers,
+                Json(serde_json::from_value::<RequestReport>(report).unwrap()),
+            )
+        };
+        let report =
+            |username: &str, report: serde_json::Value| report_as(username, &token, report);
+
+        let (status, _) = report(
+            "alice",
+            serde_json::json!({"durations_seconds": [0.02, 0.3]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = report(
+            "alice",
+            serde_json::json!({"requests": 8, "durations_seconds": [7.0]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        // Only alice's own token is accepted for alice
+        let (status, _) = report("bob", serde_json::json!({"requests": 1})).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = report_as("alice", "forged", serde_json::json!({"requests": 1})).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = report("alice", serde_json::json!({"durations_seconds": [-1.0]})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let metrics = handlers::get_metrics(State(Arc::clone(&manager))).await;
+        assert!(metrics.contains("frame_requests_total{user=\"alice\"} 10\n"));
+        assert!(metrics
+            .contains("frame_request_duration_seconds_bucket{user=\"alice\",le=\"0.025\"} 1\n"));
+        assert!(metrics
+            .contains("frame_request_duration_seconds_bucket{user=\"alice\",le=\"+Inf\"} 3\n"));
+        assert!(metrics.contains("frame_request_duration_seconds_count{user=\"alice\"} 3\n"));
+    }
+
+    #[tokio::test]
+    async fn test_reload_over_api() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("frame.conf");
+        let manager = FrameManager::with_config_path(test_config(dir.path()), config_path.clone())
+            .await
+            .unwrap();
+
+        std::fs::write(
+            &config_path,
+            "[service]\nhealth_check_interval = 45\n[security]\napi_token = secret\n",
+        )
+        .unwrap();
+        let (status, Json(response)) = handlers::reload_config(State(Arc::clone(&manager))).await;
+        assert_eq!(status, StatusCode::OK);
+        let settings = response.data.unwrap();
+        assert_eq!(settings["service"]["health_check_interval"], 45);
+        assert!(settings["security"].get("api_token").is_none());
+
+        // An invalid file is rejected whole and the loaded settings stay
+        std::fs::write(
+            &config_path,
+            "[service]\nhealth_check_interval = 60\nport_range_start = 40000\n\
+             port_range_end = 30000\n",
+        )
+        .unwrap();
+        let (status, Json(response)) = handlers::reload_config(State(Arc::clone(&manager))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(response.errors[0].contains("port_range_start"));
+        let settings = manager.get_settings().await.unwrap();
+        assert_eq!(settings["service"]["health_check_interval"], 45);
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_default_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("frame.conf");
+        let manager = FrameManager::with_config_path(test_config(dir.path()), config_path.clone())
+            .await
+            .unwrap();
+        manager.create_instance("alice", None, false).await.unwrap();
+
+        std::fs::write(&config_path, "[defaults]\ncpu_limit = 35\n").unwrap();
+        manager.reload_config().await.unwrap();
+
+        let limits = manager.all_effective_limits().await.unwrap();
+        assert_eq!(limits[0].username, "alice");
+        assert_eq!(limits[0].limits.cpu_percent, 35);
+    }
+
+    #[tokio::test]
+    async fn test_settings_update_reports_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("frame.conf");
+        std::fs::write(&config_path, "[service]\n[security]\napi_token = secret\n").unwrap();
+        let mut config = test_config(dir.path());
+        config.security.api_token = Some("secret".to_string());
+        let manager = FrameManager::with_config_path(config, config_path)
+            .await
+            .unwrap();
+        let update = |section: &str, values: serde_json::Value| {
+            handlers::update_settings_section(
+                State(Arc::clone(&manager)),
+                Path(section.to_string()),
+                Json(serde_json::from_value(values).unwrap()),
+            )
+        };
+
+        // New defaults apply right away
+        let (status, Json(response)) =
+            update("defaults", serde_json::json!({"memory_limit": 256})).await;
+        assert_eq!(status, StatusCode::OK);
+        let result = response.data.unwrap();
+        assert_eq!(result.settings["memory_limit"], 256);
+        assert!(!result.restart_required);
+
+        // The API port is only bound at startup
+        let (status, Json(response)) = update(
+            "service",
+            serde_json::json!({"enabled": true, "manager_port": 9100}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let result = response.data.unwrap();
+        assert!(result.restart_required);
+        assert_eq!(result.restart_keys, ["manager_port"]);
+    }
+
+    #[tokio::test]
+    async fn test_settings_writes_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("frame.conf");
+        let original = "[service]\n[proxy]\nswitch_command = /bin/true\n";
+        std::fs::write(&config_path, original).unwrap();
+        let manager = FrameManager::with_config_path(test_config(dir.path()), config_path.clone())
+            .await
+            .unwrap();
+        let update = |section: &str, values: serde_json::Value| {
+            handlers::update_settings_section(
+                State(Arc::clone(&manager)),
+                Path(section.to_string()),
+                Json(serde_json::from_value(values).unwrap()),
+            )
+        };
+
+        // Nothing can be written while every local user can reach the API
+        let (status, _) = update("defaults", serde_json::json!({"memory_limit": 256})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = handlers::update_settings(
+            State(Arc::clone(&manager)),
+            Json(handlers::SettingsUpdate {
+                enabled: Some(false),
+                auto_start: None,
+                health_check_interval: None,
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // With a token, commands, paths and the token itself stay off limits
+        std::fs::write(
+            &config_path,
+            format!("{}[security]\napi_token = secret\n", original),
+        )
+        .unwrap();
+        manager.reload_config().await.unwrap();
+        for (section, values) in [
+            ("proxy", serde_json::json!({"switch_command": "/tmp/evil"})),
+            (
+                "paths",
+                serde_json::json!({"frame_server_path": "/tmp/evil"}),
+            ),
+            ("paths", serde_json::json!({"hooks_dir": "/tmp"})),
+            ("service", serde_json::json!({"backups_dir": "/tmp"})),
+            ("security", serde_json::json!({"api_token": "mine"})),
+            ("security", serde_json::json!({"require_https": false})),
+        ] {
+            let (status, Json(response)) = update(section, values).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", section);
+            assert!(response.errors[0].contains("can't be changed"));
+        }
+        let content = std::fs::read_to_string(&config_path).unwrap();
+        assert!(content.contains("switch_command = /bin/true"));
+        assert!(!content.contains("/tmp"));
+    }
+
+    #[tokio::test]
+    async fn test_batch_with_invalid_usernames() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FrameManager::new(test_config(dir.path())).await.unwrap();
+        manager.create_instance("alice", None, false).await.unwrap();
+        manager.create_instance("bob", None, false).await.unwrap();
+        manager.start_operation_workers().await;
+        let batch = |request: serde_json::Value| {
+            handlers::batch_instances(
+                State(Arc::clone(&manager)),
+                Json(serde_json::from_value(request).unwrap()),
+            )
+        };
+
+        // Bad names fail on their own; the rest of the batch still runs
+        let (status, Json(response)) = batch(serde_json::json!({
+            "action": "stop",
+            "usernames": ["alice", "ghost", "../etc", "bob", "alice"]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let results = response.data.unwrap();
+        assert_eq!(
+            results.keys().collect::<Vec<_>>(),
+            vec!["../etc", "alice", "bob", "ghost"]
+        );
+        assert!(results["alice"].success && results["bob"].success);
+        assert!(!results["ghost"].success && !results["../etc"].success);
+        assert!(results["ghost"]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("not found"));
+        // Run through the operation queue like single-instance requests
+        let queued: Vec<String> = manager
+            .list_operations()
+            .await
+            .into_iter()
+            .map(|op| op.username)
+            .collect();
+        assert_eq!(queued.len(), 3);
+        assert!(queued.contains(&"ghost".to_string()));
+
+        let (status, Json(response)) =
+            batch(serde_json::json!({"action": "stop", "all": true})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.data.unwrap().len(), 2);
+        let (status, _) = batch(serde_json::json!({"action": "restart"})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_log_event_with_crlf() {
+        for line in ["GET / 200\r", "\r\n", "progress 10%\rprogress 20%\r\n"] {
+            let event = format!("{:?}", handlers::log_event(line));
+            assert!(!event.contains("\\r"), "{}", event);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FrameManager::new(test_config(dir.path())).await.unwrap();
+
+        let (status, Json(response)) =
+            restart_group(State(manager), Path("no-such-group".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(response.errors, vec!["Unknown group: no-such-group"]);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_log_level() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FrameManager::new(test_config(dir.path())).await.unwrap();
+
+        let (status, Json(response)) = set_instance_log_level(
+            State(manager),
+            Path("alice".to_string()),
+            Json(LogLevelUpdate {
+                log_level: Some("verbose".to_string()),
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.errors,
+            vec!["Unknown log level: verbose (expected one of: trace, debug, info, warn, error)"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_package_name_cannot_escape() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FrameManager::new(test_config(dir.path())).await.unwrap();
+
+        let update: PackageUpdate =
+            serde_json::from_value(serde_json::json!({"max_apps": 1})).unwrap();
+        let (status, _) = update_package(
+            State(Arc::clone(&manager)),
+            Path("../escaped".to_string()),
+            Json(update),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!dir.path().join("escaped.conf").exists());
+
+        let (status, _) = apply_package(State(manager), Path("../escaped".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_deploy_request_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FrameManager::new(test_config(dir.path())).await.unwrap();
+        manager.create_instance("alice", None, false).await.unwrap();
+        let deploy = |username: &str, app: &str, artifact: &std::path::Path| {
+            handlers::deploy_app(
+                State(Arc::clone(&manager)),
+                Path((username.to_string(), app.to_string())),
+                Json(serde_json::from_value(serde_json::json!({ "artifact": artifact })).unwrap()),
+            )
+        };
+
+        let outside = dir.path().join("artifact");
+        std::fs::create_dir_all(&outside).unwrap();
+        let (status, _) = deploy("alice", "../blog", &outside).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = deploy("alice", "blog", &outside).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = deploy("alice", "blog", &dir.path().join("missing")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = deploy("bob", "blog", &outside).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_operation_workers_release_manager() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FrameManager::new(test_config(dir.path())).await.unwrap();
+        manager.start_operation_workers().await;
+
+        let weak = Arc::downgrade(&manager);
+        drop(manager);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rotate_api_token() {
+        use axum::http::{header, HeaderMap, HeaderValue, Method};
+
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("frame.conf");
+        let manager = FrameManager::with_config_path(test_config(dir.path()), config_path.clone())
+            .await
+            .unwrap();
+
+        // Nothing to rotate while the API is open
+        let (status, _) = handlers::rotate_api_token(State(Arc::clone(&manager))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        std::fs::write(
+            &config_path,
+            "# Frame\n[security]\napi_token = old\napi_public_reads = false\n",
+        )
+        .unwrap();
+        manager.reload_config().await.unwrap();
+
+        // An edit that hasn't been reloaded yet must stay unapplied
+        std::fs::write(
+            &config_path,
+            "# Frame\n[security]\napi_token = old\napi_public_reads = true\n",
+        )
+        .unwrap();
+
+        let (status, Json(response)) =
+            handlers::rotate_api_token(State(Arc::clone(&manager))).await;
+        assert_eq!(status, StatusCode::OK);
+        let token = response.data.unwrap().token;
+        assert_eq!(token.len(), 64);
+
+        let bearer = |token: &str| {
+            let mut headers = HeaderMap::new();
+            let value = HeaderValue::from_str(&format!("Bearer {}", token)).unwrap();
+            headers.insert(header::AUTHORIZATION, value);
+            headers
+        };
+        let auth = manager.api_auth().await;
+        assert!(!auth.public_reads);
+        assert!(auth
+            .check(&Method::POST, "/frame/instances", &bearer("old"))
+            .is_err());
+        assert!(auth
+            .check(&Method::POST, "/frame/instances", &bearer(&token))
+            .is_ok());
+
+        let content = std::fs::read_to_string(&config_path).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "# Frame\n[security]\napi_token = {}\napi_public_reads = true\n",
+                token
+            )
+        );
+        assert!(!dir.path().join("frame.conf.tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn test_create_instance_over_api() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stands in for frame-server: stays up until stopped
+        let dir = tempfile::tempdir().unwrap();
+        let server = dir.path().join("frame-server");
+        std::fs::write(&server, "#!/bin/sh\nexec sleep 30\n").unwrap();
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut config = test_config(dir.path());
+        config.paths.frame_server_path = server.to_string_lossy().into_owned();
+        let manager = FrameManager::running_as_manager(config).await.unwrap();
+        let create = |request: serde_json::Value| {
+            handlers::create_instance(
+                State(Arc::clone(&manager)),
+                Json(serde_json::from_value(request).unwrap()),
+            )
+        };
+
+        let (status, Json(response)) =
+            create(serde_json::json!({"username": "alice", "auto_start": true})).await;
+        assert_eq!(status, StatusCode::CREATED, "{:?}", response.errors);
+        let instance = response.data.unwrap();
+        assert_eq!(instance.status, "running");
+        assert!(instance.port > 0);
+
+        let (status, _) = create(serde_json::json!({"username": "alice"})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        for username in ["../etc", "root"] {
+            let (status, _) = create(serde_json::json!({ "username": username })).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", username);
+        }
+
+        // Concurrent creates of one user: exactly one wins
+        let results =
+            futures::future::join_all((0..4).map(|_| manager.create_instance("bob", None, false)))
+                .await;
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+
+        manager.stop_instance("alice").await.unwrap();
+    }
+}
@@ -1,41 +1,169 @@
 //! API Route Definitions
 
 use axum::{
-    extract::{Path, State},
-    routing::{get, post, put},
-    Json, Router,
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
 };
 use std::sync::Arc;
 
+use super::auth::require_token;
+use super::event_stream::stream_events;
 use super::handlers::*;
 use crate::manager::FrameManager;
 
 /// State type for handlers
 pub type AppState = Arc<FrameManager>;
 
+/// Endpoints listed by the index at `/`; keep in sync with `create_routes`
+pub const ENDPOINTS: &[(&str, &str)] = &[
+    ("GET", "/frame/status"),
+    ("POST", "/frame/restart"),
+    ("GET", "/frame/service"),
+    ("POST", "/frame/service/enable"),
+    ("POST", "/frame/service/disable"),
+    ("GET", "/frame/instances"),
+    ("POST", "/frame/instances"),
+    ("GET", "/frame/instances/limits"),
+    ("POST", "/frame/instances/batch"),
+    ("DELETE", "/frame/instances/:username"),
+    ("POST", "/frame/instances/:username/start"),
+    ("POST", "/frame/instances/:username/stop"),
+    ("POST", "/frame/instances/:username/restart"),
+    ("POST", "/frame/instances/:username/reload"),
+    ("POST", "/frame/instances/:username/force-kill"),
+    ("POST", "/frame/instances/:username/freeze"),
+    ("POST", "/frame/instances/:username/thaw"),
+    ("GET", "/frame/instances/:username/logs"),
+    ("GET", "/frame/instances/:username/logs/stream"),
+    ("GET", "/frame/instances/:username/status"),
+    ("PUT", "/frame/instances/:username/log-level"),
+    ("POST", "/frame/instances/:username/metrics"),
+    ("POST", "/frame/instances/:username/apps/:app/deploy"),
+    ("GET", "/frame/operations"),
+    ("GET", "/frame/operations/:id"),
+    ("POST", "/frame/groups/:name/start"),
+    ("POST", "/frame/groups/:name/stop"),
+    ("POST", "/frame/groups/:name/restart"),
+    ("GET", "/frame/logs/stream"),
+    ("GET", "/frame/settings"),
+    ("PUT", "/frame/settings"),
+    ("GET", "/frame/settings/:section"),
+    ("PUT", "/frame/settings/:section"),
+    ("GET", "/frame/config/effective"),
+    ("POST", "/frame/config/validate"),
+    ("POST", "/frame/config/rotate-token"),
+    ("POST", "/frame/reload"),
+    ("GET", "/frame/packages"),
+    ("PUT", "/frame/packages/:name"),
+    ("POST", "/frame/packages/:name/apply"),
+    ("GET", "/frame/ports"),
+    ("GET", "/frame/events"),
+    ("GET", "/frame/events/ws"),
+    ("GET", "/frame/hooks"),
+    ("POST", "/frame/hooks/:event/test"),
+    ("GET", "/metrics"),
+    ("GET", "/health"),
+    ("GET", "/health/live"),
+    ("GET", "/health/ready"),
+];
+
 /// Create all API routes
 pub fn create_routes(manager: Arc<FrameManager>) -> Router {
-    Router::new()
+    let router = Router::new()
+        .route("/", get(api_index))
         // Service endpoints
         .route("/frame/status", get(get_status))
         .route("/frame/restart", post(restart_service))
+        .route("/frame/service", get(get_service_state))
+        .route("/frame/service/enable", post(enable_service))
+        .route("/frame/service/disable", post(disable_service))
         // Instance endpoints
-        .route("/frame/instances", get(list_instances))
+        .route(
+            "/frame/instances",
+            get(list_instances).post(create_instance),
+        )
+        .route("/frame/instances/limits", get(list_instance_limits))
+        .route("/frame/instances/batch", post(batch_instances))
+        .route("/frame/instances/:username", delete(delete_instance))
         .route("/frame/instances/:username/start", post(start_instance))
         .route("/frame/instances/:username/stop", post(stop_instance))
         .route("/frame/instances/:username/restart", post(restart_instance))
+        .route("/frame/instances/:username/reload", post(reload_instance))
+        .route(
+            "/frame/instances/:username/force-kill",
+            post(force_kill_instance),
+        )
+        .route("/frame/instances/:username/freeze", post(freeze_instance))
+        .route("/frame/instances/:username/thaw", post(thaw_instance))
         .route("/frame/instances/:username/logs", get(get_instance_logs))
-        .route("/frame/instances/:username/status", get(get_instance_status))
+        .route(
+            "/frame/instances/:username/logs/stream",
+            get(stream_instance_logs),
+        )
+        .route(
+            "/frame/instances/:username/status",
+            get(get_instance_status),
+        )
+        .route(
+            "/frame/instances/:username/log-level",
+            put(set_instance_log_level),
+        )
+        .route(
+            "/frame/instances/:username/metrics",
+            post(report_instance_requests),
+        )
+        .route(
+            "/frame/instances/:username/apps/:app/deploy",
+            post(deploy_app),
+        )
+        // Operation endpoints
+        .route("/frame/operations", get(list_operations))
+        .route("/frame/operations/:id", get(get_operation))
+        // Group endpoints
+        .route("/frame/groups/:name/start", post(start_group))
+        .route("/frame/groups/:name/stop", post(stop_group))
+        .route("/frame/groups/:name/restart", post(restart_group))
+        // Log endpoints
+        .route("/frame/logs/stream", get(stream_logs))
         // Settings endpoints
         .route("/frame/settings", get(get_settings).put(update_settings))
+        .route(
+            "/frame/settings/:section",
+            get(get_settings_section).put(update_settings_section),
+        )
+        .route("/frame/config/effective", get(get_effective_config))
+        .route("/frame/config/validate", post(validate_config))
+        .route("/frame/config/rotate-token", post(rotate_api_token))
+        .route("/frame/reload", post(reload_config))
         // Package endpoints
         .route("/frame/packages", get(list_packages))
         .route("/frame/packages/:name", put(update_package))
+        .route("/frame/packages/:name/apply", post(apply_package))
         // Port endpoints
         .route("/frame/ports", get(list_ports))
+        // Event endpoints
+        .route("/frame/events", get(list_events))
+        .route("/frame/events/ws", get(stream_events))
+        // Hook endpoints
+        .route("/frame/hooks", get(list_hooks))
+        .route("/frame/hooks/:event/test", post(test_hook))
         // Metrics endpoint
         .route("/metrics", get(get_metrics))
-        // Health endpoint
+        // Health endpoints; /health is the liveness check
         .route("/health", get(health_check))
+        .route("/health/live", get(health_check))
+        .route("/health/ready", get(readiness_check))
+        .fallback(not_found);
+
+    // Synthetic instances for integration tests; never in release builds
+    #[cfg(feature = "testing")]
+    let router = router.route("/frame/test/instances", post(inject_test_instance));
+
+    router
+        .layer(middleware::from_fn_with_state(
+            Arc::clone(&manager),
+            require_token,
+        ))
         .with_state(manager)
 }
@@ -0,0 +1,92 @@
+//! Configuration Checks
+//!
+//! Validates a configuration file without applying it, so operators can
+//! catch a bad change before `reload` swaps it in.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use super::{Config, ConfigParser};
+
+/// Outcome of validating a configuration file
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigValidation {
+    pub path: PathBuf,
+    pub valid: bool,
+    /// Every problem found, empty if the file is valid
+    pub problems: Vec<String>,
+}
+
+impl ConfigValidation {
+    /// Parse and validate a file on its own
+    ///
+    /// Returns the parsed configuration when it loads, even with invalid
+    /// values, so cross-checks against the running service can add theirs.
+    pub fn load(path: &Path) -> (Self, Option<Config>) {
+        let mut validation = Self {
+            path: path.to_path_buf(),
+            valid: true,
+            problems: Vec::new(),
+        };
+
+        // Config::load falls back to defaults for a missing file; a dry run
+        // shouldn't pass a path that doesn't exist
+        if !path.exists() {
+            validation.problem(format!("Configuration file not found: {}", path.display()));
+            return (validation, None);
+        }
+
+        match ConfigParser::new().parse_unvalidated(path) {
+            Ok(config) => {
+                for problem in config.problems() {
+                    validation.problem(problem);
+                }
+                (validation, Some(config))
+            }
+            Err(e) => {
+                validation.problem(format!("{:#}", e));
+                (validation, None)
+            }
+        }
+    }
+
+    /// Record a problem, marking the file invalid
+    pub fn problem(&mut self, problem: String) {
+        self.valid = false;
+        self.problems.push(problem);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation_reports_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frame.conf");
+
+        let (validation, config) = ConfigValidation::load(&path);
+        assert!(!validation.valid);
+        assert!(config.is_none());
+
+        std::fs::write(
+            &path,
+            "[service]\nport_range_start = 40000\nport_range_end = 30000\n\
+             health_check_concurrency = 0\n[defaults]\ncpu_limit = 150\n",
+        )
+        .unwrap();
+        let (validation, _) = ConfigValidation::load(&path);
+        assert!(!validation.valid);
+        // Every problem is reported, not just the first
+        assert_eq!(validation.problems.len(), 3, "{:?}", validation.problems);
+        assert!(validation.problems[0].contains("port_range_start"));
+        assert!(validation.problems[1].contains("health_check_concurrency"));
+        assert!(validation.problems[2].contains("cpu_limit"));
+
+        std::fs::write(&path, "[service]\nhealth_check_interval = 60\n").unwrap();
+        let (validation, config) = ConfigValidation::load(&path);
+        assert!(validation.valid && validation.problems.is_empty());
+        assert_eq!(config.unwrap().service.health_check_interval, 60);
+    }
+}
@@ -0,0 +1,359 @@
+//! Known Configuration Keys
+//!
+//! The parser only reads the keys it knows, so anything else in a file is
+//! silently ignored. These tables let it report unknown sections and keys,
+//! with a suggestion when the name looks like a typo.
+
+use configparser::ini::Ini;
+use std::fmt;
+
+/// Sections and keys of a configuration file format
+pub type KnownKeys = &'static [(&'static str, &'static [&'static str])];
+
+/// Keys recognized in each section of the main configuration file
+pub const MAIN_KEYS: KnownKeys = &[
+    (
+        "service",
+        &[
+            "config_version",
+            "strict_config",
+            "enabled",
+            "port_range_start",
+            "port_range_end",
+            "reuse_released_ports",
+            "manager_port",
+            "auto_start",
+            "auto_start_batch_size",
+            "auto_start_batch_delay_secs",
+            "auto_start_max_load",
+            "health_check_interval",
+            "health_check_concurrency",
+            "health_check_timeout_ms",
+            "health_check_tls_warn_days",
+            "health_check_accept_redirects",
+            "disk_check_interval",
+            "min_port_range_size",
+            "spawn_concurrency",
+            "operation_queue_size",
+            "max_concurrent_starts",
+            "flap_threshold",
+            "flap_window_secs",
+            "stop_grace_ms",
+            "exit_code_actions",
+            "backups_dir",
+            "readiness_probe_timeout_ms",
+            "readiness_probe_retries",
+            "metrics_interval",
+            "unhealthy_threshold",
+            "max_restart_attempts",
+            "restart_window_secs",
+            "restart_backoff_secs",
+            "restart_backoff_max_secs",
+            "api_tcp_backlog",
+            "api_tcp_keepalive_secs",
+            "api_tcp_keepalive_interval_secs",
+            "api_tcp_keepalive_retries",
+            "instance_reload_supported",
+            "instance_reload_signal",
+            "cgroup_backend",
+            "spawn_mode",
+        ],
+    ),
+    (
+        "defaults",
+        &["memory_limit", "cpu_limit", "max_apps", "disk_quota"],
+    ),
+    (
+        "logging",
+        &[
+            "level",
+            "retention_days",
+            "max_file_size",
+            "stream_max_watchers",
+        ],
+    ),
+    (
+        "security",
+        &[
+            "allow_fs_access",
+            "allow_sys_access",
+            "require_https",
+            "env_var_allowlist",
+            "env_var_denylist",
+            "managed_users_allow",
+            "managed_users_deny",
+            "api_token",
+            "api_public_reads",
+        ],
+    ),
+    (
+        "proxy",
+        &["backend", "timeout", "websocket", "switch_command"],
+    ),
+    (
+        "paths",
+        &[
+            "instances_dir",
+            "registry_path",
+            "frame_server_path",
+            "hooks_dir",
+            "packages_dir",
+            "groups_path",
+            "cpanel_users_dir",
+            "state_dir",
+        ],
+    ),
+    (
+        "events",
+        &[
+            "webhook_url",
+            "webhook_secret",
+            "webhook_timeout_ms",
+            "webhook_retries",
+            "enabled_events",
+            "hook_timeout_secs",
+            "history_size",
+        ],
+    ),
+];
+
+/// Keys of the main configuration file the running service picks up when
+/// they change; the rest are read once at startup
+pub const LIVE_KEYS: KnownKeys = &[
+    (
+        "service",
+        &[
+            "enabled",
+            "instance_reload_supported",
+            "instance_reload_signal",
+        ],
+    ),
+    (
+        "defaults",
+        &["memory_limit", "cpu_limit", "max_apps", "disk_quota"],
+    ),
+    (
+        "logging",
+        &["retention_days", "max_file_size", "stream_max_watchers"],
+    ),
+    ("security", &["api_token", "api_public_reads"]),
+    ("proxy", &["switch_command"]),
+];
+
+/// Keys of the main configuration file the settings API may change
+///
+/// Commands, paths, the API token and TLS settings are left out: the API
+/// must not be a way to make the root daemon run or write something new.
+pub const WRITABLE_KEYS: KnownKeys = &[
+    (
+        "service",
+        &[
+            "strict_config",
+            "enabled",
+            "port_range_start",
+            "port_range_end",
+            "reuse_released_ports",
+            "manager_port",
+            "auto_start",
+            "auto_start_batch_size",
+            "auto_start_batch_delay_secs",
+            "auto_start_max_load",
+            "health_check_interval",
+            "health_check_concurrency",
+            "health_check_timeout_ms",
+            "health_check_accept_redirects",
+            "disk_check_interval",
+            "min_port_range_size",
+            "spawn_concurrency",
+            "operation_queue_size",
+            "max_concurrent_starts",
+            "flap_threshold",
+            "flap_window_secs",
+            "stop_grace_ms",
+            "exit_code_actions",
+            "readiness_probe_timeout_ms",
+            "readiness_probe_retries",
+            "metrics_interval",
+            "unhealthy_threshold",
+            "max_restart_attempts",
+            "restart_window_secs",
+            "restart_backoff_secs",
+            "restart_backoff_max_secs",
+            "api_tcp_backlog",
+            "api_tcp_keepalive_secs",
+            "api_tcp_keepalive_interval_secs",
+            "api_tcp_keepalive_retries",
+            "instance_reload_supported",
+            "instance_reload_signal",
+            "cgroup_backend",
+            "spawn_mode",
+        ],
+    ),
+    (
+        "defaults",
+        &["memory_limit", "cpu_limit", "max_apps", "disk_quota"],
+    ),
+    (
+        "logging",
+        &[
+            "level",
+            "retention_days",
+            "max_file_size",
+            "stream_max_watchers",
+        ],
+    ),
+    (
+        "security",
+        &[
+            "allow_fs_access",
+            "allow_sys_access",
+            "env_var_allowlist",
+            "env_var_denylist",
+            "managed_users_allow",
+            "managed_users_deny",
+            "api_public_reads",
+        ],
+    ),
+    ("proxy", &["backend", "timeout", "websocket"]),
+];
+
+/// Keys recognized in each section of a package file
+pub const PACKAGE_KEYS: KnownKeys = &[
+    (
+        "limits",
+        &["memory_limit", "cpu_limit", "max_apps", "disk_quota"],
+    ),
+    (
+        "features",
+        &["fs_access", "sys_access", "custom_domains", "ssl_support"],
+    ),
+];
+
+/// Largest edit distance still reported as a likely typo
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// A section or key the parser doesn't recognize
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKey {
+    pub section: String,
+    /// `None` when the whole section is unknown
+    pub key: Option<String>,
+    /// Likely intended name, e.g. `[defaults] memory_limit`
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "unknown key [{}] {}", self.section, key)?,
+            None => write!(f, "unknown section [{}]", self.section)?,
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean {}?)", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sections and keys present in `ini` but not in `known`, sorted
+pub fn unknown_keys(ini: &Ini, known: KnownKeys) -> Vec<UnknownKey> {
+    let mut unknown = Vec::new();
+
+    for (section, keys) in ini.get_map_ref() {
+        let Some((_, section_keys)) = known.iter().find(|(name, _)| name == section) else {
+            let suggestion = closest(section, known.iter().map(|(name, _)| *name))
+                .map(|name| format!("[{}]", name));
+            unknown.push(UnknownKey {
+                section: section.clone(),
+                key: None,
+                suggestion,
+            });
+            continue;
+        };
+
+        for key in keys.keys() {
+            if section_keys.contains(&key.as_str()) {
+                continue;
+            }
+            // A correctly spelled key in the wrong section is the likelier mistake
+            let suggestion = known
+                .iter()
+                .find(|(_, keys)| keys.contains(&key.as_str()))
+                .map(|(name, _)| format!("[{}] {}", name, key))
+                .or_else(|| {
+                    closest(key, section_keys.iter().copied())
+                        .map(|name| format!("[{}] {}", section, name))
+                });
+            unknown.push(UnknownKey {
+                section: section.clone(),
+                key: Some(key.clone()),
+                suggestion,
+            });
+        }
+    }
+
+    unknown.sort_by(|a, b| (&a.section, &a.key).cmp(&(&b.section, &b.key)));
+    unknown
+}
+
+/// Candidate closest to `name`, if within the typo distance
+fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_keys() {
+        let mut ini = Ini::new();
+        ini.read(
+            "[service]\nenabled = true\nmemory_limit = 256\n\
+             [defaults]\nmamory_limit = 512\nfoo = 1\n\
+             [proxxy]\nbackend = nginx\n"
+                .to_string(),
+        )
+        .unwrap();
+
+        let unknown: Vec<String> = unknown_keys(&ini, MAIN_KEYS)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            unknown,
+            [
+                "unknown key [defaults] foo",
+                "unknown key [defaults] mamory_limit (did you mean [defaults] memory_limit?)",
+                "unknown section [proxxy] (did you mean [proxy]?)",
+                "unknown key [service] memory_limit (did you mean [defaults] memory_limit?)",
+            ]
+        );
+    }
+}
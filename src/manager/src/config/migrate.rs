@@ -0,0 +1,203 @@
+//! Configuration Versioning and Migration
+//!
+//! Upgrades `frame.conf` files written for older manager versions. Migrations
+//! work on the raw text so comments and layout survive when the upgraded file
+//! is written back.
+
+use anyhow::Result;
+
+/// Version of the configuration format understood by this manager
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Version assumed for files without a `config_version` key
+const UNVERSIONED: u32 = 1;
+
+/// Changes needed to bring a config up to `version`
+struct Migration {
+    version: u32,
+    /// Keys renamed in this version: (section, old key, new key)
+    renames: &'static [(&'static str, &'static str, &'static str)],
+}
+
+/// Migrations in version order
+///
+/// Version 2 introduced `config_version` itself; later renames are listed
+/// here so older files keep their meaning.
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 2,
+    renames: &[],
+}];
+
+/// Result of migrating a configuration file
+#[derive(Debug, Clone)]
+pub struct MigratedConfig {
+    /// Upgraded file contents
+    pub content: String,
+    /// Version the file was written for
+    pub from_version: u32,
+    /// Human-readable description of each change made
+    pub changes: Vec<String>,
+}
+
+impl MigratedConfig {
+    /// Whether the file needed upgrading
+    pub fn is_changed(&self) -> bool {
+        self.from_version != CONFIG_VERSION || !self.changes.is_empty()
+    }
+}
+
+/// Upgrade configuration text to the current version
+pub fn migrate(content: &str) -> Result<MigratedConfig> {
+    apply_migrations(content, MIGRATIONS)
+}
+
+fn apply_migrations(content: &str, migrations: &[Migration]) -> Result<MigratedConfig> {
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut changes = Vec::new();
+
+    let from_version = match find_key(&lines, "service", "config_version") {
+        Some(index) => {
+            let value = value_of(&lines[index]);
+            value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid config_version: {}", value))?
+        }
+        None => UNVERSIONED,
+    };
+
+    if from_version > CONFIG_VERSION {
+        anyhow::bail!(
+            "config_version {} is newer than this manager supports ({})",
+            from_version,
+            CONFIG_VERSION
+        );
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > from_version) {
+        for (section, old, new) in migration.renames {
+            let Some(index) = find_key(&lines, section, old) else {
+                continue;
+            };
+            if find_key(&lines, section, new).is_some() {
+                lines[index] = format!("# {} (replaced by {})", lines[index].trim(), new);
+                changes.push(format!(
+                    "[{}] {} ignored because {} is also set",
+                    section, old, new
+                ));
+            } else {
+                let value = value_of(&lines[index]).to_string();
+                lines[index] = format!("{} = {}", new, value);
+                changes.push(format!("[{}] {} renamed to {}", section, old, new));
+            }
+        }
+    }
+
+    if from_version != CONFIG_VERSION {
+        set_version(&mut lines);
+        changes.push(format!(
+            "config_version upgraded from {} to {}",
+            from_version, CONFIG_VERSION
+        ));
+    }
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+
+    Ok(MigratedConfig {
+        content,
+        from_version,
+        changes,
+    })
+}
+
+/// Find the line index of `key` within `[section]`
+pub(super) fn find_key(lines: &[String], section: &str, key: &str) -> Option<usize> {
+    let mut current = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            current = name.trim().to_lowercase();
+        } else if current == section {
+            if let Some((name, _)) = trimmed.split_once('=') {
+                if name.trim().eq_ignore_ascii_case(key) {
+                    return Some(index);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Value part of a `key = value` line
+fn value_of(line: &str) -> &str {
+    line.split_once('=').map(|(_, v)| v.trim()).unwrap_or("")
+}
+
+/// Set `config_version` in `[service]`, adding the section if needed
+fn set_version(lines: &mut Vec<String>) {
+    let entry = format!("config_version = {}", CONFIG_VERSION);
+
+    if let Some(index) = find_key(lines, "service", "config_version") {
+        lines[index] = entry;
+        return;
+    }
+
+    match lines
+        .iter()
+        .position(|l| l.trim().eq_ignore_ascii_case("[service]"))
+    {
+        Some(index) => lines.insert(index + 1, entry),
+        None => {
+            lines.insert(0, String::new());
+            lines.insert(0, entry);
+            lines.insert(0, "[service]".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_unversioned() {
+        let migrated = migrate("# comment\n[service]\nenabled = true\n").unwrap();
+        assert_eq!(migrated.from_version, 1);
+        assert!(migrated.is_changed());
+        assert_eq!(
+            migrated.content,
+            "# comment\n[service]\nconfig_version = 2\nenabled = true\n"
+        );
+
+        // Already current: nothing to do
+        let again = migrate(&migrated.content).unwrap();
+        assert!(!again.is_changed());
+        assert_eq!(again.content, migrated.content);
+    }
+
+    #[test]
+    fn test_migrate_rejects_newer_version() {
+        assert!(migrate("[service]\nconfig_version = 99\n").is_err());
+        assert!(migrate("[service]\nconfig_version = two\n").is_err());
+    }
+
+    #[test]
+    fn test_migrate_renames() {
+        let migrations = [Migration {
+            version: 2,
+            renames: &[
+                ("service", "check_interval", "health_check_interval"),
+                ("proxy", "tmo", "timeout"),
+            ],
+        }];
+        let content = "[service]\ncheck_interval = 10\n[proxy]\ntmo = 5\ntimeout = 60\n";
+
+        let migrated = apply_migrations(content, &migrations).unwrap();
+        assert_eq!(
+            migrated.content,
+            "[service]\nconfig_version = 2\nhealth_check_interval = 10\n[proxy]\n\
+             # tmo = 5 (replaced by timeout)\ntimeout = 60\n"
+        );
+        assert_eq!(migrated.changes.len(), 3);
+    }
+}
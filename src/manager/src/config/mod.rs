@@ -2,22 +2,49 @@
 //!
 //! Handles loading and parsing of Frame Manager configuration files.
 
+mod check;
+mod keys;
+mod migrate;
 mod parser;
+mod provenance;
+mod units;
+mod writer;
 
 use anyhow::{Context, Result};
+use nix::sys::signal::Signal;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
+use keys::{LIVE_KEYS, MAIN_KEYS, WRITABLE_KEYS};
+
+pub use check::ConfigValidation;
+pub use migrate::{migrate, MigratedConfig, CONFIG_VERSION};
 pub use parser::ConfigParser;
+pub use provenance::{EffectiveConfig, EffectiveValue, Provenance, ValueSource};
+pub use units::{deserialize_memory_mb, parse_memory_mb};
+
+use crate::api::ListenerOptions;
+use crate::health::RestartPolicy;
+use crate::instance::{CgroupBackend, EnvPolicy, ExitPolicy, SpawnMode, UserPolicy};
 
 /// Main configuration structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     pub service: ServiceConfig,
     pub defaults: DefaultsConfig,
     pub logging: LoggingConfig,
     pub security: SecurityConfig,
     pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub paths: PathsConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    /// Which settings were read from the file rather than defaulted
+    #[serde(skip)]
+    pub provenance: Provenance,
 }
 
 /// Service configuration section
@@ -29,12 +56,85 @@ pub struct ServiceConfig {
     pub port_range_start: u16,
     /// End of port range for user instances
     pub port_range_end: u16,
+    /// Hand released ports out again; when false, ports are assigned monotonically
+    pub reuse_released_ports: bool,
     /// Manager API port
     pub manager_port: u16,
     /// Auto-start instances on boot
     pub auto_start: bool,
+    /// Instances auto-started together per wave (0 starts them one at a time without pauses)
+    pub auto_start_batch_size: usize,
+    /// Pause between auto-start waves in seconds
+    pub auto_start_batch_delay_secs: u64,
+    /// 1-minute load average above which the next auto-start wave waits (0 to ignore load)
+    pub auto_start_max_load: f64,
     /// Health check interval in seconds
     pub health_check_interval: u64,
+    /// Maximum number of instances health-checked concurrently
+    pub health_check_concurrency: usize,
+    /// Deadline for each network health check (connect, request and response) in milliseconds
+    pub health_check_timeout_ms: u64,
+    /// Days before expiry at which the TLS certificate check starts failing
+    pub health_check_tls_warn_days: u32,
+    /// Treat 3xx responses from the HTTP health endpoint as healthy (redirects are not followed)
+    pub health_check_accept_redirects: bool,
+    /// Interval in seconds between disk quota checks (0 disables them)
+    pub disk_check_interval: u64,
+    /// Minimum number of ports the user port range must contain
+    pub min_port_range_size: u16,
+    /// Maximum number of queued start/stop/restart operations executed at once
+    pub spawn_concurrency: usize,
+    /// Maximum number of operations waiting in the queue
+    pub operation_queue_size: usize,
+    /// Maximum number of instances spawning at the same time, across
+    /// auto-start, API operations and restarts
+    pub max_concurrent_starts: usize,
+    /// Unexpected exits within `flap_window_secs` that mark an instance unstable (0 disables)
+    pub flap_threshold: usize,
+    /// Window in seconds over which flapping is measured
+    pub flap_window_secs: u64,
+    /// Milliseconds a stop waits for the process to be reaped and its port freed
+    pub stop_grace_ms: u64,
+    /// `CODE:ACTION` entries (restart, no-restart, quarantine) for instance exits
+    pub exit_code_actions: Vec<String>,
+    /// Directory receiving archives of removed instances
+    pub backups_dir: String,
+    /// Milliseconds each readiness probe attempt may run
+    pub readiness_probe_timeout_ms: u64,
+    /// Readiness probe retries (one second apart) before a start fails
+    pub readiness_probe_retries: u32,
+    /// Seconds between metrics refreshes; `/metrics` serves the latest refresh
+    pub metrics_interval: u64,
+    /// Consecutive failed health checks before an instance is restarted
+    pub unhealthy_threshold: u32,
+    /// Auto-restarts within `restart_window_secs` before the health monitor
+    /// gives up on an instance (0 means no limit)
+    pub max_restart_attempts: u32,
+    /// Window over which auto-restarts are counted
+    pub restart_window_secs: u64,
+    /// Seconds before the second auto-restart in a row, doubled for each
+    /// further one (0 restarts without waiting)
+    pub restart_backoff_secs: u64,
+    /// Longest wait between auto-restarts in seconds
+    pub restart_backoff_max_secs: u64,
+    /// Pending connections the API listener queues before refusing more
+    pub api_tcp_backlog: u32,
+    /// Idle seconds before keepalive probes on API connections (0 disables)
+    pub api_tcp_keepalive_secs: u64,
+    /// Seconds between unanswered keepalive probes
+    pub api_tcp_keepalive_interval_secs: u64,
+    /// Unanswered keepalive probes before a connection is dropped
+    pub api_tcp_keepalive_retries: u32,
+    /// Whether frame-server reloads its config and apps on `instance_reload_signal`
+    pub instance_reload_supported: bool,
+    /// Signal sent to an instance to reload it: SIGHUP, SIGUSR1 or SIGUSR2
+    pub instance_reload_signal: String,
+    /// Refuse to load a config containing unknown sections or keys
+    pub strict_config: bool,
+    /// How instances are placed in cgroups: direct or systemd
+    pub cgroup_backend: String,
+    /// How settings are passed to frame-server: args or config_file
+    pub spawn_mode: String,
 }
 
 /// Default resource limits
@@ -55,10 +155,12 @@ pub struct DefaultsConfig {
 pub struct LoggingConfig {
     /// Log level: trace, debug, info, warn, error
     pub level: String,
-    /// Log retention in days
+    /// Days rotated instance logs are kept (0 keeps them forever)
     pub retention_days: u32,
-    /// Max log file size in MB
+    /// Size in MB at which an instance's log is rotated (0 never rotates)
     pub max_file_size: u64,
+    /// Max number of instance logs followed by a combined log stream
+    pub stream_max_watchers: usize,
 }
 
 /// Security configuration
@@ -70,6 +172,21 @@ pub struct SecurityConfig {
     pub allow_sys_access: bool,
     /// Require HTTPS for external connections
     pub require_https: bool,
+    /// If non-empty, only these instance env var keys are passed to frame-server
+    /// (a trailing `*` matches any suffix)
+    pub env_var_allowlist: Vec<String>,
+    /// Instance env var keys that are never passed to frame-server
+    pub env_var_denylist: Vec<String>,
+    /// If non-empty, only users matching one of these glob patterns get an instance
+    pub managed_users_allow: Vec<String>,
+    /// Users matching any of these glob patterns never get an instance (wins over allow)
+    pub managed_users_deny: Vec<String>,
+    /// Bearer token required by the API (no authentication if unset); never
+    /// serialized, so the settings API can't leak it
+    #[serde(default, skip_serializing)]
+    pub api_token: Option<String>,
+    /// Serve GET requests without the API token
+    pub api_public_reads: bool,
 }
 
 /// Proxy configuration
@@ -81,18 +198,114 @@ pub struct ProxyConfig {
     pub timeout: u64,
     /// Enable WebSocket proxying
     pub websocket: bool,
+    /// Command run as `<command> USER OLD_PORT NEW_PORT` to repoint a user's proxy
+    pub switch_command: String,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            service: ServiceConfig::default(),
-            defaults: DefaultsConfig::default(),
-            logging: LoggingConfig::default(),
-            security: SecurityConfig::default(),
-            proxy: ProxyConfig::default(),
+/// Filesystem locations, read once at startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathsConfig {
+    /// Directory holding one directory per instance
+    pub instances_dir: String,
+    /// Port allocation registry
+    pub registry_path: String,
+    /// frame-server binary spawned for each instance
+    pub frame_server_path: String,
+    /// Directory of event hook scripts
+    pub hooks_dir: String,
+    /// Directory of hosting package configs (`<package>.conf`)
+    pub packages_dir: String,
+    /// Named instance groups for bulk start/stop/restart
+    pub groups_path: String,
+    /// cPanel account files, read for each user's hosting package
+    pub cpanel_users_dir: String,
+    /// Root-only directory of per-instance runtime state kept by the manager
+    pub state_dir: String,
+}
+
+/// Event delivery configuration section
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventsConfig {
+    /// URL every event is POSTed to as JSON (empty to disable)
+    pub webhook_url: String,
+    /// Secret signing webhook bodies; never included in serialized output
+    #[serde(default, skip_serializing)]
+    pub webhook_secret: Option<String>,
+    /// Deadline for each delivery attempt in milliseconds
+    pub webhook_timeout_ms: u64,
+    /// Retries of a failed delivery before it is dropped
+    pub webhook_retries: u32,
+    /// Events whose hooks run, by name (e.g. `instance.crashed`); all if empty
+    pub enabled_events: Vec<String>,
+    /// Seconds the hook scripts for one event may run, together, before
+    /// they're killed
+    pub hook_timeout_secs: u64,
+    /// Recent events kept for `GET /frame/events` (0 keeps none)
+    pub history_size: usize,
+}
+
+impl ServiceConfig {
+    /// When the health monitor restarts failing instances
+    pub fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy {
+            unhealthy_threshold: self.unhealthy_threshold,
+            max_attempts: self.max_restart_attempts,
+            window: chrono::Duration::seconds(self.restart_window_secs as i64),
+            backoff_base: chrono::Duration::seconds(self.restart_backoff_secs as i64),
+            backoff_cap: chrono::Duration::seconds(self.restart_backoff_max_secs as i64),
         }
     }
+
+    /// TCP options for the API listener
+    pub fn listener_options(&self) -> ListenerOptions {
+        ListenerOptions {
+            backlog: self.api_tcp_backlog,
+            keepalive: (self.api_tcp_keepalive_secs > 0)
+                .then(|| Duration::from_secs(self.api_tcp_keepalive_secs)),
+            keepalive_interval: Duration::from_secs(self.api_tcp_keepalive_interval_secs),
+            keepalive_retries: self.api_tcp_keepalive_retries,
+        }
+    }
+
+    /// Signal used to reload instances, accepting names with or without `SIG`
+    ///
+    /// Only SIGHUP, SIGUSR1 and SIGUSR2: anything else would stop, kill or
+    /// suspend instances instead of reloading them.
+    pub fn reload_signal(&self) -> Result<Signal> {
+        let name = self.instance_reload_signal.trim().to_uppercase();
+        let name = if name.starts_with("SIG") {
+            name
+        } else {
+            format!("SIG{}", name)
+        };
+        match Signal::from_str(&name) {
+            Ok(signal @ (Signal::SIGHUP | Signal::SIGUSR1 | Signal::SIGUSR2)) => Ok(signal),
+            _ => anyhow::bail!(
+                "instance_reload_signal must be SIGHUP, SIGUSR1 or SIGUSR2, not {}",
+                self.instance_reload_signal
+            ),
+        }
+    }
+
+    /// Parsed `exit_code_actions`
+    pub fn exit_policy(&self) -> Result<ExitPolicy> {
+        ExitPolicy::parse(&self.exit_code_actions)
+            .map_err(|e| anyhow::anyhow!("exit_code_actions: {}", e))
+    }
+
+    /// Parsed `cgroup_backend`
+    pub fn cgroup_backend(&self) -> Result<CgroupBackend> {
+        self.cgroup_backend
+            .parse()
+            .map_err(|e: String| anyhow::anyhow!(e))
+    }
+
+    /// Parsed `spawn_mode`
+    pub fn spawn_mode(&self) -> Result<SpawnMode> {
+        self.spawn_mode
+            .parse()
+            .map_err(|e: String| anyhow::anyhow!(e))
+    }
 }
 
 impl Default for ServiceConfig {
@@ -101,9 +314,44 @@ impl Default for ServiceConfig {
             enabled: true,
             port_range_start: 30001,
             port_range_end: 32000,
+            reuse_released_ports: true,
             manager_port: 30000,
             auto_start: true,
+            auto_start_batch_size: 0,
+            auto_start_batch_delay_secs: 10,
+            auto_start_max_load: 0.0,
             health_check_interval: 30,
+            health_check_concurrency: 16,
+            health_check_timeout_ms: 5000,
+            health_check_tls_warn_days: 14,
+            health_check_accept_redirects: false,
+            disk_check_interval: 300,
+            min_port_range_size: 10,
+            spawn_concurrency: 4,
+            operation_queue_size: 256,
+            max_concurrent_starts: 4,
+            flap_threshold: 5,
+            flap_window_secs: 600,
+            stop_grace_ms: 2000,
+            exit_code_actions: vec!["78:no-restart".to_string()],
+            backups_dir: "/var/frame/backups".to_string(),
+            readiness_probe_timeout_ms: 5000,
+            readiness_probe_retries: 10,
+            metrics_interval: 15,
+            unhealthy_threshold: 3,
+            max_restart_attempts: 5,
+            restart_window_secs: 600,
+            restart_backoff_secs: 10,
+            restart_backoff_max_secs: 300,
+            api_tcp_backlog: 1024,
+            api_tcp_keepalive_secs: 60,
+            api_tcp_keepalive_interval_secs: 10,
+            api_tcp_keepalive_retries: 5,
+            instance_reload_supported: false,
+            instance_reload_signal: "SIGHUP".to_string(),
+            strict_config: false,
+            cgroup_backend: "direct".to_string(),
+            spawn_mode: "args".to_string(),
         }
     }
 }
@@ -125,6 +373,7 @@ impl Default for LoggingConfig {
             level: "info".to_string(),
             retention_days: 30,
             max_file_size: 100,
+            stream_max_watchers: 256,
         }
     }
 }
@@ -135,6 +384,41 @@ impl Default for SecurityConfig {
             allow_fs_access: false,
             allow_sys_access: false,
             require_https: true,
+            env_var_allowlist: Vec::new(),
+            env_var_denylist: EnvPolicy::default().denylist,
+            managed_users_allow: Vec::new(),
+            managed_users_deny: UserPolicy::default().deny,
+            api_token: None,
+            api_public_reads: false,
+        }
+    }
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        Self {
+            instances_dir: "/var/frame/instances".to_string(),
+            registry_path: "/var/frame/manager/ports.json".to_string(),
+            frame_server_path: "/usr/local/cpanel/3rdparty/bin/frame-server".to_string(),
+            hooks_dir: "/usr/local/cpanel/scripts/frame".to_string(),
+            packages_dir: "/etc/frame/packages".to_string(),
+            groups_path: "/etc/frame/groups.conf".to_string(),
+            cpanel_users_dir: "/var/cpanel/users".to_string(),
+            state_dir: "/var/frame/manager/state".to_string(),
+        }
+    }
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            webhook_url: String::new(),
+            webhook_secret: None,
+            webhook_timeout_ms: 5000,
+            webhook_retries: 3,
+            enabled_events: Vec::new(),
+            hook_timeout_secs: 30,
+            history_size: crate::events::DEFAULT_HISTORY_SIZE,
         }
     }
 }
@@ -145,6 +429,25 @@ impl Default for ProxyConfig {
             backend: "apache".to_string(),
             timeout: 60,
             websocket: true,
+            switch_command: "/usr/local/bin/frame-apache-ctl.sh switch-port".to_string(),
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// Env var policy applied to instance processes
+    pub fn env_policy(&self) -> EnvPolicy {
+        EnvPolicy {
+            allowlist: self.env_var_allowlist.clone(),
+            denylist: self.env_var_denylist.clone(),
+        }
+    }
+
+    /// Policy deciding which users the manager runs instances for
+    pub fn user_policy(&self) -> UserPolicy {
+        UserPolicy {
+            allow: self.managed_users_allow.clone(),
+            deny: self.managed_users_deny.clone(),
         }
     }
 }
@@ -166,26 +469,301 @@ impl Config {
             .with_context(|| format!("Failed to parse config file: {}", path.display()))
     }
 
+    /// Upgrade a configuration file to the current version
+    ///
+    /// Returns the migration result; with `write` set, a changed file is
+    /// replaced in one step after saving the original as `<file>.bak`.
+    pub fn migrate_file(path: &Path, write: bool) -> Result<MigratedConfig> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let migrated = migrate(&content)?;
+
+        if write && migrated.is_changed() {
+            let backup = path.with_extension("conf.bak");
+            std::fs::copy(path, &backup)
+                .with_context(|| format!("Failed to back up config to {}", backup.display()))?;
+            write_atomically(path, &migrated.content)
+                .with_context(|| format!("Failed to write config file: {}", path.display()))?;
+        }
+
+        Ok(migrated)
+    }
+
+    /// Replace the `[security] api_token` line of a configuration file
+    ///
+    /// Nothing else in the file changes. The token must already be set there.
+    pub fn store_api_token(path: &Path, token: &str) -> Result<()> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        let index = migrate::find_key(&lines, "security", "api_token").ok_or_else(|| {
+            anyhow::anyhow!("No [security] api_token in config file: {}", path.display())
+        })?;
+        lines[index] = format!("api_token = {}", token);
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        write_atomically(path, &content)
+            .with_context(|| format!("Failed to write config file: {}", path.display()))
+    }
+
+    /// Names of the sections in the main configuration file
+    pub fn sections() -> impl Iterator<Item = &'static str> {
+        MAIN_KEYS.iter().map(|(section, _)| *section)
+    }
+
+    /// Whether a change to `[section] key` only takes effect when the
+    /// service restarts
+    pub fn needs_restart(section: &str, key: &str) -> bool {
+        !LIVE_KEYS
+            .iter()
+            .any(|(name, keys)| *name == section && keys.contains(&key))
+    }
+
+    /// Whether the settings API may change `[section] key`
+    pub fn is_writable(section: &str, key: &str) -> bool {
+        WRITABLE_KEYS
+            .iter()
+            .any(|(name, keys)| *name == section && keys.contains(&key))
+    }
+
+    /// Apply a partial update to one section of a configuration file
+    ///
+    /// Only the given keys are rewritten; the result is parsed and validated
+    /// before the file is touched, and returned as the new configuration.
+    /// Keys outside `WRITABLE_KEYS` are refused.
+    pub fn update_section(
+        path: &Path,
+        section: &str,
+        values: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Self> {
+        let (_, keys) = MAIN_KEYS
+            .iter()
+            .find(|(name, _)| *name == section)
+            .ok_or_else(|| anyhow::anyhow!("Unknown config section: {}", section))?;
+
+        let mut entries = Vec::new();
+        for (key, value) in values {
+            if !keys.contains(&key.as_str()) {
+                anyhow::bail!("Unknown key [{}] {}", section, key);
+            }
+            if !Self::is_writable(section, key) {
+                anyhow::bail!("[{}] {} can't be changed through the API", section, key);
+            }
+            let value = writer::ini_value(value)
+                .with_context(|| format!("Invalid value for [{}] {}", section, key))?;
+            entries.push((key.clone(), value));
+        }
+
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read config file: {}", path.display()))
+            }
+        };
+        let content = writer::set_values(&content, section, &entries);
+        let config = ConfigParser::new().parse_str(&content, path)?;
+
+        write_atomically(path, &content)
+            .with_context(|| format!("Failed to write config file: {}", path.display()))?;
+        Ok(config)
+    }
+
     /// Validate configuration
     pub fn validate(&self) -> Result<()> {
+        let problems = self.problems();
+        if !problems.is_empty() {
+            anyhow::bail!("{}", problems.join("; "));
+        }
+        Ok(())
+    }
+
+    /// Every problem that makes this configuration invalid, not just the first
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let range_size = (u32::from(self.service.port_range_end) + 1)
+            .saturating_sub(u32::from(self.service.port_range_start));
         if self.service.port_range_start >= self.service.port_range_end {
-            anyhow::bail!("port_range_start must be less than port_range_end");
+            problems.push("port_range_start must be less than port_range_end".to_string());
+        } else if range_size < u32::from(self.service.min_port_range_size) {
+            problems.push(format!(
+                "port range {}-{} has {} ports, fewer than min_port_range_size ({})",
+                self.service.port_range_start,
+                self.service.port_range_end,
+                range_size,
+                self.service.min_port_range_size
+            ));
+        }
+
+        if self.service.manager_port == 0 {
+            problems.push("manager_port must be set".to_string());
         }
 
         if self.service.manager_port >= self.service.port_range_start
             && self.service.manager_port <= self.service.port_range_end
         {
-            anyhow::bail!("manager_port must be outside the user port range");
+            problems.push("manager_port must be outside the user port range".to_string());
+        }
+
+        if self.service.health_check_concurrency == 0 {
+            problems.push("health_check_concurrency must be greater than 0".to_string());
+        }
+
+        if self.service.health_check_timeout_ms == 0 {
+            problems.push("health_check_timeout_ms must be greater than 0".to_string());
+        }
+
+        let max_load = self.service.auto_start_max_load;
+        if max_load.is_nan() || max_load < 0.0 {
+            problems.push("auto_start_max_load must be 0 or greater".to_string());
+        }
+
+        if self.service.spawn_concurrency == 0 {
+            problems.push("spawn_concurrency must be greater than 0".to_string());
+        }
+
+        if self.service.operation_queue_size == 0 {
+            problems.push("operation_queue_size must be greater than 0".to_string());
+        }
+
+        if self.service.max_concurrent_starts == 0 {
+            problems.push("max_concurrent_starts must be greater than 0".to_string());
+        }
+
+        if self.service.flap_window_secs == 0 {
+            problems.push("flap_window_secs must be greater than 0".to_string());
+        }
+
+        if let Err(e) = self.service.reload_signal() {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = self.service.cgroup_backend() {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = self.service.spawn_mode() {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = self.service.exit_policy() {
+            problems.push(e.to_string());
+        }
+
+        if self.service.metrics_interval == 0 {
+            problems.push("metrics_interval must be greater than 0".to_string());
+        }
+
+        if self.service.restart_backoff_max_secs < self.service.restart_backoff_secs {
+            problems.push(
+                "restart_backoff_max_secs must not be less than restart_backoff_secs".to_string(),
+            );
+        }
+        if self.service.unhealthy_threshold == 0 {
+            problems.push("unhealthy_threshold must be greater than 0".to_string());
+        }
+
+        if self.service.api_tcp_backlog == 0 || self.service.api_tcp_backlog > i32::MAX as u32 {
+            problems.push(format!(
+                "api_tcp_backlog must be between 1 and {}",
+                i32::MAX
+            ));
+        }
+        if self.service.api_tcp_keepalive_secs > 0
+            && (self.service.api_tcp_keepalive_interval_secs == 0
+                || self.service.api_tcp_keepalive_retries == 0)
+        {
+            problems.push(
+                "api_tcp_keepalive_interval_secs and api_tcp_keepalive_retries must be greater \
+                 than 0 when keepalive is enabled"
+                    .to_string(),
+            );
+        }
+
+        if self.service.readiness_probe_timeout_ms == 0 {
+            problems.push("readiness_probe_timeout_ms must be greater than 0".to_string());
+        }
+
+        for (key, path) in [
+            ("instances_dir", &self.paths.instances_dir),
+            ("registry_path", &self.paths.registry_path),
+            ("frame_server_path", &self.paths.frame_server_path),
+            ("hooks_dir", &self.paths.hooks_dir),
+            ("packages_dir", &self.paths.packages_dir),
+            ("groups_path", &self.paths.groups_path),
+            ("cpanel_users_dir", &self.paths.cpanel_users_dir),
+            ("state_dir", &self.paths.state_dir),
+        ] {
+            if !Path::new(path).is_absolute() {
+                problems.push(format!(
+                    "[paths] {} must be an absolute path, got '{}'",
+                    key, path
+                ));
+            }
+        }
+
+        if !self.events.webhook_url.is_empty() {
+            if let Err(e) = crate::events::WebhookSink::new(&self.events.webhook_url) {
+                problems.push(e.to_string());
+            }
+            if self.events.webhook_timeout_ms == 0 {
+                problems.push("webhook_timeout_ms must be greater than 0".to_string());
+            }
+        }
+
+        if self.events.hook_timeout_secs == 0 {
+            problems.push("hook_timeout_secs must be greater than 0".to_string());
+        }
+        let event_names = crate::events::EventEmitter::event_names();
+        if let Some(name) = self
+            .events
+            .enabled_events
+            .iter()
+            .find(|name| !event_names.contains(&name.as_str()))
+        {
+            problems.push(format!(
+                "Unknown event '{}' in enabled_events (expected one of: {})",
+                name,
+                event_names.join(", ")
+            ));
         }
 
         if self.defaults.cpu_limit > 100 {
-            anyhow::bail!("cpu_limit must be between 0 and 100");
+            problems.push("cpu_limit must be between 0 and 100".to_string());
         }
 
-        Ok(())
+        problems
     }
 }
 
+/// Replace a file's content in one step, keeping its permissions
+///
+/// The file may hold the API token, so a new file is only readable by its
+/// owner.
+fn write_atomically(path: &Path, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mode = match std::fs::metadata(path) {
+        Ok(metadata) => metadata.permissions().mode() & 0o7777,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0o600,
+        Err(e) => return Err(e),
+    };
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = std::path::PathBuf::from(tmp);
+    let _ = std::fs::remove_file(&tmp);
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&tmp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
 /// Package-specific configuration overrides
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PackageConfig {
@@ -211,9 +789,250 @@ pub struct PackageFeatures {
 }
 
 impl PackageConfig {
+    /// Check a package name is safe to use as a file name under packages_dir
+    ///
+    /// Same characters as usernames, so a name can't reach outside the
+    /// packages directory.
+    pub fn validate_name(name: &str) -> Result<()> {
+        let valid = !name.is_empty()
+            && name.len() <= 255
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+
+        if !valid {
+            anyhow::bail!(
+                "Invalid package name '{}': use letters, digits, '_', '-' or '.', not starting with '.'",
+                name
+            );
+        }
+        Ok(())
+    }
+
     /// Load package configuration from file
     pub fn load(path: &Path) -> Result<Self> {
         let parser = ConfigParser::new();
         parser.parse_package(path)
     }
 }
+
+/// Named instance groups used for cohort operations
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GroupsConfig {
+    /// Group name to member usernames
+    pub groups: BTreeMap<String, Vec<String>>,
+}
+
+impl GroupsConfig {
+    /// Load groups from file, treating a missing file as no groups
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let parser = ConfigParser::new();
+        parser.parse_groups(path)
+    }
+
+    /// Members of a group, if it is defined
+    pub fn members(&self, name: &str) -> Option<&[String]> {
+        self.groups.get(name).map(|m| m.as_slice())
+    }
+}
+
+/// Group lookup failure callers are expected to handle
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum GroupError {
+    /// No group with this name is defined in groups.conf
+    #[error("Unknown group: {0}")]
+    Unknown(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_port_range_size() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+
+        config.service.port_range_start = 30001;
+        config.service.port_range_end = 30003;
+        assert!(config.validate().is_err());
+
+        config.service.min_port_range_size = 3;
+        assert!(config.validate().is_ok());
+
+        // Out of range values are refused, not wrapped into something valid
+        let err = ConfigParser::new()
+            .parse_str(
+                "[service]\nmin_port_range_size = 65546\n",
+                Path::new("frame.conf"),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("min_port_range_size"), "{}", err);
+        assert!(ConfigParser::new()
+            .parse_str(
+                "[service]\nport_range_end = 95536\n",
+                Path::new("frame.conf")
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_package_names() {
+        for name in ["default", "reseller_gold", "pro-2.0"] {
+            assert!(PackageConfig::validate_name(name).is_ok(), "{}", name);
+        }
+        for name in ["", "../etc/frame/frame", ".hidden", "a/b", "gold plan"] {
+            assert!(PackageConfig::validate_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_reload_signal() {
+        let mut config = Config::default();
+        assert_eq!(config.service.reload_signal().unwrap(), Signal::SIGHUP);
+
+        config.service.instance_reload_signal = "usr2".to_string();
+        assert_eq!(config.service.reload_signal().unwrap(), Signal::SIGUSR2);
+
+        config.service.instance_reload_signal = "SIGNOPE".to_string();
+        assert!(config.validate().is_err());
+
+        for name in ["KILL", "SIGTERM", "sigstop"] {
+            config.service.instance_reload_signal = name.to_string();
+            assert!(config.service.reload_signal().is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_paths_section() {
+        let path = Path::new("frame.conf");
+        let config = ConfigParser::new()
+            .parse_str("[paths]\ninstances_dir = /srv/frame/instances\n", path)
+            .unwrap();
+        assert_eq!(config.paths.instances_dir, "/srv/frame/instances");
+        assert_eq!(
+            config.paths.registry_path,
+            PathsConfig::default().registry_path
+        );
+
+        let err = ConfigParser::new()
+            .parse_str("[paths]\nhooks_dir = hooks\n", path)
+            .unwrap_err();
+        assert!(err.to_string().contains("hooks_dir"));
+    }
+
+    #[test]
+    fn test_start_and_queue_concurrency_are_separate() {
+        let config = ConfigParser::new()
+            .parse_str(
+                "[service]\nspawn_concurrency = 8\nmax_concurrent_starts = 2\n",
+                Path::new("frame.conf"),
+            )
+            .unwrap();
+        assert_eq!(config.service.spawn_concurrency, 8);
+        assert_eq!(config.service.max_concurrent_starts, 2);
+
+        let mut config = Config::default();
+        config.service.max_concurrent_starts = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_update_section_keeps_other_sections() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frame.conf");
+        let shipped = include_str!("../../../../packaging/config/frame.conf");
+        std::fs::write(&path, shipped.replace("api_token =", "api_token = secret")).unwrap();
+        let before = Config::load(&path).unwrap();
+
+        let mut values = serde_json::Map::new();
+        values.insert("health_check_interval".to_string(), 45.into());
+        Config::update_section(&path, "service", &values).unwrap();
+
+        let after = Config::load(&path).unwrap();
+        assert_eq!(after.service.health_check_interval, 45);
+        assert_eq!(after.service.auto_start, before.service.auto_start);
+        for section in [
+            "defaults", "logging", "security", "proxy", "paths", "events",
+        ] {
+            assert_eq!(
+                serde_json::to_value(&after).unwrap()[section],
+                serde_json::to_value(&before).unwrap()[section],
+                "[{}] changed",
+                section
+            );
+        }
+        assert_eq!(after.security.api_token, before.security.api_token);
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("# Health check interval in seconds\nhealth_check_interval = 45\n"));
+    }
+
+    #[test]
+    fn test_load_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("groups.conf");
+        assert!(GroupsConfig::load(&path).unwrap().groups.is_empty());
+
+        std::fs::write(&path, "[Beta]\nmembers = alice, bob\n\n[empty]\n").unwrap();
+        let groups = GroupsConfig::load(&path).unwrap();
+        assert_eq!(
+            groups.members("Beta"),
+            Some(&["alice".to_string(), "bob".to_string()][..])
+        );
+        assert_eq!(groups.members("empty"), Some(&[][..]));
+        assert!(groups.members("beta").is_none());
+    }
+
+    #[test]
+    fn test_store_api_token() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frame.conf");
+        std::fs::write(
+            &path,
+            "# Frame\n[service]\nenabled = true\n\n[security]\napi_token = old\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        Config::store_api_token(&path, "new").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# Frame\n[service]\nenabled = true\n\n[security]\napi_token = new\n"
+        );
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!dir.path().join("frame.conf.tmp").exists());
+
+        std::fs::write(&path, "[security]\n").unwrap();
+        assert!(Config::store_api_token(&path, "new").is_err());
+    }
+
+    #[test]
+    fn test_migrate_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frame.conf");
+        let old = "[service]\nenabled = true\n";
+        std::fs::write(&path, old).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        let migrated = Config::migrate_file(&path, true).unwrap();
+        assert!(migrated.is_changed());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), migrated.content);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("frame.conf.bak")).unwrap(),
+            old
+        );
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert!(!dir.path().join("frame.conf.tmp").exists());
+    }
+}
@@ -2,11 +2,16 @@
 
 use anyhow::Result;
 use configparser::ini::Ini;
+use std::collections::BTreeMap;
 use std::path::Path;
 
+use super::keys::{unknown_keys, KnownKeys, MAIN_KEYS, PACKAGE_KEYS};
+use super::migrate::migrate;
+use super::units::parse_memory_mb;
 use super::{
-    Config, DefaultsConfig, LoggingConfig, PackageConfig, PackageFeatures, PackageLimits,
-    ProxyConfig, SecurityConfig, ServiceConfig,
+    Config, DefaultsConfig, EventsConfig, GroupsConfig, LoggingConfig, PackageConfig,
+    PackageFeatures, PackageLimits, PathsConfig, Provenance, ProxyConfig, SecurityConfig,
+    ServiceConfig,
 };
 
 /// Configuration file parser
@@ -20,16 +25,61 @@ impl ConfigParser {
     }
 
     /// Parse main configuration file
+    ///
+    /// Files written for an older config version are migrated in memory first.
     pub fn parse(&self, path: &Path) -> Result<Config> {
+        let config = self.parse_unvalidated(path)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parse a main configuration file without validating the values, for
+    /// reporting every problem at once (see `Config::problems`)
+    pub fn parse_unvalidated(&self, path: &Path) -> Result<Config> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load config: {}", e))?;
+        self.parse_str_unvalidated(&content, path)
+    }
+
+    /// Parse main configuration text read from (or destined for) `path`
+    pub fn parse_str(&self, content: &str, path: &Path) -> Result<Config> {
+        let config = self.parse_str_unvalidated(content, path)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn parse_str_unvalidated(&self, content: &str, path: &Path) -> Result<Config> {
+        let migrated = migrate(content)?;
+        if migrated.is_changed() {
+            for change in &migrated.changes {
+                tracing::warn!("Config migration: {}", change);
+            }
+            tracing::warn!(
+                "{} was written for config version {}; run `frame-manager config migrate --write` \
+                 to upgrade it",
+                path.display(),
+                migrated.from_version
+            );
+        }
+
         let mut ini = Ini::new();
-        ini.load(path)
+        ini.read(migrated.content)
             .map_err(|e| anyhow::anyhow!("Failed to load config: {}", e))?;
 
+        let strict = ini
+            .getbool("service", "strict_config")
+            .ok()
+            .flatten()
+            .unwrap_or(false);
+        report_unknown_keys(path, &ini, MAIN_KEYS, strict)?;
+
         let service = self.parse_service_section(&ini)?;
         let defaults = self.parse_defaults_section(&ini)?;
         let logging = self.parse_logging_section(&ini)?;
         let security = self.parse_security_section(&ini)?;
         let proxy = self.parse_proxy_section(&ini)?;
+        let paths = self.parse_paths_section(&ini);
+        let events = self.parse_events_section(&ini);
 
         let config = Config {
             service,
@@ -37,9 +87,11 @@ impl ConfigParser {
             logging,
             security,
             proxy,
+            paths,
+            events,
+            provenance: Provenance::from_ini(&ini),
         };
 
-        config.validate()?;
         Ok(config)
     }
 
@@ -49,21 +101,126 @@ impl ConfigParser {
         if let Ok(Some(val)) = ini.getbool("service", "enabled") {
             config.enabled = val;
         }
-        if let Ok(Some(val)) = ini.getuint("service", "port_range_start") {
-            config.port_range_start = val as u16;
+        if let Some(val) = get_u16(ini, "service", "port_range_start")? {
+            config.port_range_start = val;
+        }
+        if let Some(val) = get_u16(ini, "service", "port_range_end")? {
+            config.port_range_end = val;
         }
-        if let Ok(Some(val)) = ini.getuint("service", "port_range_end") {
-            config.port_range_end = val as u16;
+        if let Ok(Some(val)) = ini.getbool("service", "reuse_released_ports") {
+            config.reuse_released_ports = val;
         }
-        if let Ok(Some(val)) = ini.getuint("service", "manager_port") {
-            config.manager_port = val as u16;
+        if let Some(val) = get_u16(ini, "service", "manager_port")? {
+            config.manager_port = val;
         }
         if let Ok(Some(val)) = ini.getbool("service", "auto_start") {
             config.auto_start = val;
         }
+        if let Ok(Some(val)) = ini.getuint("service", "auto_start_batch_size") {
+            config.auto_start_batch_size = val as usize;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "auto_start_batch_delay_secs") {
+            config.auto_start_batch_delay_secs = val;
+        }
+        if let Ok(Some(val)) = ini.getfloat("service", "auto_start_max_load") {
+            config.auto_start_max_load = val;
+        }
         if let Ok(Some(val)) = ini.getuint("service", "health_check_interval") {
             config.health_check_interval = val;
         }
+        if let Ok(Some(val)) = ini.getuint("service", "health_check_concurrency") {
+            config.health_check_concurrency = val as usize;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "spawn_concurrency") {
+            config.spawn_concurrency = val as usize;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "operation_queue_size") {
+            config.operation_queue_size = val as usize;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "max_concurrent_starts") {
+            config.max_concurrent_starts = val as usize;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "flap_threshold") {
+            config.flap_threshold = val as usize;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "flap_window_secs") {
+            config.flap_window_secs = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "stop_grace_ms") {
+            config.stop_grace_ms = val;
+        }
+        if let Some(val) = ini.get("service", "exit_code_actions") {
+            config.exit_code_actions = parse_list(&val);
+        }
+        if let Some(val) = ini.get("service", "backups_dir") {
+            config.backups_dir = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "readiness_probe_timeout_ms") {
+            config.readiness_probe_timeout_ms = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "readiness_probe_retries") {
+            config.readiness_probe_retries = val as u32;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "metrics_interval") {
+            config.metrics_interval = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "unhealthy_threshold") {
+            config.unhealthy_threshold = val as u32;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "max_restart_attempts") {
+            config.max_restart_attempts = val as u32;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "restart_backoff_secs") {
+            config.restart_backoff_secs = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "restart_backoff_max_secs") {
+            config.restart_backoff_max_secs = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "restart_window_secs") {
+            config.restart_window_secs = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "api_tcp_backlog") {
+            config.api_tcp_backlog = u32::try_from(val).unwrap_or(u32::MAX);
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "api_tcp_keepalive_secs") {
+            config.api_tcp_keepalive_secs = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "api_tcp_keepalive_interval_secs") {
+            config.api_tcp_keepalive_interval_secs = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "api_tcp_keepalive_retries") {
+            config.api_tcp_keepalive_retries = val as u32;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "health_check_timeout_ms") {
+            config.health_check_timeout_ms = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "health_check_tls_warn_days") {
+            config.health_check_tls_warn_days = val as u32;
+        }
+        if let Ok(Some(val)) = ini.getbool("service", "health_check_accept_redirects") {
+            config.health_check_accept_redirects = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("service", "disk_check_interval") {
+            config.disk_check_interval = val;
+        }
+        if let Some(val) = get_u16(ini, "service", "min_port_range_size")? {
+            config.min_port_range_size = val;
+        }
+        if let Ok(Some(val)) = ini.getbool("service", "instance_reload_supported") {
+            config.instance_reload_supported = val;
+        }
+        if let Some(val) = ini.get("service", "instance_reload_signal") {
+            config.instance_reload_signal = val;
+        }
+        if let Some(val) = ini.get("service", "cgroup_backend") {
+            config.cgroup_backend = val;
+        }
+        if let Some(val) = ini.get("service", "spawn_mode") {
+            config.spawn_mode = val;
+        }
+        if let Ok(Some(val)) = ini.getbool("service", "strict_config") {
+            config.strict_config = val;
+        }
 
         Ok(config)
     }
@@ -71,8 +228,9 @@ impl ConfigParser {
     fn parse_defaults_section(&self, ini: &Ini) -> Result<DefaultsConfig> {
         let mut config = DefaultsConfig::default();
 
-        if let Ok(Some(val)) = ini.getuint("defaults", "memory_limit") {
-            config.memory_limit = val;
+        if let Some(val) = ini.get("defaults", "memory_limit") {
+            config.memory_limit = parse_memory_mb(&val)
+                .map_err(|e| anyhow::anyhow!("[defaults] memory_limit: {}", e))?;
         }
         if let Ok(Some(val)) = ini.getuint("defaults", "cpu_limit") {
             config.cpu_limit = val as u8;
@@ -99,6 +257,9 @@ impl ConfigParser {
         if let Ok(Some(val)) = ini.getuint("logging", "max_file_size") {
             config.max_file_size = val;
         }
+        if let Ok(Some(val)) = ini.getuint("logging", "stream_max_watchers") {
+            config.stream_max_watchers = val as usize;
+        }
 
         Ok(config)
     }
@@ -115,6 +276,24 @@ impl ConfigParser {
         if let Ok(Some(val)) = ini.getbool("security", "require_https") {
             config.require_https = val;
         }
+        if let Some(val) = ini.get("security", "env_var_allowlist") {
+            config.env_var_allowlist = parse_list(&val);
+        }
+        if let Some(val) = ini.get("security", "env_var_denylist") {
+            config.env_var_denylist = parse_list(&val);
+        }
+        if let Some(val) = ini.get("security", "managed_users_allow") {
+            config.managed_users_allow = parse_list(&val);
+        }
+        if let Some(val) = ini.get("security", "managed_users_deny") {
+            config.managed_users_deny = parse_list(&val);
+        }
+        if let Some(val) = ini.get("security", "api_token") {
+            config.api_token = Some(val.trim().to_string()).filter(|token| !token.is_empty());
+        }
+        if let Ok(Some(val)) = ini.getbool("security", "api_public_reads") {
+            config.api_public_reads = val;
+        }
 
         Ok(config)
     }
@@ -131,15 +310,69 @@ impl ConfigParser {
         if let Ok(Some(val)) = ini.getbool("proxy", "websocket") {
             config.websocket = val;
         }
+        if let Some(val) = ini.get("proxy", "switch_command") {
+            config.switch_command = val;
+        }
 
         Ok(config)
     }
 
+    fn parse_paths_section(&self, ini: &Ini) -> PathsConfig {
+        let mut config = PathsConfig::default();
+
+        for (key, value) in [
+            ("instances_dir", &mut config.instances_dir),
+            ("registry_path", &mut config.registry_path),
+            ("frame_server_path", &mut config.frame_server_path),
+            ("hooks_dir", &mut config.hooks_dir),
+            ("packages_dir", &mut config.packages_dir),
+            ("groups_path", &mut config.groups_path),
+            ("cpanel_users_dir", &mut config.cpanel_users_dir),
+            ("state_dir", &mut config.state_dir),
+        ] {
+            if let Some(val) = ini.get("paths", key) {
+                *value = val;
+            }
+        }
+
+        config
+    }
+
+    fn parse_events_section(&self, ini: &Ini) -> EventsConfig {
+        let mut config = EventsConfig::default();
+
+        if let Some(val) = ini.get("events", "webhook_url") {
+            config.webhook_url = val.trim().to_string();
+        }
+        if let Some(val) = ini.get("events", "webhook_secret") {
+            config.webhook_secret =
+                Some(val.trim().to_string()).filter(|secret| !secret.is_empty());
+        }
+        if let Ok(Some(val)) = ini.getuint("events", "webhook_timeout_ms") {
+            config.webhook_timeout_ms = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("events", "webhook_retries") {
+            config.webhook_retries = val as u32;
+        }
+        if let Some(val) = ini.get("events", "enabled_events") {
+            config.enabled_events = parse_list(&val);
+        }
+        if let Ok(Some(val)) = ini.getuint("events", "hook_timeout_secs") {
+            config.hook_timeout_secs = val;
+        }
+        if let Ok(Some(val)) = ini.getuint("events", "history_size") {
+            config.history_size = val as usize;
+        }
+
+        config
+    }
+
     /// Parse package-specific configuration
     pub fn parse_package(&self, path: &Path) -> Result<PackageConfig> {
         let mut ini = Ini::new();
         ini.load(path)
             .map_err(|e| anyhow::anyhow!("Failed to load package config: {}", e))?;
+        report_unknown_keys(path, &ini, PACKAGE_KEYS, false)?;
 
         let name = path
             .file_stem()
@@ -147,7 +380,7 @@ impl ConfigParser {
             .unwrap_or("unknown")
             .to_string();
 
-        let limits = self.parse_package_limits(&ini);
+        let limits = self.parse_package_limits(&ini)?;
         let features = self.parse_package_features(&ini);
 
         Ok(PackageConfig {
@@ -157,25 +390,122 @@ impl ConfigParser {
         })
     }
 
-    fn parse_package_limits(&self, ini: &Ini) -> PackageLimits {
-        PackageLimits {
-            memory_limit: ini.getuint("limits", "memory_limit").ok().flatten().unwrap_or(512),
-            cpu_limit: ini.getuint("limits", "cpu_limit").ok().flatten().unwrap_or(25) as u8,
-            max_apps: ini.getuint("limits", "max_apps").ok().flatten().unwrap_or(5) as u32,
-            disk_quota: ini.getuint("limits", "disk_quota").ok().flatten().unwrap_or(1024),
+    /// Parse the instance groups file
+    ///
+    /// Each section is a group with a comma-separated `members` list.
+    pub fn parse_groups(&self, path: &Path) -> Result<GroupsConfig> {
+        // Group names are case-sensitive, unlike the other config files
+        let mut ini = Ini::new_cs();
+        ini.load(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load groups config: {}", e))?;
+
+        let mut groups = BTreeMap::new();
+        for name in ini.sections() {
+            let members = ini
+                .get(&name, "members")
+                .map(|val| parse_list(&val))
+                .unwrap_or_default();
+            groups.insert(name, members);
         }
+
+        Ok(GroupsConfig { groups })
+    }
+
+    fn parse_package_limits(&self, ini: &Ini) -> Result<PackageLimits> {
+        let memory_limit = match ini.get("limits", "memory_limit") {
+            Some(val) => parse_memory_mb(&val)
+                .map_err(|e| anyhow::anyhow!("[limits] memory_limit: {}", e))?,
+            None => 512,
+        };
+
+        Ok(PackageLimits {
+            memory_limit,
+            cpu_limit: ini
+                .getuint("limits", "cpu_limit")
+                .ok()
+                .flatten()
+                .unwrap_or(25) as u8,
+            max_apps: ini
+                .getuint("limits", "max_apps")
+                .ok()
+                .flatten()
+                .unwrap_or(5) as u32,
+            disk_quota: ini
+                .getuint("limits", "disk_quota")
+                .ok()
+                .flatten()
+                .unwrap_or(1024),
+        })
     }
 
     fn parse_package_features(&self, ini: &Ini) -> PackageFeatures {
         PackageFeatures {
-            fs_access: ini.getbool("features", "fs_access").ok().flatten().unwrap_or(false),
-            sys_access: ini.getbool("features", "sys_access").ok().flatten().unwrap_or(false),
-            custom_domains: ini.getbool("features", "custom_domains").ok().flatten().unwrap_or(true),
-            ssl_support: ini.getbool("features", "ssl_support").ok().flatten().unwrap_or(true),
+            fs_access: ini
+                .getbool("features", "fs_access")
+                .ok()
+                .flatten()
+                .unwrap_or(false),
+            sys_access: ini
+                .getbool("features", "sys_access")
+                .ok()
+                .flatten()
+                .unwrap_or(false),
+            custom_domains: ini
+                .getbool("features", "custom_domains")
+                .ok()
+                .flatten()
+                .unwrap_or(true),
+            ssl_support: ini
+                .getbool("features", "ssl_support")
+                .ok()
+                .flatten()
+                .unwrap_or(true),
         }
     }
 }
 
+/// Read an unsigned 16-bit value, refusing ones too large instead of
+/// truncating them
+fn get_u16(ini: &Ini, section: &str, key: &str) -> Result<Option<u16>> {
+    match ini.getuint(section, key) {
+        Ok(Some(val)) => u16::try_from(val).map(Some).map_err(|_| {
+            anyhow::anyhow!("[{}] {}: {} is larger than {}", section, key, val, u16::MAX)
+        }),
+        _ => Ok(None),
+    }
+}
+
+/// Warn about unknown sections and keys, or fail on them in strict mode
+fn report_unknown_keys(path: &Path, ini: &Ini, known: KnownKeys, strict: bool) -> Result<()> {
+    let unknown = unknown_keys(ini, known);
+    if unknown.is_empty() {
+        return Ok(());
+    }
+
+    if strict {
+        let list: Vec<String> = unknown.iter().map(ToString::to_string).collect();
+        anyhow::bail!(
+            "{}: {} (strict_config is enabled)",
+            path.display(),
+            list.join("; ")
+        );
+    }
+
+    for entry in &unknown {
+        tracing::warn!("{}: {} is ignored", path.display(), entry);
+    }
+    Ok(())
+}
+
+/// Parse a comma-separated list value
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
 impl Default for ConfigParser {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,117 @@
+//! Configuration Provenance
+//!
+//! Records where each setting's value came from, alongside the parsed
+//! `Config`, so operators can tell an explicit setting from a default.
+
+use anyhow::Result;
+use configparser::ini::Ini;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::keys::MAIN_KEYS;
+use super::Config;
+
+/// Where a setting's effective value came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueSource {
+    /// Set in the configuration file
+    File,
+    /// Not set anywhere; the built-in default applies
+    Default,
+}
+
+/// Keys explicitly set in the configuration, by section
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    set: BTreeSet<(String, String)>,
+}
+
+impl Provenance {
+    /// Record the keys present in a parsed configuration file
+    pub fn from_ini(ini: &Ini) -> Self {
+        let set = ini
+            .get_map_ref()
+            .iter()
+            .flat_map(|(section, keys)| {
+                keys.keys()
+                    .map(move |key| (section.to_lowercase(), key.to_lowercase()))
+            })
+            .collect();
+        Self { set }
+    }
+
+    /// Source of one setting
+    pub fn source(&self, section: &str, key: &str) -> ValueSource {
+        if self.set.contains(&(section.to_string(), key.to_string())) {
+            ValueSource::File
+        } else {
+            ValueSource::Default
+        }
+    }
+}
+
+/// A setting's effective value and where it came from
+#[derive(Debug, Clone, Serialize)]
+pub struct EffectiveValue {
+    pub value: serde_json::Value,
+    pub source: ValueSource,
+}
+
+/// Every known setting by section and key
+pub type EffectiveConfig = BTreeMap<String, BTreeMap<String, EffectiveValue>>;
+
+impl Config {
+    /// Effective value and source of every known setting
+    ///
+    /// Keys without a serialized value (e.g. the API token) are left out.
+    pub fn effective(&self) -> Result<EffectiveConfig> {
+        let values = serde_json::to_value(self)?;
+
+        let mut effective = EffectiveConfig::new();
+        for (section, keys) in MAIN_KEYS {
+            let entries = effective.entry(section.to_string()).or_default();
+            for key in *keys {
+                let Some(value) = values.get(section).and_then(|s| s.get(key)) else {
+                    continue;
+                };
+                entries.insert(
+                    key.to_string(),
+                    EffectiveValue {
+                        value: value.clone(),
+                        source: self.provenance.source(section, key),
+                    },
+                );
+            }
+        }
+        Ok(effective)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigParser;
+    use std::path::Path;
+
+    #[test]
+    fn test_effective_sources() {
+        let config = ConfigParser::new()
+            .parse_str(
+                "[service]\nmetrics_interval = 30\n[security]\napi_token = secret\n",
+                Path::new("frame.conf"),
+            )
+            .unwrap();
+        let effective = config.effective().unwrap();
+
+        let metrics = &effective["service"]["metrics_interval"];
+        assert_eq!(metrics.value, 30);
+        assert_eq!(metrics.source, ValueSource::File);
+
+        let stop_grace = &effective["service"]["stop_grace_ms"];
+        assert_eq!(stop_grace.value, 2000);
+        assert_eq!(stop_grace.source, ValueSource::Default);
+
+        assert!(!effective["security"].contains_key("api_token"));
+    }
+}
@@ -0,0 +1,95 @@
+//! Human-friendly unit parsing for configuration values
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer};
+
+/// Parse a memory size into megabytes
+///
+/// Accepts a bare integer (megabytes, for compatibility with existing
+/// configs) or an integer with a binary unit suffix: `K`, `M` or `G`,
+/// optionally followed by `B` or `iB` (`1024K`, `512M`, `2GiB`). Anything
+/// else, including sizes that aren't a whole number of megabytes, is rejected.
+pub fn parse_memory_mb(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, suffix) = value.split_at(split);
+
+    if digits.is_empty() {
+        anyhow::bail!("Invalid memory size '{}': expected a number", value);
+    }
+    let amount: u64 = digits
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid memory size '{}': number too large", value))?;
+
+    let suffix = suffix.trim_start();
+    let mb = match suffix {
+        "" | "M" | "MB" | "MiB" => Some(amount),
+        "G" | "GB" | "GiB" => amount.checked_mul(1024),
+        "K" | "KB" | "KiB" => {
+            if !amount.is_multiple_of(1024) {
+                anyhow::bail!(
+                    "Invalid memory size '{}': not a whole number of megabytes",
+                    value
+                );
+            }
+            Some(amount / 1024)
+        }
+        _ => anyhow::bail!(
+            "Invalid memory size '{}': unknown unit '{}' (use K, M or G)",
+            value,
+            suffix
+        ),
+    };
+
+    mb.ok_or_else(|| anyhow::anyhow!("Invalid memory size '{}': number too large", value))
+}
+
+/// Deserialize an optional memory size given as megabytes or a string with units
+pub fn deserialize_memory_mb<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum MemoryValue {
+        Megabytes(u64),
+        Text(String),
+    }
+
+    match Option::<MemoryValue>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(MemoryValue::Megabytes(mb)) => Ok(Some(mb)),
+        Some(MemoryValue::Text(text)) => parse_memory_mb(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memory_mb() {
+        assert_eq!(parse_memory_mb("512").unwrap(), 512);
+        assert_eq!(parse_memory_mb("512M").unwrap(), 512);
+        assert_eq!(parse_memory_mb("512MB").unwrap(), 512);
+        assert_eq!(parse_memory_mb("2G").unwrap(), 2048);
+        assert_eq!(parse_memory_mb("2 GiB").unwrap(), 2048);
+        assert_eq!(parse_memory_mb("1024K").unwrap(), 1);
+
+        assert!(parse_memory_mb("").is_err());
+        assert!(parse_memory_mb("M").is_err());
+        assert!(parse_memory_mb("1500K").is_err());
+        assert!(parse_memory_mb("1T").is_err());
+        assert!(parse_memory_mb("512b").is_err());
+        assert!(parse_memory_mb("512B").is_err());
+        assert!(parse_memory_mb("512iB").is_err());
+        assert!(parse_memory_mb("512MiBB").is_err());
+        assert!(parse_memory_mb("512m").is_err());
+        assert!(parse_memory_mb("1.5G").is_err());
+        assert!(parse_memory_mb("-1M").is_err());
+    }
+}
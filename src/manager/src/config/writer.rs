@@ -0,0 +1,127 @@
+//! Configuration File Updates
+//!
+//! Settings changed through the API are written into the existing file text,
+//! replacing only the affected lines, so comments and the other sections
+//! survive the read-modify-write.
+
+use anyhow::Result;
+use serde_json::Value;
+
+use super::migrate::find_key;
+
+/// Render a JSON setting as an INI value (lists become comma-separated)
+///
+/// Values that could break out of their line (line breaks, or a leading `[`
+/// that reads as a section header) are rejected.
+pub fn ini_value(value: &Value) -> Result<String> {
+    match value {
+        Value::Null => Ok(String::new()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::String(s) => {
+            if s.contains(['\r', '\n']) {
+                anyhow::bail!("Config values can't contain line breaks");
+            }
+            if s.trim_start().starts_with('[') {
+                anyhow::bail!("Config values can't start with '['");
+            }
+            Ok(s.clone())
+        }
+        Value::Array(items) => Ok(items
+            .iter()
+            .map(ini_value)
+            .collect::<Result<Vec<_>>>()?
+            .join(", ")),
+        Value::Object(_) => anyhow::bail!("Nested objects can't be stored in the config file"),
+    }
+}
+
+/// Set `key = value` pairs in `[section]`
+///
+/// Existing keys are replaced in place; new ones are added after the last
+/// entry of the section, which is appended to the file if it's missing.
+pub fn set_values(content: &str, section: &str, values: &[(String, String)]) -> String {
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+
+    for (key, value) in values {
+        let entry = format!("{} = {}", key, value);
+        if let Some(index) = find_key(&lines, section, key) {
+            lines[index] = entry;
+            continue;
+        }
+
+        match section_end(&lines, section) {
+            Some(index) => lines.insert(index, entry),
+            None => {
+                if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                    lines.push(String::new());
+                }
+                lines.push(format!("[{}]", section));
+                lines.push(entry);
+            }
+        }
+    }
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+/// Index just past the last non-blank line of `[section]`
+fn section_end(lines: &[String], section: &str) -> Option<usize> {
+    let header = format!("[{}]", section);
+    let start = lines
+        .iter()
+        .position(|line| line.trim().eq_ignore_ascii_case(&header))?;
+
+    let mut end = start + 1;
+    for (index, line) in lines.iter().enumerate().skip(start + 1) {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            break;
+        }
+        if !trimmed.is_empty() {
+            end = index + 1;
+        }
+    }
+    Some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_values_keeps_other_sections() {
+        let content =
+            "# Frame\n[service]\nenabled = true\n\n[proxy]\n# backend\nbackend = apache\n";
+        let values = [
+            ("backend".to_string(), "nginx".to_string()),
+            ("timeout".to_string(), "30".to_string()),
+        ];
+
+        assert_eq!(
+            set_values(content, "proxy", &values),
+            "# Frame\n[service]\nenabled = true\n\n[proxy]\n# backend\nbackend = nginx\ntimeout = 30\n"
+        );
+        assert_eq!(
+            set_values("[service]\nenabled = true\n", "logging", &values[1..]),
+            "[service]\nenabled = true\n\n[logging]\ntimeout = 30\n"
+        );
+
+        assert_eq!(
+            ini_value(&serde_json::json!(["root", "nobody"])).unwrap(),
+            "root, nobody"
+        );
+        assert!(ini_value(&serde_json::json!({"a": 1})).is_err());
+    }
+
+    #[test]
+    fn test_ini_value_rejects_injection() {
+        assert!(ini_value(&serde_json::json!("nginx\n[security]\napi_token =")).is_err());
+        assert!(ini_value(&serde_json::json!("a\rb")).is_err());
+        assert!(ini_value(&serde_json::json!(" [security]")).is_err());
+        assert!(ini_value(&serde_json::json!(["ok", "x\ny"])).is_err());
+        assert_eq!(ini_value(&serde_json::json!("a [b]")).unwrap(), "a [b]");
+    }
+}
@@ -0,0 +1,211 @@
+//! Daemon Support
+//!
+//! Detaching from the terminal, and the pidfile lock that keeps a single
+//! manager running per host.
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use nix::sys::stat::{umask, Mode};
+use nix::unistd::{chdir, dup2, fork, setsid, ForkResult};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Detach the current process from the terminal
+///
+/// Performs the classic double fork so the daemon can never reacquire a
+/// controlling terminal, then redirects stdin to `/dev/null` and stdout/stderr
+/// to `log_file`. Must be called before any threads (including the Tokio
+/// runtime) are started.
+pub fn daemonize(log_file: &Path) -> Result<()> {
+    if let Some(parent) = log_file.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create log directory: {}", parent.display()))?;
+    }
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .with_context(|| format!("Failed to open log file: {}", log_file.display()))?;
+    let null = OpenOptions::new()
+        .read(true)
+        .open("/dev/null")
+        .context("Failed to open /dev/null")?;
+
+    // SAFETY: called from a single-threaded process before the runtime starts
+    match unsafe { fork() }.context("First fork failed")? {
+        ForkResult::Parent { .. } => std::process::exit(0),
+        ForkResult::Child => {}
+    }
+
+    setsid().context("setsid failed")?;
+
+    // SAFETY: still single-threaded
+    match unsafe { fork() }.context("Second fork failed")? {
+        ForkResult::Parent { .. } => std::process::exit(0),
+        ForkResult::Child => {}
+    }
+
+    chdir("/").context("Failed to change directory to /")?;
+    umask(Mode::from_bits_truncate(0o022));
+
+    dup2(null.as_raw_fd(), 0).context("Failed to redirect stdin")?;
+    dup2(log.as_raw_fd(), 1).context("Failed to redirect stdout")?;
+    dup2(log.as_raw_fd(), 2).context("Failed to redirect stderr")?;
+
+    Ok(())
+}
+
+/// Locked pidfile for a running manager
+///
+/// An exclusive advisory lock (flock) is held on the file for the life of the
+/// process, so a second manager can't start against the same state.
+///
+/// The file is removed when dropped, which only happens when the daemon
+/// returns normally (including after SIGTERM/SIGINT). After SIGKILL, an abort
+/// or `std::process::exit` it is left behind; that is harmless because the
+/// kernel releases the lock with the process and the next manager takes the
+/// stale file over.
+///
+/// A manager that opened the file just before it was removed ends up locking
+/// the unlinked inode, so a lock only counts once the path still names the
+/// locked file; otherwise the file is opened again.
+pub struct Pidfile {
+    path: PathBuf,
+    _lock: Flock<File>,
+}
+
+impl Pidfile {
+    /// Fail if another live manager holds the pidfile lock
+    pub fn ensure_not_running(path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        // The probe lock is released as soon as it is dropped
+        Self::lock(path).map(|_| ())
+    }
+
+    /// Acquire the pidfile lock and write the current process ID
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create pidfile directory: {}", parent.display())
+            })?;
+        }
+
+        let mut lock = Self::lock(path)?;
+        lock.set_len(0)
+            .and_then(|_| lock.write_all(format!("{}\n", std::process::id()).as_bytes()))
+            .and_then(|_| lock.sync_all())
+            .with_context(|| format!("Failed to write pidfile: {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            _lock: lock,
+        })
+    }
+
+    /// Open the pidfile and take an exclusive, non-blocking lock on it
+    fn lock(path: &Path) -> Result<Flock<File>> {
+        loop {
+            let lock = Self::try_lock(path)?;
+            if Self::is_current(&lock, path)? {
+                return Ok(lock);
+            }
+            // Removed by the previous holder after we opened it; try again
+        }
+    }
+
+    /// Whether `path` still names the open file `file`
+    fn is_current(file: &File, path: &Path) -> Result<bool> {
+        use std::os::unix::fs::MetadataExt;
+
+        let locked = file.metadata()?;
+        match std::fs::metadata(path) {
+            Ok(current) => Ok(current.dev() == locked.dev() && current.ino() == locked.ino()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => {
+                Err(e).with_context(|| format!("Failed to check pidfile: {}", path.display()))
+            }
+        }
+    }
+
+    /// Open the pidfile and lock whatever file that turns out to be
+    fn try_lock(path: &Path) -> Result<Flock<File>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Failed to open pidfile: {}", path.display()))?;
+
+        match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Ok(lock) => Ok(lock),
+            Err((mut file, Errno::EWOULDBLOCK)) => {
+                let mut content = String::new();
+                let _ = file.read_to_string(&mut content);
+                match content.trim().parse::<u32>() {
+                    Ok(pid) => anyhow::bail!("Frame Manager is already running (pid {})", pid),
+                    Err(_) => anyhow::bail!("Frame Manager is already running"),
+                }
+            }
+            Err((_, e)) => {
+                Err(e).with_context(|| format!("Failed to lock pidfile: {}", path.display()))
+            }
+        }
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        // Remove while still holding the lock; a manager that opened the old
+        // file meanwhile sees it is no longer current and opens the new one
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_pidfile_lifecycle() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("frame-manager.pid");
+
+        {
+            let _pidfile = Pidfile::create(&path).unwrap();
+            assert!(path.exists());
+
+            // The held lock blocks a second start
+            let err = Pidfile::create(&path).err().unwrap();
+            assert!(err
+                .to_string()
+                .contains(&format!("pid {}", std::process::id())));
+            assert!(Pidfile::ensure_not_running(&path).is_err());
+        }
+
+        assert!(!path.exists());
+
+        // A manager that opened the file before it was removed doesn't keep
+        // its lock on the removed file
+        {
+            let first = Pidfile::create(&path).unwrap();
+            let opened_early = File::open(&path).unwrap();
+            drop(first);
+            let late = Flock::lock(opened_early, FlockArg::LockExclusiveNonblock).unwrap();
+            assert!(!Pidfile::is_current(&late, &path).unwrap());
+            let second = Pidfile::create(&path).unwrap();
+            assert!(Pidfile::is_current(&second._lock, &path).unwrap());
+        }
+
+        // A stale pidfile without a lock holder doesn't block startup
+        std::fs::write(&path, "999999\n").unwrap();
+        assert!(Pidfile::ensure_not_running(&path).is_ok());
+        let _pidfile = Pidfile::create(&path).unwrap();
+    }
+}
@@ -0,0 +1,371 @@
+//! Blue/Green Deployment Module
+//!
+//! Filesystem and proxy helpers used to roll out a new app version next to
+//! the running one and switch traffic over once it is healthy.
+
+mod tree;
+
+use anyhow::{Context, Result};
+use nix::sys::stat::Mode;
+use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tree::Entry;
+
+/// Directory (inside an instance dir) holding release app trees
+const RELEASES_DIR: &str = "releases";
+
+/// Outcome of a successful blue/green deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeployResult {
+    pub username: String,
+    pub app_name: String,
+    pub release_id: String,
+    pub previous_port: u16,
+    pub port: u16,
+}
+
+/// Deployment request failure callers are expected to handle
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DeployError {
+    /// The user, app name or artifact path is not acceptable
+    #[error("{0}")]
+    Invalid(String),
+    /// The instance or artifact doesn't exist
+    #[error("{0}")]
+    NotFound(String),
+}
+
+/// Validate an app name (alphanumeric and hyphens, as enforced by the cPanel UI)
+pub fn validate_app_name(app_name: &str) -> Result<(), DeployError> {
+    let valid = !app_name.is_empty()
+        && app_name.len() <= 64
+        && !app_name.starts_with('-')
+        && !app_name.ends_with('-')
+        && app_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+
+    if !valid {
+        return Err(DeployError::Invalid(format!(
+            "Invalid application name '{}': use alphanumeric characters and hyphens",
+            app_name
+        )));
+    }
+    Ok(())
+}
+
+/// Port registry key used for the candidate ("green") instance of a user
+pub fn candidate_key(username: &str) -> String {
+    format!("{}.deploy", username)
+}
+
+/// Build a release app tree for a deployment
+///
+/// The release directory contains the new version of `app_name` copied from
+/// `artifact` (relative to the instance directory), and symlinks to every
+/// other currently deployed app. Returns the release id and the release
+/// directory.
+pub async fn prepare_release(
+    instance_dir: &Path,
+    app_name: &str,
+    artifact: &Path,
+) -> Result<(String, PathBuf)> {
+    let release_id = format!(
+        "{}-{}",
+        chrono::Utc::now().format("%Y%m%d%H%M%S"),
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    );
+    let release_dir = instance_dir.join(RELEASES_DIR).join(&release_id);
+
+    let base = instance_dir.to_path_buf();
+    let app = app_name.to_string();
+    let rel_release = Path::new(RELEASES_DIR).join(&release_id);
+    let src = artifact.to_path_buf();
+    tokio::task::spawn_blocking(move || build_release(&base, &app, &rel_release, &src))
+        .await?
+        .with_context(|| format!("Failed to prepare release {}", release_dir.display()))?;
+
+    Ok((release_id, release_dir))
+}
+
+/// Install a release's app into the instance's persistent apps directory
+///
+/// The previous version is swapped out by rename so `apps/<app>` is never
+/// missing, then deleted.
+pub async fn install_app(instance_dir: &Path, app_name: &str, release_dir: &Path) -> Result<()> {
+    let release = release_dir
+        .strip_prefix(instance_dir)
+        .with_context(|| format!("Release {} is outside the instance", release_dir.display()))?
+        .join(app_name);
+    let base = instance_dir.to_path_buf();
+    let app = app_name.to_string();
+    tokio::task::spawn_blocking(move || swap_in(&base, &app, &release)).await??;
+    Ok(())
+}
+
+/// Remove all releases except the one currently being served
+pub async fn prune_releases(instance_dir: &Path, keep: Option<&str>) -> Result<()> {
+    let base = instance_dir.to_path_buf();
+    let keep = keep.map(OsString::from);
+    tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        let Some(mut releases) = tree::open_dir(&base, Path::new(RELEASES_DIR))? else {
+            return Ok(());
+        };
+        for name in tree::subdirs(&mut releases)? {
+            if Some(&name) == keep.as_ref() {
+                continue;
+            }
+            if let Err(e) = tree::remove(&releases, &name) {
+                tracing::warn!("Failed to remove release {}: {}", name.to_string_lossy(), e);
+            }
+        }
+        Ok(())
+    })
+    .await??;
+    Ok(())
+}
+
+/// Point the user's reverse proxy configuration at a new port
+pub async fn switch_proxy(
+    command: &str,
+    username: &str,
+    old_port: u16,
+    new_port: u16,
+) -> Result<()> {
+    let mut parts = command.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Proxy switch command is not configured"))?;
+
+    let output = Command::new(program)
+        .args(parts)
+        .arg(username)
+        .arg(old_port.to_string())
+        .arg(new_port.to_string())
+        .output()
+        .await
+        .with_context(|| format!("Failed to run proxy switch command: {}", command))?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Proxy switch failed with status {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Create the release directory, link the other apps and stage the artifact
+fn build_release(
+    instance_dir: &Path,
+    app_name: &str,
+    release: &Path,
+    artifact: &Path,
+) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let release_dir = tree::create_dir(instance_dir, release)?;
+
+    // Link all other apps so the candidate serves the full app set
+    let apps_dir = instance_dir.join("apps");
+    if let Some(mut apps) = tree::open_dir(instance_dir, Path::new("apps"))? {
+        for name in tree::subdirs(&mut apps)? {
+            if name == app_name || name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+            nix::unistd::symlinkat(
+                &apps_dir.join(&name),
+                Some(release_dir.as_raw_fd()),
+                name.as_os_str(),
+            )?;
+        }
+    }
+
+    // Copy the new app version
+    let target = tree::make_dir(
+        &release_dir,
+        OsStr::new(app_name),
+        Mode::from_bits_truncate(0o755),
+    )?;
+    match tree::open(instance_dir, artifact)? {
+        Some(Entry::Dir(mut dir)) => tree::copy_dir(&mut dir, &target)?,
+        Some(Entry::File(file)) => {
+            let name = artifact.file_name().unwrap_or_default();
+            tree::copy_file(file, &target, name)?;
+        }
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Artifact {} is not a file or directory", artifact.display()),
+            ))
+        }
+    }
+
+    // Keep the existing app config if the artifact doesn't ship one
+    let config = OsStr::new("app.json");
+    if !tree::exists(&target, config)? {
+        let existing = Path::new("apps").join(app_name).join(config);
+        if let Ok(Some(Entry::File(file))) = tree::open(instance_dir, &existing) {
+            tree::copy_file(file, &target, config)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy a release's app next to `apps/<app>` and rename it into place
+fn swap_in(instance_dir: &Path, app_name: &str, release: &Path) -> std::io::Result<()> {
+    let apps = tree::create_dir(instance_dir, Path::new("apps"))?;
+    let current = OsString::from(app_name);
+    let incoming = OsString::from(format!(".{}.incoming", app_name));
+    let outgoing = OsString::from(format!(".{}.outgoing", app_name));
+    for stale in [&incoming, &outgoing] {
+        tree::remove(&apps, stale)?;
+    }
+
+    let mut src = tree::open_dir(instance_dir, release)?.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Release app {} not found", release.display()),
+        )
+    })?;
+    let dst = tree::make_dir(&apps, &incoming, Mode::from_bits_truncate(0o755))?;
+    tree::copy_dir(&mut src, &dst)?;
+
+    if tree::exists(&apps, &current)? {
+        tree::rename(&apps, &current, &outgoing)?;
+    }
+    tree::rename(&apps, &incoming, &current)?;
+    tree::remove(&apps, &outgoing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_validate_app_name() {
+        assert!(validate_app_name("my-app").is_ok());
+        assert!(validate_app_name("app1").is_ok());
+        assert!(validate_app_name("").is_err());
+        assert!(validate_app_name("-app").is_err());
+        assert!(validate_app_name("../app").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_prepare_and_install_release() {
+        let dir = tempdir().unwrap();
+        let instance_dir = dir.path().join("alice");
+        std::fs::create_dir_all(instance_dir.join("apps/blog")).unwrap();
+        std::fs::create_dir_all(instance_dir.join("apps/shop")).unwrap();
+        std::fs::write(instance_dir.join("apps/blog/app.json"), "{}").unwrap();
+        std::fs::write(instance_dir.join("apps/blog/main.wasm"), "v1").unwrap();
+
+        let artifact = instance_dir.join("uploads/blog");
+        std::fs::create_dir_all(&artifact).unwrap();
+        std::fs::write(artifact.join("main.wasm"), "v2").unwrap();
+
+        let (release_id, release_dir) =
+            prepare_release(&instance_dir, "blog", Path::new("uploads/blog"))
+                .await
+                .unwrap();
+
+        assert!(release_dir.join("shop").is_symlink());
+        assert_eq!(
+            std::fs::read_to_string(release_dir.join("blog/main.wasm")).unwrap(),
+            "v2"
+        );
+        assert!(release_dir.join("blog/app.json").exists());
+
+        install_app(&instance_dir, "blog", &release_dir)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(instance_dir.join("apps/blog/main.wasm")).unwrap(),
+            "v2"
+        );
+
+        prune_releases(&instance_dir, Some(&release_id))
+            .await
+            .unwrap();
+        assert!(release_dir.exists());
+        prune_releases(&instance_dir, None).await.unwrap();
+        assert!(!release_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_symlinks_are_not_followed() {
+        let dir = tempdir().unwrap();
+        let instance_dir = dir.path().join("alice");
+        std::fs::create_dir_all(instance_dir.join("apps/blog")).unwrap();
+        let secret = dir.path().join("secret");
+        std::fs::write(&secret, "root only").unwrap();
+        std::os::unix::fs::symlink(&secret, instance_dir.join("apps/blog/app.json")).unwrap();
+
+        let artifact = instance_dir.join("uploads/blog");
+        std::fs::create_dir_all(&artifact).unwrap();
+        std::fs::write(artifact.join("main.wasm"), "v2").unwrap();
+        std::os::unix::fs::symlink(&secret, artifact.join("leak")).unwrap();
+
+        let (_, release_dir) = prepare_release(&instance_dir, "blog", Path::new("uploads/blog"))
+            .await
+            .unwrap();
+        let staged = release_dir.join("blog");
+        assert_eq!(std::fs::read_link(staged.join("leak")).unwrap(), secret);
+        assert!(!staged.join("app.json").exists());
+
+        install_app(&instance_dir, "blog", &release_dir)
+            .await
+            .unwrap();
+        assert!(instance_dir.join("apps/blog/leak").is_symlink());
+
+        // A linked artifact or app directory is refused, not read through
+        std::os::unix::fs::symlink(dir.path(), instance_dir.join("uploads/linked")).unwrap();
+        assert!(
+            prepare_release(&instance_dir, "blog", Path::new("uploads/linked/secret"))
+                .await
+                .is_err()
+        );
+        assert!(
+            prepare_release(&instance_dir, "blog", Path::new("uploads/linked"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_symlinked_app_dir_is_not_followed() {
+        let dir = tempdir().unwrap();
+        let instance_dir = dir.path().join("alice");
+        let other = dir.path().join("bob/apps/blog");
+        std::fs::create_dir_all(&other).unwrap();
+        std::fs::write(other.join("app.json"), "bob's config").unwrap();
+        std::fs::create_dir_all(instance_dir.join("apps")).unwrap();
+        std::os::unix::fs::symlink(&other, instance_dir.join("apps/blog")).unwrap();
+        std::fs::create_dir_all(instance_dir.join("uploads/blog")).unwrap();
+        std::fs::write(instance_dir.join("uploads/blog/main.wasm"), "v2").unwrap();
+
+        let (_, release_dir) = prepare_release(&instance_dir, "blog", Path::new("uploads/blog"))
+            .await
+            .unwrap();
+        assert!(!release_dir.join("blog/app.json").exists());
+
+        // Installing replaces the link itself; bob's tree is untouched
+        install_app(&instance_dir, "blog", &release_dir)
+            .await
+            .unwrap();
+        let installed = instance_dir.join("apps/blog");
+        assert!(!installed.is_symlink());
+        assert!(installed.join("main.wasm").exists());
+        assert_eq!(
+            std::fs::read_to_string(other.join("app.json")).unwrap(),
+            "bob's config"
+        );
+        assert!(!other.join("main.wasm").exists());
+    }
+}
@@ -0,0 +1,274 @@
+//! Symlink-Safe Tree Operations
+//!
+//! Deployments copy, rename and delete trees inside the user's instance
+//! directory as root. The user can swap any entry for a symlink at any time,
+//! so nothing below the instance directory is resolved by path: every
+//! component is opened relative to its parent's descriptor with
+//! `O_NOFOLLOW`, and a symlink where a directory or file is expected is an
+//! error (`ELOOP`) rather than a detour.
+
+use nix::dir::Dir;
+use nix::errno::Errno;
+use nix::fcntl::{openat, readlinkat, renameat, AtFlags, OFlag};
+use nix::sys::stat::{fstat, fstatat, mkdirat, FileStat, Mode, SFlag};
+use nix::unistd::{symlinkat, unlinkat, UnlinkatFlags};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Component, Path};
+
+/// Flags for opening a directory that must not be a symlink
+const DIR_FLAGS: OFlag = OFlag::O_RDONLY
+    .union(OFlag::O_DIRECTORY)
+    .union(OFlag::O_NOFOLLOW)
+    .union(OFlag::O_CLOEXEC);
+
+/// Flags for opening a file that must not be a symlink; non-blocking so a
+/// FIFO can't stall the open
+const FILE_FLAGS: OFlag = OFlag::O_RDONLY
+    .union(OFlag::O_NOFOLLOW)
+    .union(OFlag::O_NONBLOCK)
+    .union(OFlag::O_CLOEXEC);
+
+/// A directory or regular file opened without following symlinks
+pub enum Entry {
+    Dir(Dir),
+    File(File),
+}
+
+/// Open the directory `rel` below the trusted directory `base`
+///
+/// Returns `None` if it doesn't exist.
+pub fn open_dir(base: &Path, rel: &Path) -> io::Result<Option<Dir>> {
+    match walk(base, rel, false) {
+        Ok(dir) => Ok(Some(dir)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Open the directory `rel` below `base`, creating missing components
+pub fn create_dir(base: &Path, rel: &Path) -> io::Result<Dir> {
+    walk(base, rel, true)
+}
+
+/// Open `rel` below `base` if it is a directory or regular file
+///
+/// Returns `None` if it doesn't exist or is anything else.
+pub fn open(base: &Path, rel: &Path) -> io::Result<Option<Entry>> {
+    let (parent, name) = match (rel.parent(), rel.file_name()) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => return Err(invalid(rel)),
+    };
+    match open_dir(base, parent)? {
+        Some(parent) => open_entry(&parent, name),
+        None => Ok(None),
+    }
+}
+
+/// Whether `dir` has an entry called `name` (of any type)
+pub fn exists(dir: &Dir, name: &OsStr) -> io::Result<bool> {
+    match fstatat(Some(dir.as_raw_fd()), name, AtFlags::AT_SYMLINK_NOFOLLOW) {
+        Ok(_) => Ok(true),
+        Err(Errno::ENOENT) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Names of the entries of `dir` that are directories, not symlinks to them
+pub fn subdirs(dir: &mut Dir) -> io::Result<Vec<OsString>> {
+    let fd = dir.as_raw_fd();
+    let mut subdirs = Vec::new();
+    for name in names(dir)? {
+        if is_kind(&stat_at(fd, &name)?, SFlag::S_IFDIR) {
+            subdirs.push(name);
+        }
+    }
+    Ok(subdirs)
+}
+
+/// Copy everything in `src` into `dst`
+///
+/// Symlinks are recreated as links rather than followed, and special files
+/// are skipped.
+pub fn copy_dir(src: &mut Dir, dst: &Dir) -> io::Result<()> {
+    let fd = src.as_raw_fd();
+    for name in names(src)? {
+        let stat = stat_at(fd, &name)?;
+        if is_kind(&stat, SFlag::S_IFLNK) {
+            let target = readlinkat(Some(fd), name.as_os_str())?;
+            symlinkat(target.as_os_str(), Some(dst.as_raw_fd()), name.as_os_str())?;
+            continue;
+        }
+        match open_entry(src, &name)? {
+            Some(Entry::Dir(mut subdir)) => {
+                let copy = make_dir(dst, &name, Mode::from_bits_truncate(stat.st_mode))?;
+                copy_dir(&mut subdir, &copy)?;
+            }
+            Some(Entry::File(file)) => copy_file(file, dst, &name)?,
+            // Changed type since it was listed, or a special file
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Copy an open file into `dst` as a new file called `name`
+pub fn copy_file(mut file: File, dst: &Dir, name: &OsStr) -> io::Result<()> {
+    let mode = Mode::from_bits_truncate(fstat(file.as_raw_fd())?.st_mode & 0o777);
+    let flags =
+        OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    let fd = openat(Some(dst.as_raw_fd()), name, flags, mode)?;
+    // SAFETY: openat just returned this descriptor and nothing else owns it
+    let mut copy = unsafe { File::from_raw_fd(fd) };
+    io::copy(&mut file, &mut copy)?;
+    Ok(())
+}
+
+/// Create the directory `name` in `parent` and open it
+pub fn make_dir(parent: &Dir, name: &OsStr, mode: Mode) -> io::Result<Dir> {
+    mkdirat(
+        Some(parent.as_raw_fd()),
+        name,
+        mode & Mode::from_bits_truncate(0o777),
+    )?;
+    Ok(Dir::openat(
+        Some(parent.as_raw_fd()),
+        name,
+        DIR_FLAGS,
+        Mode::empty(),
+    )?)
+}
+
+/// Rename `from` to `to`, both entries of `dir`
+pub fn rename(dir: &Dir, from: &OsStr, to: &OsStr) -> io::Result<()> {
+    let fd = dir.as_raw_fd();
+    Ok(renameat(Some(fd), from, Some(fd), to)?)
+}
+
+/// Delete the entry `name` of `parent` and everything below it
+///
+/// A symlink is removed itself; nothing it points to is touched. Missing
+/// entries are not an error.
+pub fn remove(parent: &Dir, name: &OsStr) -> io::Result<()> {
+    let fd = parent.as_raw_fd();
+    let stat = match fstatat(Some(fd), name, AtFlags::AT_SYMLINK_NOFOLLOW) {
+        Ok(stat) => stat,
+        Err(Errno::ENOENT) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if is_kind(&stat, SFlag::S_IFDIR) {
+        let mut dir = Dir::openat(Some(fd), name, DIR_FLAGS, Mode::empty())?;
+        for child in names(&mut dir)? {
+            remove(&dir, &child)?;
+        }
+        unlinkat(Some(fd), name, UnlinkatFlags::RemoveDir)?;
+    } else {
+        unlinkat(Some(fd), name, UnlinkatFlags::NoRemoveDir)?;
+    }
+    Ok(())
+}
+
+/// Open each component of `rel` below `base` without following symlinks
+fn walk(base: &Path, rel: &Path, create: bool) -> io::Result<Dir> {
+    let mut dir = Dir::open(
+        base,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    for component in rel.components() {
+        let Component::Normal(name) = component else {
+            return Err(invalid(rel));
+        };
+        if create {
+            match mkdirat(Some(dir.as_raw_fd()), name, Mode::from_bits_truncate(0o755)) {
+                Ok(()) | Err(Errno::EEXIST) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        dir = Dir::openat(Some(dir.as_raw_fd()), name, DIR_FLAGS, Mode::empty())?;
+    }
+    Ok(dir)
+}
+
+/// Open an entry of `parent` if it is a directory or regular file
+fn open_entry(parent: &Dir, name: &OsStr) -> io::Result<Option<Entry>> {
+    let fd = match openat(Some(parent.as_raw_fd()), name, FILE_FLAGS, Mode::empty()) {
+        Ok(fd) => fd,
+        Err(Errno::ENOENT | Errno::ELOOP) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    // SAFETY: openat just returned this descriptor and nothing else owns it
+    let file = unsafe { File::from_raw_fd(fd) };
+    let stat = fstat(file.as_raw_fd())?;
+    if is_kind(&stat, SFlag::S_IFDIR) {
+        Ok(Some(Entry::Dir(Dir::from(file)?)))
+    } else if is_kind(&stat, SFlag::S_IFREG) {
+        Ok(Some(Entry::File(file)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Names in a directory, without `.` and `..`
+fn names(dir: &mut Dir) -> io::Result<Vec<OsString>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut names = Vec::new();
+    for entry in dir.iter() {
+        let name = OsStr::from_bytes(entry?.file_name().to_bytes()).to_os_string();
+        if name != "." && name != ".." {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+fn stat_at(dir: RawFd, name: &OsStr) -> io::Result<FileStat> {
+    Ok(fstatat(Some(dir), name, AtFlags::AT_SYMLINK_NOFOLLOW)?)
+}
+
+fn is_kind(stat: &FileStat, kind: SFlag) -> bool {
+    SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == kind
+}
+
+fn invalid(rel: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "Invalid path below the instance directory: {}",
+            rel.display()
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_symlinked_components_are_refused() {
+        let dir = tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        std::fs::create_dir_all(outside.join("app")).unwrap();
+        std::fs::write(outside.join("app/app.json"), "root only").unwrap();
+        let base = dir.path().join("alice");
+        std::fs::create_dir_all(base.join("apps")).unwrap();
+        std::os::unix::fs::symlink(outside.join("app"), base.join("apps/blog")).unwrap();
+        std::os::unix::fs::symlink(&outside, base.join("releases")).unwrap();
+
+        assert!(open(&base, Path::new("apps/blog/app.json")).is_err());
+        assert!(open(&base, Path::new("apps/blog")).unwrap().is_none());
+        assert!(create_dir(&base, Path::new("releases/1")).is_err());
+        assert!(!outside.join("1").exists());
+        assert!(open_dir(&base, Path::new("../alice")).is_err());
+
+        // Removing a link leaves its target alone
+        let apps = open_dir(&base, Path::new("apps")).unwrap().unwrap();
+        remove(&apps, OsStr::new("blog")).unwrap();
+        assert!(outside.join("app/app.json").exists());
+        assert!(!exists(&apps, OsStr::new("blog")).unwrap());
+    }
+}
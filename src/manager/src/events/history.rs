@@ -0,0 +1,127 @@
+//! Event History
+//!
+//! Keeps the most recent events in memory so the API can answer "what
+//! happened to this user lately" without external log aggregation. History
+//! is lost when the service restarts.
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use super::{EventEmitter, EventEnvelope};
+
+/// Events kept by default
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// Filters for querying recent events
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventQuery {
+    /// Only events about this user
+    pub username: Option<String>,
+    /// Only events emitted at or after this time (RFC 3339)
+    pub since: Option<DateTime<Utc>>,
+    /// Only events with this name, e.g. `instance.crashed`
+    #[serde(rename = "type")]
+    pub event_type: Option<String>,
+}
+
+/// Bounded buffer of the most recent events, oldest evicted first
+pub struct EventHistory {
+    capacity: usize,
+    events: Mutex<VecDeque<EventEnvelope>>,
+}
+
+impl EventHistory {
+    /// History keeping up to `capacity` events (none if 0)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Record an event, evicting the oldest when full
+    pub fn push(&self, envelope: EventEnvelope) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Ok(mut events) = self.events.lock() {
+            if events.len() == self.capacity {
+                events.pop_front();
+            }
+            events.push_back(envelope);
+        }
+    }
+
+    /// Recorded events matching a query, oldest first
+    pub fn query(&self, query: &EventQuery) -> Vec<EventEnvelope> {
+        let Ok(events) = self.events.lock() else {
+            return Vec::new();
+        };
+        events
+            .iter()
+            .filter(|envelope| {
+                query
+                    .username
+                    .as_deref()
+                    .is_none_or(|username| envelope.event.username() == Some(username))
+                    && query.since.is_none_or(|since| envelope.timestamp >= since)
+                    && query.event_type.as_deref().is_none_or(|event_type| {
+                        EventEmitter::event_name(&envelope.event) == event_type
+                    })
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+
+    fn stopped(username: &str) -> EventEnvelope {
+        EventEnvelope::new(Event::InstanceStopped {
+            username: username.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_history_evicts_and_filters() {
+        let history = EventHistory::new(3);
+        history.push(stopped("alice"));
+        let cutoff = Utc::now();
+        history.push(EventEnvelope::new(Event::ConfigReloaded));
+        history.push(stopped("bob"));
+        history.push(EventEnvelope::new(Event::InstanceCrashed {
+            username: "alice".to_string(),
+            exit_code: Some(1),
+            reason: "segfault".to_string(),
+        }));
+
+        // The first event was evicted
+        let all = history.query(&EventQuery::default());
+        assert_eq!(all.len(), 3);
+        assert!(matches!(all[0].event, Event::ConfigReloaded));
+
+        let alice = history.query(&EventQuery {
+            username: Some("alice".to_string()),
+            ..EventQuery::default()
+        });
+        assert_eq!(alice.len(), 1);
+        assert!(matches!(alice[0].event, Event::InstanceCrashed { .. }));
+
+        let stops = history.query(&EventQuery {
+            event_type: Some("instance.stopped".to_string()),
+            since: Some(cutoff),
+            ..EventQuery::default()
+        });
+        assert_eq!(stops.len(), 1);
+        assert_eq!(stops[0].event.username(), Some("bob"));
+
+        let disabled = EventHistory::new(0);
+        disabled.push(stopped("alice"));
+        assert!(disabled.query(&EventQuery::default()).is_empty());
+    }
+}
@@ -1,66 +1,351 @@
 //! Hook Execution
 
-use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::sync::Mutex;
+use std::time::Duration;
 use tokio::process::Command;
 
-use super::Event;
+use super::{Event, EventEmitter};
+
+/// Default time the hook scripts for an event may run before they're killed
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Event types (as serialized in the `event` tag) and the hook each one runs
+pub const HOOKS: &[(&str, &str)] = &[
+    ("instance_started", "on_instance_started"),
+    ("instance_stopped", "on_instance_stopped"),
+    ("instance_crashed", "on_instance_crashed"),
+    ("instance_removed", "on_instance_removed"),
+    ("app_deployed", "on_app_deployed"),
+    ("app_removed", "on_app_removed"),
+    ("resource_limit_reached", "on_resource_limit"),
+    ("health_check_failed", "on_health_check_failed"),
+    ("auto_start_failed", "on_autostart_failed"),
+    ("instance_unstable", "on_instance_unstable"),
+    ("port_reassigned", "on_port_reassigned"),
+    ("config_reloaded", "on_config_reloaded"),
+    ("service_started", "on_service_started"),
+    ("service_stopped", "on_service_stopped"),
+];
 
 /// Hook script executor
 pub struct HookExecutor {
     hooks_dir: PathBuf,
+    /// Event names (as in `EventEmitter::event_name`) whose hooks run; all if `None`
+    enabled_events: Option<HashSet<String>>,
+    /// Time the scripts for one event may run, together, before they're killed
+    timeout: Duration,
+    /// Most recent execution result per script
+    last_runs: Mutex<HashMap<PathBuf, HookRun>>,
+}
+
+/// Result of the most recent execution of a hook script
+#[derive(Debug, Clone, Serialize)]
+pub struct HookRun {
+    pub timestamp: DateTime<Utc>,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// State of a single hook script on disk
+#[derive(Debug, Clone, Serialize)]
+pub struct HookScript {
+    pub path: String,
+    pub exists: bool,
+    pub executable: bool,
+    pub last_run: Option<HookRun>,
+}
+
+/// Output of a hook script run by a test fire
+#[derive(Debug, Clone, Serialize)]
+pub struct HookTestResult {
+    pub path: String,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Hook scripts configured for an event type
+#[derive(Debug, Clone, Serialize)]
+pub struct HookInfo {
+    pub event: String,
+    pub hook: String,
+    /// Whether emitted events of this type run the hook
+    pub enabled: bool,
+    /// The `<hook>` script itself
+    pub script: HookScript,
+    /// Entries in the `<hook>.d` directory, run after the script in name order
+    pub dropins: Vec<HookScript>,
 }
 
 impl HookExecutor {
     /// Create a new hook executor
     pub fn new(hooks_dir: PathBuf) -> Self {
-        Self { hooks_dir }
+        Self {
+            hooks_dir,
+            enabled_events: None,
+            timeout: DEFAULT_HOOK_TIMEOUT,
+            last_runs: Mutex::new(HashMap::new()),
+        }
     }
 
-    /// Execute hooks for an event
-    pub async fn execute(&self, event: &Event) {
-        let hook_name = match event {
+    /// Only run hooks for the named events (all events if `names` is empty)
+    pub fn with_enabled_events(mut self, names: &[String]) -> Self {
+        self.enabled_events = (!names.is_empty()).then(|| names.iter().cloned().collect());
+        self
+    }
+
+    /// Kill scripts still running `timeout` after an event's hooks started
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Whether emitted events of this type run hooks
+    pub fn is_enabled(&self, event: &Event) -> bool {
+        self.enabled_events
+            .as_ref()
+            .is_none_or(|names| names.contains(EventEmitter::event_name(event)))
+    }
+
+    /// Hook name run for an event
+    pub fn hook_name(event: &Event) -> &'static str {
+        match event {
             Event::InstanceStarted { .. } => "on_instance_started",
             Event::InstanceStopped { .. } => "on_instance_stopped",
             Event::InstanceCrashed { .. } => "on_instance_crashed",
+            Event::InstanceRemoved { .. } => "on_instance_removed",
             Event::AppDeployed { .. } => "on_app_deployed",
             Event::AppRemoved { .. } => "on_app_removed",
             Event::ResourceLimitReached { .. } => "on_resource_limit",
             Event::HealthCheckFailed { .. } => "on_health_check_failed",
+            Event::AutoStartFailed { .. } => "on_autostart_failed",
+            Event::InstanceUnstable { .. } => "on_instance_unstable",
+            Event::PortReassigned { .. } => "on_port_reassigned",
             Event::ConfigReloaded => "on_config_reloaded",
             Event::ServiceStarted => "on_service_started",
             Event::ServiceStopped => "on_service_stopped",
-        };
-
-        let hook_path = self.hooks_dir.join(hook_name);
+        }
+    }
 
-        if !hook_path.exists() {
+    /// Execute hooks for an event
+    pub async fn execute(&self, event: &Event) {
+        if !self.is_enabled(event) {
+            tracing::debug!(
+                "Hooks disabled for {}, skipping",
+                EventEmitter::event_name(event)
+            );
             return;
         }
+        let hook_name = Self::hook_name(event);
 
         // Build environment variables from event
         let env_vars = self.event_to_env(event);
 
-        match Command::new(&hook_path)
-            .envs(env_vars)
-            .output()
-            .await
-        {
-            Ok(output) => {
-                if !output.status.success() {
-                    tracing::warn!(
-                        "Hook {} failed with status {}: {}",
-                        hook_name,
-                        output.status,
-                        String::from_utf8_lossy(&output.stderr)
-                    );
-                } else {
-                    tracing::debug!("Hook {} executed successfully", hook_name);
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        for hook_path in self.runnable_scripts(hook_name) {
+            if tokio::time::Instant::now() >= deadline {
+                tracing::warn!(
+                    "Hook {} skipped: the hooks for {} ran out of time",
+                    hook_path.display(),
+                    EventEmitter::event_name(event)
+                );
+                continue;
+            }
+            match self.run_script(&hook_path, &env_vars, deadline).await {
+                Ok(output) => {
+                    if !output.status.success() {
+                        tracing::warn!(
+                            "Hook {} failed with status {}: {}",
+                            hook_path.display(),
+                            output.status,
+                            String::from_utf8_lossy(&output.stderr)
+                        );
+                    } else {
+                        tracing::debug!("Hook {} executed successfully", hook_path.display());
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    tracing::warn!("Hook {} killed: {}", hook_path.display(), e);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to execute hook {}: {}", hook_path.display(), e);
                 }
             }
-            Err(e) => {
-                tracing::error!("Failed to execute hook {}: {}", hook_name, e);
+        }
+    }
+
+    /// Run the hooks for a synthetic event and capture their output
+    ///
+    /// Scripts see `FRAME_HOOK_TEST=1` in their environment so they can skip
+    /// side effects that shouldn't happen for a test event.
+    pub async fn test_fire(&self, event: &Event) -> anyhow::Result<Vec<HookTestResult>> {
+        let hook_name = Self::hook_name(event);
+        let scripts = self.runnable_scripts(hook_name);
+        if scripts.is_empty() {
+            anyhow::bail!("No hook scripts installed for {}", hook_name);
+        }
+
+        let mut env_vars = self.event_to_env(event);
+        env_vars.push(("FRAME_HOOK_TEST".to_string(), "1".to_string()));
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut results = Vec::new();
+        for hook_path in scripts {
+            let output = self
+                .run_script(&hook_path, &env_vars, deadline)
+                .await
+                .map_err(|e| {
+                    anyhow::anyhow!("Failed to execute hook {}: {}", hook_path.display(), e)
+                })?;
+            results.push(HookTestResult {
+                path: hook_path.display().to_string(),
+                exit_code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+
+        Ok(results)
+    }
+
+    /// List the hook scripts for every event type
+    pub fn list(&self) -> Vec<HookInfo> {
+        HOOKS
+            .iter()
+            .map(|(event, hook)| {
+                let script_path = self.hooks_dir.join(hook);
+                let dropins = Self::dropin_paths(&self.hooks_dir.join(format!("{}.d", hook)))
+                    .iter()
+                    .map(|path| self.describe(path))
+                    .collect();
+
+                HookInfo {
+                    event: event.to_string(),
+                    hook: hook.to_string(),
+                    enabled: Event::sample(event, serde_json::Map::new())
+                        .is_ok_and(|sample| self.is_enabled(&sample)),
+                    script: self.describe(&script_path),
+                    dropins,
+                }
+            })
+            .collect()
+    }
+
+    /// Scripts that will run for a hook: the script itself, then `.d` entries
+    fn runnable_scripts(&self, hook_name: &str) -> Vec<PathBuf> {
+        let mut scripts = Vec::new();
+
+        let hook_path = self.hooks_dir.join(hook_name);
+        if hook_path.exists() {
+            scripts.push(hook_path);
+        }
+
+        let dropin_dir = self.hooks_dir.join(format!("{}.d", hook_name));
+        scripts.extend(
+            Self::dropin_paths(&dropin_dir)
+                .into_iter()
+                .filter(|path| is_executable(path)),
+        );
+
+        scripts
+    }
+
+    /// Regular files in a `.d` directory, sorted by name
+    fn dropin_paths(dir: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        paths.sort();
+        paths
+    }
+
+    fn describe(&self, path: &Path) -> HookScript {
+        let last_run = self
+            .last_runs
+            .lock()
+            .ok()
+            .and_then(|runs| runs.get(path).cloned());
+
+        HookScript {
+            path: path.display().to_string(),
+            exists: path.exists(),
+            executable: is_executable(path),
+            last_run,
+        }
+    }
+
+    /// Run a single hook script and record the outcome
+    ///
+    /// The script leads its own process group, so whatever it started is
+    /// killed with it when `deadline` passes.
+    async fn run_script(
+        &self,
+        path: &Path,
+        env_vars: &[(String, String)],
+        deadline: tokio::time::Instant,
+    ) -> std::io::Result<Output> {
+        let result = match Command::new(path)
+            .envs(env_vars.iter().cloned())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn()
+        {
+            Ok(child) => {
+                let pid = child.id();
+                match tokio::time::timeout_at(deadline, child.wait_with_output()).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        if let Some(pid) = pid {
+                            let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+                        }
+                        Err(std::io::Error::new(
+                            std::io::ErrorKind::TimedOut,
+                            format!("timed out after {}s", self.timeout.as_secs_f64()),
+                        ))
+                    }
+                }
             }
+            Err(e) => Err(e),
+        };
+
+        let run = match &result {
+            Ok(output) => HookRun {
+                timestamp: Utc::now(),
+                success: output.status.success(),
+                exit_code: output.status.code(),
+                error: None,
+            },
+            Err(e) => HookRun {
+                timestamp: Utc::now(),
+                success: false,
+                exit_code: None,
+                error: Some(e.to_string()),
+            },
+        };
+        if let Ok(mut runs) = self.last_runs.lock() {
+            runs.insert(path.to_path_buf(), run);
         }
+
+        result
     }
 
     /// Convert event to environment variables
@@ -68,7 +353,11 @@ impl HookExecutor {
         let mut env = Vec::new();
 
         match event {
-            Event::InstanceStarted { username, port, apps } => {
+            Event::InstanceStarted {
+                username,
+                port,
+                apps,
+            } => {
                 env.push(("FRAME_USERNAME".to_string(), username.clone()));
                 env.push(("FRAME_PORT".to_string(), port.to_string()));
                 env.push(("FRAME_APPS".to_string(), apps.join(",")));
@@ -87,6 +376,10 @@ impl HookExecutor {
                 }
                 env.push(("FRAME_REASON".to_string(), reason.clone()));
             }
+            Event::InstanceRemoved { username, purged } => {
+                env.push(("FRAME_USERNAME".to_string(), username.clone()));
+                env.push(("FRAME_PURGED".to_string(), purged.to_string()));
+            }
             Event::AppDeployed { username, app_name } => {
                 env.push(("FRAME_USERNAME".to_string(), username.clone()));
                 env.push(("FRAME_APP_NAME".to_string(), app_name.clone()));
@@ -115,9 +408,214 @@ impl HookExecutor {
                 env.push(("FRAME_CHECK_NAME".to_string(), check_name.clone()));
                 env.push(("FRAME_MESSAGE".to_string(), message.clone()));
             }
+            Event::AutoStartFailed { username, reason } => {
+                env.push(("FRAME_USERNAME".to_string(), username.clone()));
+                env.push(("FRAME_REASON".to_string(), reason.clone()));
+            }
+            Event::InstanceUnstable {
+                username,
+                transitions,
+                window_secs,
+            } => {
+                env.push(("FRAME_USERNAME".to_string(), username.clone()));
+                env.push(("FRAME_TRANSITIONS".to_string(), transitions.to_string()));
+                env.push(("FRAME_WINDOW_SECS".to_string(), window_secs.to_string()));
+            }
+            Event::PortReassigned {
+                username,
+                old_port,
+                new_port,
+                reason,
+            } => {
+                env.push(("FRAME_USERNAME".to_string(), username.clone()));
+                env.push(("FRAME_OLD_PORT".to_string(), old_port.to_string()));
+                env.push(("FRAME_NEW_PORT".to_string(), new_port.to_string()));
+                env.push(("FRAME_REASON".to_string(), reason.clone()));
+            }
             Event::ConfigReloaded | Event::ServiceStarted | Event::ServiceStopped => {}
         }
 
         env
     }
 }
+
+/// Whether a path is a regular file with any execute bit set
+fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_script(path: &Path, body: &str, mode: u32) {
+        std::fs::write(path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_reports_scripts_and_last_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let dropins = dir.path().join("on_instance_stopped.d");
+        std::fs::create_dir(&dropins).unwrap();
+        write_script(&dir.path().join("on_instance_stopped"), "exit 0", 0o755);
+        write_script(&dropins.join("10-notify"), "exit 3", 0o755);
+        write_script(&dropins.join("20-disabled"), "exit 0", 0o644);
+
+        let executor = HookExecutor::new(dir.path().to_path_buf());
+        executor
+            .execute(&Event::InstanceStopped {
+                username: "alice".to_string(),
+            })
+            .await;
+
+        let hooks = executor.list();
+        assert_eq!(hooks.len(), HOOKS.len());
+
+        let stopped = hooks
+            .iter()
+            .find(|h| h.event == "instance_stopped")
+            .unwrap();
+        assert!(stopped.script.executable);
+        assert!(stopped.script.last_run.as_ref().unwrap().success);
+        assert_eq!(stopped.dropins.len(), 2);
+        assert_eq!(
+            stopped.dropins[0].last_run.as_ref().unwrap().exit_code,
+            Some(3)
+        );
+        assert!(!stopped.dropins[1].executable);
+        assert!(stopped.dropins[1].last_run.is_none());
+
+        let started = hooks
+            .iter()
+            .find(|h| h.event == "instance_started")
+            .unwrap();
+        assert!(!started.script.exists);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_events_skip_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        for hook in ["on_config_reloaded", "on_instance_stopped"] {
+            write_script(
+                &dir.path().join(hook),
+                &format!("echo {} >> {}", hook, marker.display()),
+                0o755,
+            );
+        }
+
+        let executor = HookExecutor::new(dir.path().to_path_buf())
+            .with_enabled_events(&["instance.stopped".to_string()]);
+        executor.execute(&Event::ConfigReloaded).await;
+        assert!(!marker.exists());
+        executor
+            .execute(&Event::InstanceStopped {
+                username: "alice".to_string(),
+            })
+            .await;
+        assert_eq!(
+            std::fs::read_to_string(&marker).unwrap(),
+            "on_instance_stopped\n"
+        );
+
+        let hooks = executor.list();
+        let reloaded = hooks.iter().find(|h| h.event == "config_reloaded").unwrap();
+        assert!(!reloaded.enabled);
+    }
+
+    #[tokio::test]
+    async fn test_hung_hook_is_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        write_script(
+            &dir.path().join("on_service_started"),
+            &format!("echo $$ > {}; sleep 60; true", pid_file.display()),
+            0o755,
+        );
+        // Runs after the hung script, once the time for the event is spent
+        let dropin_marker = dir.path().join("dropin");
+        std::fs::create_dir(dir.path().join("on_service_started.d")).unwrap();
+        write_script(
+            &dir.path().join("on_service_started.d/10-after"),
+            &format!("touch {}", dropin_marker.display()),
+            0o755,
+        );
+
+        let executor =
+            HookExecutor::new(dir.path().to_path_buf()).with_timeout(Duration::from_millis(500));
+        let started = std::time::Instant::now();
+        executor.execute(&Event::ServiceStarted).await;
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let hooks = executor.list();
+        let hook = hooks
+            .iter()
+            .find(|h| h.hook == "on_service_started")
+            .unwrap();
+        let last_run = hook.script.last_run.as_ref().unwrap();
+        assert!(!last_run.success);
+        assert!(last_run.error.as_ref().unwrap().contains("timed out"));
+        assert!(!dropin_marker.exists());
+
+        // The script and the sleep it forked are gone (or zombies awaiting
+        // reaping) shortly after
+        let pgid: i32 = std::fs::read_to_string(&pid_file)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        let killed = || {
+            std::fs::read_dir("/proc").unwrap().flatten().all(|entry| {
+                let stat = std::fs::read_to_string(entry.path().join("stat")).unwrap_or_default();
+                // Fields after the command: state, ppid, pgrp
+                let fields: Vec<&str> = stat
+                    .rsplit_once(')')
+                    .map_or(vec![], |(_, rest)| rest.split_whitespace().collect());
+                fields.len() < 3 || fields[0] == "Z" || fields[2] != pgid.to_string()
+            })
+        };
+        for _ in 0..50 {
+            if killed() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(killed());
+    }
+
+    #[test]
+    fn test_sample_events_match_hook_table() {
+        for (event_type, hook) in HOOKS {
+            let event = Event::sample(event_type, serde_json::Map::new()).unwrap();
+            assert_eq!(HookExecutor::hook_name(&event), *hook);
+        }
+        assert!(Event::sample("nope", serde_json::Map::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fire_captures_output() {
+        let dir = tempfile::tempdir().unwrap();
+        write_script(
+            &dir.path().join("on_app_deployed"),
+            "echo \"$FRAME_APP_NAME $FRAME_HOOK_TEST\"; echo oops >&2; exit 2",
+            0o755,
+        );
+
+        let mut fields = serde_json::Map::new();
+        fields.insert("app_name".to_string(), serde_json::json!("blog"));
+        let event = Event::sample("app_deployed", fields).unwrap();
+
+        let executor = HookExecutor::new(dir.path().to_path_buf());
+        let results = executor.test_fire(&event).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].exit_code, Some(2));
+        assert_eq!(results[0].stdout, "blog 1\n");
+        assert_eq!(results[0].stderr, "oops\n");
+
+        let stopped = Event::sample("service_stopped", serde_json::Map::new()).unwrap();
+        assert!(executor.test_fire(&stopped).await.is_err());
+    }
+}
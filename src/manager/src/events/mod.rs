@@ -2,14 +2,21 @@
 //!
 //! Event emission and hook execution system.
 
+mod history;
 mod hooks;
+mod webhook;
 
+use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::broadcast;
 
-pub use hooks::HookExecutor;
+pub use history::{EventHistory, EventQuery, DEFAULT_HISTORY_SIZE};
+pub use hooks::{HookExecutor, HookInfo, HookRun, HookScript, HookTestResult, HOOKS};
+pub use webhook::WebhookSink;
 
 /// Event types
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +35,10 @@ pub enum Event {
         exit_code: Option<i32>,
         reason: String,
     },
+    InstanceRemoved {
+        username: String,
+        purged: bool,
+    },
     AppDeployed {
         username: String,
         app_name: String,
@@ -47,11 +58,100 @@ pub enum Event {
         check_name: String,
         message: String,
     },
+    AutoStartFailed {
+        username: String,
+        reason: String,
+    },
+    InstanceUnstable {
+        username: String,
+        transitions: u32,
+        window_secs: u64,
+    },
+    PortReassigned {
+        username: String,
+        old_port: u16,
+        new_port: u16,
+        reason: String,
+    },
     ConfigReloaded,
     ServiceStarted,
     ServiceStopped,
 }
 
+impl Event {
+    /// User the event is about, if any
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            Event::InstanceStarted { username, .. }
+            | Event::InstanceStopped { username }
+            | Event::InstanceCrashed { username, .. }
+            | Event::InstanceRemoved { username, .. }
+            | Event::AppDeployed { username, .. }
+            | Event::AppRemoved { username, .. }
+            | Event::ResourceLimitReached { username, .. }
+            | Event::HealthCheckFailed { username, .. }
+            | Event::AutoStartFailed { username, .. }
+            | Event::InstanceUnstable { username, .. }
+            | Event::PortReassigned { username, .. } => Some(username),
+            Event::ConfigReloaded | Event::ServiceStarted | Event::ServiceStopped => None,
+        }
+    }
+
+    /// Build a synthetic event for testing hooks
+    ///
+    /// `event_type` is the serialized `event` tag (e.g. `instance_started`).
+    /// Sample values are used for any field not given in `fields`.
+    pub fn sample(
+        event_type: &str,
+        fields: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Self> {
+        let mut sample = match event_type {
+            "instance_started" => {
+                json!({"username": "frametest", "port": 30001, "apps": ["sample"]})
+            }
+            "instance_stopped" => json!({"username": "frametest"}),
+            "instance_crashed" => {
+                json!({"username": "frametest", "exit_code": 1, "reason": "test event"})
+            }
+            "instance_removed" => json!({"username": "frametest", "purged": false}),
+            "app_deployed" | "app_removed" => {
+                json!({"username": "frametest", "app_name": "sample"})
+            }
+            "resource_limit_reached" => json!({
+                "username": "frametest",
+                "resource": "memory",
+                "current": 600,
+                "limit": 512
+            }),
+            "health_check_failed" => json!({
+                "username": "frametest",
+                "check_name": "http",
+                "message": "test event"
+            }),
+            "auto_start_failed" => json!({"username": "frametest", "reason": "test event"}),
+            "instance_unstable" => {
+                json!({"username": "frametest", "transitions": 5, "window_secs": 600})
+            }
+            "port_reassigned" => json!({
+                "username": "frametest",
+                "old_port": 30001,
+                "new_port": 30002,
+                "reason": "test event"
+            }),
+            "config_reloaded" | "service_started" | "service_stopped" => json!({}),
+            _ => anyhow::bail!("Unknown event type: {}", event_type),
+        };
+
+        if let Some(object) = sample.as_object_mut() {
+            object.extend(fields);
+            object.insert("event".to_string(), json!(event_type));
+        }
+
+        serde_json::from_value(sample)
+            .map_err(|e| anyhow::anyhow!("Invalid fields for {} event: {}", event_type, e))
+    }
+}
+
 /// Event with metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventEnvelope {
@@ -79,6 +179,10 @@ impl EventEnvelope {
 pub struct EventEmitter {
     sender: broadcast::Sender<EventEnvelope>,
     hook_executor: HookExecutor,
+    /// Most recent events, for querying after the fact
+    history: EventHistory,
+    /// Where events are POSTed, if a webhook is configured
+    webhook: Option<Arc<WebhookSink>>,
 }
 
 impl EventEmitter {
@@ -88,15 +192,53 @@ impl EventEmitter {
         Self {
             sender,
             hook_executor: HookExecutor::new(hooks_dir),
+            history: EventHistory::new(DEFAULT_HISTORY_SIZE),
+            webhook: None,
         }
     }
 
+    /// Keep up to `size` recent events (none if 0)
+    pub fn with_history_size(mut self, size: usize) -> Self {
+        self.history = EventHistory::new(size);
+        self
+    }
+
+    /// Only run hooks for the named events (all events if `names` is empty)
+    pub fn with_hook_events(mut self, names: &[String]) -> Self {
+        self.hook_executor = self.hook_executor.with_enabled_events(names);
+        self
+    }
+
+    /// Kill hook scripts still running after `timeout`
+    pub fn with_hook_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.hook_executor = self.hook_executor.with_timeout(timeout);
+        self
+    }
+
+    /// Also deliver every event to a webhook
+    pub fn with_webhook(mut self, sink: WebhookSink) -> Self {
+        self.webhook = Some(Arc::new(sink));
+        self
+    }
+
     /// Emit an event
     pub async fn emit(&self, event: Event) {
         let envelope = EventEnvelope::new(event.clone());
 
         // Send to subscribers
         let _ = self.sender.send(envelope.clone());
+        self.history.push(envelope.clone());
+
+        // Deliver to the webhook in the background so retries don't hold up the caller
+        if let Some(sink) = &self.webhook {
+            let sink = Arc::clone(sink);
+            let envelope = envelope.clone();
+            tokio::spawn(async move {
+                if let Err(e) = sink.send(&envelope).await {
+                    tracing::warn!("{:#}", e);
+                }
+            });
+        }
 
         // Execute hooks
         self.hook_executor.execute(&event).await;
@@ -104,21 +246,44 @@ impl EventEmitter {
         tracing::debug!("Event emitted: {:?}", event);
     }
 
+    /// Hook executor used for emitted events
+    pub fn hooks(&self) -> &HookExecutor {
+        &self.hook_executor
+    }
+
+    /// Recently emitted events
+    pub fn history(&self) -> &EventHistory {
+        &self.history
+    }
+
     /// Subscribe to events
     pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
         self.sender.subscribe()
     }
 
+    /// Names of every event type, as returned by [`Self::event_name`]
+    pub fn event_names() -> Vec<&'static str> {
+        HOOKS
+            .iter()
+            .filter_map(|(event_type, _)| Event::sample(event_type, serde_json::Map::new()).ok())
+            .map(|event| Self::event_name(&event))
+            .collect()
+    }
+
     /// Get event name for logging
     pub fn event_name(event: &Event) -> &'static str {
         match event {
             Event::InstanceStarted { .. } => "instance.started",
             Event::InstanceStopped { .. } => "instance.stopped",
             Event::InstanceCrashed { .. } => "instance.crashed",
+            Event::InstanceRemoved { .. } => "instance.removed",
             Event::AppDeployed { .. } => "app.deployed",
             Event::AppRemoved { .. } => "app.removed",
             Event::ResourceLimitReached { .. } => "resource.limit_reached",
             Event::HealthCheckFailed { .. } => "health_check.failed",
+            Event::AutoStartFailed { .. } => "instance.autostart_failed",
+            Event::InstanceUnstable { .. } => "instance.unstable",
+            Event::PortReassigned { .. } => "port.reassigned",
             Event::ConfigReloaded => "config.reloaded",
             Event::ServiceStarted => "service.started",
             Event::ServiceStopped => "service.stopped",
@@ -0,0 +1,325 @@
+//! Webhook Event Sink
+//!
+//! POSTs every emitted event as JSON to a configured URL, for monitoring
+//! that collects events centrally instead of through local hook scripts.
+//! With a secret configured, each body is signed so the receiver can check
+//! it came from this server.
+
+use anyhow::{Context, Result};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::client::conn::http1;
+use hyper::{Request, Uri};
+use hyper_util::rt::TokioIo;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tokio_rustls::rustls::crypto::ring as rustls_ring;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use super::{EventEmitter, EventEnvelope};
+use crate::config::EventsConfig;
+
+/// Header carrying the event name
+pub const EVENT_HEADER: &str = "x-frame-event";
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of the body>`
+pub const SIGNATURE_HEADER: &str = "x-frame-signature";
+
+/// Delay before the first retry, doubled for each further attempt
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Where and how events are delivered
+pub struct WebhookSink {
+    url: Uri,
+    secret: Option<String>,
+    timeout: Duration,
+    retries: u32,
+    /// Delay before the first retry
+    retry_delay: Duration,
+}
+
+impl WebhookSink {
+    /// Sink for a webhook URL (`http://` or `https://`)
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            url: parse_url(url)?,
+            secret: None,
+            timeout: Duration::from_secs(5),
+            retries: 0,
+            retry_delay: RETRY_DELAY,
+        })
+    }
+
+    /// Sink configured by the `[events]` section, if a URL is set
+    pub fn from_config(config: &EventsConfig) -> Result<Option<Self>> {
+        if config.webhook_url.is_empty() {
+            return Ok(None);
+        }
+        let mut sink = Self::new(&config.webhook_url)?
+            .with_timeout(Duration::from_millis(config.webhook_timeout_ms))
+            .with_retries(config.webhook_retries);
+        sink.secret = config.webhook_secret.clone();
+        Ok(Some(sink))
+    }
+
+    /// Sign bodies with `secret`
+    pub fn with_secret(mut self, secret: &str) -> Self {
+        self.secret = Some(secret.to_string());
+        self
+    }
+
+    /// Give up on an attempt after `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Retry a failed delivery up to `retries` times
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Deliver an event, retrying with backoff
+    pub async fn send(&self, envelope: &EventEnvelope) -> Result<()> {
+        let body = Bytes::from(serde_json::to_vec(envelope)?);
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+        loop {
+            let result = match timeout(self.timeout, self.post(envelope, body.clone())).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!(
+                    "timed out after {}ms",
+                    self.timeout.as_millis()
+                )),
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= self.retries => {
+                    return Err(e.context(format!("Webhook delivery to {} failed", self.url)))
+                }
+                Err(e) => {
+                    tracing::debug!("Webhook delivery to {} failed, retrying: {:#}", self.url, e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Make one delivery attempt
+    async fn post(&self, envelope: &EventEnvelope, body: Bytes) -> Result<()> {
+        let https = self.url.scheme_str() == Some("https");
+        let (host, port, host_header) = endpoint(&self.url, https);
+
+        let mut request = Request::post(
+            self.url
+                .path_and_query()
+                .map(|path| path.as_str())
+                .unwrap_or("/"),
+        )
+        .header(hyper::header::HOST, host_header)
+        .header(hyper::header::USER_AGENT, "frame-manager")
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, EventEmitter::event_name(&envelope.event));
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, &body));
+        }
+        let request = request.body(Full::new(body))?;
+
+        let stream = TcpStream::connect((host, port))
+            .await
+            .with_context(|| format!("Failed to connect to {}:{}", host, port))?;
+        let status = if https {
+            let server_name = ServerName::try_from(host.to_string())?;
+            let stream = tls_connector()?.connect(server_name, stream).await?;
+            send_request(stream, request).await?
+        } else {
+            send_request(stream, request).await?
+        };
+
+        if !status.is_success() {
+            anyhow::bail!("receiver responded with {}", status);
+        }
+        Ok(())
+    }
+}
+
+/// Send one request over a connection and return the response status
+async fn send_request<S>(stream: S, request: Request<Full<Bytes>>) -> Result<hyper::StatusCode>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = http1::handshake(TokioIo::new(stream)).await?;
+    // Drives the connection; ends when the sender is dropped
+    tokio::spawn(connection);
+    Ok(sender.send_request(request).await?.status())
+}
+
+/// TLS connector trusting the bundled web PKI roots
+fn tls_connector() -> Result<TlsConnector> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls_ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Host and port to connect to for a URL, and the `Host` header naming them
+///
+/// IPv6 literals are bracketed in URLs and `Host` headers but not when
+/// connecting; the port is only named when it isn't the scheme's default.
+fn endpoint(url: &Uri, https: bool) -> (&str, u16, String) {
+    let host = url.host().unwrap_or_default();
+    let bare = host.trim_start_matches('[').trim_end_matches(']');
+    let default_port = if https { 443 } else { 80 };
+    let port = url.port_u16().unwrap_or(default_port);
+    let name = if bare.contains(':') {
+        format!("[{}]", bare)
+    } else {
+        bare.to_string()
+    };
+    let header = if port == default_port {
+        name
+    } else {
+        format!("{}:{}", name, port)
+    };
+    (bare, port, header)
+}
+
+/// Check that a webhook URL can be delivered to
+fn parse_url(url: &str) -> Result<Uri> {
+    let uri: Uri = url
+        .parse()
+        .with_context(|| format!("Invalid webhook URL '{}'", url))?;
+    if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.host().is_none() {
+        anyhow::bail!("Webhook URL '{}' must be an http:// or https:// URL", url);
+    }
+    Ok(uri)
+}
+
+/// Signature header value for a body
+fn sign(secret: &str, body: &[u8]) -> String {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = ring::hmac::sign(&key, body);
+    let hex: String = tag
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Read one HTTP request: its head and body
+    async fn read_request(socket: &mut TcpStream) -> (String, Vec<u8>) {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let read = socket.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..read]);
+            let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+                continue;
+            };
+            let head = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .and_then(|len| len.trim().parse().ok())
+                .unwrap_or(0);
+            if buf.len() >= end + 4 + length {
+                return (head, buf[end + 4..end + 4 + length].to_vec());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhook_retries_and_signs() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in ["500 Internal Server Error", "204 No Content"] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                requests.push(read_request(&mut socket).await);
+                let response = format!("HTTP/1.1 {}\r\nconnection: close\r\n\r\n", status);
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+
+        let mut sink = WebhookSink::new(&format!("http://127.0.0.1:{}/events", port))
+            .unwrap()
+            .with_secret("s3cret")
+            .with_retries(1);
+        sink.retry_delay = Duration::from_millis(10);
+        let envelope = EventEnvelope::new(Event::InstanceStopped {
+            username: "alice".to_string(),
+        });
+        sink.send(&envelope).await.unwrap();
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let (head, body) = &requests[1];
+        assert!(head.starts_with("post /events http/1.1"));
+        assert!(head.contains(&format!("host: 127.0.0.1:{}", port)));
+        assert!(head.contains("x-frame-event: instance.stopped"));
+        assert!(head.contains(&format!("x-frame-signature: {}", sign("s3cret", body))));
+        let received: EventEnvelope = serde_json::from_slice(body).unwrap();
+        assert!(matches!(
+            received.event,
+            Event::InstanceStopped { ref username } if username == "alice"
+        ));
+
+        // Nothing listening any more: fails once retries run out
+        assert!(sink.send(&envelope).await.is_err());
+        assert!(parse_url("ftp://example.com/hook").is_err());
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let endpoint_of = |url: &str| {
+            let uri = parse_url(url).unwrap();
+            let (host, port, header) = endpoint(&uri, uri.scheme_str() == Some("https"));
+            (host.to_string(), port, header)
+        };
+        assert_eq!(
+            endpoint_of("https://hooks.example.com/frame"),
+            (
+                "hooks.example.com".to_string(),
+                443,
+                "hooks.example.com".to_string()
+            )
+        );
+        assert_eq!(
+            endpoint_of("http://hooks.example.com:8080/frame"),
+            (
+                "hooks.example.com".to_string(),
+                8080,
+                "hooks.example.com:8080".to_string()
+            )
+        );
+        assert_eq!(
+            endpoint_of("http://[::1]:8080/frame"),
+            ("::1".to_string(), 8080, "[::1]:8080".to_string())
+        );
+        assert_eq!(
+            endpoint_of("http://[::1]/frame"),
+            ("::1".to_string(), 80, "[::1]".to_string())
+        );
+    }
+}
@@ -1,22 +1,70 @@
 //! Health Check Implementations
 
 use chrono::{DateTime, Utc};
-use nix::sys::signal::{kill, Signal};
+use http_body_util::Empty;
+use hyper::body::Bytes;
+use hyper::client::conn::http1;
+use hyper::Request;
+use hyper_util::rt::TokioIo;
+use nix::sys::signal::kill;
 use nix::unistd::Pid;
 use serde::{Deserialize, Serialize};
-use std::net::TcpStream;
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::process::Stdio;
 use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use super::tls;
+
+/// Default deadline for checks that make network calls
+pub const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// PATH for custom check scripts, which don't inherit the manager's environment
+const EXEC_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Bytes of script output kept in the check message
+const MAX_EXEC_OUTPUT: usize = 1024;
 
 /// Health check definition
 pub struct HealthCheck {
     check_type: CheckType,
+    /// Overall deadline for the check, covering connect, write and read
+    timeout: Duration,
 }
 
 enum CheckType {
     Process(u32),
     Port(u16),
-    Http(u16, String),
+    Http(HttpCheck),
     Memory(u32, u64),
+    /// (port, warn_days)
+    TlsCertExpiry(u16, u32),
+    Exec(ExecCheck),
+}
+
+/// HTTP endpoint check; passes on a 2xx response (or 3xx if accepted)
+struct HttpCheck {
+    port: u16,
+    path: String,
+    /// Count redirects as healthy instead of failing on them
+    accept_redirects: bool,
+}
+
+/// Custom script check; passes when the script exits with status 0
+struct ExecCheck {
+    path: PathBuf,
+    args: Vec<String>,
+    /// How the command is named in result messages
+    label: String,
+    /// Working directory
+    dir: Option<PathBuf>,
+    env: Vec<(String, String)>,
+    /// (uid, gid) to run the script as
+    run_as: Option<(u32, u32)>,
 }
 
 /// Result of a health check
@@ -27,6 +75,9 @@ pub struct HealthCheckResult {
     pub message: String,
     pub duration_ms: u64,
     pub timestamp: DateTime<Utc>,
+    /// Days until the certificate expires (certificate expiry checks only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days_until_expiry: Option<i64>,
 }
 
 impl HealthCheck {
@@ -34,6 +85,7 @@ impl HealthCheck {
     pub fn process(pid: u32) -> Self {
         Self {
             check_type: CheckType::Process(pid),
+            timeout: DEFAULT_CHECK_TIMEOUT,
         }
     }
 
@@ -41,13 +93,19 @@ impl HealthCheck {
     pub fn port(port: u16) -> Self {
         Self {
             check_type: CheckType::Port(port),
+            timeout: DEFAULT_CHECK_TIMEOUT,
         }
     }
 
     /// Create an HTTP endpoint check
     pub fn http(port: u16, path: &str) -> Self {
         Self {
-            check_type: CheckType::Http(port, path.to_string()),
+            check_type: CheckType::Http(HttpCheck {
+                port,
+                path: path.to_string(),
+                accept_redirects: false,
+            }),
+            timeout: DEFAULT_CHECK_TIMEOUT,
         }
     }
 
@@ -55,17 +113,91 @@ impl HealthCheck {
     pub fn memory(pid: u32, limit_bytes: u64) -> Self {
         Self {
             check_type: CheckType::Memory(pid, limit_bytes),
+            timeout: DEFAULT_CHECK_TIMEOUT,
+        }
+    }
+
+    /// Create a TLS certificate expiry check
+    ///
+    /// Fails once the certificate on `port` expires within `warn_days`.
+    pub fn tls_cert_expiry(port: u16, warn_days: u32) -> Self {
+        Self {
+            check_type: CheckType::TlsCertExpiry(port, warn_days),
+            timeout: DEFAULT_CHECK_TIMEOUT,
         }
     }
 
+    /// Create a custom script check
+    ///
+    /// The script runs with a cleared environment containing only `env` and a
+    /// default PATH; its output is captured into the result message.
+    pub fn exec(path: PathBuf, env: Vec<(String, String)>) -> Self {
+        Self {
+            check_type: CheckType::Exec(ExecCheck {
+                label: path.display().to_string(),
+                dir: path.parent().map(PathBuf::from),
+                path,
+                args: Vec::new(),
+                env,
+                run_as: None,
+            }),
+            timeout: DEFAULT_CHECK_TIMEOUT,
+        }
+    }
+
+    /// Create a check running a shell command in `dir`
+    ///
+    /// Same environment and output handling as [`exec`](Self::exec).
+    pub fn shell(command: &str, dir: PathBuf, env: Vec<(String, String)>) -> Self {
+        Self {
+            check_type: CheckType::Exec(ExecCheck {
+                path: PathBuf::from("/bin/sh"),
+                args: vec!["-c".to_string(), command.to_string()],
+                label: format!("'{}'", command),
+                dir: Some(dir),
+                env,
+                run_as: None,
+            }),
+            timeout: DEFAULT_CHECK_TIMEOUT,
+        }
+    }
+
+    /// Run a script check as the given user and group
+    pub fn run_as(mut self, uid: u32, gid: u32) -> Self {
+        if let CheckType::Exec(exec) = &mut self.check_type {
+            exec.run_as = Some((uid, gid));
+        }
+        self
+    }
+
+    /// Treat 3xx responses to an HTTP check as healthy (redirects are never followed)
+    pub fn accept_redirects(mut self, accept: bool) -> Self {
+        if let CheckType::Http(http) = &mut self.check_type {
+            http.accept_redirects = accept;
+        }
+        self
+    }
+
+    /// Set the overall deadline for the check
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
     /// Execute the health check
     pub async fn execute(&self) -> HealthCheckResult {
         let start = std::time::Instant::now();
+        let mut days_until_expiry = None;
         let (name, passed, message) = match &self.check_type {
             CheckType::Process(pid) => self.check_process(*pid),
-            CheckType::Port(port) => self.check_port(*port),
-            CheckType::Http(port, path) => self.check_http(*port, path).await,
+            CheckType::Port(port) => self.bounded("port", self.check_port(*port)).await,
+            CheckType::Http(http) => self.bounded("http", self.check_http(http)).await,
             CheckType::Memory(pid, limit) => self.check_memory(*pid, *limit),
+            CheckType::TlsCertExpiry(port, warn_days) => {
+                let check = self.check_tls_cert(*port, *warn_days, &mut days_until_expiry);
+                self.bounded("tls_cert", check).await
+            }
+            CheckType::Exec(exec) => self.bounded("exec", self.check_exec(exec)).await,
         };
         let duration_ms = start.elapsed().as_millis() as u64;
 
@@ -75,6 +207,7 @@ impl HealthCheck {
             message,
             duration_ms,
             timestamp: Utc::now(),
+            days_until_expiry,
         }
     }
 
@@ -89,12 +222,25 @@ impl HealthCheck {
         ("process".to_string(), passed, message)
     }
 
-    fn check_port(&self, port: u16) -> (String, bool, String) {
-        let addr = format!("127.0.0.1:{}", port);
-        match TcpStream::connect_timeout(
-            &addr.parse().unwrap(),
-            Duration::from_secs(2),
-        ) {
+    /// Run a check under the overall deadline, failing it if the deadline passes
+    async fn bounded(
+        &self,
+        name: &str,
+        check: impl Future<Output = (String, bool, String)>,
+    ) -> (String, bool, String) {
+        match timeout(self.timeout, check).await {
+            Ok(result) => result,
+            Err(_) => (
+                name.to_string(),
+                false,
+                format!("Check timed out after {}ms", self.timeout.as_millis()),
+            ),
+        }
+    }
+
+    async fn check_port(&self, port: u16) -> (String, bool, String) {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        match TcpStream::connect(addr).await {
             Ok(_) => (
                 "port".to_string(),
                 true,
@@ -108,62 +254,141 @@ impl HealthCheck {
         }
     }
 
-    async fn check_http(&self, port: u16, path: &str) -> (String, bool, String) {
-        let url = format!("http://127.0.0.1:{}{}", port, path);
+    async fn check_http(&self, http: &HttpCheck) -> (String, bool, String) {
+        let url = format!("http://127.0.0.1:{}{}", http.port, http.path);
+        let fail = |message: String| ("http".to_string(), false, message);
 
-        // Simple HTTP check using TCP
-        let addr = format!("127.0.0.1:{}", port);
-        match TcpStream::connect_timeout(
-            &addr.parse().unwrap(),
-            Duration::from_secs(5),
-        ) {
-            Ok(mut stream) => {
-                use std::io::{Read, Write};
-
-                let request = format!(
-                    "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
-                    path
-                );
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, http.port));
+        let stream = match TcpStream::connect(addr).await {
+            Ok(stream) => stream,
+            Err(e) => return fail(format!("Failed to connect to {}: {}", url, e)),
+        };
+        let (mut sender, connection) = match http1::handshake(TokioIo::new(stream)).await {
+            Ok(handshake) => handshake,
+            Err(e) => return fail(format!("HTTP handshake with {} failed: {}", url, e)),
+        };
+        // Drives the connection; ends when the sender is dropped
+        tokio::spawn(connection);
 
-                if stream.write_all(request.as_bytes()).is_err() {
-                    return (
-                        "http".to_string(),
-                        false,
-                        format!("Failed to send HTTP request to {}", url),
-                    );
-                }
+        let request = Request::get(http.path.as_str())
+            .header(hyper::header::HOST, format!("127.0.0.1:{}", http.port))
+            .header(hyper::header::USER_AGENT, "frame-manager health check")
+            .body(Empty::<Bytes>::new());
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => return fail(format!("Invalid health check path {}: {}", http.path, e)),
+        };
 
-                let mut response = String::new();
-                if stream.read_to_string(&mut response).is_err() {
-                    return (
-                        "http".to_string(),
-                        false,
-                        format!("Failed to read HTTP response from {}", url),
-                    );
-                }
+        // Only the status matters; the body is never read
+        let status = match sender.send_request(request).await {
+            Ok(response) => response.status(),
+            Err(e) => return fail(format!("HTTP request to {} failed: {}", url, e)),
+        };
+        let passed = status.is_success() || (http.accept_redirects && status.is_redirection());
+        (
+            "http".to_string(),
+            passed,
+            format!("HTTP endpoint {} responded with {}", url, status),
+        )
+    }
 
-                // Check for 2xx status code
-                if response.starts_with("HTTP/1.1 2") || response.starts_with("HTTP/1.0 2") {
-                    (
-                        "http".to_string(),
-                        true,
-                        format!("HTTP endpoint {} responded with success", url),
-                    )
-                } else {
-                    let status_line = response.lines().next().unwrap_or("unknown");
-                    (
-                        "http".to_string(),
-                        false,
-                        format!("HTTP endpoint {} responded with: {}", url, status_line),
-                    )
-                }
+    async fn check_tls_cert(
+        &self,
+        port: u16,
+        warn_days: u32,
+        days_until_expiry: &mut Option<i64>,
+    ) -> (String, bool, String) {
+        let not_after = match tls::peer_cert_not_after(port).await {
+            Ok(not_after) => not_after,
+            Err(e) => {
+                return (
+                    "tls_cert".to_string(),
+                    false,
+                    format!("Failed to read certificate on port {}: {:#}", port, e),
+                )
             }
-            Err(e) => (
-                "http".to_string(),
+        };
+
+        let days = (not_after - Utc::now()).num_days();
+        *days_until_expiry = Some(days);
+
+        let (passed, message) = if not_after <= Utc::now() {
+            (
                 false,
-                format!("Failed to connect to {}: {}", url, e),
-            ),
+                format!("Certificate on port {} expired on {}", port, not_after),
+            )
+        } else if days < warn_days as i64 {
+            (
+                false,
+                format!(
+                    "Certificate on port {} expires in {} days ({})",
+                    port, days, not_after
+                ),
+            )
+        } else {
+            (
+                true,
+                format!(
+                    "Certificate on port {} is valid for {} more days",
+                    port, days
+                ),
+            )
+        };
+        ("tls_cert".to_string(), passed, message)
+    }
+
+    async fn check_exec(&self, exec: &ExecCheck) -> (String, bool, String) {
+        let mut cmd = Command::new(&exec.path);
+        cmd.args(&exec.args)
+            .env_clear()
+            .env("PATH", EXEC_PATH)
+            .envs(exec.env.iter().cloned())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Killed if the check times out
+            .kill_on_drop(true);
+        if let Some(dir) = &exec.dir {
+            cmd.current_dir(dir);
+        }
+        if let Some((uid, gid)) = exec.run_as {
+            cmd.uid(uid).gid(gid);
+        }
+
+        let output = match cmd.output().await {
+            Ok(output) => output,
+            Err(e) => {
+                return (
+                    "exec".to_string(),
+                    false,
+                    format!("Failed to run {}: {}", exec.label, e),
+                )
+            }
+        };
+
+        let mut captured = String::from_utf8_lossy(&output.stdout).into_owned();
+        captured.push_str(&String::from_utf8_lossy(&output.stderr));
+        let mut captured = captured.trim().to_string();
+        if captured.len() > MAX_EXEC_OUTPUT {
+            let mut end = MAX_EXEC_OUTPUT;
+            while !captured.is_char_boundary(end) {
+                end -= 1;
+            }
+            captured.truncate(end);
+            captured.push_str("...");
         }
+
+        let passed = output.status.success();
+        let status = match output.status.code() {
+            Some(code) => format!("exited with status {}", code),
+            None => "was killed by a signal".to_string(),
+        };
+        let message = if captured.is_empty() {
+            format!("{} {}", exec.label, status)
+        } else {
+            format!("{} {}: {}", exec.label, status, captured)
+        };
+        ("exec".to_string(), passed, message)
     }
 
     fn check_memory(&self, pid: u32, limit_bytes: u64) -> (String, bool, String) {
@@ -211,3 +436,123 @@ impl HealthCheck {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_http_check_times_out_on_silent_server() {
+        // Accepts the connection but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let _server = tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        let result = HealthCheck::http(port, "/health")
+            .with_timeout(Duration::from_millis(200))
+            .execute()
+            .await;
+
+        assert!(!result.passed);
+        assert!(result.message.contains("timed out"));
+    }
+
+    /// Serve one fixed response per connection and keep the connection open
+    async fn serve(response: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let _ = socket.read(&mut buf).await;
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_http_check_status_codes() {
+        let ok = serve("HTTP/1.1 204 No Content\r\n\r\n").await;
+        let result = HealthCheck::http(ok, "/health").execute().await;
+        assert!(result.passed);
+        assert_eq!(
+            result.message,
+            format!(
+                "HTTP endpoint http://127.0.0.1:{}/health responded with 204 No Content",
+                ok
+            )
+        );
+
+        let redirect =
+            serve("HTTP/1.1 302 Found\r\nLocation: /login\r\nContent-Length: 0\r\n\r\n").await;
+        assert!(
+            !HealthCheck::http(redirect, "/health")
+                .execute()
+                .await
+                .passed
+        );
+        assert!(
+            HealthCheck::http(redirect, "/health")
+                .accept_redirects(true)
+                .execute()
+                .await
+                .passed
+        );
+
+        let error = serve("HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n").await;
+        let result = HealthCheck::http(error, "/health")
+            .accept_redirects(true)
+            .execute()
+            .await;
+        assert!(!result.passed);
+        assert!(result
+            .message
+            .ends_with("responded with 503 Service Unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_exec_check() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("check.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\necho \"db at $DB_HOST\"\n[ \"$DB_HOST\" = up ]\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let env = |value: &str| vec![("DB_HOST".to_string(), value.to_string())];
+
+        let result = HealthCheck::exec(script.clone(), env("up")).execute().await;
+        assert!(result.passed);
+        assert!(result.message.ends_with("exited with status 0: db at up"));
+
+        let result = HealthCheck::exec(script, env("down")).execute().await;
+        assert!(!result.passed);
+        assert!(result.message.ends_with("exited with status 1: db at down"));
+
+        let result = HealthCheck::shell(
+            "test -f check.sh && echo $DB_HOST",
+            dir.path().into(),
+            env("ok"),
+        )
+        .execute()
+        .await;
+        assert!(result.passed);
+        assert_eq!(
+            result.message,
+            "'test -f check.sh && echo $DB_HOST' exited with status 0: ok"
+        );
+    }
+}
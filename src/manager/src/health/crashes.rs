@@ -0,0 +1,156 @@
+//! Crash Handling
+//!
+//! Reacts to an instance process exiting as soon as it is reaped, instead of
+//! waiting up to a full check interval for the health checks to notice.
+
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use super::{HealthStatus, MonitorLoop, RestartDecision};
+use crate::events::Event;
+use crate::instance::{ExitAction, InstanceStatus, ProcessExit};
+
+impl MonitorLoop {
+    /// Handle process exits for the lifetime of the manager
+    ///
+    /// Exits seen while the monitor is stopped are left to the health checks
+    /// once it starts again.
+    pub(super) async fn watch_exits(self, mut exits: UnboundedReceiver<u32>) {
+        while let Some(pid) = exits.recv().await {
+            if *self.running.read().await {
+                self.handle_crash(pid).await;
+            }
+        }
+    }
+
+    /// Apply the exit policy and restart policy to a crashed instance
+    async fn handle_crash(&self, pid: u32) {
+        // Exits of stopped instances or replaced processes aren't crashes
+        let Some(username) = self.instance_manager.running_instance_with_pid(pid).await else {
+            return;
+        };
+        let Some(exit) = self.instance_manager.handle_exit(&username).await else {
+            return;
+        };
+        self.handle_exited(&username, exit).await;
+    }
+
+    /// Report an exit `handle_exit` applied and restart the instance if the
+    /// exit and restart policies allow
+    pub(super) async fn handle_exited(&self, username: &str, exit: ProcessExit) {
+        self.metrics.write().await.record_crash(username);
+
+        self.events
+            .emit(Event::InstanceCrashed {
+                username: username.to_string(),
+                exit_code: exit.code,
+                reason: exit.reason.clone(),
+            })
+            .await;
+        if exit.action != ExitAction::Restart {
+            return;
+        }
+
+        match self.record_crash(username).await {
+            RestartDecision::None => {}
+            RestartDecision::Restart => self.restart_crashed(username).await,
+            RestartDecision::Backoff(at) => {
+                tracing::warn!(
+                    "Instance for {} crashed, restart backed off until {}",
+                    username,
+                    at
+                );
+                self.instance_manager
+                    .set_status_detail(username, Some(restarting_at(&exit.reason, at)))
+                    .await;
+                tokio::spawn(self.clone().restart_after_backoff(
+                    username.to_string(),
+                    exit.reason,
+                    at,
+                ));
+            }
+            RestartDecision::GiveUp => self.give_up(username, &exit.reason).await,
+        }
+    }
+
+    /// Restart a crashed instance once its backoff has passed
+    ///
+    /// The instance waits as failed with no PID; nothing happens if it was
+    /// started, stopped or removed in the meantime.
+    async fn restart_after_backoff(self, username: String, reason: String, mut at: DateTime<Utc>) {
+        loop {
+            tokio::time::sleep((at - Utc::now()).to_std().unwrap_or_default()).await;
+            if !*self.running.read().await {
+                return;
+            }
+            match self.instance_manager.status(&username).await {
+                Ok(instance)
+                    if instance.status == InstanceStatus::Failed && instance.pid.is_none() => {}
+                _ => return,
+            }
+
+            match self.record_crash(&username).await {
+                RestartDecision::None => return,
+                RestartDecision::Restart => return self.restart_crashed(&username).await,
+                RestartDecision::Backoff(next) => {
+                    at = next;
+                    self.instance_manager
+                        .set_status_detail(&username, Some(restarting_at(&reason, at)))
+                        .await;
+                }
+                RestartDecision::GiveUp => return self.give_up(&username, &reason).await,
+            }
+        }
+    }
+
+    /// Count a crash against the instance's restart budget
+    async fn record_crash(&self, username: &str) -> RestartDecision {
+        let mut cache = self.status_cache.write().await;
+        let now = Utc::now();
+        cache
+            .entry(username.to_string())
+            .or_insert_with(|| HealthStatus::new(username, now))
+            .record_crash(now, &self.settings.restart)
+    }
+
+    /// Restart a crashed instance on its current port
+    async fn restart_crashed(&self, username: &str) {
+        tracing::warn!("Instance for {} crashed, restarting", username);
+        let port = match self.instance_manager.status(username).await {
+            Ok(instance) => instance.port,
+            Err(e) => {
+                tracing::error!("Failed to restart instance for {}: {}", username, e);
+                return;
+            }
+        };
+        match self.instance_manager.restart(username, port).await {
+            Ok(()) => self.metrics.write().await.record_restart(username, "crash"),
+            Err(e) => {
+                tracing::error!("Failed to restart instance for {}: {}", username, e)
+            }
+        }
+    }
+
+    /// Stop auto-restarting an instance and say why in its status detail
+    async fn give_up(&self, username: &str, exit_reason: &str) {
+        let reason = format!(
+            "{} after {} restarts in {}s, auto-restart stopped",
+            exit_reason,
+            self.settings.restart.max_attempts,
+            self.settings.restart.window.num_seconds()
+        );
+        tracing::error!("Instance for {} {}", username, reason);
+        self.instance_manager
+            .set_status_detail(username, Some(reason))
+            .await;
+    }
+}
+
+/// Status detail of a crashed instance waiting out its restart backoff
+fn restarting_at(exit_reason: &str, at: DateTime<Utc>) -> String {
+    format!(
+        "{}, restarting at {}",
+        exit_reason,
+        at.format("%Y-%m-%d %H:%M:%S UTC")
+    )
+}
@@ -0,0 +1,153 @@
+//! Disk Quota Checks
+//!
+//! Periodically measures each instance's apps and data against its disk
+//! quota. An instance going over quota raises `ResourceLimitReached` once and
+//! is flagged in its status detail until it's back under; nothing is deleted.
+
+use std::collections::HashSet;
+use tokio::time::{interval, Duration};
+
+use super::MonitorLoop;
+use crate::events::{Event, EventEmitter};
+use crate::instance::{Instance, InstanceManager};
+
+/// Status detail of an instance using more than its disk quota
+const OVER_QUOTA_DETAIL: &str = "over disk quota";
+
+impl MonitorLoop {
+    /// Check disk quotas every `every` until the monitor is stopped
+    pub(super) async fn watch_disk(self, every: Duration) {
+        let mut ticker = interval(every);
+        let mut over_quota = HashSet::new();
+
+        loop {
+            ticker.tick().await;
+            if !*self.running.read().await {
+                break;
+            }
+
+            for instance in self.instance_manager.list().await {
+                if self.instance_manager.is_synthetic(&instance.username) {
+                    continue;
+                }
+                check_disk_quota(
+                    &self.instance_manager,
+                    &self.events,
+                    &mut over_quota,
+                    &instance,
+                )
+                .await;
+            }
+        }
+    }
+}
+
+/// Compare an instance's disk usage with its quota
+///
+/// `over_quota` holds the users already reported, so the event fires once per
+/// crossing rather than on every check. Returns whether the instance is over.
+async fn check_disk_quota(
+    instance_manager: &InstanceManager,
+    events: &EventEmitter,
+    over_quota: &mut HashSet<String>,
+    instance: &Instance,
+) -> bool {
+    let username = &instance.username;
+    let limit = instance.limits.disk_quota_bytes();
+    if limit == 0 {
+        return false;
+    }
+
+    let current = instance_manager.disk_usage(username).await;
+    if current <= limit {
+        if over_quota.remove(username)
+            && instance
+                .status_detail
+                .as_deref()
+                .is_some_and(|detail| detail.starts_with(OVER_QUOTA_DETAIL))
+        {
+            instance_manager.set_status_detail(username, None).await;
+        }
+        return false;
+    }
+
+    if over_quota.insert(username.clone()) {
+        tracing::warn!(
+            "Instance for {} uses {} MB of its {} MB disk quota",
+            username,
+            current / 1024 / 1024,
+            instance.limits.disk_quota_mb
+        );
+        if instance.status_detail.is_none() {
+            instance_manager
+                .set_status_detail(
+                    username,
+                    Some(format!(
+                        "{} ({} of {} MB)",
+                        OVER_QUOTA_DETAIL,
+                        current / 1024 / 1024,
+                        instance.limits.disk_quota_mb
+                    )),
+                )
+                .await;
+        }
+        events
+            .emit(Event::ResourceLimitReached {
+                username: username.clone(),
+                resource: "disk".to_string(),
+                current,
+                limit,
+            })
+            .await;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::{test_manager, ResourceLimits};
+
+    #[tokio::test]
+    async fn test_disk_quota_exceeded() {
+        let (dir, manager) = test_manager(ResourceLimits {
+            disk_quota_mb: 1,
+            ..ResourceLimits::default()
+        })
+        .await;
+        let data_dir = manager.instance_dir("alice").join("data");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("dump.sql"), vec![0u8; 2 * 1024 * 1024]).unwrap();
+
+        let events = EventEmitter::new(dir.path().join("hooks"));
+        let mut received = events.subscribe();
+        let mut over_quota = HashSet::new();
+
+        for _ in 0..2 {
+            let instance = manager.status("alice").await.unwrap();
+            assert!(check_disk_quota(&manager, &events, &mut over_quota, &instance).await);
+        }
+
+        let envelope = received.try_recv().unwrap();
+        assert!(matches!(
+            envelope.event,
+            Event::ResourceLimitReached { ref username, ref resource, current, limit }
+                if username == "alice" && resource == "disk"
+                    && current >= 2 * 1024 * 1024 && limit == 1024 * 1024
+        ));
+        assert!(received.try_recv().is_err());
+        let detail = manager.status("alice").await.unwrap().status_detail;
+        assert!(detail.unwrap().starts_with(OVER_QUOTA_DETAIL));
+
+        // Back under quota clears the flag
+        std::fs::remove_file(data_dir.join("dump.sql")).unwrap();
+        let instance = manager.status("alice").await.unwrap();
+        assert!(!check_disk_quota(&manager, &events, &mut over_quota, &instance).await);
+        assert!(manager
+            .status("alice")
+            .await
+            .unwrap()
+            .status_detail
+            .is_none());
+    }
+}
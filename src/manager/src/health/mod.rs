@@ -3,25 +3,64 @@
 //! Performs periodic health checks on Frame instances.
 
 mod checks;
+mod crashes;
+mod disk;
+mod restarts;
+mod tls;
 
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use futures::FutureExt;
 use serde::{Deserialize, Serialize};
+use std::any::Any;
 use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tokio::time::{interval, Duration};
 
 pub use checks::{HealthCheck, HealthCheckResult};
-
-use crate::instance::InstanceManager;
+pub use restarts::{RestartDecision, RestartPolicy};
+
+use crate::events::{Event, EventEmitter};
+use crate::instance::{Instance, InstanceManager, InstanceStatus, ProcessExit};
+use crate::metrics::MetricsCollector;
+
+/// Delay before the supervisor restarts a monitor loop that died
+const MONITOR_RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Settings applied to every check of an instance
+#[derive(Debug, Clone, Copy)]
+struct CheckSettings {
+    /// Deadline applied to each network check
+    timeout: Duration,
+    /// Days before certificate expiry at which the TLS check fails
+    tls_warn_days: u32,
+    /// When failing instances are restarted
+    restart: RestartPolicy,
+    /// Whether a 3xx from the HTTP endpoint counts as healthy
+    http_accept_redirects: bool,
+}
 
 /// Health monitor service
 pub struct HealthMonitor {
     /// Check interval in seconds
     interval_secs: u64,
+    /// Maximum number of instances checked concurrently
+    concurrency: usize,
+    /// Settings applied to each check
+    settings: CheckSettings,
+    /// Interval of the disk quota checks, if enabled
+    disk_check_interval: Option<Duration>,
     /// Instance manager reference
     instance_manager: Arc<InstanceManager>,
+    /// Receives `HealthCheckFailed` when an instance turns unhealthy,
+    /// `InstanceCrashed` for crashes and when the monitor gives up on one, and
+    /// `ResourceLimitReached` when an instance goes over its disk quota
+    events: Arc<EventEmitter>,
+    /// Counts the restarts and crashes the monitor handles
+    metrics: Arc<RwLock<MetricsCollector>>,
     /// Health status cache
     status_cache: Arc<RwLock<HashMap<String, HealthStatus>>>,
     /// Running flag
@@ -36,19 +75,172 @@ pub struct HealthStatus {
     pub checks: Vec<HealthCheckResult>,
     pub last_check: DateTime<Utc>,
     pub consecutive_failures: u32,
+    /// Auto-restarts within the restart window, oldest first
+    #[serde(default)]
+    pub recent_restarts: Vec<DateTime<Utc>>,
+    /// Auto-restart gave up; no more restarts until a check passes
+    #[serde(default)]
+    pub restarts_exhausted: bool,
+    /// Auto-restarts in a row without a healthy window in between
+    #[serde(default)]
+    pub consecutive_restarts: u32,
+    /// Restarts before this time are backed off
+    #[serde(default)]
+    pub next_restart_allowed_at: Option<DateTime<Utc>>,
+}
+
+impl HealthStatus {
+    /// Status of an instance that hasn't been checked yet
+    pub fn new(username: &str, now: DateTime<Utc>) -> Self {
+        Self {
+            username: username.to_string(),
+            healthy: true,
+            checks: Vec::new(),
+            last_check: now,
+            consecutive_failures: 0,
+            recent_restarts: Vec::new(),
+            restarts_exhausted: false,
+            consecutive_restarts: 0,
+            next_restart_allowed_at: None,
+        }
+    }
+}
+
+/// State shared by the periodic check loop, cloned for each (re)start
+#[derive(Clone)]
+struct MonitorLoop {
+    interval_secs: u64,
+    concurrency: usize,
+    settings: CheckSettings,
+    instance_manager: Arc<InstanceManager>,
+    events: Arc<EventEmitter>,
+    metrics: Arc<RwLock<MetricsCollector>>,
+    status_cache: Arc<RwLock<HashMap<String, HealthStatus>>>,
+    running: Arc<RwLock<bool>>,
+}
+
+impl MonitorLoop {
+    /// Run checks every interval until the monitor is stopped
+    async fn run(self) {
+        let mut ticker = interval(Duration::from_secs(self.interval_secs));
+
+        loop {
+            ticker.tick().await;
+
+            let is_running = *self.running.read().await;
+            if !is_running {
+                break;
+            }
+
+            // Get all running instances; frozen ones are paused on purpose
+            let instances: Vec<Instance> = self
+                .instance_manager
+                .list()
+                .await
+                .into_iter()
+                .filter(|i| i.status == InstanceStatus::Running)
+                .filter(|i| !self.instance_manager.is_synthetic(&i.username))
+                .collect();
+
+            // Check instances concurrently so one slow instance can't starve the rest.
+            // A panic while checking one instance is logged and doesn't abort the round.
+            stream::iter(instances)
+                .map(|instance| {
+                    let username = instance.username.clone();
+                    AssertUnwindSafe(self.check(instance))
+                        .catch_unwind()
+                        .map(move |result| {
+                            if let Err(panic) = result {
+                                tracing::error!(
+                                    "Health check for {} panicked: {}",
+                                    username,
+                                    panic_message(panic.as_ref())
+                                );
+                            }
+                        })
+                })
+                .buffer_unordered(self.concurrency)
+                .collect::<Vec<()>>()
+                .await;
+        }
+    }
+
+    /// Check one instance, handling an exited process as a crash
+    async fn check(&self, instance: Instance) {
+        let username = instance.username.clone();
+        let exit = HealthMonitor::check_instance(
+            &self.instance_manager,
+            &self.events,
+            &self.metrics,
+            &self.status_cache,
+            self.settings,
+            instance,
+        )
+        .await;
+        if let Some(exit) = exit {
+            self.handle_exited(&username, exit).await;
+        }
+    }
+}
+
+/// Extract a readable message from a panic payload
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
 }
 
 impl HealthMonitor {
     /// Create a new health monitor
-    pub fn new(interval_secs: u64, instance_manager: Arc<InstanceManager>) -> Self {
+    pub fn new(
+        interval_secs: u64,
+        concurrency: usize,
+        check_timeout: Duration,
+        tls_warn_days: u32,
+        restart: RestartPolicy,
+        instance_manager: Arc<InstanceManager>,
+        events: Arc<EventEmitter>,
+    ) -> Self {
         Self {
             interval_secs,
+            concurrency: concurrency.max(1),
+            settings: CheckSettings {
+                timeout: check_timeout,
+                tls_warn_days,
+                restart,
+                http_accept_redirects: false,
+            },
+            disk_check_interval: None,
             instance_manager,
+            events,
+            metrics: Arc::new(RwLock::new(MetricsCollector::default())),
             status_cache: Arc::new(RwLock::new(HashMap::new())),
             running: Arc::new(RwLock::new(false)),
         }
     }
 
+    /// Count 3xx responses to the HTTP check as healthy
+    pub fn with_http_accept_redirects(mut self, accept: bool) -> Self {
+        self.settings.http_accept_redirects = accept;
+        self
+    }
+
+    /// Check instance disk usage against quotas every `secs` seconds (0 disables)
+    pub fn with_disk_check_interval(mut self, secs: u64) -> Self {
+        self.disk_check_interval = (secs > 0).then(|| Duration::from_secs(secs));
+        self
+    }
+
+    /// Count restarts and crashes in `metrics` instead of a private collector
+    pub fn with_metrics(mut self, metrics: Arc<RwLock<MetricsCollector>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
     /// Start the health monitor
     pub async fn start(&self) {
         let mut running = self.running.write().await;
@@ -58,91 +250,237 @@ impl HealthMonitor {
         *running = true;
         drop(running);
 
-        let interval_secs = self.interval_secs;
-        let instance_manager = Arc::clone(&self.instance_manager);
-        let status_cache = Arc::clone(&self.status_cache);
-        let running = Arc::clone(&self.running);
+        let monitor = MonitorLoop {
+            interval_secs: self.interval_secs,
+            concurrency: self.concurrency,
+            settings: self.settings,
+            instance_manager: Arc::clone(&self.instance_manager),
+            events: Arc::clone(&self.events),
+            metrics: Arc::clone(&self.metrics),
+            status_cache: Arc::clone(&self.status_cache),
+            running: Arc::clone(&self.running),
+        };
 
-        tokio::spawn(async move {
-            let mut ticker = interval(Duration::from_secs(interval_secs));
+        // Crashes are handled as soon as the process is reaped; the check
+        // loop catches the rest (e.g. readopted processes, hung servers)
+        if let Some(exits) = self.instance_manager.take_exit_notifications() {
+            tokio::spawn(monitor.clone().watch_exits(exits));
+        }
+        if let Some(every) = self.disk_check_interval {
+            tokio::spawn(monitor.clone().watch_disk(every));
+        }
 
+        // Supervise the monitor loop so a panic or unexpected exit doesn't
+        // silently stop health checking for the whole fleet
+        tokio::spawn(async move {
             loop {
-                ticker.tick().await;
+                let result = tokio::spawn(monitor.clone().run()).await;
 
-                let is_running = *running.read().await;
-                if !is_running {
+                if !*monitor.running.read().await {
                     break;
                 }
 
-                // Get all instances
-                let instances = instance_manager.list().await;
+                match result {
+                    Err(e) if e.is_panic() => tracing::error!(
+                        "Health monitor panicked, restarting in {}s: {}",
+                        MONITOR_RESTART_DELAY.as_secs(),
+                        panic_message(e.into_panic().as_ref())
+                    ),
+                    _ => tracing::error!(
+                        "Health monitor exited unexpectedly, restarting in {}s",
+                        MONITOR_RESTART_DELAY.as_secs()
+                    ),
+                }
 
-                for instance in instances {
-                    if instance.status != crate::instance::InstanceStatus::Running {
-                        continue;
-                    }
+                tokio::time::sleep(MONITOR_RESTART_DELAY).await;
+            }
+        });
 
-                    let username = instance.username.clone();
-                    let mut checks = Vec::new();
-                    let mut all_passed = true;
-
-                    // Process check
-                    if let Some(pid) = instance.pid {
-                        let process_check = HealthCheck::process(pid);
-                        let result = process_check.execute().await;
-                        all_passed = all_passed && result.passed;
-                        checks.push(result);
-                    }
+        tracing::info!(
+            "Health monitor started (interval: {}s, concurrency: {}, check timeout: {}ms)",
+            self.interval_secs,
+            self.concurrency,
+            self.settings.timeout.as_millis()
+        );
+    }
 
-                    // Port check
-                    let port_check = HealthCheck::port(instance.port);
-                    let result = port_check.execute().await;
-                    all_passed = all_passed && result.passed;
-                    checks.push(result);
-
-                    // HTTP check
-                    let http_check = HealthCheck::http(instance.port, "/health");
-                    let result = http_check.execute().await;
-                    all_passed = all_passed && result.passed;
-                    checks.push(result);
-
-                    // Update status cache
-                    let mut cache = status_cache.write().await;
-                    let status = cache.entry(username.clone()).or_insert(HealthStatus {
-                        username: username.clone(),
-                        healthy: true,
-                        checks: Vec::new(),
-                        last_check: Utc::now(),
-                        consecutive_failures: 0,
-                    });
-
-                    status.healthy = all_passed;
-                    status.checks = checks;
-                    status.last_check = Utc::now();
-
-                    if all_passed {
-                        status.consecutive_failures = 0;
-                    } else {
-                        status.consecutive_failures += 1;
-
-                        // Auto-restart after 3 consecutive failures
-                        if status.consecutive_failures >= 3 {
-                            tracing::warn!(
-                                "Instance for {} has failed {} consecutive health checks, restarting",
-                                username,
-                                status.consecutive_failures
-                            );
-                            if let Err(e) = instance_manager.restart(&username, instance.port).await {
-                                tracing::error!("Failed to restart instance for {}: {}", username, e);
-                            }
-                            status.consecutive_failures = 0;
-                        }
+    /// Run the standard checks against an instance
+    ///
+    /// The certificate check is advisory: restarting an instance can't renew
+    /// its certificate, so a failing one is reported but doesn't make the
+    /// instance unhealthy.
+    async fn run_checks(
+        instance_manager: &InstanceManager,
+        instance: &Instance,
+        settings: CheckSettings,
+    ) -> (Vec<HealthCheckResult>, bool) {
+        let check_timeout = settings.timeout;
+        let mut checks = Vec::new();
+        let mut all_passed = true;
+
+        // Process check
+        if let Some(pid) = instance.pid {
+            let process_check = HealthCheck::process(pid);
+            let result = process_check.execute().await;
+            all_passed = all_passed && result.passed;
+            checks.push(result);
+        }
+
+        // Port check
+        let port_check = HealthCheck::port(instance.port).with_timeout(check_timeout);
+        let result = port_check.execute().await;
+        all_passed = all_passed && result.passed;
+        checks.push(result);
+
+        // HTTP check
+        let http_check = HealthCheck::http(instance.port, &instance.health_path)
+            .accept_redirects(settings.http_accept_redirects)
+            .with_timeout(check_timeout);
+        let result = http_check.execute().await;
+        all_passed = all_passed && result.passed;
+        checks.push(result);
+
+        // Custom script check, run as the instance user
+        if let Some(path) = &instance.custom_health_check {
+            let result = match nix::unistd::User::from_name(&instance.username) {
+                Ok(Some(user)) => {
+                    HealthCheck::exec(path.clone(), instance_manager.health_check_env(instance))
+                        .run_as(user.uid.as_raw(), user.gid.as_raw())
+                        .with_timeout(check_timeout)
+                        .execute()
+                        .await
+                }
+                _ => HealthCheckResult {
+                    check_name: "exec".to_string(),
+                    passed: false,
+                    message: format!(
+                        "Cannot run custom check: unknown user {}",
+                        instance.username
+                    ),
+                    duration_ms: 0,
+                    timestamp: Utc::now(),
+                    days_until_expiry: None,
+                },
+            };
+            all_passed = all_passed && result.passed;
+            checks.push(result);
+        }
+
+        // TLS certificate expiry check
+        if let Some(tls_port) = instance.tls_port {
+            let tls_check = HealthCheck::tls_cert_expiry(tls_port, settings.tls_warn_days)
+                .with_timeout(check_timeout);
+            let result = tls_check.execute().await;
+            if !result.passed {
+                tracing::warn!("{}: {}", instance.username, result.message);
+            }
+            checks.push(result);
+        }
+
+        (checks, all_passed)
+    }
+
+    /// Check a single instance and record the result in the status cache
+    ///
+    /// Returns the exit of a process found to have exited, which is handled
+    /// like a crash rather than counted as a failed check.
+    async fn check_instance(
+        instance_manager: &InstanceManager,
+        events: &EventEmitter,
+        metrics: &RwLock<MetricsCollector>,
+        status_cache: &RwLock<HashMap<String, HealthStatus>>,
+        settings: CheckSettings,
+        instance: Instance,
+    ) -> Option<ProcessExit> {
+        let username = instance.username.clone();
+        let (checks, all_passed) = Self::run_checks(instance_manager, &instance, settings).await;
+
+        // An exited process takes the instance out of health checking; the
+        // crash handling decides whether it's restarted
+        let exit = if all_passed {
+            None
+        } else {
+            instance_manager.handle_exit(&username).await
+        };
+
+        // Failures are reported once when the instance turns unhealthy,
+        // not again on every failing tick after that
+        let (decision, newly_failed) = {
+            let mut cache = status_cache.write().await;
+            let now = Utc::now();
+            let status = cache
+                .entry(username.clone())
+                .or_insert_with(|| HealthStatus::new(&username, now));
+
+            let newly_failed: Vec<HealthCheckResult> = if status.healthy && !all_passed {
+                checks.iter().filter(|c| !c.passed).cloned().collect()
+            } else {
+                Vec::new()
+            };
+            status.healthy = all_passed;
+            status.checks = checks;
+            status.last_check = now;
+            // Crashes count against the restart budget on their own
+            let decision = if exit.is_none() {
+                status.record_check(all_passed, now, &settings.restart)
+            } else {
+                RestartDecision::None
+            };
+            (decision, newly_failed)
+        };
+        for check in newly_failed {
+            events
+                .emit(Event::HealthCheckFailed {
+                    username: username.clone(),
+                    check_name: check.check_name,
+                    message: check.message,
+                })
+                .await;
+        }
+
+        match decision {
+            RestartDecision::None => {}
+            RestartDecision::Backoff(at) => {
+                tracing::debug!(
+                    "Instance for {} is unhealthy, restart backed off until {}",
+                    username,
+                    at
+                );
+            }
+            RestartDecision::Restart => {
+                tracing::warn!(
+                    "Instance for {} has failed {} consecutive health checks, restarting",
+                    username,
+                    settings.restart.unhealthy_threshold
+                );
+                match instance_manager.restart(&username, instance.port).await {
+                    Ok(()) => metrics.write().await.record_restart(&username, "health"),
+                    Err(e) => {
+                        tracing::error!("Failed to restart instance for {}: {}", username, e)
                     }
                 }
             }
-        });
+            RestartDecision::GiveUp => {
+                let reason = format!(
+                    "still unhealthy after {} restarts in {}s, auto-restart stopped",
+                    settings.restart.max_attempts,
+                    settings.restart.window.num_seconds()
+                );
+                tracing::error!("Instance for {} {}", username, reason);
+                instance_manager
+                    .set_status_detail(&username, Some(reason.clone()))
+                    .await;
+                events
+                    .emit(Event::InstanceCrashed {
+                        username,
+                        exit_code: None,
+                        reason,
+                    })
+                    .await;
+            }
+        }
 
-        tracing::info!("Health monitor started (interval: {}s)", self.interval_secs);
+        exit
     }
 
     /// Stop the health monitor
@@ -173,38 +511,120 @@ impl HealthMonitor {
     /// Run a manual health check
     pub async fn check_now(&self, username: &str) -> Result<HealthStatus> {
         let instance = self.instance_manager.status(username).await?;
-        let mut checks = Vec::new();
-        let mut all_passed = true;
+        let (checks, all_passed) =
+            Self::run_checks(&self.instance_manager, &instance, self.settings).await;
 
-        if let Some(pid) = instance.pid {
-            let process_check = HealthCheck::process(pid);
-            let result = process_check.execute().await;
-            all_passed = all_passed && result.passed;
-            checks.push(result);
-        }
+        // Update cache, keeping the auto-restart history
+        let mut cache = self.status_cache.write().await;
+        let now = Utc::now();
+        let status = cache
+            .entry(username.to_string())
+            .or_insert_with(|| HealthStatus::new(username, now));
+        status.healthy = all_passed;
+        status.checks = checks;
+        status.last_check = now;
+        status.consecutive_failures = 0;
+
+        Ok(status.clone())
+    }
+}
 
-        let port_check = HealthCheck::port(instance.port);
-        let result = port_check.execute().await;
-        all_passed = all_passed && result.passed;
-        checks.push(result);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::{test_manager, ResourceLimits};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_http_check_uses_instance_health_path() {
+        // Only /healthz is healthy
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let response = if buf[..n].starts_with(b"GET /healthz ") {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+                } else {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
 
-        let http_check = HealthCheck::http(instance.port, "/health");
-        let result = http_check.execute().await;
-        all_passed = all_passed && result.passed;
-        checks.push(result);
+        let (_dir, manager) = test_manager(ResourceLimits::default()).await;
+        let mut instance = manager.status("alice").await.unwrap();
+        instance.port = port;
+        let settings = CheckSettings {
+            timeout: Duration::from_secs(5),
+            tls_warn_days: 14,
+            restart: RestartPolicy::default(),
+            http_accept_redirects: false,
+        };
 
-        let status = HealthStatus {
-            username: username.to_string(),
-            healthy: all_passed,
-            checks,
-            last_check: Utc::now(),
-            consecutive_failures: 0,
+        let (_, passed) = HealthMonitor::run_checks(&manager, &instance, settings).await;
+        assert!(!passed);
+
+        instance.health_path = "/healthz".to_string();
+        let (checks, passed) = HealthMonitor::run_checks(&manager, &instance, settings).await;
+        assert!(passed);
+        assert!(checks
+            .iter()
+            .any(|check| check.message.contains("/healthz responded with 200")));
+    }
+
+    #[tokio::test]
+    async fn test_health_check_failed_emitted_once() {
+        // Accepts connections but reports every request as failing
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let _ = socket
+                    .write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n")
+                    .await;
+            }
+        });
+
+        let (dir, manager) = test_manager(ResourceLimits::default()).await;
+        let mut instance = manager.status("alice").await.unwrap();
+        instance.port = port;
+
+        let events = EventEmitter::new(dir.path().join("hooks"));
+        let mut received = events.subscribe();
+        let status_cache = RwLock::new(HashMap::new());
+        let settings = CheckSettings {
+            timeout: Duration::from_secs(5),
+            tls_warn_days: 14,
+            restart: RestartPolicy {
+                unhealthy_threshold: 100,
+                ..RestartPolicy::default()
+            },
+            http_accept_redirects: false,
         };
 
-        // Update cache
-        let mut cache = self.status_cache.write().await;
-        cache.insert(username.to_string(), status.clone());
+        for _ in 0..3 {
+            HealthMonitor::check_instance(
+                &manager,
+                &events,
+                &RwLock::new(MetricsCollector::default()),
+                &status_cache,
+                settings,
+                instance.clone(),
+            )
+            .await;
+        }
 
-        Ok(status)
+        let envelope = received.try_recv().unwrap();
+        assert!(matches!(
+            envelope.event,
+            Event::HealthCheckFailed { ref username, ref check_name, .. }
+                if username == "alice" && check_name == "http"
+        ));
+        assert!(received.try_recv().is_err());
     }
 }
@@ -0,0 +1,229 @@
+//! Auto-Restart Policy
+//!
+//! Decides when an instance failing its health checks is restarted, and when
+//! the monitor gives up on one that keeps failing after restarts instead of
+//! restarting it forever. Restarts of an instance that keeps failing back off
+//! exponentially so a crash loop doesn't hammer the server.
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::HealthStatus;
+
+/// When unhealthy instances are restarted
+#[derive(Debug, Clone, Copy)]
+pub struct RestartPolicy {
+    /// Consecutive failed checks that trigger a restart
+    pub unhealthy_threshold: u32,
+    /// Auto-restarts within the window before giving up (0 means no limit)
+    pub max_attempts: u32,
+    /// Sliding window the restarts are counted over
+    pub window: Duration,
+    /// Wait before the second restart in a row, doubled for each further one
+    /// (zero restarts straight away every time)
+    pub backoff_base: Duration,
+    /// Longest wait between restarts
+    pub backoff_cap: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            unhealthy_threshold: 3,
+            max_attempts: 5,
+            window: Duration::minutes(10),
+            backoff_base: Duration::seconds(10),
+            backoff_cap: Duration::minutes(5),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Wait after the `restarts`-th restart in a row before the next one
+    pub fn backoff(&self, restarts: u32) -> Duration {
+        if restarts == 0 || self.backoff_base <= Duration::zero() {
+            return Duration::zero();
+        }
+        // Past 2^20 the cap has long been reached
+        let factor = 1i32 << (restarts - 1).min(20);
+        (self.backoff_base * factor).min(self.backoff_cap)
+    }
+}
+
+/// What the monitor should do after a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartDecision {
+    /// Nothing (healthy, below the threshold, or already given up)
+    None,
+    /// Restart the instance
+    Restart,
+    /// Restart once this time has passed; restarts are backing off
+    Backoff(DateTime<Utc>),
+    /// Stop restarting the instance and report it as crashed
+    GiveUp,
+}
+
+impl HealthStatus {
+    /// Record the outcome of a round of checks and decide on a restart
+    pub fn record_check(
+        &mut self,
+        passed: bool,
+        now: DateTime<Utc>,
+        policy: &RestartPolicy,
+    ) -> RestartDecision {
+        if passed {
+            self.consecutive_failures = 0;
+            self.restarts_exhausted = false;
+            // Healthy for a whole window since the last restart ends the backoff
+            if self
+                .recent_restarts
+                .last()
+                .is_none_or(|restart| now - *restart > policy.window)
+            {
+                self.consecutive_restarts = 0;
+                self.next_restart_allowed_at = None;
+            }
+            return RestartDecision::None;
+        }
+
+        self.consecutive_failures += 1;
+        if self.consecutive_failures < policy.unhealthy_threshold.max(1) {
+            return RestartDecision::None;
+        }
+        let decision = self.decide_restart(now, policy);
+        // While backing off the failures stay counted, so the restart happens
+        // on the first failed check after the wait
+        if !matches!(decision, RestartDecision::Backoff(_)) {
+            self.consecutive_failures = 0;
+        }
+        decision
+    }
+
+    /// Decide on a restart after the instance's process crashed
+    ///
+    /// A crash needs no confirmation by repeated checks, but counts against
+    /// the same restart budget.
+    pub fn record_crash(&mut self, now: DateTime<Utc>, policy: &RestartPolicy) -> RestartDecision {
+        self.healthy = false;
+        self.consecutive_failures = 0;
+        self.decide_restart(now, policy)
+    }
+
+    fn decide_restart(&mut self, now: DateTime<Utc>, policy: &RestartPolicy) -> RestartDecision {
+        if self.restarts_exhausted {
+            return RestartDecision::None;
+        }
+
+        self.recent_restarts
+            .retain(|restart| now - *restart <= policy.window);
+        if policy.max_attempts > 0 && self.recent_restarts.len() >= policy.max_attempts as usize {
+            self.restarts_exhausted = true;
+            return RestartDecision::GiveUp;
+        }
+        if let Some(allowed_at) = self.next_restart_allowed_at.filter(|at| now < *at) {
+            return RestartDecision::Backoff(allowed_at);
+        }
+
+        self.recent_restarts.push(now);
+        self.consecutive_restarts += 1;
+        self.next_restart_allowed_at = Some(now + policy.backoff(self.consecutive_restarts));
+        RestartDecision::Restart
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_at_threshold() {
+        let policy = RestartPolicy {
+            unhealthy_threshold: 4,
+            max_attempts: 2,
+            window: Duration::minutes(10),
+            backoff_base: Duration::zero(),
+            backoff_cap: Duration::zero(),
+        };
+        let now = Utc::now();
+        let mut status = HealthStatus::new("alice", now);
+
+        let decisions: Vec<RestartDecision> = (0..12)
+            .map(|tick| status.record_check(false, now + Duration::seconds(tick), &policy))
+            .collect();
+        let restarts: Vec<usize> = decisions
+            .iter()
+            .enumerate()
+            .filter(|(_, decision)| **decision == RestartDecision::Restart)
+            .map(|(tick, _)| tick)
+            .collect();
+
+        // Exactly on every fourth failure, then gives up once
+        assert_eq!(restarts, [3, 7]);
+        assert_eq!(decisions[11], RestartDecision::GiveUp);
+        assert_eq!(
+            status.record_check(false, now, &policy),
+            RestartDecision::None
+        );
+
+        // A healthy check re-arms the monitor, but restarts stay counted
+        // until they leave the window
+        status.record_check(true, now, &policy);
+        for _ in 0..3 {
+            status.record_check(false, now, &policy);
+        }
+        assert_eq!(
+            status.record_check(false, now, &policy),
+            RestartDecision::GiveUp
+        );
+        status.record_check(true, now, &policy);
+        let later = now + Duration::minutes(11);
+        for _ in 0..3 {
+            status.record_check(false, later, &policy);
+        }
+        assert_eq!(
+            status.record_check(false, later, &policy),
+            RestartDecision::Restart
+        );
+
+        // Crashes restart straight away from the same budget
+        assert_eq!(
+            status.record_crash(later, &policy),
+            RestartDecision::Restart
+        );
+        assert_eq!(status.record_crash(later, &policy), RestartDecision::GiveUp);
+    }
+
+    #[test]
+    fn test_restarts_back_off() {
+        let policy = RestartPolicy {
+            unhealthy_threshold: 1,
+            max_attempts: 0,
+            window: Duration::minutes(10),
+            backoff_base: Duration::seconds(10),
+            backoff_cap: Duration::seconds(60),
+        };
+        let start = Utc::now();
+        let mut status = HealthStatus::new("alice", start);
+
+        // Fail every second and note when restarts are allowed through
+        let mut restarts = Vec::new();
+        for tick in 0..200 {
+            let now = start + Duration::seconds(tick);
+            match status.record_check(false, now, &policy) {
+                RestartDecision::Restart => restarts.push(tick),
+                RestartDecision::Backoff(at) => assert!(at > now),
+                decision => panic!("unexpected {:?}", decision),
+            }
+        }
+        let delays: Vec<i64> = restarts.windows(2).map(|w| w[1] - w[0]).collect();
+        assert_eq!(delays[..5], [10, 20, 40, 60, 60]);
+
+        // A healthy window resets the backoff
+        let later = start + Duration::seconds(200) + policy.window + Duration::seconds(1);
+        status.record_check(true, later, &policy);
+        assert_eq!(status.consecutive_restarts, 0);
+        assert_eq!(
+            status.record_check(false, later, &policy),
+            RestartDecision::Restart
+        );
+    }
+}
@@ -0,0 +1,68 @@
+//! Disk Usage
+//!
+//! Measures the space an instance's apps and data take up, for reporting
+//! against its disk quota.
+
+use std::path::Path;
+
+use super::InstanceManager;
+
+/// Instance subdirectories counted towards its disk usage
+const COUNTED_DIRS: &[&str] = &["apps", "data"];
+
+/// Total size of the files under `path` in bytes
+///
+/// Symlinks are counted as links, not followed; unreadable entries are skipped.
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| dir_size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+impl InstanceManager {
+    /// Bytes used by a user's apps and data
+    ///
+    /// Walks the whole tree, so this can be slow for large instances.
+    pub async fn disk_usage(&self, username: &str) -> u64 {
+        let instance_dir = self.instance_dir(username);
+        tokio::task::spawn_blocking(move || {
+            COUNTED_DIRS
+                .iter()
+                .map(|dir| dir_size(&instance_dir.join(dir)))
+                .sum()
+        })
+        .await
+        .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dir_size() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("apps/blog")).unwrap();
+        std::fs::write(dir.path().join("apps/blog/index.html"), vec![0u8; 1000]).unwrap();
+        std::fs::write(dir.path().join("apps/app.js"), vec![0u8; 24]).unwrap();
+        std::os::unix::fs::symlink("/usr", dir.path().join("apps/usr")).unwrap();
+
+        let size = dir_size(&dir.path().join("apps"));
+        // The symlink counts as itself, not as /usr
+        assert!((1024..1024 + 64).contains(&size));
+        assert_eq!(dir_size(&dir.path().join("missing")), 0);
+    }
+}
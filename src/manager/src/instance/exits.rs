@@ -0,0 +1,101 @@
+//! Exit Code Policy
+//!
+//! Decides what happens when an instance's process exits on its own. Some
+//! exit codes (e.g. 78, EX_CONFIG) mean the server will fail again the same
+//! way, so restarting it only produces a crash loop.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// What to do with an instance whose process exited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExitAction {
+    /// Restart through the normal health check policy
+    #[default]
+    Restart,
+    /// Mark the instance failed and leave it stopped
+    NoRestart,
+    /// Mark the instance failed and leave it out of auto-start until it is
+    /// started again
+    Quarantine,
+}
+
+impl FromStr for ExitAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "restart" => Ok(Self::Restart),
+            "no-restart" => Ok(Self::NoRestart),
+            "quarantine" => Ok(Self::Quarantine),
+            other => Err(format!(
+                "Unknown exit action '{}' (expected restart, no-restart or quarantine)",
+                other
+            )),
+        }
+    }
+}
+
+/// A process exit observed by the crash handler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessExit {
+    /// Exit code (`None` if killed by a signal)
+    pub code: Option<i32>,
+    /// Human-readable reason, e.g. "exited with code 1"
+    pub reason: String,
+    /// What the exit policy decided
+    pub action: ExitAction,
+}
+
+/// Exit code to action mapping; unlisted codes and signal deaths restart
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExitPolicy {
+    actions: HashMap<i32, ExitAction>,
+}
+
+impl ExitPolicy {
+    /// Parse `CODE:ACTION` entries, e.g. `["78:quarantine", "64:no-restart"]`
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        let mut actions = HashMap::new();
+        for entry in entries {
+            let (code, action) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("Expected CODE:ACTION, got '{}'", entry))?;
+            let code = code
+                .trim()
+                .parse::<i32>()
+                .map_err(|_| format!("Invalid exit code in '{}'", entry))?;
+            actions.insert(code, action.parse()?);
+        }
+        Ok(Self { actions })
+    }
+
+    /// Action for a process that exited with `code` (`None` if killed by a signal)
+    pub fn action(&self, code: Option<i32>) -> ExitAction {
+        code.and_then(|code| self.actions.get(&code).copied())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_policy() {
+        let policy =
+            ExitPolicy::parse(&["78:quarantine".to_string(), "64: No-Restart".to_string()])
+                .unwrap();
+
+        assert_eq!(policy.action(Some(78)), ExitAction::Quarantine);
+        assert_eq!(policy.action(Some(64)), ExitAction::NoRestart);
+        assert_eq!(policy.action(Some(1)), ExitAction::Restart);
+        assert_eq!(policy.action(None), ExitAction::Restart);
+
+        assert!(ExitPolicy::parse(&["78".to_string()]).is_err());
+        assert!(ExitPolicy::parse(&["x:restart".to_string()]).is_err());
+        assert!(ExitPolicy::parse(&["1:reboot".to_string()]).is_err());
+    }
+}
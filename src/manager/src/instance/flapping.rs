@@ -0,0 +1,96 @@
+//! Status Flap Detection
+//!
+//! An instance that keeps crashing and being restarted may pass individual
+//! health checks between cycles, so the consecutive-failure counter never
+//! trips. Counting how often its process exits unexpectedly within a
+//! sliding window catches that instability. Deliberate stops, restarts and
+//! deploys are not exits of this kind and never count.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::VecDeque;
+
+/// When an instance counts as flapping
+#[derive(Debug, Clone, Copy)]
+pub struct FlapPolicy {
+    /// Unexpected exits within the window that mark an instance as flapping
+    /// (0 disables detection)
+    pub threshold: usize,
+    /// Sliding window the exits are counted over
+    pub window: Duration,
+}
+
+impl Default for FlapPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            window: Duration::minutes(10),
+        }
+    }
+}
+
+/// Unexpected exit history of one instance
+#[derive(Debug, Default)]
+pub struct FlapTracker {
+    /// When the instance's process exited, oldest first
+    exits: VecDeque<DateTime<Utc>>,
+    flapping: bool,
+}
+
+impl FlapTracker {
+    /// Record an unexpected exit of the instance's process
+    ///
+    /// Returns the number of exits in the window when the instance has just
+    /// started flapping, so the caller can raise an alert once per episode.
+    pub fn record_exit(&mut self, now: DateTime<Utc>, policy: &FlapPolicy) -> Option<usize> {
+        self.exits.push_back(now);
+
+        let was_flapping = self.flapping;
+        self.refresh(now, policy);
+        (self.flapping && !was_flapping).then_some(self.exits.len())
+    }
+
+    /// Whether the instance is currently flapping
+    pub fn is_flapping(&mut self, now: DateTime<Utc>, policy: &FlapPolicy) -> bool {
+        self.refresh(now, policy);
+        self.flapping
+    }
+
+    /// Drop exits that left the window and re-evaluate the flapping flag
+    fn refresh(&mut self, now: DateTime<Utc>, policy: &FlapPolicy) {
+        while self
+            .exits
+            .front()
+            .is_some_and(|exit| now - *exit > policy.window)
+        {
+            self.exits.pop_front();
+        }
+        self.flapping = policy.threshold > 0 && self.exits.len() >= policy.threshold;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flap_detection() {
+        let policy = FlapPolicy {
+            threshold: 3,
+            window: Duration::minutes(10),
+        };
+        let start = Utc::now();
+        let mut tracker = FlapTracker::default();
+
+        // Crash loop: the process exits once a minute
+        let alerts: Vec<_> = (0..4)
+            .filter_map(|minute| tracker.record_exit(start + Duration::minutes(minute), &policy))
+            .collect();
+
+        // Alerted once, when the third exit happened
+        assert_eq!(alerts, [3]);
+        assert!(tracker.is_flapping(start + Duration::minutes(4), &policy));
+
+        // Settles once the exits age out of the window
+        assert!(!tracker.is_flapping(start + Duration::minutes(13), &policy));
+    }
+}
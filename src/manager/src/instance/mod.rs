@@ -3,19 +3,81 @@
 //! Manages per-user Frame instances including process lifecycle,
 //! resource limits, and monitoring.
 
+mod disk;
+mod exits;
+mod flapping;
 mod process;
+mod removal;
 mod resource;
+mod server_config;
+mod starts;
+mod state;
+#[cfg(feature = "testing")]
+mod synthetic;
+mod users;
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use chrono::{DateTime, Utc};
+use nix::dir::{Dir, Type as DirType};
+use nix::errno::Errno;
+use nix::fcntl::{AtFlags, OFlag};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::stat::Mode;
+use nix::unistd::{fchownat, Gid, Pid, Uid};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::os::fd::{AsRawFd, RawFd};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::RwLock;
 
-pub use process::ProcessManager;
-pub use resource::ResourceLimits;
+use crate::events::{Event, EventEmitter};
+use crate::health::HealthCheck;
+use crate::port::is_port_in_use;
+
+pub use exits::{ExitAction, ExitPolicy, ProcessExit};
+pub use flapping::{FlapPolicy, FlapTracker};
+pub use process::{EnvPolicy, ProcessManager, RunAs, SpawnRequest};
+pub use removal::{RemoveOptions, RemoveReport};
+pub use resource::{
+    CgroupBackend, CgroupController, ResourceController, ResourceLimits, SystemdController,
+};
+pub use server_config::SpawnMode;
+pub use starts::{StartLimiter, StartSlot, DEFAULT_MAX_CONCURRENT_STARTS};
+#[cfg(feature = "testing")]
+pub use synthetic::SyntheticInstance;
+pub use users::{cpanel_plan, validate_username, UserPolicy, MAX_USERNAME_LEN};
+
+use state::InstanceState;
+
+/// Default state directory, inside the root-owned instances directory
+///
+/// Not a valid username, so it can never collide with an instance.
+const STATE_DIR: &str = ".state";
+
+/// Delay between readiness probe attempts
+const READINESS_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Path requested by the HTTP health check unless an instance overrides it
+pub const DEFAULT_HEALTH_PATH: &str = "/health";
+
+fn default_health_path() -> String {
+    DEFAULT_HEALTH_PATH.to_string()
+}
+
+/// Fresh secret for an instance's metrics reports
+///
+/// Without one the instance's reports are refused.
+fn new_metrics_token() -> Option<String> {
+    match crate::api::auth::generate_token() {
+        Ok(token) => Some(token),
+        Err(e) => {
+            tracing::warn!("Failed to generate a metrics token: {}", e);
+            None
+        }
+    }
+}
 
 /// Instance manager
 pub struct InstanceManager {
@@ -27,8 +89,35 @@ pub struct InstanceManager {
     process_manager: ProcessManager,
     /// Active instances
     instances: Arc<RwLock<HashMap<String, Instance>>>,
-    /// Default resource limits
-    default_limits: ResourceLimits,
+    /// Default resource limits, replaced when `[defaults]` changes
+    default_limits: Mutex<ResourceLimits>,
+    /// Which users get an instance
+    user_policy: UserPolicy,
+    /// How instances are placed in cgroups
+    cgroup_backend: CgroupBackend,
+    /// How long `stop` waits for a stopped process to be reaped and its port freed
+    stop_grace: Duration,
+    /// What to do when an instance's process exits on its own
+    exit_policy: ExitPolicy,
+    /// Where instance directories are archived before removal
+    backups_dir: PathBuf,
+    /// Manager-owned directory of persisted runtime state, one file per user
+    state_dir: PathBuf,
+    /// Deadline for each readiness probe attempt
+    readiness_timeout: Duration,
+    /// Readiness probe attempts after the first before a start fails
+    readiness_retries: u32,
+    /// When an instance counts as flapping
+    flap_policy: FlapPolicy,
+    /// Unexpected exit history per instance
+    flaps: Mutex<HashMap<String, FlapTracker>>,
+    /// Receives `InstanceUnstable` events
+    events: Option<Arc<EventEmitter>>,
+    /// Bounds how many instances spawn at once
+    start_limiter: StartLimiter,
+    /// Users whose instance was injected through the test API
+    #[cfg(feature = "testing")]
+    synthetic: Mutex<HashSet<String>>,
 }
 
 /// Represents a user's Frame instance
@@ -40,6 +129,8 @@ pub struct Instance {
     pub port: u16,
     /// Current status
     pub status: InstanceStatus,
+    /// Explanation of the current status (e.g. progress of a transition)
+    pub status_detail: Option<String>,
     /// Process ID (if running)
     pub pid: Option<u32>,
     /// Memory usage in bytes
@@ -50,10 +141,180 @@ pub struct Instance {
     pub app_count: u32,
     /// Resource limits
     pub limits: ResourceLimits,
+    /// Environment variables passed to the frame-server
+    #[serde(skip)]
+    pub env_vars: HashMap<String, String>,
+    /// Log level passed to the frame-server (server default if unset)
+    pub log_level: Option<String>,
+    /// Port on which the instance serves HTTPS itself
+    pub tls_port: Option<u16>,
+    /// Path requested by the HTTP health check
+    pub health_path: String,
+    /// Custom health check script (absolute path)
+    pub custom_health_check: Option<PathBuf>,
+    /// Shell command that must succeed before a started instance is Running
+    pub readiness_probe: Option<String>,
     /// When the instance was started
     pub started_at: Option<DateTime<Utc>>,
     /// Last health check
     pub last_health_check: Option<DateTime<Utc>>,
+    /// Times the instance has been restarted
+    pub restart_count: u32,
+    /// Why the process last died on its own (e.g. "exited with code 1")
+    pub last_exit_reason: Option<String>,
+    /// Left out of auto-start after an exit mapped to quarantine, until the
+    /// instance is started again
+    pub quarantined: bool,
+    /// Secret the instance presents when reporting its request metrics,
+    /// passed to it as `FRAME_METRICS_TOKEN`
+    #[serde(skip)]
+    pub metrics_token: Option<String>,
+}
+
+impl Instance {
+    /// Seconds the instance has been running at `now`, if it is
+    pub fn uptime(&self, now: DateTime<Utc>) -> Option<u64> {
+        let started_at = self.started_at?;
+        if self.status != InstanceStatus::Running {
+            return None;
+        }
+        // A clock stepped backwards never yields a negative uptime
+        Some((now - started_at).num_seconds().max(0) as u64)
+    }
+}
+
+/// Recursively hand ownership of a path to a system user (requires root)
+///
+/// Failures are ignored; the manager may run unprivileged in development.
+/// The tree is walked on the blocking pool.
+pub async fn chown_to_user(path: &Path, username: &str) {
+    let (path, username) = (path.to_path_buf(), username.to_string());
+    let _ = tokio::task::spawn_blocking(move || {
+        if let Ok(Some(user)) = nix::unistd::User::from_name(&username) {
+            let _ = chown_recursive(&path, user.uid.as_raw(), user.gid.as_raw());
+        }
+    })
+    .await;
+}
+
+/// Change ownership of a tree without following symlinks
+///
+/// The user owns the tree and can swap a directory for a symlink at any
+/// time, so it is walked by directory descriptor: each entry is changed
+/// and opened relative to its parent's descriptor, never through a link.
+fn chown_recursive(path: &Path, uid: u32, gid: u32) -> std::io::Result<()> {
+    let (uid, gid) = (Some(Uid::from_raw(uid)), Some(Gid::from_raw(gid)));
+    fchownat(None, path, uid, gid, AtFlags::AT_SYMLINK_NOFOLLOW)?;
+    match open_subdir(None, path)? {
+        Some(dir) => chown_entries(dir, uid, gid),
+        None => Ok(()),
+    }
+}
+
+/// Change ownership of everything inside an open directory
+fn chown_entries(mut dir: Dir, uid: Option<Uid>, gid: Option<Gid>) -> std::io::Result<()> {
+    let fd = dir.as_raw_fd();
+    let mut subdirs = Vec::new();
+    for entry in dir.iter() {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == c"." || name == c".." {
+            continue;
+        }
+        fchownat(Some(fd), name, uid, gid, AtFlags::AT_SYMLINK_NOFOLLOW)?;
+        if matches!(entry.file_type(), Some(DirType::Directory) | None) {
+            subdirs.push(name.to_owned());
+        }
+    }
+    for name in subdirs {
+        if let Some(subdir) = open_subdir(Some(fd), name.as_c_str())? {
+            chown_entries(subdir, uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
+/// Open a directory without following a symlink in its place; `None` if
+/// it isn't a directory (any more)
+fn open_subdir<P: ?Sized + nix::NixPath>(
+    parent: Option<RawFd>,
+    path: &P,
+) -> std::io::Result<Option<Dir>> {
+    let flags = OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    match Dir::openat(parent, path, flags, Mode::empty()) {
+        Ok(dir) => Ok(Some(dir)),
+        Err(Errno::ENOTDIR | Errno::ELOOP | Errno::ENOENT) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Create a directory of an instance, or keep the one already there
+///
+/// A directory kept from a removed instance belongs to its former owner, so
+/// a symlink or anything else in its place is refused rather than used.
+async fn create_instance_subdir(path: &Path) -> Result<()> {
+    match tokio::fs::create_dir(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            if tokio::fs::symlink_metadata(path).await?.is_dir() {
+                Ok(())
+            } else {
+                anyhow::bail!("{} exists and is not a directory", path.display())
+            }
+        }
+        Err(e) => Err(anyhow::anyhow!(
+            "Failed to create {}: {}",
+            path.display(),
+            e
+        )),
+    }
+}
+
+/// Why an instance couldn't be created, for errors the caller can act on
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CreateError {
+    /// The user already has an instance
+    #[error("Instance already exists for user: {0}")]
+    Exists(String),
+    /// The name is invalid or the managed users policy excludes it
+    #[error("{0}")]
+    InvalidUser(String),
+}
+
+/// Ownership of an instance directory before and after a fix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OwnershipChange {
+    pub old_uid: u32,
+    pub old_gid: u32,
+    pub new_uid: u32,
+    pub new_gid: u32,
+}
+
+/// Re-chown a tree if its root isn't owned by the user's current UID/GID
+///
+/// cPanel can renumber an account, leaving files owned by the old IDs.
+/// Returns the change made, or `None` if the ownership was already correct.
+pub fn fix_ownership(path: &Path, username: &str) -> Result<Option<OwnershipChange>> {
+    use std::os::unix::fs::MetadataExt;
+
+    validate_username(username)?;
+
+    let user = nix::unistd::User::from_name(username)?
+        .ok_or_else(|| anyhow::anyhow!("System user {} does not exist", username))?;
+    let metadata = std::fs::symlink_metadata(path)?;
+    let change = OwnershipChange {
+        old_uid: metadata.uid(),
+        old_gid: metadata.gid(),
+        new_uid: user.uid.as_raw(),
+        new_gid: user.gid.as_raw(),
+    };
+    if change.old_uid == change.new_uid && change.old_gid == change.new_gid {
+        return Ok(None);
+    }
+
+    chown_recursive(path, change.new_uid, change.new_gid)
+        .map_err(|e| anyhow::anyhow!("Failed to change ownership of {}: {}", path.display(), e))?;
+    Ok(Some(change))
 }
 
 /// Instance status
@@ -66,6 +327,8 @@ pub enum InstanceStatus {
     Stopping,
     Failed,
     Unknown,
+    /// Suspended through the cgroup freezer for debugging
+    Frozen,
 }
 
 impl std::fmt::Display for InstanceStatus {
@@ -77,6 +340,7 @@ impl std::fmt::Display for InstanceStatus {
             InstanceStatus::Stopping => write!(f, "stopping"),
             InstanceStatus::Failed => write!(f, "failed"),
             InstanceStatus::Unknown => write!(f, "unknown"),
+            InstanceStatus::Frozen => write!(f, "frozen"),
         }
     }
 }
@@ -88,6 +352,29 @@ pub struct InstanceConfig {
     pub memory_limit: u64,
     pub max_apps: u32,
     pub env_vars: HashMap<String, String>,
+    /// Log level passed to the frame-server (server default if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    /// CPU limit percentage (configured default if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_limit: Option<u8>,
+    /// Disk quota in MB (configured default if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_quota: Option<u64>,
+    /// Port on which the instance serves HTTPS itself (enables the certificate check)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_port: Option<u16>,
+    /// Path requested by the HTTP health check (e.g. `/healthz`)
+    #[serde(default = "default_health_path")]
+    pub health_path: String,
+    /// Script run as the user during health checks; passes on exit code 0.
+    /// Relative paths are resolved against the instance directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_health_check: Option<PathBuf>,
+    /// Shell command run as the user after spawn, from the instance
+    /// directory; the instance only becomes Running once it exits 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readiness_probe: Option<String>,
 }
 
 impl Default for InstanceConfig {
@@ -97,42 +384,330 @@ impl Default for InstanceConfig {
             memory_limit: 512,
             max_apps: 5,
             env_vars: HashMap::new(),
+            log_level: None,
+            cpu_limit: None,
+            disk_quota: None,
+            tls_port: None,
+            health_path: default_health_path(),
+            custom_health_check: None,
+            readiness_probe: None,
         }
     }
 }
 
+/// Outcome of force-killing an instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ForceKillReport {
+    /// Process that was killed, if the instance had one
+    pub pid: Option<u32>,
+    /// Whether the process is gone
+    pub killed: bool,
+    /// The process survived SIGKILL in uninterruptible sleep
+    pub uninterruptible: bool,
+    /// Nothing listens on the instance's port any more, so it can be
+    /// handed out again
+    pub port_released: bool,
+}
+
+/// How a limits change reached an instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitsApplied {
+    /// Written to the running instance's cgroup
+    Live,
+    /// Saved; takes effect on the next start
+    OnRestart,
+}
+
 impl InstanceManager {
     /// Create a new instance manager
     pub fn new(
         instances_dir: PathBuf,
         frame_server_path: PathBuf,
         default_limits: ResourceLimits,
+        env_policy: EnvPolicy,
+        user_policy: UserPolicy,
     ) -> Self {
         Self {
+            state_dir: instances_dir.join(STATE_DIR),
             instances_dir,
             frame_server_path,
-            process_manager: ProcessManager::new(),
+            process_manager: ProcessManager::with_env_policy(env_policy),
             instances: Arc::new(RwLock::new(HashMap::new())),
-            default_limits,
+            default_limits: Mutex::new(default_limits),
+            user_policy,
+            cgroup_backend: CgroupBackend::default(),
+            stop_grace: Duration::from_secs(2),
+            exit_policy: ExitPolicy::default(),
+            backups_dir: PathBuf::from("/var/frame/backups"),
+            readiness_timeout: Duration::from_secs(5),
+            readiness_retries: 10,
+            flap_policy: FlapPolicy::default(),
+            flaps: Mutex::new(HashMap::new()),
+            events: None,
+            start_limiter: StartLimiter::default(),
+            #[cfg(feature = "testing")]
+            synthetic: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Whether an instance was injected through the test API
+    ///
+    /// Always false unless built with the `testing` feature.
+    pub fn is_synthetic(&self, username: &str) -> bool {
+        #[cfg(feature = "testing")]
+        {
+            self.synthetic
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .contains(username)
+        }
+        #[cfg(not(feature = "testing"))]
+        {
+            let _ = username;
+            false
+        }
+    }
+
+    /// Place instances in cgroups using `backend`
+    pub fn with_cgroup_backend(mut self, backend: CgroupBackend) -> Self {
+        self.process_manager =
+            std::mem::take(&mut self.process_manager).with_cgroup_backend(backend);
+        self.cgroup_backend = backend;
+        self
+    }
+
+    /// Pass settings to frame-server as `mode` describes
+    pub fn with_spawn_mode(mut self, mode: SpawnMode) -> Self {
+        self.process_manager = std::mem::take(&mut self.process_manager).with_spawn_mode(mode);
+        self
+    }
+
+    /// Run spawned servers as their user the way `run_as` describes
+    pub fn with_run_as(mut self, run_as: RunAs) -> Self {
+        self.process_manager = std::mem::take(&mut self.process_manager).with_run_as(run_as);
+        self
+    }
+
+    /// Wait up to `grace` for a stopped instance's process and port to be released
+    pub fn with_stop_grace(mut self, grace: Duration) -> Self {
+        self.stop_grace = grace;
+        self
+    }
+
+    /// Decide what happens to instances whose process exits using `policy`
+    pub fn with_exit_policy(mut self, policy: ExitPolicy) -> Self {
+        self.exit_policy = policy;
+        self
+    }
+
+    /// Bound readiness probes to `timeout` per attempt and `retries` retries
+    pub fn with_readiness_probe(mut self, timeout: Duration, retries: u32) -> Self {
+        self.readiness_timeout = timeout;
+        self.readiness_retries = retries;
+        self
+    }
+
+    /// Spawn at most `max` instances at the same time
+    pub fn with_max_concurrent_starts(mut self, max: usize) -> Self {
+        self.start_limiter = StartLimiter::new(max);
+        self
+    }
+
+    /// Archive removed instances into `dir`
+    pub fn with_backups_dir(mut self, dir: PathBuf) -> Self {
+        self.backups_dir = dir;
+        self
+    }
+
+    /// Keep runtime state in `dir` instead of `.state` in the instances directory
+    pub fn with_state_dir(mut self, dir: PathBuf) -> Self {
+        self.state_dir = dir;
+        self
+    }
+
+    /// Current default resource limits
+    fn default_limits(&self) -> ResourceLimits {
+        self.default_limits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Limits an instance config describes, with defaults for unset ones
+    fn limits_from_config(&self, config: &InstanceConfig) -> ResourceLimits {
+        let defaults = self.default_limits();
+        ResourceLimits {
+            memory_mb: config.memory_limit,
+            cpu_percent: config.cpu_limit.unwrap_or(defaults.cpu_percent),
+            max_connections: defaults.max_connections,
+            max_apps: config.max_apps,
+            disk_quota_mb: config.disk_quota.unwrap_or(defaults.disk_quota_mb),
+        }
+    }
+
+    /// Resource controller for the control group of a user's instance
+    /// listening on `port`
+    fn resource_controller(
+        &self,
+        username: &str,
+        port: u16,
+    ) -> Option<Box<dyn ResourceController>> {
+        match self.cgroup_backend {
+            CgroupBackend::Direct => CgroupController::open_for_user(username)
+                .map(|cgroup| Box::new(cgroup) as Box<dyn ResourceController>),
+            CgroupBackend::Systemd => {
+                Some(Box::new(SystemdController::for_instance(username, port)))
+            }
+        }
+    }
+
+    /// Detect flapping instances with `policy`, reporting them to `events`
+    pub fn with_flap_detection(mut self, policy: FlapPolicy, events: Arc<EventEmitter>) -> Self {
+        self.flap_policy = policy;
+        self.events = Some(events);
+        self
+    }
+
+    /// Whether an instance keeps cycling out of Running
+    pub fn is_flapping(&self, username: &str) -> bool {
+        let mut flaps = self.flaps.lock().unwrap_or_else(|e| e.into_inner());
+        flaps
+            .get_mut(username)
+            .is_some_and(|tracker| tracker.is_flapping(Utc::now(), &self.flap_policy))
+    }
+
+    /// Users whose instances are currently flapping
+    pub fn flapping(&self) -> HashSet<String> {
+        let now = Utc::now();
+        let mut flaps = self.flaps.lock().unwrap_or_else(|e| e.into_inner());
+        flaps
+            .iter_mut()
+            .filter_map(|(username, tracker)| {
+                tracker
+                    .is_flapping(now, &self.flap_policy)
+                    .then(|| username.clone())
+            })
+            .collect()
+    }
+
+    /// Record an unexpected exit and alert when the instance starts flapping
+    async fn track_exit(&self, username: &str) {
+        let started_flapping = {
+            let mut flaps = self.flaps.lock().unwrap_or_else(|e| e.into_inner());
+            flaps
+                .entry(username.to_string())
+                .or_default()
+                .record_exit(Utc::now(), &self.flap_policy)
+        };
+
+        let Some(transitions) = started_flapping else {
+            return;
+        };
+        tracing::warn!(
+            "Instance for {} exited unexpectedly {} times in {}s, marking it unstable",
+            username,
+            transitions,
+            self.flap_policy.window.num_seconds()
+        );
+        if let Some(events) = &self.events {
+            events
+                .emit(Event::InstanceUnstable {
+                    username: username.to_string(),
+                    transitions: transitions as u32,
+                    window_secs: self.flap_policy.window.num_seconds() as u64,
+                })
+                .await;
+        }
+    }
+
+    /// Check whether the managed users policy allows an instance for a user
+    pub fn is_managed(&self, username: &str) -> bool {
+        self.user_policy.allows(username)
+    }
+
+    /// Fail if a user is excluded by the managed users policy
+    pub fn ensure_managed(&self, username: &str) -> Result<()> {
+        if !self.is_managed(username) {
+            anyhow::bail!("User {} is excluded by the managed users policy", username);
         }
+        Ok(())
     }
 
     /// Initialize the instance manager
     pub async fn init(&self) -> Result<()> {
         // Scan existing instance directories
         if self.instances_dir.exists() {
+            let mut phantom = 0;
+            let mut excluded = 0;
             let mut entries = tokio::fs::read_dir(&self.instances_dir).await?;
             while let Some(entry) = entries.next_entry().await? {
-                if entry.file_type().await?.is_dir() {
+                if entry.file_type().await?.is_dir() && entry.path() != self.state_dir {
                     if let Some(username) = entry.file_name().to_str() {
+                        if let Err(e) = validate_username(username) {
+                            tracing::warn!("Skipping instance directory: {}", e);
+                            continue;
+                        }
+                        // Skip leftover directories of removed users; they can never start
+                        if !Self::system_user_exists(username) {
+                            tracing::debug!(
+                                "Skipping instance directory for non-existent user {}",
+                                username
+                            );
+                            phantom += 1;
+                            continue;
+                        }
+                        if removal::is_removed(&self.removed_marker(username)) {
+                            tracing::debug!(
+                                "Skipping data kept from removed instance of user {}",
+                                username
+                            );
+                            continue;
+                        }
+                        if !self.is_managed(username) {
+                            tracing::debug!(
+                                "Skipping instance directory for unmanaged user {}",
+                                username
+                            );
+                            excluded += 1;
+                            continue;
+                        }
                         self.load_instance(username).await?;
                     }
                 }
             }
+
+            if phantom > 0 {
+                tracing::warn!(
+                    "Skipped {} instance directories with no matching system user",
+                    phantom
+                );
+            }
+            if excluded > 0 {
+                tracing::info!(
+                    "Skipped {} instance directories excluded by the managed users policy",
+                    excluded
+                );
+            }
         }
         Ok(())
     }
 
+    /// Check whether a system user exists
+    ///
+    /// Lookup failures are treated as "exists" so a transient NSS problem
+    /// doesn't hide real instances.
+    fn system_user_exists(username: &str) -> bool {
+        match nix::unistd::User::from_name(username) {
+            Ok(Some(_)) => true,
+            Ok(None) => false,
+            Err(e) => {
+                tracing::warn!("Failed to look up system user {}: {}", username, e);
+                true
+            }
+        }
+    }
+
     /// Load an existing instance
     async fn load_instance(&self, username: &str) -> Result<()> {
         let instance_dir = self.instances_dir.join(username);
@@ -145,31 +720,165 @@ impl InstanceManager {
             InstanceConfig::default()
         };
 
-        let instance = Instance {
+        let mut instance = Instance {
             username: username.to_string(),
             port: 0, // Will be set by port allocator
             status: InstanceStatus::Stopped,
+            status_detail: None,
             pid: None,
             memory_usage: 0,
             cpu_usage: 0.0,
             app_count: self.count_apps(username).await?,
-            limits: ResourceLimits {
-                memory_mb: config.memory_limit,
-                cpu_percent: self.default_limits.cpu_percent,
-                max_connections: self.default_limits.max_connections,
-                max_apps: config.max_apps,
-                disk_quota_mb: self.default_limits.disk_quota_mb,
+            limits: self.limits_from_config(&config),
+            env_vars: config.env_vars,
+            log_level: config.log_level,
+            tls_port: config.tls_port,
+            health_path: if config.health_path.starts_with('/') {
+                config.health_path
+            } else {
+                format!("/{}", config.health_path)
             },
+            custom_health_check: config
+                .custom_health_check
+                .map(|path| instance_dir.join(path)),
+            readiness_probe: config.readiness_probe,
             started_at: None,
             last_health_check: None,
+            restart_count: 0,
+            last_exit_reason: None,
+            quarantined: false,
+            metrics_token: new_metrics_token(),
         };
 
+        match InstanceState::load(&self.state_path(username)).await {
+            Ok(Some(state)) => self.readopt(&mut instance, &state).await,
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Ignoring unreadable state for {}: {}", username, e),
+        }
+
         let mut instances = self.instances.write().await;
         instances.insert(username.to_string(), instance);
 
         Ok(())
     }
 
+    /// Restore an instance from the state saved by a previous manager run
+    ///
+    /// The saved status is only a hint; the actual process and port decide
+    /// whether the instance is running, so an instance caught mid-transition
+    /// by a crash never stays stuck in Starting/Stopping.
+    async fn readopt(&self, instance: &mut Instance, state: &InstanceState) {
+        let process_alive = state
+            .pid
+            .map(|pid| {
+                self.process_manager.is_running(pid)
+                    && self.is_frame_server(pid)
+                    && Self::runs_as(pid, &instance.username)
+            })
+            .unwrap_or(false);
+        let port_open = process_alive
+            && tokio::time::timeout(
+                std::time::Duration::from_secs(2),
+                tokio::net::TcpStream::connect(("127.0.0.1", state.port)),
+            )
+            .await
+            .map(|r| r.is_ok())
+            .unwrap_or(false);
+
+        let reconciled = state.reconcile(process_alive, port_open);
+        if reconciled.status != state.status {
+            tracing::warn!(
+                "Reconciled instance for {} from {} to {}",
+                instance.username,
+                state.status,
+                reconciled.status
+            );
+        }
+        if reconciled.status == InstanceStatus::Running {
+            tracing::info!(
+                "Re-adopted running instance for {} on port {} (PID: {})",
+                instance.username,
+                state.port,
+                state.pid.unwrap_or_default()
+            );
+            instance.started_at = state.started_at;
+        }
+
+        instance.restart_count = state.restart_count;
+        instance.last_exit_reason = state.last_exit_reason.clone();
+        instance.quarantined = state.quarantined;
+        if state.metrics_token.is_some() {
+            instance.metrics_token = state.metrics_token.clone();
+        }
+        if state.pid.is_some()
+            && reconciled.pid.is_none()
+            && reconciled.status == InstanceStatus::Failed
+        {
+            instance.last_exit_reason = reconciled.detail.clone();
+        }
+
+        instance.port = state.port;
+        instance.status = reconciled.status;
+        instance.pid = reconciled.pid;
+        instance.status_detail = reconciled.detail;
+    }
+
+    /// Check that a PID still belongs to a frame-server and wasn't reused
+    fn is_frame_server(&self, pid: u32) -> bool {
+        match std::fs::read(format!("/proc/{}/cmdline", pid)) {
+            Ok(cmdline) => {
+                let program = cmdline.split(|b| *b == 0).next().unwrap_or_default();
+                Path::new(&*String::from_utf8_lossy(program)).file_name()
+                    == self.frame_server_path.file_name()
+            }
+            // Without /proc there's no way to tell; trust the PID
+            Err(_) => true,
+        }
+    }
+
+    /// Check that a PID runs as the instance's user
+    ///
+    /// Guards against re-adopting (and later signalling) some other user's
+    /// process that was given a recycled PID.
+    fn runs_as(pid: u32, username: &str) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        let Ok(meta) = std::fs::metadata(format!("/proc/{}", pid)) else {
+            // Without /proc there's no way to tell; trust the PID
+            return true;
+        };
+        match nix::unistd::User::from_name(username) {
+            Ok(Some(user)) => meta.uid() == user.uid.as_raw(),
+            _ => false,
+        }
+    }
+
+    /// Path of the file holding a user's persisted runtime state
+    fn state_path(&self, username: &str) -> PathBuf {
+        self.state_dir.join(format!("{}.json", username))
+    }
+
+    /// Marker left in the state directory by a removal that kept the data
+    fn removed_marker(&self, username: &str) -> PathBuf {
+        self.state_dir.join(format!("{}.removed", username))
+    }
+
+    /// Persist an instance's runtime state for re-adoption after a restart
+    ///
+    /// Called on every status transition.
+    async fn save_state(&self, username: &str) {
+        let state = {
+            let instances = self.instances.read().await;
+            match instances.get(username) {
+                Some(instance) => InstanceState::of(instance),
+                None => return,
+            }
+        };
+        if let Err(e) = state.save(&self.state_path(username)).await {
+            tracing::warn!("Failed to save state for {}: {}", username, e);
+        }
+    }
+
     /// Count apps for a user
     async fn count_apps(&self, username: &str) -> Result<u32> {
         let apps_dir = self.instances_dir.join(username).join("apps");
@@ -189,169 +898,1015 @@ impl InstanceManager {
 
     /// Start an instance
     pub async fn start(&self, username: &str, port: u16) -> Result<()> {
-        let mut instances = self.instances.write().await;
-
-        let instance = instances
-            .get_mut(username)
-            .ok_or_else(|| anyhow::anyhow!("Instance not found for user: {}", username))?;
+        validate_username(username)?;
+        self.ensure_managed(username)?;
 
-        if instance.status == InstanceStatus::Running {
-            return Ok(());
+        // A renumbered account can't write to files owned by its old UID
+        if let Err(e) = self.fix_ownership(username).await {
+            tracing::warn!("Could not check ownership for user {}: {:#}", username, e);
         }
 
-        instance.status = InstanceStatus::Starting;
-        instance.port = port;
+        let (limits, env_vars, log_level, readiness_probe, metrics_token) = {
+            let mut instances = self.instances.write().await;
+
+            let instance = instances
+                .get_mut(username)
+                .ok_or_else(|| anyhow::anyhow!("Instance not found for user: {}", username))?;
+
+            match instance.status {
+                InstanceStatus::Running => return Ok(()),
+                InstanceStatus::Starting | InstanceStatus::Stopping => {
+                    anyhow::bail!(
+                        "Instance for user {} is currently {}",
+                        username,
+                        instance.status
+                    )
+                }
+                // Its suspended server still holds the port and the cgroup
+                InstanceStatus::Frozen => {
+                    anyhow::bail!("Instance for user {} is frozen, thaw it first", username)
+                }
+                _ => {}
+            }
+
+            instance.status = InstanceStatus::Starting;
+            instance.status_detail = Some("waiting for a start slot".to_string());
+            instance.port = port;
+            (
+                instance.limits.clone(),
+                instance.env_vars.clone(),
+                instance.log_level.clone(),
+                instance.readiness_probe.clone(),
+                instance.metrics_token.clone(),
+            )
+        };
+        self.save_state(username).await;
 
-        // Start the process
-        let pid = self
+        // Held through spawn and readiness, the expensive part of a start
+        let _slot = self.start_limiter.acquire(username).await;
+        self.set_status_detail(username, Some("spawning".to_string()))
+            .await;
+
+        // Start the process without holding the lock so the transition is observable
+        let instance_dir = self.instances_dir.join(username);
+        let result = self
             .process_manager
             .spawn(
-                username,
                 &self.frame_server_path,
-                port,
-                &self.instances_dir.join(username),
-                &instance.limits,
+                SpawnRequest {
+                    username,
+                    port,
+                    instance_dir: &instance_dir,
+                    apps_dir: &instance_dir.join("apps"),
+                    limits: &limits,
+                    env_vars: &env_vars,
+                    log_level: log_level.as_deref(),
+                    metrics_token: metrics_token.as_deref(),
+                },
             )
-            .await?;
+            .await;
 
-        instance.pid = Some(pid);
-        instance.status = InstanceStatus::Running;
-        instance.started_at = Some(Utc::now());
+        let mut instances = self.instances.write().await;
+        let Some(instance) = instances.get_mut(username) else {
+            // Instance was removed while starting; don't leave an orphan process behind
+            if let Ok(pid) = result {
+                let _ = self.process_manager.stop(pid).await;
+            }
+            anyhow::bail!("Instance for user {} was removed while starting", username);
+        };
 
-        tracing::info!("Started instance for user {} on port {} (PID: {})", username, port, pid);
+        let pid = match result {
+            Ok(pid) => pid,
+            Err(e) => {
+                instance.status = InstanceStatus::Failed;
+                instance.status_detail = Some(format!("spawn failed: {}", e));
+                drop(instances);
+                self.save_state(username).await;
+                return Err(e);
+            }
+        };
 
-        Ok(())
-    }
+        instance.pid = Some(pid);
 
-    /// Stop an instance
-    pub async fn stop(&self, username: &str) -> Result<()> {
-        let mut instances = self.instances.write().await;
+        // The readiness probe gates the transition to Running
+        if let Some(command) = readiness_probe {
+            instance.status_detail = Some("awaiting readiness probe".to_string());
+            drop(instances);
+            self.save_state(username).await;
 
-        let instance = instances
-            .get_mut(username)
-            .ok_or_else(|| anyhow::anyhow!("Instance not found for user: {}", username))?;
+            if let Err(e) = self.await_readiness(username, pid, &command).await {
+                let _ = self.process_manager.stop(pid).await;
+                self.process_manager.take_exit_status(pid);
 
-        if instance.status == InstanceStatus::Stopped {
-            return Ok(());
+                let mut instances = self.instances.write().await;
+                if let Some(instance) = instances.get_mut(username) {
+                    instance.pid = None;
+                    instance.status = InstanceStatus::Failed;
+                    instance.status_detail = Some(format!("readiness probe failed: {}", e));
+                }
+                drop(instances);
+                self.save_state(username).await;
+                return Err(e);
+            }
+            instances = self.instances.write().await;
         }
 
-        instance.status = InstanceStatus::Stopping;
-
-        if let Some(pid) = instance.pid {
-            self.process_manager.stop(pid).await?;
+        if let Some(instance) = instances.get_mut(username) {
+            instance.status = InstanceStatus::Running;
+            instance.status_detail = None;
+            instance.started_at = Some(Utc::now());
+            instance.quarantined = false;
         }
+        drop(instances);
+        self.save_state(username).await;
 
-        instance.pid = None;
-        instance.status = InstanceStatus::Stopped;
-        instance.started_at = None;
-
-        tracing::info!("Stopped instance for user {}", username);
+        tracing::info!(
+            "Started instance for user {} on port {} (PID: {})",
+            username,
+            port,
+            pid
+        );
 
         Ok(())
     }
 
-    /// Restart an instance
-    pub async fn restart(&self, username: &str, port: u16) -> Result<()> {
-        self.stop(username).await?;
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        self.start(username, port).await?;
-        Ok(())
-    }
+    /// Run an instance's readiness probe until it passes or retries run out
+    async fn await_readiness(&self, username: &str, pid: u32, command: &str) -> Result<()> {
+        let instance = self.status(username).await?;
+        let user = nix::unistd::User::from_name(username)?
+            .ok_or_else(|| anyhow::anyhow!("System user {} does not exist", username))?;
+        let probe = HealthCheck::shell(
+            command,
+            self.instance_dir(username),
+            self.health_check_env(&instance),
+        )
+        .run_as(user.uid.as_raw(), user.gid.as_raw())
+        .with_timeout(self.readiness_timeout);
 
-    /// Get instance status
-    pub async fn status(&self, username: &str) -> Result<Instance> {
-        let instances = self.instances.read().await;
-        instances
-            .get(username)
-            .cloned()
-            .ok_or_else(|| anyhow::anyhow!("Instance not found for user: {}", username))
-    }
+        let attempts = self.readiness_retries + 1;
+        let mut last_failure = String::new();
+        for attempt in 1..=attempts {
+            if !self.process_manager.is_running(pid) {
+                anyhow::bail!("process {} exited before becoming ready", pid);
+            }
 
-    /// List all instances
-    pub async fn list(&self) -> Vec<Instance> {
-        let instances = self.instances.read().await;
-        instances.values().cloned().collect()
-    }
+            let result = probe.execute().await;
+            if result.passed {
+                tracing::debug!(
+                    "Readiness probe for {} passed on attempt {}",
+                    username,
+                    attempt
+                );
+                return Ok(());
+            }
+            tracing::debug!(
+                "Readiness probe for {} failed (attempt {}/{}): {}",
+                username,
+                attempt,
+                attempts,
+                result.message
+            );
+            last_failure = result.message;
 
-    /// Create a new instance for a user
-    pub async fn create(&self, username: &str, limits: Option<ResourceLimits>) -> Result<()> {
-        let instance_dir = self.instances_dir.join(username);
+            if attempt < attempts {
+                tokio::time::sleep(READINESS_PROBE_INTERVAL).await;
+            }
+        }
 
-        // Create directory structure
-        tokio::fs::create_dir_all(instance_dir.join("apps")).await?;
-        tokio::fs::create_dir_all(instance_dir.join("data")).await?;
-        tokio::fs::create_dir_all(instance_dir.join("logs")).await?;
+        anyhow::bail!("{} (after {} attempts)", last_failure, attempts)
+    }
 
-        // Create default config
-        let config = InstanceConfig::default();
-        let config_json = serde_json::to_string_pretty(&config)?;
-        tokio::fs::write(instance_dir.join("config.json"), config_json).await?;
+    /// Stop an instance
+    pub async fn stop(&self, username: &str) -> Result<()> {
+        validate_username(username)?;
+        let (pid, port, frozen) = {
+            let mut instances = self.instances.write().await;
 
-        // Set ownership (requires root)
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::chown;
-            if let Ok(Some(user)) = nix::unistd::User::from_name(username) {
-                let uid = user.uid.as_raw();
-                let gid = user.gid.as_raw();
-
-                fn chown_recursive(path: &Path, uid: u32, gid: u32) -> std::io::Result<()> {
-                    chown(path, Some(uid), Some(gid))?;
-                    if path.is_dir() {
-                        for entry in std::fs::read_dir(path)? {
-                            chown_recursive(&entry?.path(), uid, gid)?;
-                        }
-                    }
-                    Ok(())
+            let instance = instances
+                .get_mut(username)
+                .ok_or_else(|| anyhow::anyhow!("Instance not found for user: {}", username))?;
+
+            match instance.status {
+                InstanceStatus::Stopped => return Ok(()),
+                InstanceStatus::Starting | InstanceStatus::Stopping => {
+                    anyhow::bail!(
+                        "Instance for user {} is currently {}",
+                        username,
+                        instance.status
+                    )
                 }
+                _ => {}
+            }
+
+            let frozen = instance.status == InstanceStatus::Frozen;
+            instance.status = InstanceStatus::Stopping;
+            instance.status_detail = Some("awaiting SIGTERM shutdown".to_string());
+            (instance.pid, instance.port, frozen)
+        };
+        self.save_state(username).await;
 
-                let _ = chown_recursive(&instance_dir, uid, gid);
+        // A frozen process can't handle SIGTERM until it's thawed
+        if frozen {
+            if let Err(e) = self.thaw_processes(username, port).await {
+                tracing::warn!("Failed to thaw instance for {}: {}", username, e);
             }
         }
 
-        let instance = Instance {
-            username: username.to_string(),
-            port: 0,
-            status: InstanceStatus::Stopped,
-            pid: None,
-            memory_usage: 0,
-            cpu_usage: 0.0,
-            app_count: 0,
-            limits: limits.unwrap_or_else(|| self.default_limits.clone()),
-            started_at: None,
-            last_health_check: None,
+        // Stop the process without holding the lock so the transition is observable
+        let result = match pid {
+            Some(pid) => self.process_manager.stop(pid).await,
+            None => Ok(()),
         };
 
+        // Don't report Stopped while the process or its port is still around,
+        // or an immediate start would race the old process for the port
+        if let (Ok(()), Some(pid)) = (&result, pid) {
+            if !self
+                .process_manager
+                .wait_for_release(pid, port, self.stop_grace)
+                .await
+            {
+                tracing::warn!(
+                    "Process {} for user {} or port {} still not released after {:?}",
+                    pid,
+                    username,
+                    port,
+                    self.stop_grace
+                );
+            }
+            // A deliberate stop isn't a crash; drop the recorded status
+            self.process_manager.take_exit_status(pid);
+
+            if let CgroupBackend::Direct = self.cgroup_backend {
+                if let Some(cgroup) = CgroupController::open_for_user(username) {
+                    if let Err(e) = cgroup.remove() {
+                        tracing::warn!("Failed to remove cgroup of {}: {}", username, e);
+                    }
+                }
+            }
+        }
+
         let mut instances = self.instances.write().await;
-        instances.insert(username.to_string(), instance);
+        if let Some(instance) = instances.get_mut(username) {
+            if let Err(e) = result {
+                instance.status = InstanceStatus::Failed;
+                instance.status_detail = Some(format!("stop failed: {}", e));
+                drop(instances);
+                self.save_state(username).await;
+                return Err(e);
+            }
 
-        tracing::info!("Created instance for user {}", username);
+            instance.pid = None;
+            instance.status = InstanceStatus::Stopped;
+            instance.status_detail = None;
+            instance.started_at = None;
+            drop(instances);
+            self.save_state(username).await;
+        } else {
+            result?;
+        }
+
+        tracing::info!("Stopped instance for user {}", username);
 
         Ok(())
     }
 
-    /// Remove an instance
-    pub async fn remove(&self, username: &str) -> Result<()> {
-        // Stop if running
-        let _ = self.stop(username).await;
+    /// Spawn a candidate process serving an alternate apps directory
+    ///
+    /// The candidate runs alongside the instance's current process and is not
+    /// tracked until it is promoted.
+    pub async fn spawn_candidate(&self, username: &str, port: u16, apps_dir: &Path) -> Result<u32> {
+        validate_username(username)?;
+        let instance = self.status(username).await?;
+        let instance_dir = self.instances_dir.join(username);
+
+        self.process_manager
+            .spawn(
+                &self.frame_server_path,
+                SpawnRequest {
+                    username,
+                    port,
+                    instance_dir: &instance_dir,
+                    apps_dir,
+                    limits: &instance.limits,
+                    env_vars: &instance.env_vars,
+                    log_level: instance.log_level.as_deref(),
+                    metrics_token: instance.metrics_token.as_deref(),
+                },
+            )
+            .await
+    }
+
+    /// Make a candidate process the instance's active process
+    ///
+    /// Returns the PID of the process it replaced, if any.
+    pub async fn promote_candidate(
+        &self,
+        username: &str,
+        port: u16,
+        pid: u32,
+    ) -> Result<Option<u32>> {
+        validate_username(username)?;
+        let app_count = self.count_apps(username).await?;
+        let mut instances = self.instances.write().await;
+
+        let instance = instances
+            .get_mut(username)
+            .ok_or_else(|| anyhow::anyhow!("Instance not found for user: {}", username))?;
+
+        let previous = instance.pid.replace(pid);
+        instance.port = port;
+        instance.status = InstanceStatus::Running;
+        instance.status_detail = None;
+        instance.started_at = Some(Utc::now());
+        instance.app_count = app_count;
+        drop(instances);
+        self.save_state(username).await;
+
+        tracing::info!(
+            "Promoted candidate for user {} on port {} (PID: {})",
+            username,
+            port,
+            pid
+        );
+
+        Ok(previous)
+    }
+
+    /// Stop a process that is not tracked as an instance's active process
+    pub async fn stop_process(&self, pid: u32) -> Result<()> {
+        self.process_manager.stop(pid).await?;
+        self.process_manager.take_exit_status(pid);
+        Ok(())
+    }
+
+    /// SIGKILL an instance's process and mark it failed whatever its state
+    ///
+    /// Escape hatch for instances wedged in a transition. The instance stops
+    /// being tracked as running even if the process can't be killed (stuck
+    /// in uninterruptible sleep); that is reported rather than waited on.
+    pub async fn force_kill(&self, username: &str) -> Result<ForceKillReport> {
+        validate_username(username)?;
+        let instance = self.status(username).await?;
+        let (pid, port) = (instance.pid, instance.port);
+
+        // Whatever sudo left running in the cgroup goes too
+        if let Some(controller) = self.resource_controller(username, port) {
+            if let Err(e) = controller.kill_all().await {
+                tracing::warn!("Failed to kill the cgroup of {}: {}", username, e);
+            }
+        }
+
+        let mut report = ForceKillReport {
+            pid,
+            killed: true,
+            uninterruptible: false,
+            port_released: port == 0 || !is_port_in_use(port),
+        };
+        if let Some(pid) = pid {
+            report.killed = self.process_manager.kill(pid, self.stop_grace).await?;
+            if report.killed {
+                self.process_manager.take_exit_status(pid);
+                report.port_released = port == 0
+                    || self
+                        .process_manager
+                        .wait_for_release(pid, port, self.stop_grace)
+                        .await;
+            } else {
+                report.uninterruptible = self.process_manager.is_uninterruptible(pid);
+                report.port_released = false;
+            }
+        }
+
+        let detail = match (pid, report.killed, report.uninterruptible) {
+            (Some(pid), false, true) => format!(
+                "force-killed; process {} is stuck in uninterruptible sleep",
+                pid
+            ),
+            (Some(pid), false, false) => {
+                format!("force-killed; process {} did not exit", pid)
+            }
+            _ => "force-killed".to_string(),
+        };
+        tracing::warn!("Instance for {} {}", username, detail);
+
+        {
+            let mut instances = self.instances.write().await;
+            if let Some(instance) = instances.get_mut(username) {
+                instance.status = InstanceStatus::Failed;
+                instance.status_detail = Some(detail);
+                instance.pid = None;
+                instance.started_at = None;
+            }
+        }
+        self.save_state(username).await;
+
+        Ok(report)
+    }
+
+    /// Take the stream of PIDs of exited instance processes
+    ///
+    /// Only processes spawned by this manager are reported; readopted ones
+    /// aren't children and are still found by health checks.
+    pub fn take_exit_notifications(&self) -> Option<tokio::sync::mpsc::UnboundedReceiver<u32>> {
+        self.process_manager.take_exit_notifications()
+    }
+
+    /// User of the running instance whose process has `pid`
+    pub async fn running_instance_with_pid(&self, pid: u32) -> Option<String> {
+        let instances = self.instances.read().await;
+        instances
+            .values()
+            .find(|instance| {
+                instance.status == InstanceStatus::Running && instance.pid == Some(pid)
+            })
+            .map(|instance| instance.username.clone())
+    }
+
+    /// Apply the exit policy to a running instance whose process has exited
+    ///
+    /// Returns `None` while the process is alive (or its exit wasn't
+    /// observed, or was already handled). The instance is marked failed
+    /// with its PID cleared, as "restarting" when the exit code allows a
+    /// restart; quarantine also keeps the instance out of auto-start until
+    /// it is started again, recorded in the manager's own state where the
+    /// user can't clear it.
+    pub async fn handle_exit(&self, username: &str) -> Option<ProcessExit> {
+        let pid = {
+            let instances = self.instances.read().await;
+            let instance = instances.get(username)?;
+            if instance.status != InstanceStatus::Running {
+                return None;
+            }
+            instance.pid?
+        };
+        let status = self.process_manager.take_exit_status(pid)?;
+        self.track_exit(username).await;
+        let action = self.exit_policy.action(status.code());
+        let exited = match status.code() {
+            Some(code) => format!("exited with code {}", code),
+            None => format!("exited ({})", status),
+        };
+
+        {
+            let mut instances = self.instances.write().await;
+            if let Some(instance) = instances.get_mut(username) {
+                instance.last_exit_reason = Some(exited.clone());
+            }
+        }
+
+        let exit = ProcessExit {
+            code: status.code(),
+            reason: exited.clone(),
+            action,
+        };
+        let detail = match action {
+            ExitAction::Restart => format!("{}, restarting", exited),
+            ExitAction::NoRestart => format!("{}, not restarted", exited),
+            ExitAction::Quarantine => format!("{}, quarantined (auto-start disabled)", exited),
+        };
+        tracing::warn!("Instance for {} {}", username, detail);
+
+        {
+            let mut instances = self.instances.write().await;
+            if let Some(instance) = instances.get_mut(username) {
+                instance.quarantined = action == ExitAction::Quarantine;
+                instance.status = InstanceStatus::Failed;
+                instance.status_detail = Some(detail);
+                instance.pid = None;
+                instance.started_at = None;
+            }
+        }
+        self.save_state(username).await;
+
+        Some(exit)
+    }
+
+    /// Directory holding every instance's directory
+    pub fn instances_dir(&self) -> &Path {
+        &self.instances_dir
+    }
+
+    /// Get the data directory for a user's instance
+    pub fn instance_dir(&self, username: &str) -> PathBuf {
+        self.instances_dir.join(username)
+    }
+
+    /// Re-chown a user's instance directory if their UID/GID changed
+    ///
+    /// The tree is walked on the blocking pool.
+    pub async fn fix_ownership(&self, username: &str) -> Result<Option<OwnershipChange>> {
+        let (path, user) = (self.instance_dir(username), username.to_string());
+        let change = tokio::task::spawn_blocking(move || fix_ownership(&path, &user)).await??;
+        if let Some(change) = change {
+            tracing::info!(
+                "Instance directory for user {} was owned by {}:{}, changed to {}:{}",
+                username,
+                change.old_uid,
+                change.old_gid,
+                change.new_uid,
+                change.new_gid
+            );
+        }
+        Ok(change)
+    }
+
+    /// Send a signal to a running instance's process
+    pub async fn signal(&self, username: &str, signal: Signal) -> Result<()> {
+        validate_username(username)?;
+        let instances = self.instances.read().await;
+        let instance = instances
+            .get(username)
+            .ok_or_else(|| anyhow::anyhow!("Instance not found for user: {}", username))?;
+
+        let pid = match (instance.status, instance.pid) {
+            (InstanceStatus::Running, Some(pid)) => pid,
+            _ => anyhow::bail!(
+                "Instance for user {} is not running (status: {})",
+                username,
+                instance.status
+            ),
+        };
+
+        kill(Pid::from_raw(pid as i32), signal)
+            .map_err(|e| anyhow::anyhow!("Failed to send {} to PID {}: {}", signal, pid, e))
+    }
+
+    /// Suspend a running instance's processes, e.g. to inspect it in place
+    ///
+    /// The instance is Frozen until `thaw`; health checks leave it alone.
+    pub async fn freeze(&self, username: &str) -> Result<()> {
+        validate_username(username)?;
+        let instance = self.status(username).await?;
+        if instance.status != InstanceStatus::Running {
+            anyhow::bail!(
+                "Instance for user {} is not running (status: {})",
+                username,
+                instance.status
+            );
+        }
+
+        let controller = self
+            .resource_controller(username, instance.port)
+            .ok_or_else(|| {
+                anyhow::anyhow!("Instance for user {} has no cgroup to freeze", username)
+            })?;
+        controller
+            .freeze()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to freeze instance for {}: {}", username, e))?;
+
+        {
+            let mut instances = self.instances.write().await;
+            let current = instances.get_mut(username).filter(|current| {
+                current.status == InstanceStatus::Running && current.pid == instance.pid
+            });
+            let Some(current) = current else {
+                // Stopped or replaced while freezing; don't leave it suspended
+                drop(instances);
+                let _ = controller.thaw().await;
+                anyhow::bail!("Instance for user {} changed while freezing", username);
+            };
+            current.status = InstanceStatus::Frozen;
+            current.status_detail = Some("frozen for debugging".to_string());
+        }
+        self.save_state(username).await;
+
+        tracing::info!("Froze instance for {}", username);
+        Ok(())
+    }
+
+    /// Resume a frozen instance
+    pub async fn thaw(&self, username: &str) -> Result<()> {
+        validate_username(username)?;
+        let instance = self.status(username).await?;
+        if instance.status != InstanceStatus::Frozen {
+            anyhow::bail!(
+                "Instance for user {} is not frozen (status: {})",
+                username,
+                instance.status
+            );
+        }
+
+        self.thaw_processes(username, instance.port).await?;
+
+        {
+            let mut instances = self.instances.write().await;
+            if let Some(current) = instances
+                .get_mut(username)
+                .filter(|current| current.status == InstanceStatus::Frozen)
+            {
+                current.status = InstanceStatus::Running;
+                current.status_detail = None;
+            }
+        }
+        self.save_state(username).await;
+
+        tracing::info!("Thawed instance for {}", username);
+        Ok(())
+    }
+
+    /// Thaw the cgroup of a user's instance listening on `port`
+    async fn thaw_processes(&self, username: &str, port: u16) -> Result<()> {
+        let controller = self.resource_controller(username, port).ok_or_else(|| {
+            anyhow::anyhow!("Instance for user {} has no cgroup to thaw", username)
+        })?;
+        controller
+            .thaw()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to thaw instance for {}: {}", username, e))
+    }
+
+    /// Set the log level passed to an instance's frame-server
+    ///
+    /// Saved to the instance's config.json; takes effect on the next start.
+    pub async fn set_log_level(&self, username: &str, log_level: Option<String>) -> Result<()> {
+        validate_username(username)?;
+
+        // Held across the rewrite so concurrent config updates can't race
+        let mut instances = self.instances.write().await;
+        let instance = instances
+            .get_mut(username)
+            .ok_or_else(|| anyhow::anyhow!("Instance not found for user: {}", username))?;
+
+        // Update the saved config first so memory never gets ahead of disk
+        let mut config = self.read_config(username).await?;
+        config.log_level = log_level.clone();
+        self.write_config(username, &config).await?;
+        instance.log_level = log_level;
+
+        Ok(())
+    }
+
+    /// Update an instance's resource limits
+    ///
+    /// Saved to the instance's config.json. A running instance with a cgroup
+    /// gets the new memory and CPU limits immediately; otherwise they take
+    /// effect on the next start.
+    pub async fn set_limits(
+        &self,
+        username: &str,
+        limits: ResourceLimits,
+    ) -> Result<LimitsApplied> {
+        validate_username(username)?;
+        limits.validate().map_err(|e| anyhow::anyhow!(e))?;
+
+        {
+            // Held while the config is rewritten so concurrent updates can't
+            // lose each other's changes
+            let instances = self.instances.write().await;
+            if !instances.contains_key(username) {
+                anyhow::bail!("Instance not found for user: {}", username);
+            }
+
+            let mut config = self.read_config(username).await?;
+            config.memory_limit = limits.memory_mb;
+            config.cpu_limit = Some(limits.cpu_percent);
+            config.max_apps = limits.max_apps;
+            config.disk_quota = Some(limits.disk_quota_mb);
+            self.write_config(username, &config).await?;
+        }
+
+        self.apply_limits(username, limits).await
+    }
+
+    /// Limits a user's config.json gives the instance, with the current
+    /// defaults for unset ones
+    pub async fn configured_limits(&self, username: &str) -> Result<ResourceLimits> {
+        validate_username(username)?;
+        let config = self.read_config(username).await?;
+        Ok(self.limits_from_config(&config))
+    }
+
+    /// Read a user's instance config.json, or the defaults if there is none
+    ///
+    /// The file sits in the user's directory, so a symlink in its place is
+    /// refused rather than followed.
+    async fn read_config(&self, username: &str) -> Result<InstanceConfig> {
+        use tokio::io::AsyncReadExt;
+
+        let config_path = self.instances_dir.join(username).join("config.json");
+        let mut file = match tokio::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(nix::fcntl::OFlag::O_NOFOLLOW.bits())
+            .open(&config_path)
+            .await
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(InstanceConfig::default())
+            }
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "Failed to read {}: {}",
+                    config_path.display(),
+                    e
+                ))
+            }
+        };
+        let mut content = String::new();
+        file.read_to_string(&mut content).await?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Replace a user's instance config.json without following symlinks
+    async fn write_config(&self, username: &str, config: &InstanceConfig) -> Result<()> {
+        let config_path = self.instances_dir.join(username).join("config.json");
+        process::write_private(&config_path, &serde_json::to_string_pretty(config)?).await?;
+        chown_to_user(&config_path, username).await;
+        Ok(())
+    }
+
+    /// Put new resource limits into effect without saving them
+    ///
+    /// Updates the instance's limits in memory; a running instance with a
+    /// cgroup gets the new memory and CPU caps written to it without a
+    /// restart. For limits already saved to the instance's config.json.
+    pub async fn apply_limits(
+        &self,
+        username: &str,
+        limits: ResourceLimits,
+    ) -> Result<LimitsApplied> {
+        self.apply_limits_with(username, limits, |username, port| {
+            self.resource_controller(username, port)
+        })
+        .await
+    }
+
+    /// Apply limits through the resource controller `controller` opens
+    async fn apply_limits_with(
+        &self,
+        username: &str,
+        limits: ResourceLimits,
+        controller: impl FnOnce(&str, u16) -> Option<Box<dyn ResourceController>>,
+    ) -> Result<LimitsApplied> {
+        limits.validate().map_err(|e| anyhow::anyhow!(e))?;
+
+        let controller = {
+            let mut instances = self.instances.write().await;
+            let instance = instances
+                .get_mut(username)
+                .ok_or_else(|| anyhow::anyhow!("Instance not found for user: {}", username))?;
+            instance.limits = limits.clone();
+            match instance.status {
+                InstanceStatus::Running => controller(username, instance.port),
+                _ => None,
+            }
+        };
+
+        // Written without the lock held; the systemd backend shells out
+        match controller {
+            Some(controller) => {
+                controller.set_memory_limit(limits.memory_bytes()).await?;
+                controller.set_cpu_limit(limits.cpu_percent).await?;
+                Ok(LimitsApplied::Live)
+            }
+            None => Ok(LimitsApplied::OnRestart),
+        }
+    }
+
+    /// Replace the default resource limits
+    ///
+    /// Instances whose config.json leaves a limit unset follow the defaults;
+    /// each one whose effective limits change has them applied, live where
+    /// it's running. Returns how the change reached each of those instances.
+    pub async fn set_default_limits(
+        &self,
+        defaults: ResourceLimits,
+    ) -> BTreeMap<String, Result<LimitsApplied>> {
+        *self
+            .default_limits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = defaults;
+
+        let instances: Vec<(String, ResourceLimits)> = self
+            .instances
+            .read()
+            .await
+            .values()
+            .map(|i| (i.username.clone(), i.limits.clone()))
+            .collect();
+
+        let mut results = BTreeMap::new();
+        for (username, current) in instances {
+            let config = match self.read_config(&username).await {
+                Ok(config) => config,
+                Err(e) => {
+                    results.insert(username, Err(e));
+                    continue;
+                }
+            };
+
+            let limits = self.limits_from_config(&config);
+            if limits == current {
+                continue;
+            }
+            let result = self.apply_limits(&username, limits).await;
+            results.insert(username, result);
+        }
+
+        results
+    }
+
+    /// Environment for an instance's custom health check script
+    pub fn health_check_env(&self, instance: &Instance) -> Vec<(String, String)> {
+        let mut env = self
+            .process_manager
+            .allowed_env(&instance.username, &instance.env_vars);
+        env.push(("FRAME_USERNAME".to_string(), instance.username.clone()));
+        env.push(("FRAME_PORT".to_string(), instance.port.to_string()));
+        env.push((
+            "FRAME_INSTANCE_DIR".to_string(),
+            self.instance_dir(&instance.username).display().to_string(),
+        ));
+        env
+    }
+
+    /// Set the detail message explaining an instance's current status
+    pub async fn set_status_detail(&self, username: &str, detail: Option<String>) {
+        let mut instances = self.instances.write().await;
+        if let Some(instance) = instances.get_mut(username) {
+            instance.status_detail = detail;
+        }
+    }
+
+    /// Restart an instance
+    pub async fn restart(&self, username: &str, port: u16) -> Result<()> {
+        validate_username(username)?;
+        {
+            let mut instances = self.instances.write().await;
+            if let Some(instance) = instances.get_mut(username) {
+                instance.restart_count += 1;
+            }
+        }
+        self.stop(username).await?;
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        self.start(username, port).await?;
+        Ok(())
+    }
+
+    /// Get instance status
+    pub async fn status(&self, username: &str) -> Result<Instance> {
+        validate_username(username)?;
+        let instances = self.instances.read().await;
+        instances
+            .get(username)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Instance not found for user: {}", username))
+    }
+
+    /// Whether `presented` is the instance's metrics token
+    pub async fn metrics_token_matches(&self, username: &str, presented: &str) -> bool {
+        let instances = self.instances.read().await;
+        instances
+            .get(username)
+            .and_then(|instance| instance.metrics_token.as_deref())
+            .is_some_and(|token| {
+                crate::api::auth::constant_time_eq(presented.as_bytes(), token.as_bytes())
+            })
+    }
+
+    /// List all instances
+    pub async fn list(&self) -> Vec<Instance> {
+        let instances = self.instances.read().await;
+        instances.values().cloned().collect()
+    }
+
+    /// Map every instance to a lightweight summary
+    ///
+    /// Only what `f` copies out is cloned, keeping the read lock short even
+    /// with thousands of instances; callers sort and serialize afterwards.
+    pub async fn summarize<T>(&self, f: impl FnMut(&Instance) -> T) -> Vec<T> {
+        let instances = self.instances.read().await;
+        instances.values().map(f).collect()
+    }
+
+    /// Create a new instance for a user
+    pub async fn create(&self, username: &str, limits: Option<ResourceLimits>) -> Result<()> {
+        validate_username(username)
+            .and_then(|()| self.ensure_managed(username))
+            .map_err(|e| CreateError::InvalidUser(e.to_string()))?;
+        // Held until the instance is inserted, so two creates can't both
+        // pass the check
+        let mut instances = self.instances.write().await;
+        if instances.contains_key(username) {
+            return Err(CreateError::Exists(username.to_string()).into());
+        }
+
+        let instance_dir = self.instances_dir.join(username);
+
+        // Create directory structure, reusing data kept from a removed instance
+        tokio::fs::create_dir_all(&instance_dir).await?;
+        for name in ["apps", "data", "logs"] {
+            create_instance_subdir(&instance_dir.join(name)).await?;
+        }
+
+        // Create default config, keeping explicit limits across restarts
+        let mut config = InstanceConfig::default();
+        if let Some(limits) = &limits {
+            config.memory_limit = limits.memory_mb;
+            config.cpu_limit = Some(limits.cpu_percent);
+            config.max_apps = limits.max_apps;
+            config.disk_quota = Some(limits.disk_quota_mb);
+        }
+        self.write_config(username, &config).await?;
+        removal::clear_removed(&self.removed_marker(username)).await?;
+
+        // Set ownership (requires root)
+        chown_to_user(&instance_dir, username).await;
+
+        let instance = Instance {
+            username: username.to_string(),
+            port: 0,
+            status: InstanceStatus::Stopped,
+            status_detail: None,
+            pid: None,
+            memory_usage: 0,
+            cpu_usage: 0.0,
+            app_count: 0,
+            limits: limits.unwrap_or_else(|| self.default_limits()),
+            env_vars: HashMap::new(),
+            log_level: None,
+            tls_port: None,
+            health_path: default_health_path(),
+            custom_health_check: None,
+            readiness_probe: None,
+            started_at: None,
+            last_health_check: None,
+            restart_count: 0,
+            last_exit_reason: None,
+            quarantined: false,
+            metrics_token: new_metrics_token(),
+        };
+
+        instances.insert(username.to_string(), instance);
+
+        tracing::info!("Created instance for user {}", username);
+
+        Ok(())
+    }
+
+    /// Remove an instance
+    ///
+    /// Unless `options.purge` is set, the user's apps and data are left in
+    /// the instance directory and only the runtime files are deleted.
+    pub async fn remove(&self, username: &str, options: RemoveOptions) -> Result<RemoveReport> {
+        // The name ends up in a recursive delete; it must stay inside instances_dir
+        validate_username(username)?;
+
+        // Stop if running
+        let _ = self.stop(username).await;
+
+        let instance_dir = self.instances_dir.join(username);
+        let mut report = RemoveReport {
+            archive: None,
+            purged: options.purge,
+        };
+
+        // Archive before anything is deleted, and keep the instance if that fails
+        if options.archive && instance_dir.exists() {
+            let archive = removal::archive(&instance_dir, &self.backups_dir).await?;
+            tracing::info!(
+                "Archived instance for user {} to {}",
+                username,
+                archive.display()
+            );
+            report.archive = Some(archive);
+        }
 
         // Remove from tracked instances
         let mut instances = self.instances.write().await;
         instances.remove(username);
 
-        // Remove directory
-        let instance_dir = self.instances_dir.join(username);
         if instance_dir.exists() {
-            tokio::fs::remove_dir_all(&instance_dir).await?;
+            if options.purge {
+                tokio::fs::remove_dir_all(&instance_dir).await?;
+            } else {
+                removal::remove_runtime_state(&instance_dir).await?;
+                removal::mark_removed(&self.removed_marker(username)).await?;
+            }
+        }
+        match tokio::fs::remove_file(self.state_path(username)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
         }
 
-        tracing::info!("Removed instance for user {}", username);
+        if options.purge {
+            tracing::info!("Removed instance and data for user {}", username);
+        } else {
+            tracing::info!(
+                "Removed instance for user {}, keeping apps and data in {}",
+                username,
+                instance_dir.display()
+            );
+        }
 
-        Ok(())
+        Ok(report)
     }
 
     /// Update instance resource usage
     pub async fn update_usage(&self, username: &str) -> Result<()> {
+        validate_username(username)?;
         let mut instances = self.instances.write().await;
 
         if let Some(instance) = instances.get_mut(username) {
@@ -398,3 +1953,513 @@ impl InstanceManager {
         instances.len()
     }
 }
+
+/// Instance manager over a temporary directory, with an instance for alice
+#[cfg(test)]
+pub(crate) async fn test_manager(
+    default_limits: ResourceLimits,
+) -> (tempfile::TempDir, InstanceManager) {
+    let dir = tempfile::tempdir().unwrap();
+    let manager = InstanceManager::new(
+        dir.path().to_path_buf(),
+        dir.path().join("frame-server"),
+        default_limits,
+        EnvPolicy::default(),
+        UserPolicy::default(),
+    )
+    .with_run_as(RunAs::Manager);
+    manager.create("alice", None).await.unwrap();
+    (dir, manager)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::BoxFuture;
+
+    #[tokio::test]
+    async fn test_create_instance() {
+        let (_dir, manager) = test_manager(ResourceLimits::default()).await;
+        let limits = ResourceLimits {
+            memory_mb: 256,
+            disk_quota_mb: 2048,
+            ..ResourceLimits::default()
+        };
+
+        manager.create("bob", Some(limits)).await.unwrap();
+        assert_eq!(manager.status("bob").await.unwrap().limits.memory_mb, 256);
+        let config: InstanceConfig = serde_json::from_str(
+            &std::fs::read_to_string(manager.instance_dir("bob").join("config.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(config.memory_limit, 256);
+        assert_eq!(config.disk_quota, Some(2048));
+
+        // Duplicates, unsafe names and excluded users are refused
+        let duplicate = manager.create("alice", None).await.unwrap_err();
+        assert!(duplicate.to_string().contains("already exists"));
+        assert!(manager.create("../alice", None).await.is_err());
+        assert!(manager.create("root", None).await.is_err());
+    }
+
+    /// Records the limits written to it, failing if the instances are locked
+    struct RecordingController(
+        Arc<Mutex<Vec<String>>>,
+        Arc<RwLock<HashMap<String, Instance>>>,
+    );
+
+    impl RecordingController {
+        fn record(&self, write: String) -> BoxFuture<'_, std::io::Result<()>> {
+            if self.1.try_write().is_err() {
+                let locked = std::io::Error::other("written with the instances lock held");
+                return Box::pin(futures::future::ready(Err(locked)));
+            }
+            self.0.lock().unwrap().push(write);
+            Box::pin(futures::future::ready(Ok(())))
+        }
+    }
+
+    impl ResourceController for RecordingController {
+        fn set_memory_limit(&self, limit_bytes: u64) -> BoxFuture<'_, std::io::Result<()>> {
+            self.record(format!("memory.max={}", limit_bytes))
+        }
+        fn set_cpu_limit(&self, percent: u8) -> BoxFuture<'_, std::io::Result<()>> {
+            self.record(format!("cpu={}", percent))
+        }
+        fn add_process(&self, _pid: u32) -> BoxFuture<'_, std::io::Result<()>> {
+            Box::pin(futures::future::ready(Ok(())))
+        }
+        fn freeze(&self) -> BoxFuture<'_, std::io::Result<()>> {
+            Box::pin(futures::future::ready(Ok(())))
+        }
+        fn thaw(&self) -> BoxFuture<'_, std::io::Result<()>> {
+            Box::pin(futures::future::ready(Ok(())))
+        }
+        fn kill_all(&self) -> BoxFuture<'_, std::io::Result<()>> {
+            Box::pin(futures::future::ready(Ok(())))
+        }
+        fn remove(&self) -> BoxFuture<'_, std::io::Result<()>> {
+            Box::pin(futures::future::ready(Ok(())))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_limits_to_running_instance() {
+        let (_dir, manager) = test_manager(ResourceLimits::default()).await;
+        manager.create("bob", None).await.unwrap();
+        manager
+            .instances
+            .write()
+            .await
+            .get_mut("alice")
+            .unwrap()
+            .status = InstanceStatus::Running;
+
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let recorder = || {
+            |_: &str, _| {
+                let controller =
+                    RecordingController(Arc::clone(&writes), Arc::clone(&manager.instances));
+                Some(Box::new(controller) as Box<_>)
+            }
+        };
+        let limits = ResourceLimits {
+            memory_mb: 1024,
+            cpu_percent: 50,
+            ..ResourceLimits::default()
+        };
+
+        // Running: written to the cgroup and kept in memory
+        let applied = manager
+            .apply_limits_with("alice", limits.clone(), recorder())
+            .await
+            .unwrap();
+        assert_eq!(applied, LimitsApplied::Live);
+        assert_eq!(
+            *writes.lock().unwrap(),
+            vec![
+                format!("memory.max={}", 1024 * 1024 * 1024),
+                "cpu=50".to_string()
+            ]
+        );
+        assert_eq!(manager.status("alice").await.unwrap().limits, limits);
+
+        // Stopped: nothing written until the next start
+        let applied = manager
+            .apply_limits_with("bob", limits.clone(), recorder())
+            .await
+            .unwrap();
+        assert_eq!(applied, LimitsApplied::OnRestart);
+        assert_eq!(writes.lock().unwrap().len(), 2);
+        assert_eq!(manager.status("bob").await.unwrap().limits, limits);
+
+        // New defaults reach instances that leave the CPU limit unset
+        manager.set_limits("bob", limits.clone()).await.unwrap();
+        let results = manager
+            .set_default_limits(ResourceLimits {
+                cpu_percent: 10,
+                ..ResourceLimits::default()
+            })
+            .await;
+        assert_eq!(results.keys().collect::<Vec<_>>(), vec!["alice"]);
+        assert_eq!(
+            manager.status("alice").await.unwrap().limits.cpu_percent,
+            10
+        );
+        assert_eq!(manager.status("bob").await.unwrap().limits, limits);
+    }
+
+    #[tokio::test]
+    async fn test_set_limits_never_writes_through_symlinks() {
+        let (dir, manager) = test_manager(ResourceLimits::default()).await;
+        let target = dir.path().join("target");
+        std::fs::write(&target, "untouched").unwrap();
+        let config_path = manager.instance_dir("alice").join("config.json");
+        std::fs::remove_file(&config_path).unwrap();
+        std::os::unix::fs::symlink(&target, &config_path).unwrap();
+        let limits = ResourceLimits {
+            memory_mb: 1024,
+            ..ResourceLimits::default()
+        };
+
+        // A planted link is neither read nor written through
+        assert!(manager.set_limits("alice", limits.clone()).await.is_err());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "untouched");
+
+        // Once the link is gone, the new config replaces whatever is there
+        std::fs::remove_file(&config_path).unwrap();
+        manager.set_limits("alice", limits).await.unwrap();
+        assert!(std::fs::symlink_metadata(&config_path).unwrap().is_file());
+        let config: InstanceConfig =
+            serde_json::from_str(&std::fs::read_to_string(&config_path).unwrap()).unwrap();
+        assert_eq!(config.memory_limit, 1024);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "untouched");
+    }
+
+    #[tokio::test]
+    async fn test_set_log_level_never_writes_through_symlinks() {
+        let (dir, manager) = test_manager(ResourceLimits::default()).await;
+        let target = dir.path().join("target");
+        std::fs::write(&target, "untouched").unwrap();
+        let config_path = manager.instance_dir("alice").join("config.json");
+        std::fs::remove_file(&config_path).unwrap();
+        std::os::unix::fs::symlink(&target, &config_path).unwrap();
+
+        let level = Some("debug".to_string());
+        assert!(manager.set_log_level("alice", level.clone()).await.is_err());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "untouched");
+        assert_eq!(manager.status("alice").await.unwrap().log_level, None);
+
+        std::fs::remove_file(&config_path).unwrap();
+        manager.set_log_level("alice", level.clone()).await.unwrap();
+        assert!(std::fs::symlink_metadata(&config_path).unwrap().is_file());
+        assert_eq!(manager.status("alice").await.unwrap().log_level, level);
+    }
+
+    #[tokio::test]
+    async fn test_create_never_writes_through_symlinks() {
+        let (dir, manager) = test_manager(ResourceLimits::default()).await;
+        let target = dir.path().join("target");
+        std::fs::write(&target, "untouched").unwrap();
+        let outside = dir.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        manager
+            .remove("alice", RemoveOptions::default())
+            .await
+            .unwrap();
+
+        // Links the former owner left in the kept directory aren't followed
+        let instance_dir = manager.instance_dir("alice");
+        let config_path = instance_dir.join("config.json");
+        std::os::unix::fs::symlink(&target, &config_path).unwrap();
+        manager.create("alice", None).await.unwrap();
+        assert!(std::fs::symlink_metadata(&config_path).unwrap().is_file());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "untouched");
+
+        manager
+            .remove("alice", RemoveOptions::default())
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(instance_dir.join("apps")).unwrap();
+        std::os::unix::fs::symlink(&outside, instance_dir.join("apps")).unwrap();
+        let err = manager.create("alice", None).await.unwrap_err();
+        assert!(err.to_string().contains("is not a directory"));
+        assert!(manager.status("alice").await.is_err());
+    }
+
+    #[test]
+    fn test_chown_recursive_stays_inside_the_tree() {
+        use std::os::unix::fs::MetadataExt;
+
+        // Changing ownership needs root
+        if !nix::unistd::Uid::effective().is_root() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("secret"), "root only").unwrap();
+        let tree = dir.path().join("tree");
+        std::fs::create_dir_all(tree.join("apps/site")).unwrap();
+        std::fs::write(tree.join("apps/site/index.html"), "hi").unwrap();
+        std::os::unix::fs::symlink(&outside, tree.join("apps/link")).unwrap();
+
+        chown_recursive(&tree, 4321, 4321).unwrap();
+
+        let owner = |path: &Path| std::fs::symlink_metadata(path).unwrap().uid();
+        assert_eq!(owner(&tree.join("apps/site/index.html")), 4321);
+        assert_eq!(owner(&tree.join("apps/link")), 4321);
+        assert_eq!(owner(&outside), 0);
+        assert_eq!(owner(&outside.join("secret")), 0);
+
+        // A symlink as the root of the walk is changed itself, not followed
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&outside, &link).unwrap();
+        chown_recursive(&link, 4321, 4321).unwrap();
+        assert_eq!(owner(&link), 4321);
+        assert_eq!(owner(&outside.join("secret")), 0);
+    }
+
+    #[tokio::test]
+    async fn test_start_refuses_frozen_instance() {
+        let (_dir, manager) = test_manager(ResourceLimits::default()).await;
+        {
+            let mut instances = manager.instances.write().await;
+            let instance = instances.get_mut("alice").unwrap();
+            instance.status = InstanceStatus::Frozen;
+            instance.pid = Some(4242);
+            instance.port = 30001;
+        }
+
+        let err = manager.start("alice", 30002).await.unwrap_err();
+        assert!(err.to_string().contains("is frozen, thaw it first"));
+        let instance = manager.status("alice").await.unwrap();
+        assert_eq!(instance.status, InstanceStatus::Frozen);
+        assert_eq!(instance.pid, Some(4242));
+        assert_eq!(instance.port, 30001);
+    }
+
+    #[tokio::test]
+    async fn test_uptime_of_running_instance() {
+        let (_dir, manager) = test_manager(ResourceLimits::default()).await;
+        let now = Utc::now();
+        assert_eq!(manager.status("alice").await.unwrap().uptime(now), None);
+
+        {
+            let mut instances = manager.instances.write().await;
+            let instance = instances.get_mut("alice").unwrap();
+            instance.status = InstanceStatus::Running;
+            instance.started_at = Some(now - chrono::Duration::seconds(5));
+        }
+        let instance = manager.status("alice").await.unwrap();
+        let first = instance.uptime(now).unwrap();
+        let later = instance
+            .uptime(now + chrono::Duration::seconds(30))
+            .unwrap();
+        assert_eq!(first, 5);
+        assert_eq!(later, 35);
+
+        // Never negative, even if the clock went backwards since the start
+        assert_eq!(
+            instance.uptime(now - chrono::Duration::seconds(60)),
+            Some(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_state_kept_outside_instance_dir() {
+        let (_dir, manager) = test_manager(ResourceLimits::default()).await;
+        manager.save_state("alice").await;
+
+        let state_file = manager.state_dir.join("alice.json");
+        assert!(state_file.exists());
+        assert!(!manager.instance_dir("alice").join("state.json").exists());
+
+        // A quarantine survives a manager restart without touching config.json
+        let config_before =
+            std::fs::read_to_string(manager.instance_dir("alice").join("config.json")).unwrap();
+        manager
+            .instances
+            .write()
+            .await
+            .get_mut("alice")
+            .unwrap()
+            .quarantined = true;
+        manager.save_state("alice").await;
+        manager.load_instance("alice").await.unwrap();
+        assert!(manager.status("alice").await.unwrap().quarantined);
+        assert_eq!(
+            std::fs::read_to_string(manager.instance_dir("alice").join("config.json")).unwrap(),
+            config_before
+        );
+
+        manager
+            .remove("alice", RemoveOptions::default())
+            .await
+            .unwrap();
+        assert!(!state_file.exists());
+
+        // A PID is only re-adopted when it runs as the instance's user
+        let me = nix::unistd::User::from_uid(nix::unistd::getuid())
+            .unwrap()
+            .unwrap();
+        assert!(InstanceManager::runs_as(std::process::id(), &me.name));
+        if me.name != "nobody" {
+            assert!(!InstanceManager::runs_as(std::process::id(), "nobody"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restartable_exit_clears_pid() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (dir, manager) = test_manager(ResourceLimits::default()).await;
+        let server = dir.path().join("frame-server");
+        std::fs::write(&server, "#!/bin/sh\nsleep 0.3\nexit 3\n").unwrap();
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+        manager.start("alice", 30001).await.unwrap();
+
+        let mut exit = None;
+        for _ in 0..50 {
+            exit = manager.handle_exit("alice").await;
+            if exit.is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        let exit = exit.unwrap();
+        assert_eq!(exit.action, ExitAction::Restart);
+
+        // Waiting for its restart the instance doesn't pose as running
+        let instance = manager.status("alice").await.unwrap();
+        assert_eq!(instance.status, InstanceStatus::Failed);
+        assert_eq!(instance.pid, None);
+        assert_eq!(
+            instance.status_detail.as_deref(),
+            Some("exited with code 3, restarting")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_start_waits_for_spawn_slot() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (dir, manager) = test_manager(ResourceLimits::default()).await;
+        let server = dir.path().join("frame-server");
+        std::fs::write(&server, "#!/bin/sh\nexec sleep 30\n").unwrap();
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let manager = Arc::new(manager.with_max_concurrent_starts(2));
+        let users = ["alice", "bob", "carol", "dave", "erin", "frank"];
+        for username in &users[1..] {
+            manager.create(username, None).await.unwrap();
+        }
+
+        let starts = futures::future::join_all(
+            users
+                .iter()
+                .zip(30001..)
+                .map(|(username, port)| manager.start(username, port)),
+        );
+        // Count the instances spawning at once while the starts run
+        let watch = async {
+            let (mut peak, mut waited) = (0, false);
+            loop {
+                let instances = manager.list().await;
+                let details: Vec<Option<&str>> = instances
+                    .iter()
+                    .map(|i| i.status_detail.as_deref())
+                    .collect();
+                let spawning = details.iter().filter(|d| **d == Some("spawning")).count();
+                peak = peak.max(spawning);
+                waited |= details.contains(&Some("waiting for a start slot"));
+                if instances
+                    .iter()
+                    .all(|i| i.status == InstanceStatus::Running)
+                {
+                    return (peak, waited);
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        };
+        let (results, (peak, waited)) = tokio::join!(starts, watch);
+
+        for result in results {
+            result.unwrap();
+        }
+        assert_eq!(peak, 2);
+        assert!(waited);
+        for username in users {
+            manager.stop(username).await.unwrap();
+        }
+    }
+
+    /// Lock hold and total time of listing a few thousand instances, by
+    /// cloning whole instances (as `list_instances` used to) and by
+    /// summarizing them into responses under the lock
+    ///
+    /// A timing, not a check: run it with
+    /// `cargo test --release -p frame-manager bench_list -- --ignored --nocapture`.
+    #[tokio::test]
+    #[ignore]
+    async fn bench_list_instances() {
+        use crate::api::InstanceStatusResponse;
+        use std::time::{Duration, Instant};
+
+        const INSTANCES: usize = 5000;
+        const ROUNDS: usize = 50;
+
+        let (_dir, manager) = test_manager(ResourceLimits::default()).await;
+        {
+            let mut instances = manager.instances.write().await;
+            let template = instances["alice"].clone();
+            for n in 0..INSTANCES {
+                let mut instance = template.clone();
+                instance.username = format!("user{:05}", n);
+                instance.status = InstanceStatus::Running;
+                instance.started_at = Some(Utc::now());
+                // A typical app configuration
+                for var in 0..8 {
+                    instance
+                        .env_vars
+                        .insert(format!("APP_SETTING_{}", var), "x".repeat(32));
+                }
+                instances.insert(instance.username.clone(), instance);
+            }
+        }
+
+        let mut before = (Duration::MAX, Duration::MAX);
+        let mut after = (Duration::MAX, Duration::MAX);
+        for _ in 0..ROUNDS {
+            let start = Instant::now();
+            let instances = manager.list().await;
+            let locked = start.elapsed();
+            let responses: Vec<InstanceStatusResponse> = instances
+                .iter()
+                .map(|i| InstanceStatusResponse::new(i, false, None))
+                .collect();
+            let body = serde_json::to_vec(&responses).unwrap();
+            before = (before.0.min(locked), before.1.min(start.elapsed()));
+            drop(body);
+
+            let start = Instant::now();
+            let responses = manager
+                .summarize(|i| InstanceStatusResponse::new(i, false, None))
+                .await;
+            let locked = start.elapsed();
+            let body = serde_json::to_vec(&responses).unwrap();
+            after = (after.0.min(locked), after.1.min(start.elapsed()));
+            drop(body);
+        }
+
+        println!(
+            "{} instances, best of {}: clone all: lock {:?}, total {:?}; \
+             summarize: lock {:?}, total {:?}",
+            INSTANCES + 1,
+            ROUNDS,
+            before.0,
+            before.1,
+            after.0,
+            after.1
+        );
+    }
+}
@@ -3,32 +3,206 @@
 //! Handles spawning and managing Frame server processes.
 
 use anyhow::{Context, Result};
-use nix::sys::signal::{kill, Signal};
+use nix::sys::signal::{kill, killpg, Signal};
 use nix::unistd::Pid;
+use std::collections::HashMap;
 use std::path::Path;
-use std::process::Stdio;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
 
-use super::ResourceLimits;
+use super::resource::{CgroupBackend, CgroupController, SystemdController};
+use super::server_config::{ServerConfig, SpawnMode, SERVER_CONFIG_FILE};
+use super::{chown_to_user, ResourceLimits};
+use crate::metrics::{clock_ticks, parse_cpu_ticks};
+use crate::port::is_port_in_use;
+
+/// Poll interval while waiting for a stopped process to be reaped
+const RELEASE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Keys that are never passed through from instance configuration
+const DEFAULT_ENV_DENYLIST: &[&str] = &[
+    "LD_*",
+    "PATH",
+    "IFS",
+    "BASH_ENV",
+    "ENV",
+    "SHELLOPTS",
+    "PS4",
+    "HOME",
+    "USER",
+    "SUDO_*",
+    "FRAME_*",
+];
+
+/// How spawned servers take on their instance user's identity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RunAs {
+    /// Through `sudo -u <user>`
+    #[default]
+    Sudo,
+    /// Unchanged, as the manager's own user; for tests, whose instance
+    /// users don't exist
+    Manager,
+}
+
+impl RunAs {
+    /// Command prefix running the rest of the command line as `username`
+    fn prefix(self, username: &str) -> Vec<&str> {
+        match self {
+            RunAs::Sudo => vec!["sudo", "-u", username],
+            RunAs::Manager => vec!["env"],
+        }
+    }
+}
+
+/// Policy deciding which instance env vars reach the frame-server process
+#[derive(Debug, Clone)]
+pub struct EnvPolicy {
+    /// If non-empty, only keys matching one of these patterns are allowed
+    pub allowlist: Vec<String>,
+    /// Keys matching any of these patterns are always dropped
+    pub denylist: Vec<String>,
+}
+
+impl Default for EnvPolicy {
+    fn default() -> Self {
+        Self {
+            allowlist: Vec::new(),
+            denylist: DEFAULT_ENV_DENYLIST.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl EnvPolicy {
+    /// Check whether an env var key may be passed to the child process
+    pub fn allows(&self, key: &str) -> bool {
+        if key.is_empty() || key.contains('=') || key.contains('\0') {
+            return false;
+        }
+        if self.denylist.iter().any(|p| Self::matches(p, key)) {
+            return false;
+        }
+        self.allowlist.is_empty() || self.allowlist.iter().any(|p| Self::matches(p, key))
+    }
+
+    /// Match a key against a pattern; a trailing `*` matches any suffix
+    fn matches(pattern: &str, key: &str) -> bool {
+        match pattern.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => pattern == key,
+        }
+    }
+}
+
+/// Parameters for spawning a Frame server process
+pub struct SpawnRequest<'a> {
+    /// User the process runs as
+    pub username: &'a str,
+    /// Port the server listens on
+    pub port: u16,
+    /// Instance data directory (holds `data/` and `logs/`)
+    pub instance_dir: &'a Path,
+    /// Directory of apps to serve
+    pub apps_dir: &'a Path,
+    /// Resource limits
+    pub limits: &'a ResourceLimits,
+    /// User-configured environment variables
+    pub env_vars: &'a HashMap<String, String>,
+    /// Log level for the server (server default if `None`)
+    pub log_level: Option<&'a str>,
+    /// Secret for the server's metrics reports
+    pub metrics_token: Option<&'a str>,
+}
 
 /// Process manager for Frame server instances
-pub struct ProcessManager;
+pub struct ProcessManager {
+    env_policy: EnvPolicy,
+    cgroup_backend: CgroupBackend,
+    spawn_mode: SpawnMode,
+    run_as: RunAs,
+    /// Exit statuses of reaped processes, until collected
+    exits: Arc<Mutex<HashMap<u32, ExitStatus>>>,
+    /// Sent the PID of each spawned process as soon as it is reaped
+    exit_tx: mpsc::UnboundedSender<u32>,
+    /// Receiving end of `exit_tx`, until the crash handler takes it
+    exit_rx: Mutex<Option<mpsc::UnboundedReceiver<u32>>>,
+    /// CPU ticks and time of the previous usage sample per PID
+    cpu_samples: Mutex<HashMap<u32, (u64, Instant)>>,
+}
 
 impl ProcessManager {
     pub fn new() -> Self {
-        Self
+        Self::with_env_policy(EnvPolicy::default())
     }
 
-    /// Spawn a new Frame server process for a user
-    pub async fn spawn(
+    /// Create a process manager enforcing the given env var policy
+    pub fn with_env_policy(env_policy: EnvPolicy) -> Self {
+        let (exit_tx, exit_rx) = mpsc::unbounded_channel();
+        Self {
+            env_policy,
+            cgroup_backend: CgroupBackend::default(),
+            spawn_mode: SpawnMode::default(),
+            run_as: RunAs::default(),
+            exits: Arc::new(Mutex::new(HashMap::new())),
+            exit_tx,
+            exit_rx: Mutex::new(Some(exit_rx)),
+            cpu_samples: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Place spawned processes in cgroups using `backend`
+    pub fn with_cgroup_backend(mut self, backend: CgroupBackend) -> Self {
+        self.cgroup_backend = backend;
+        self
+    }
+
+    /// Pass settings to frame-server as `mode` describes
+    pub fn with_spawn_mode(mut self, mode: SpawnMode) -> Self {
+        self.spawn_mode = mode;
+        self
+    }
+
+    /// Run spawned servers as their user the way `run_as` describes
+    pub fn with_run_as(mut self, run_as: RunAs) -> Self {
+        self.run_as = run_as;
+        self
+    }
+
+    /// User-configured environment variables permitted by the env var policy
+    pub fn allowed_env(
         &self,
         username: &str,
-        frame_server_path: &Path,
-        port: u16,
-        instance_dir: &Path,
-        limits: &ResourceLimits,
-    ) -> Result<u32> {
-        let apps_dir = instance_dir.join("apps");
+        env_vars: &HashMap<String, String>,
+    ) -> Vec<(String, String)> {
+        env_vars
+            .iter()
+            .filter(|(key, _)| {
+                let allowed = self.env_policy.allows(key);
+                if !allowed {
+                    tracing::warn!("Dropping disallowed env var {} for user {}", key, username);
+                }
+                allowed
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Spawn a new Frame server process for a user
+    pub async fn spawn(&self, frame_server_path: &Path, request: SpawnRequest<'_>) -> Result<u32> {
+        let SpawnRequest {
+            username,
+            port,
+            instance_dir,
+            apps_dir,
+            limits,
+            env_vars,
+            log_level,
+            metrics_token,
+        } = request;
         let data_dir = instance_dir.join("data");
         let log_file = instance_dir.join("logs").join("frame.log");
 
@@ -37,24 +211,86 @@ impl ProcessManager {
             tokio::fs::create_dir_all(parent).await?;
         }
 
-        // Build command with sudo to run as the user
-        let mut cmd = Command::new("sudo");
-        cmd.args(["-u", username])
-            .arg(frame_server_path)
-            .args(["--port", &port.to_string()])
-            .args(["--app-dir", apps_dir.to_str().unwrap()])
-            .args(["--data-dir", data_dir.to_str().unwrap()])
-            .args(["--memory-limit", &limits.memory_mb.to_string()])
-            .stdin(Stdio::null())
+        // Build command with sudo to run as the user, inside a transient
+        // scope under the user's slice when systemd manages the cgroups
+        let run_as = self.run_as.prefix(username);
+        let mut cmd = match self.cgroup_backend {
+            CgroupBackend::Direct => {
+                let mut cmd = Command::new(run_as[0]);
+                cmd.args(&run_as[1..]);
+                cmd
+            }
+            CgroupBackend::Systemd => {
+                let mut cmd = Command::new("systemd-run");
+                cmd.args(SystemdController::for_instance(username, port).scope_command(limits))
+                    .args(&run_as);
+                cmd
+            }
+        };
+        cmd.arg(frame_server_path);
+
+        let env = self.allowed_env(username, env_vars);
+        match self.spawn_mode {
+            SpawnMode::Args => {
+                cmd.args(["--port", &port.to_string()])
+                    .args(["--app-dir", apps_dir.to_str().unwrap()])
+                    .args(["--data-dir", data_dir.to_str().unwrap()])
+                    .args(["--memory-limit", &limits.memory_mb.to_string()]);
+            }
+            SpawnMode::ConfigFile => {
+                // Regenerated on every start so it never goes stale
+                let config_path = instance_dir.join(SERVER_CONFIG_FILE);
+                let config = ServerConfig {
+                    port,
+                    app_dir: apps_dir,
+                    data_dir: &data_dir,
+                    log_level,
+                    limits,
+                    env: &env,
+                };
+                write_private(&config_path, &config.render()).await?;
+                chown_to_user(&config_path, username).await;
+                cmd.arg("--config").arg(&config_path);
+            }
+        }
+        cmd.stdin(Stdio::null())
             .stdout(Stdio::null())
             .stderr(Stdio::null());
 
+        // Pass user-configured environment, dropping keys the policy forbids
+        cmd.envs(env);
+
         // Set resource limits via environment
         cmd.env("FRAME_MEMORY_LIMIT_MB", limits.memory_mb.to_string());
         cmd.env("FRAME_CPU_LIMIT_PERCENT", limits.cpu_percent.to_string());
         cmd.env("FRAME_MAX_CONNECTIONS", limits.max_connections.to_string());
+        if let Some(level) = log_level {
+            cmd.env("FRAME_LOG_LEVEL", level);
+        }
+        if let Some(token) = metrics_token {
+            cmd.env("FRAME_METRICS_TOKEN", token);
+        }
+
+        // Lead a process group of its own, so sudo and the frame-server it
+        // runs can be killed together
+        cmd.process_group(0);
 
-        let child = cmd
+        // The env vars above are advisory; the cgroup makes the kernel
+        // enforce the limits. The process joins it before exec, so it never
+        // runs unconfined. systemd-run places the process in its scope itself.
+        if let CgroupBackend::Direct = self.cgroup_backend {
+            let joined = CgroupController::prepare(username, limits)
+                .and_then(|cgroup| cgroup.join_on_spawn(cmd.as_std_mut()));
+            if let Err(e) = joined {
+                tracing::warn!(
+                    "Running {}'s instance without cgroup limits: {}",
+                    username,
+                    e
+                );
+            }
+        }
+
+        let mut child = cmd
             .spawn()
             .with_context(|| format!("Failed to spawn Frame server for user {}", username))?;
 
@@ -62,11 +298,35 @@ impl ProcessManager {
             .id()
             .ok_or_else(|| anyhow::anyhow!("Failed to get process ID"))?;
 
+        // Reap the process when it exits, keep its status for the crash
+        // handler and wake it up right away
+        let exits = Arc::clone(&self.exits);
+        let exit_tx = self.exit_tx.clone();
+        tokio::spawn(async move {
+            if let Ok(status) = child.wait().await {
+                exits
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(pid, status);
+                let _ = exit_tx.send(pid);
+            }
+        });
+
         // Wait briefly and check if process is still running
         tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
 
         if !self.is_running(pid) {
-            anyhow::bail!("Frame server process exited immediately for user {}", username);
+            match self.take_exit_status(pid) {
+                Some(status) => anyhow::bail!(
+                    "Frame server process exited immediately for user {} ({})",
+                    username,
+                    status
+                ),
+                None => anyhow::bail!(
+                    "Frame server process exited immediately for user {}",
+                    username
+                ),
+            }
         }
 
         Ok(pid)
@@ -95,7 +355,7 @@ impl ProcessManager {
 
         // Force kill if still running
         tracing::warn!("Process {} did not stop gracefully, sending SIGKILL", pid);
-        if let Err(e) = kill(nix_pid, Signal::SIGKILL) {
+        if let Err(e) = kill_group(pid) {
             if e != nix::errno::Errno::ESRCH {
                 anyhow::bail!("Failed to kill process {}: {}", pid, e);
             }
@@ -104,10 +364,103 @@ impl ProcessManager {
         Ok(())
     }
 
+    /// Wait until a stopped process is gone and its port is free
+    ///
+    /// SIGKILL returns before the kernel has reaped the process, and a child
+    /// of the tracked PID may still hold the port. Returns false if either
+    /// is still the case after `grace`.
+    pub async fn wait_for_release(&self, pid: u32, port: u16, grace: Duration) -> bool {
+        let deadline = Instant::now() + grace;
+        loop {
+            if !self.is_running(pid) && !is_port_in_use(port) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(RELEASE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// SIGKILL a process and its process group, then wait up to `grace`
+    /// for them to disappear
+    ///
+    /// Returns whether the process is gone; whether its port is free is
+    /// for `wait_for_release` to tell.
+    pub async fn kill(&self, pid: u32, grace: Duration) -> Result<bool> {
+        if let Err(e) = kill_group(pid) {
+            if e != nix::errno::Errno::ESRCH {
+                anyhow::bail!("Failed to kill process {}: {}", pid, e);
+            }
+        }
+
+        let deadline = Instant::now() + grace;
+        while self.is_running(pid) {
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            tokio::time::sleep(RELEASE_POLL_INTERVAL).await;
+        }
+        Ok(true)
+    }
+
+    /// Whether a process is in uninterruptible sleep (`D` state), where
+    /// even SIGKILL has no effect until it returns from the kernel
+    pub fn is_uninterruptible(&self, pid: u32) -> bool {
+        self.process_state(pid) == Some('D')
+    }
+
+    /// Scheduler state of a process from `/proc/<pid>/stat`
+    fn process_state(&self, pid: u32) -> Option<char> {
+        // The state follows the parenthesized command name, which may
+        // itself contain spaces or parentheses
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        let (_, rest) = stat.rsplit_once(')')?;
+        rest.split_whitespace().next()?.chars().next()
+    }
+
+    /// Take the stream of PIDs of exited processes
+    ///
+    /// There is one stream per process manager; later calls return `None`.
+    pub fn take_exit_notifications(&self) -> Option<mpsc::UnboundedReceiver<u32>> {
+        self.exit_rx
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+    }
+
+    /// Collect the exit status of a reaped process, if it has exited
+    pub fn take_exit_status(&self, pid: u32) -> Option<ExitStatus> {
+        self.cpu_samples
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&pid);
+        self.exits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&pid)
+    }
+
     /// Check if a process is running
+    ///
+    /// A process that has exited but not been reaped yet (a zombie) still
+    /// answers signals, so the reaper's record and the process state decide.
     pub fn is_running(&self, pid: u32) -> bool {
+        if self
+            .exits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(&pid)
+        {
+            return false;
+        }
         let nix_pid = Pid::from_raw(pid as i32);
-        kill(nix_pid, None).is_ok()
+        kill(nix_pid, None).is_ok() && !self.is_zombie(pid)
+    }
+
+    /// Whether a process has exited and is waiting to be reaped (`Z` state)
+    fn is_zombie(&self, pid: u32) -> bool {
+        self.process_state(pid) == Some('Z')
     }
 
     /// Get resource usage for a process
@@ -126,17 +479,20 @@ impl ProcessManager {
             let page_size = 4096u64; // Typical page size
             let memory_bytes = rss_pages * page_size;
 
-            // Get CPU usage (simplified - would need sampling for accurate %)
+            // Get CPU usage since the previous sample of this PID
             let stat = std::fs::read_to_string(&stat_path)
                 .with_context(|| format!("Failed to read {}", stat_path))?;
-            let stat_parts: Vec<&str> = stat.split_whitespace().collect();
-            let utime: u64 = stat_parts.get(13).unwrap_or(&"0").parse().unwrap_or(0);
-            let stime: u64 = stat_parts.get(14).unwrap_or(&"0").parse().unwrap_or(0);
-            let total_time = (utime + stime) as f32;
-            // This is simplified - real implementation would track over time
-            let cpu_percent = (total_time / 100.0).min(100.0);
+            let ticks =
+                parse_cpu_ticks(&stat).ok_or_else(|| anyhow::anyhow!("Malformed {}", stat_path))?;
+            let now = Instant::now();
+            let previous = self
+                .cpu_samples
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(pid, (ticks, now));
+            let cpu_percent = cpu_percent(previous, (ticks, now), clock_ticks());
 
-            return Ok((memory_bytes, cpu_percent));
+            Ok((memory_bytes, cpu_percent))
         }
 
         // Fallback for non-Linux
@@ -147,8 +503,231 @@ impl ProcessManager {
     }
 }
 
+/// Write a file readable only by its owner (it may hold secrets from env vars)
+///
+/// The directory belongs to the instance's user, who could plant a symlink
+/// at `path` to have root write elsewhere. The content goes to a freshly
+/// created temporary file that is never followed through a link, and is then
+/// renamed over `path`, which replaces a link rather than writing through it.
+pub(super) async fn write_private(path: &Path, content: &str) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Invalid file path {}", path.display()))?;
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name));
+    // A leftover (or a planted link) would make the exclusive create fail
+    match tokio::fs::remove_file(&tmp_path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to remove {}", tmp_path.display()))
+        }
+        _ => {}
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .custom_flags(nix::fcntl::OFlag::O_NOFOLLOW.bits())
+        .mode(0o600)
+        .open(&tmp_path)
+        .await
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    file.write_all(content.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// CPU usage between two `(ticks, time)` samples, as percent of one core
+/// (the unit of `cpu_limit`); 0 without a previous sample
+fn cpu_percent(previous: Option<(u64, Instant)>, current: (u64, Instant), clock_ticks: u64) -> f32 {
+    let Some((last_ticks, last_time)) = previous else {
+        return 0.0;
+    };
+    let elapsed = current.1.duration_since(last_time).as_secs_f32();
+    if elapsed <= 0.0 {
+        return 0.0;
+    }
+    let cpu_secs = current.0.saturating_sub(last_ticks) as f32 / clock_ticks as f32;
+    cpu_secs / elapsed * 100.0
+}
+
 impl Default for ProcessManager {
     fn default() -> Self {
         Self::new()
     }
 }
+
+/// SIGKILL the process group led by `pid`
+///
+/// Instances spawned by this manager lead their own group, which also holds
+/// the frame-server sudo started. A re-adopted process from an older
+/// manager may not; then only the process itself is killed.
+fn kill_group(pid: u32) -> nix::Result<()> {
+    let pid = Pid::from_raw(pid as i32);
+    match killpg(pid, Signal::SIGKILL) {
+        Err(nix::errno::Errno::ESRCH) => kill(pid, Signal::SIGKILL),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_policy_denylist() {
+        let policy = EnvPolicy::default();
+
+        assert!(policy.allows("APP_MODE"));
+        assert!(!policy.allows("LD_PRELOAD"));
+        assert!(!policy.allows("PATH"));
+        assert!(!policy.allows("FRAME_MEMORY_LIMIT_MB"));
+        assert!(!policy.allows("BAD=KEY"));
+    }
+
+    #[test]
+    fn test_env_policy_allowlist() {
+        let policy = EnvPolicy {
+            allowlist: vec!["APP_*".to_string(), "DATABASE_URL".to_string()],
+            ..EnvPolicy::default()
+        };
+
+        assert!(policy.allows("APP_MODE"));
+        assert!(policy.allows("DATABASE_URL"));
+        assert!(!policy.allows("OTHER"));
+    }
+
+    #[test]
+    fn test_cpu_percent_from_stat_deltas() {
+        let stat = |utime: u64, stime: u64| {
+            format!(
+                "4242 (frame server) S 1 4242 4242 0 -1 4194560 120 0 0 0 {} {} 0 0 20 0 4 0",
+                utime, stime
+            )
+        };
+        let start = Instant::now();
+        let first = (parse_cpu_ticks(&stat(300, 100)).unwrap(), start);
+        let second = (
+            parse_cpu_ticks(&stat(330, 120)).unwrap(),
+            start + Duration::from_secs(2),
+        );
+
+        // No baseline yet
+        assert_eq!(cpu_percent(None, first, 100), 0.0);
+        // 50 ticks at 100 Hz over 2 seconds
+        assert!((cpu_percent(Some(first), second, 100) - 25.0).abs() < 0.01);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_release() {
+        let mut child = Command::new("true").spawn().unwrap();
+        let pid = child.id().unwrap();
+        child.wait().await.unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let manager = ProcessManager::new();
+
+        // Port still held: the wait gives up after the grace period
+        assert!(
+            !manager
+                .wait_for_release(pid, port, Duration::from_millis(100))
+                .await
+        );
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(listener);
+        });
+        assert!(
+            manager
+                .wait_for_release(pid, port, Duration::from_secs(5))
+                .await
+        );
+    }
+
+    #[test]
+    fn test_zombie_is_not_running() {
+        let manager = ProcessManager::new();
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        assert!(manager.is_running(pid));
+
+        // Killed but not waited on: a zombie until reaped
+        child.kill().unwrap();
+        for _ in 0..50 {
+            if manager.is_zombie(pid) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(kill(Pid::from_raw(pid as i32), None).is_ok());
+        assert!(!manager.is_running(pid));
+        child.wait().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_kill_takes_the_process_group() {
+        use tokio::io::AsyncBufReadExt;
+
+        // Stands in for sudo: the leader forks the real server and waits
+        let mut leader = Command::new("sh")
+            .args(["-c", "sleep 60 & echo $!; wait"])
+            .stdout(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pid = leader.id().unwrap();
+        let mut line = String::new();
+        tokio::io::BufReader::new(leader.stdout.take().unwrap())
+            .read_line(&mut line)
+            .await
+            .unwrap();
+        let server: u32 = line.trim().parse().unwrap();
+        tokio::spawn(async move { leader.wait().await });
+
+        let manager = ProcessManager::new();
+        assert!(manager.is_running(server));
+        assert!(manager.kill(pid, Duration::from_secs(5)).await.unwrap());
+        for _ in 0..100 {
+            if !manager.is_running(server) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!manager.is_running(server));
+    }
+
+    #[tokio::test]
+    async fn test_write_private_replaces_symlinks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        std::fs::write(&target, "untouched").unwrap();
+        let path = dir.path().join(SERVER_CONFIG_FILE);
+        std::os::unix::fs::symlink(&target, &path).unwrap();
+        std::os::unix::fs::symlink(
+            &target,
+            dir.path().join(format!(".{}.tmp", SERVER_CONFIG_FILE)),
+        )
+        .unwrap();
+
+        write_private(&path, "port = 8080\n").await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "untouched");
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "port = 8080\n");
+    }
+}
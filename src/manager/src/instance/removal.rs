@@ -0,0 +1,260 @@
+//! Instance Removal
+//!
+//! Removing an instance must not take the user's apps and data with it by
+//! accident. By default only the runtime files are deleted; `purge` deletes
+//! the whole directory, and `archive` keeps a tarball of it first.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Subdirectories holding user data, kept unless the removal is a purge
+const USER_DATA_DIRS: &[&str] = &["apps", "data"];
+
+/// How an instance is removed
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoveOptions {
+    /// Tar the instance directory into the backups directory first
+    pub archive: bool,
+    /// Delete apps and data too, instead of only the runtime files
+    pub purge: bool,
+}
+
+/// What a removal did to the instance directory
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoveReport {
+    /// Archive written before deleting anything
+    pub archive: Option<PathBuf>,
+    /// Whether apps and data were deleted
+    pub purged: bool,
+}
+
+/// Write `<backups_dir>/<name>-<timestamp>.tar.gz` of an instance directory
+///
+/// Archives hold a user's whole instance directory, so both the backups
+/// directory and the archives are readable by root only.
+pub async fn archive(instance_dir: &Path, backups_dir: &Path) -> Result<PathBuf> {
+    let name = instance_dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid instance directory {}", instance_dir.display()))?;
+    let parent = instance_dir.parent().unwrap_or(Path::new("/"));
+
+    create_backups_dir(backups_dir).await?;
+    let archive = backups_dir.join(format!(
+        "{}-{}.tar.gz",
+        name,
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    ));
+
+    // Created here rather than by tar, which would use the daemon's umask
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .custom_flags(nix::fcntl::OFlag::O_NOFOLLOW.bits())
+        .mode(0o600)
+        .open(&archive)
+        .await
+        .with_context(|| format!("Failed to create {}", archive.display()))?
+        .into_std()
+        .await;
+
+    // Spawned rather than `output()`, which would capture stdout instead
+    let result = match Command::new("tar")
+        .arg("-czf")
+        .arg("-")
+        .arg("-C")
+        .arg(parent)
+        .arg(name)
+        .stdout(Stdio::from(file))
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child.wait_with_output().await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(output) if output.status.success() => Ok(archive),
+        result => {
+            // Don't leave a truncated archive that looks like a backup
+            let _ = tokio::fs::remove_file(&archive).await;
+            let output = result.context("Failed to run tar")?;
+            anyhow::bail!(
+                "Archiving {} failed: {}",
+                instance_dir.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+}
+
+/// Create the backups directory readable by root only, refusing one that
+/// other users can get into
+async fn create_backups_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    tokio::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let metadata = tokio::fs::symlink_metadata(dir).await?;
+    let mode = metadata.permissions().mode() & 0o777;
+    if !metadata.is_dir() || mode & 0o077 != 0 {
+        anyhow::bail!(
+            "Backups directory {} must be a directory only root can access (mode {:o})",
+            dir.display(),
+            mode
+        );
+    }
+    Ok(())
+}
+
+/// Whether `marker` says an instance directory only holds the data of a
+/// removed instance
+///
+/// Markers live in the manager's state directory, where the user who owns
+/// the instance directory can't create or delete them.
+pub fn is_removed(marker: &Path) -> bool {
+    marker.exists()
+}
+
+/// Mark an instance as removed with its data kept, so the manager doesn't
+/// load the directory as an instance
+pub async fn mark_removed(marker: &Path) -> Result<()> {
+    if let Some(dir) = marker.parent() {
+        tokio::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .await?;
+    }
+    tokio::fs::write(marker, "").await?;
+    Ok(())
+}
+
+/// Clear the removed marker when an instance is created again
+pub async fn clear_removed(marker: &Path) -> Result<()> {
+    match tokio::fs::remove_file(marker).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Delete everything in an instance directory except the user's apps and data
+pub async fn remove_runtime_state(instance_dir: &Path) -> Result<()> {
+    let mut entries = tokio::fs::read_dir(instance_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if USER_DATA_DIRS.iter().any(|dir| entry.file_name() == *dir) {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type().await?.is_dir() {
+            tokio::fs::remove_dir_all(&path).await?;
+        } else {
+            tokio::fs::remove_file(&path).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::{test_manager, ResourceLimits};
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_remove_keeps_user_data() {
+        let dir = tempdir().unwrap();
+        let instance_dir = dir.path().join("instances").join("alice");
+        for sub in ["apps/site", "data", "logs"] {
+            std::fs::create_dir_all(instance_dir.join(sub)).unwrap();
+        }
+        std::fs::write(instance_dir.join("state.json"), "{}").unwrap();
+        std::fs::write(instance_dir.join("data").join("db"), "rows").unwrap();
+
+        let archive = archive(&instance_dir, &dir.path().join("backups"))
+            .await
+            .unwrap();
+        assert!(archive
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("alice-"));
+        assert!(std::fs::metadata(&archive).unwrap().len() > 0);
+
+        remove_runtime_state(&instance_dir).await.unwrap();
+        let mut left: Vec<String> = std::fs::read_dir(&instance_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["apps", "data"]);
+        assert!(instance_dir.join("data").join("db").exists());
+
+        let marker = dir.path().join("state").join("alice.removed");
+        mark_removed(&marker).await.unwrap();
+        assert!(is_removed(&marker));
+        clear_removed(&marker).await.unwrap();
+        assert!(!is_removed(&marker));
+    }
+
+    #[tokio::test]
+    async fn test_archives_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let instance_dir = dir.path().join("instances").join("alice");
+        std::fs::create_dir_all(instance_dir.join("data")).unwrap();
+        std::fs::write(instance_dir.join("data").join("db"), "rows").unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let backups_dir = dir.path().join("backups");
+        let archive = archive(&instance_dir, &backups_dir).await.unwrap();
+        assert_eq!(mode(&backups_dir), 0o700);
+        assert_eq!(mode(&archive), 0o600);
+        let listing = std::process::Command::new("tar")
+            .arg("-tzf")
+            .arg(&archive)
+            .output()
+            .unwrap();
+        assert!(String::from_utf8_lossy(&listing.stdout).contains("alice/data/db"));
+
+        // A directory other users can read isn't used
+        let open_dir = dir.path().join("open");
+        std::fs::create_dir(&open_dir).unwrap();
+        std::fs::set_permissions(&open_dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(super::archive(&instance_dir, &open_dir).await.is_err());
+        assert_eq!(std::fs::read_dir(&open_dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_remove_is_idempotent() {
+        let (dir, manager) = test_manager(ResourceLimits::default()).await;
+
+        let purge = RemoveOptions {
+            purge: true,
+            ..RemoveOptions::default()
+        };
+        assert!(manager.remove("alice", purge).await.unwrap().purged);
+        assert!(!manager.instance_dir("alice").exists());
+        assert!(manager.status("alice").await.is_err());
+
+        // Already gone, or never existed
+        manager.remove("alice", purge).await.unwrap();
+        manager
+            .remove("bob", RemoveOptions::default())
+            .await
+            .unwrap();
+        assert!(manager.remove("..", purge).await.is_err());
+        assert!(dir.path().exists());
+    }
+}
@@ -2,10 +2,13 @@
 //!
 //! Defines and enforces resource limits for Frame instances.
 
+use futures::future::{self, BoxFuture};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tokio::process::Command;
 
 /// Resource limits for a Frame instance
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResourceLimits {
     /// Memory limit in MB
     pub memory_mb: u64,
@@ -68,6 +71,143 @@ impl ResourceLimits {
     }
 }
 
+/// How instances are placed in cgroups
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CgroupBackend {
+    /// The manager writes to /sys/fs/cgroup/frame itself
+    #[default]
+    Direct,
+    /// Instances run in transient scopes under a per-user systemd slice
+    Systemd,
+}
+
+impl FromStr for CgroupBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "direct" => Ok(Self::Direct),
+            "systemd" => Ok(Self::Systemd),
+            other => Err(format!(
+                "Unknown cgroup backend '{}' (expected direct or systemd)",
+                other
+            )),
+        }
+    }
+}
+
+/// Applies resource limits to an instance's control group
+///
+/// Operations may shell out (systemd), so they are async and callers
+/// shouldn't hold the instances lock across them.
+pub trait ResourceController: Send + Sync {
+    /// Apply memory limit
+    fn set_memory_limit(&self, limit_bytes: u64) -> BoxFuture<'_, std::io::Result<()>>;
+    /// Apply CPU limit (as percentage of one core)
+    fn set_cpu_limit(&self, percent: u8) -> BoxFuture<'_, std::io::Result<()>>;
+    /// Add a process to the control group
+    fn add_process(&self, pid: u32) -> BoxFuture<'_, std::io::Result<()>>;
+    /// Suspend every process in the control group
+    fn freeze(&self) -> BoxFuture<'_, std::io::Result<()>>;
+    /// Resume the processes of a frozen control group
+    fn thaw(&self) -> BoxFuture<'_, std::io::Result<()>>;
+    /// SIGKILL every process in the control group
+    fn kill_all(&self) -> BoxFuture<'_, std::io::Result<()>>;
+    /// Remove the control group
+    fn remove(&self) -> BoxFuture<'_, std::io::Result<()>>;
+}
+
+/// Resource controller for an instance started by `systemd-run`
+///
+/// Each process runs in its own transient scope (`frame-<user>-<port>.scope`)
+/// under the user's slice (`frame-<user>.slice`). Limits are properties of
+/// the scope, where `scope_command` put them; freezing and removal act on
+/// the whole slice.
+pub struct SystemdController {
+    slice: String,
+    scope: String,
+}
+
+impl SystemdController {
+    /// Controller for the instance of `username` listening on `port`
+    pub fn for_instance(username: &str, port: u16) -> Self {
+        Self {
+            slice: format!("frame-{}.slice", username),
+            scope: format!("frame-{}-{}.scope", username, port),
+        }
+    }
+
+    /// Arguments to `systemd-run` starting a command in the instance's
+    /// transient scope under the user's slice with `limits` applied
+    pub fn scope_command(&self, limits: &ResourceLimits) -> Vec<String> {
+        vec![
+            "--scope".to_string(),
+            "--quiet".to_string(),
+            "--collect".to_string(),
+            format!("--unit={}", self.scope),
+            format!("--slice={}", self.slice),
+            "-p".to_string(),
+            format!("MemoryMax={}", limits.memory_bytes()),
+            "-p".to_string(),
+            format!("CPUQuota={}%", limits.cpu_percent),
+            "--".to_string(),
+        ]
+    }
+
+    /// Run `systemctl` with `args`
+    async fn systemctl<const N: usize>(args: [&str; N]) -> std::io::Result<()> {
+        let status = Command::new("systemctl").args(args).status().await?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "systemctl {} failed with {}",
+                args.join(" "),
+                status
+            )));
+        }
+        Ok(())
+    }
+
+    /// Set a runtime property on the instance's scope
+    async fn set_property(&self, property: String) -> std::io::Result<()> {
+        Self::systemctl(["set-property", "--runtime", &self.scope, &property]).await
+    }
+}
+
+impl ResourceController for SystemdController {
+    fn set_memory_limit(&self, limit_bytes: u64) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(self.set_property(format!("MemoryMax={}", limit_bytes)))
+    }
+
+    fn set_cpu_limit(&self, percent: u8) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(self.set_property(format!("CPUQuota={}%", percent)))
+    }
+
+    fn add_process(&self, _pid: u32) -> BoxFuture<'_, std::io::Result<()>> {
+        // systemd only places processes in a scope when it starts them
+        Box::pin(future::ready(Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "processes join a systemd scope through systemd-run",
+        ))))
+    }
+
+    fn freeze(&self) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(Self::systemctl(["freeze", &self.slice]))
+    }
+
+    fn thaw(&self) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(Self::systemctl(["thaw", &self.slice]))
+    }
+
+    fn kill_all(&self) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(Self::systemctl(["kill", "--signal=SIGKILL", &self.scope]))
+    }
+
+    fn remove(&self) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(Self::systemctl(["stop", &self.slice]))
+    }
+}
+
 /// cgroups v2 resource controller
 #[cfg(target_os = "linux")]
 pub struct CgroupController {
@@ -81,9 +221,63 @@ impl CgroupController {
         let cgroup_path = std::path::PathBuf::from(format!("/sys/fs/cgroup/frame/{}", username));
         std::fs::create_dir_all(&cgroup_path)?;
 
+        // The limit files only exist once /sys/fs/cgroup/frame hands the
+        // controllers down; it may already do, so a failure here is ignored.
+        // The root cgroup is left alone: the init system manages it and
+        // already delegates memory and cpu to its children.
+        let _ = std::fs::write(
+            "/sys/fs/cgroup/frame/cgroup.subtree_control",
+            "+memory +cpu",
+        );
+
         Ok(Self { cgroup_path })
     }
 
+    /// Create the user's cgroup with `limits` applied, for a process to join
+    ///
+    /// Fails with `Unsupported` when cgroup v2 isn't mounted.
+    pub fn prepare(username: &str, limits: &ResourceLimits) -> std::io::Result<Self> {
+        if !std::path::Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "cgroup v2 is not mounted at /sys/fs/cgroup",
+            ));
+        }
+
+        let cgroup = Self::create_for_user(username)?;
+        cgroup.set_memory_limit(limits.memory_bytes())?;
+        cgroup.set_cpu_limit(limits.cpu_percent)?;
+        Ok(cgroup)
+    }
+
+    /// Have the process `cmd` spawns join this cgroup before it execs
+    ///
+    /// The process is confined from its first instruction, so nothing it
+    /// runs or forks can start outside the limits.
+    pub fn join_on_spawn(&self, cmd: &mut std::process::Command) -> std::io::Result<()> {
+        use std::io::Write;
+        use std::os::unix::process::CommandExt;
+
+        let procs = std::fs::OpenOptions::new()
+            .write(true)
+            .open(self.cgroup_path.join("cgroup.procs"))?;
+        // SAFETY: the child only writes to a descriptor opened beforehand;
+        // "0" moves the writing process itself
+        unsafe {
+            cmd.pre_exec(move || (&procs).write_all(b"0"));
+        }
+        Ok(())
+    }
+
+    /// Open a user's existing cgroup, if one has been created
+    pub fn open_for_user(username: &str) -> Option<Self> {
+        let cgroup_path = std::path::PathBuf::from(format!("/sys/fs/cgroup/frame/{}", username));
+        cgroup_path.is_dir().then_some(Self { cgroup_path })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl CgroupController {
     /// Apply memory limit
     pub fn set_memory_limit(&self, limit_bytes: u64) -> std::io::Result<()> {
         let memory_max = self.cgroup_path.join("memory.max");
@@ -109,14 +303,28 @@ impl CgroupController {
         Ok(())
     }
 
+    /// Freeze the cgroup (`cgroup.freeze`)
+    pub fn freeze(&self) -> std::io::Result<()> {
+        std::fs::write(self.cgroup_path.join("cgroup.freeze"), "1")
+    }
+
+    /// Thaw the cgroup
+    pub fn thaw(&self) -> std::io::Result<()> {
+        std::fs::write(self.cgroup_path.join("cgroup.freeze"), "0")
+    }
+
+    /// SIGKILL every process in the cgroup (`cgroup.kill`)
+    pub fn kill_all(&self) -> std::io::Result<()> {
+        std::fs::write(self.cgroup_path.join("cgroup.kill"), "1")
+    }
+
     /// Remove the cgroup
     pub fn remove(&self) -> std::io::Result<()> {
         // Move all processes to parent first
         let procs = self.cgroup_path.join("cgroup.procs");
         if procs.exists() {
             let content = std::fs::read_to_string(&procs)?;
-            let parent_procs =
-                self.cgroup_path.parent().unwrap().join("cgroup.procs");
+            let parent_procs = self.cgroup_path.parent().unwrap().join("cgroup.procs");
             for line in content.lines() {
                 let _ = std::fs::write(&parent_procs, line);
             }
@@ -137,6 +345,24 @@ impl CgroupController {
         Ok(Self)
     }
 
+    pub fn open_for_user(_username: &str) -> Option<Self> {
+        None
+    }
+
+    pub fn prepare(_username: &str, _limits: &ResourceLimits) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "cgroups are only supported on Linux",
+        ))
+    }
+
+    pub fn join_on_spawn(&self, _cmd: &mut std::process::Command) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl CgroupController {
     pub fn set_memory_limit(&self, _limit_bytes: u64) -> std::io::Result<()> {
         Ok(())
     }
@@ -149,7 +375,86 @@ impl CgroupController {
         Ok(())
     }
 
+    pub fn freeze(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    pub fn thaw(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    pub fn kill_all(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
     pub fn remove(&self) -> std::io::Result<()> {
         Ok(())
     }
 }
+
+/// cgroupfs writes don't block, so they complete right away
+impl ResourceController for CgroupController {
+    fn set_memory_limit(&self, limit_bytes: u64) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(future::ready(CgroupController::set_memory_limit(
+            self,
+            limit_bytes,
+        )))
+    }
+
+    fn set_cpu_limit(&self, percent: u8) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(future::ready(CgroupController::set_cpu_limit(
+            self, percent,
+        )))
+    }
+
+    fn add_process(&self, pid: u32) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(future::ready(CgroupController::add_process(self, pid)))
+    }
+
+    fn freeze(&self) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(future::ready(CgroupController::freeze(self)))
+    }
+
+    fn thaw(&self) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(future::ready(CgroupController::thaw(self)))
+    }
+
+    fn kill_all(&self) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(future::ready(CgroupController::kill_all(self)))
+    }
+
+    fn remove(&self) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(future::ready(CgroupController::remove(self)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_systemd_scope_command() {
+        let limits = ResourceLimits {
+            memory_mb: 256,
+            cpu_percent: 50,
+            ..ResourceLimits::default()
+        };
+        assert_eq!(
+            SystemdController::for_instance("alice", 30001).scope_command(&limits),
+            [
+                "--scope",
+                "--quiet",
+                "--collect",
+                "--unit=frame-alice-30001.scope",
+                "--slice=frame-alice.slice",
+                "-p",
+                "MemoryMax=268435456",
+                "-p",
+                "CPUQuota=50%",
+                "--",
+            ]
+        );
+        assert_eq!("Systemd".parse(), Ok(CgroupBackend::Systemd));
+        assert!("cgroupfs".parse::<CgroupBackend>().is_err());
+    }
+}
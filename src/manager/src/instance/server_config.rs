@@ -0,0 +1,137 @@
+//! Generated frame-server Configuration
+//!
+//! Some frame-server versions take their settings from a config file rather
+//! than command-line flags. For those, the manager renders the instance's
+//! settings into `frame-server.toml` in the instance directory on each start.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::ResourceLimits;
+
+/// Name of the generated config file inside an instance directory
+pub const SERVER_CONFIG_FILE: &str = "frame-server.toml";
+
+/// How settings are passed to frame-server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpawnMode {
+    /// Individual command-line flags (`--port`, `--app-dir`, ...)
+    #[default]
+    Args,
+    /// A generated config file passed as `--config <path>`
+    ConfigFile,
+}
+
+impl FromStr for SpawnMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "args" => Ok(Self::Args),
+            "config_file" => Ok(Self::ConfigFile),
+            other => Err(format!(
+                "Unknown spawn mode '{}' (expected args or config_file)",
+                other
+            )),
+        }
+    }
+}
+
+/// Settings written to the generated config file
+pub struct ServerConfig<'a> {
+    pub port: u16,
+    pub app_dir: &'a Path,
+    pub data_dir: &'a Path,
+    pub log_level: Option<&'a str>,
+    pub limits: &'a ResourceLimits,
+    /// Environment variables already filtered by the env var policy
+    pub env: &'a [(String, String)],
+}
+
+impl ServerConfig<'_> {
+    /// Render as TOML
+    pub fn render(&self) -> String {
+        let mut out =
+            String::from("# Generated by frame-manager on every start; edits are overwritten\n");
+        let _ = writeln!(out, "port = {}", self.port);
+        let _ = writeln!(out, "app_dir = {}", quote(&self.app_dir.to_string_lossy()));
+        let _ = writeln!(
+            out,
+            "data_dir = {}",
+            quote(&self.data_dir.to_string_lossy())
+        );
+        if let Some(level) = self.log_level {
+            let _ = writeln!(out, "log_level = {}", quote(level));
+        }
+
+        let _ = writeln!(out, "\n[limits]");
+        let _ = writeln!(out, "memory_mb = {}", self.limits.memory_mb);
+        let _ = writeln!(out, "cpu_percent = {}", self.limits.cpu_percent);
+        let _ = writeln!(out, "max_connections = {}", self.limits.max_connections);
+
+        let _ = writeln!(out, "\n[env]");
+        let mut env: Vec<_> = self.env.iter().collect();
+        env.sort();
+        for (key, value) in env {
+            let _ = writeln!(out, "{} = {}", quote(key), quote(value));
+        }
+        out
+    }
+}
+
+/// TOML basic string
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04X}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_server_config() {
+        let limits = ResourceLimits::default();
+        let env = [
+            ("TOKEN".to_string(), "a\"b\\c".to_string()),
+            ("APP_ENV".to_string(), "production".to_string()),
+        ];
+        let config = ServerConfig {
+            port: 30001,
+            app_dir: Path::new("/home/alice/frame/apps"),
+            data_dir: Path::new("/var/frame/instances/alice/data"),
+            log_level: Some("debug"),
+            limits: &limits,
+            env: &env,
+        };
+
+        let rendered = config.render();
+        assert!(rendered.contains("port = 30001\n"));
+        assert!(rendered.contains("app_dir = \"/home/alice/frame/apps\"\n"));
+        assert!(rendered.contains("log_level = \"debug\"\n"));
+        assert!(rendered.contains(&format!("memory_mb = {}\n", limits.memory_mb)));
+        assert!(rendered
+            .ends_with("[env]\n\"APP_ENV\" = \"production\"\n\"TOKEN\" = \"a\\\"b\\\\c\"\n"));
+
+        assert_eq!("config_file".parse(), Ok(SpawnMode::ConfigFile));
+        assert!("flags".parse::<SpawnMode>().is_err());
+    }
+}
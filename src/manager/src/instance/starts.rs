@@ -0,0 +1,111 @@
+//! Start Concurrency Limit
+//!
+//! Spawning a frame-server and waiting for it to come up is the expensive
+//! part of a start. After a reboot hundreds of instances may be started at
+//! once (auto-start, batch and group operations, crash restarts), so every
+//! start takes a slot first and at most `max_concurrent_starts` spawn at a
+//! time; the rest wait their turn.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Starts running at once unless configured otherwise
+pub const DEFAULT_MAX_CONCURRENT_STARTS: usize = 4;
+
+/// Bounds how many instances start at the same time
+pub struct StartLimiter {
+    slots: Semaphore,
+    max: usize,
+    /// Starts waiting for a slot
+    waiting: AtomicUsize,
+}
+
+/// A start slot, given back when dropped
+pub struct StartSlot<'a> {
+    _permit: SemaphorePermit<'a>,
+}
+
+impl StartLimiter {
+    /// Limiter allowing `max` concurrent starts (at least one)
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            slots: Semaphore::new(max),
+            max,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait for a free slot to start `username`'s instance
+    pub async fn acquire(&self, username: &str) -> StartSlot<'_> {
+        let waiting = self.waiting.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.slots.available_permits() == 0 {
+            tracing::info!(
+                "All {} start slots busy, instance for {} waits ({} waiting)",
+                self.max,
+                username,
+                waiting
+            );
+        }
+        // The semaphore is never closed
+        let permit = self.slots.acquire().await.expect("start slots closed");
+        let waiting = self.waiting.fetch_sub(1, Ordering::Relaxed) - 1;
+        tracing::debug!(
+            "Starting instance for {} ({} of {} start slots in use, {} waiting)",
+            username,
+            self.in_flight(),
+            self.max,
+            waiting
+        );
+        StartSlot { _permit: permit }
+    }
+
+    /// Starts currently holding a slot
+    pub fn in_flight(&self) -> usize {
+        self.max - self.slots.available_permits()
+    }
+}
+
+impl Default for StartLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CONCURRENT_STARTS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_limits_in_flight_starts() {
+        let limiter = Arc::new(StartLimiter::new(2));
+        let spawning = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        // Stub spawns that take a while, all requested at once
+        let starts: Vec<_> = (0..8)
+            .map(|i| {
+                let (limiter, spawning, peak) = (
+                    Arc::clone(&limiter),
+                    Arc::clone(&spawning),
+                    Arc::clone(&peak),
+                );
+                tokio::spawn(async move {
+                    let _slot = limiter.acquire(&format!("user{}", i)).await;
+                    let now = spawning.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    spawning.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for start in starts {
+            start.await.unwrap();
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(limiter.in_flight(), 0);
+    }
+}
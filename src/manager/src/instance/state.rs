@@ -0,0 +1,193 @@
+//! Persisted Instance Runtime State
+//!
+//! Records each instance's status, PID and port so a restarted manager can
+//! re-adopt processes that outlived it.
+//!
+//! The files live in the manager's own state directory, never in the
+//! user-owned instance directory: the manager runs as root and acts on the
+//! PID it reads back, so a user must not be able to write or redirect them.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::{Instance, InstanceStatus};
+
+/// Runtime state persisted across manager restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceState {
+    pub status: InstanceStatus,
+    pub pid: Option<u32>,
+    pub port: u16,
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub restart_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_exit_reason: Option<String>,
+    /// Kept here rather than in the user-writable config.json, so a user
+    /// can't lift a quarantine
+    #[serde(default)]
+    pub quarantined: bool,
+    /// Kept so a re-adopted process's metrics reports are still accepted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics_token: Option<String>,
+}
+
+/// What a re-adopted instance should look like after reconciliation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reconciled {
+    pub status: InstanceStatus,
+    pub pid: Option<u32>,
+    pub detail: Option<String>,
+}
+
+impl InstanceState {
+    /// Capture the runtime state of an instance
+    pub fn of(instance: &Instance) -> Self {
+        Self {
+            status: instance.status,
+            pid: instance.pid,
+            port: instance.port,
+            started_at: instance.started_at,
+            restart_count: instance.restart_count,
+            last_exit_reason: instance.last_exit_reason.clone(),
+            quarantined: instance.quarantined,
+            metrics_token: instance.metrics_token.clone(),
+        }
+    }
+
+    /// Load a saved state file, if there is one
+    pub async fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = tokio::fs::read_to_string(path).await?;
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    /// Save the state to `path`, creating its directory readable by root only
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            tokio::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .await?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_string_pretty(self)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    /// Decide the real status of an instance from its saved state
+    ///
+    /// Transitional statuses are never trusted: whatever the manager was doing
+    /// when it died, the outcome is determined from whether the process is
+    /// still alive and serving its port.
+    pub fn reconcile(&self, process_alive: bool, port_open: bool) -> Reconciled {
+        let pid = self.pid.filter(|_| process_alive);
+
+        match (self.status, pid) {
+            (InstanceStatus::Stopped, None) => Reconciled {
+                status: InstanceStatus::Stopped,
+                pid: None,
+                detail: None,
+            },
+            // The freezer outlives the manager; a frozen process may not
+            // answer on its port, and stays frozen until thawed
+            (InstanceStatus::Frozen, Some(pid)) => Reconciled {
+                status: InstanceStatus::Frozen,
+                pid: Some(pid),
+                detail: Some("frozen for debugging".to_string()),
+            },
+            (_, Some(pid)) if port_open => Reconciled {
+                status: InstanceStatus::Running,
+                pid: Some(pid),
+                detail: None,
+            },
+            (_, Some(pid)) => Reconciled {
+                status: InstanceStatus::Failed,
+                pid: Some(pid),
+                detail: Some(format!(
+                    "process {} is alive but port {} is not accepting connections",
+                    pid, self.port
+                )),
+            },
+            (InstanceStatus::Stopping, None) => Reconciled {
+                status: InstanceStatus::Stopped,
+                pid: None,
+                detail: None,
+            },
+            (InstanceStatus::Failed, None) => Reconciled {
+                status: InstanceStatus::Failed,
+                pid: None,
+                detail: Some("failed before the manager restarted".to_string()),
+            },
+            (_, None) => Reconciled {
+                status: InstanceStatus::Failed,
+                pid: None,
+                detail: Some("process exited while the manager was down".to_string()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(status: InstanceStatus) -> InstanceState {
+        InstanceState {
+            status,
+            pid: Some(1234),
+            port: 30001,
+            started_at: None,
+            restart_count: 0,
+            last_exit_reason: None,
+            quarantined: false,
+            metrics_token: None,
+        }
+    }
+
+    #[test]
+    fn test_reconcile_transitional_states() {
+        let starting = state(InstanceStatus::Starting);
+        assert_eq!(
+            starting.reconcile(true, true).status,
+            InstanceStatus::Running
+        );
+        assert_eq!(
+            starting.reconcile(true, false).status,
+            InstanceStatus::Failed
+        );
+        assert_eq!(
+            starting.reconcile(false, false).status,
+            InstanceStatus::Failed
+        );
+
+        let stopping = state(InstanceStatus::Stopping);
+        assert_eq!(
+            stopping.reconcile(true, true).status,
+            InstanceStatus::Running
+        );
+        let stopped = stopping.reconcile(false, false);
+        assert_eq!(stopped.status, InstanceStatus::Stopped);
+        assert_eq!(stopped.pid, None);
+
+        let running = state(InstanceStatus::Running);
+        assert_eq!(running.reconcile(true, true).pid, Some(1234));
+        assert_eq!(
+            running.reconcile(false, true).status,
+            InstanceStatus::Failed
+        );
+
+        let frozen = state(InstanceStatus::Frozen);
+        assert_eq!(frozen.reconcile(true, false).status, InstanceStatus::Frozen);
+        assert_eq!(
+            frozen.reconcile(false, false).status,
+            InstanceStatus::Failed
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Synthetic Instances (`testing` feature only)
+//!
+//! Lets integration tests put instances with fixed status and usage into a
+//! running daemon without spawning frame-server, so the API, metrics and
+//! summaries can be exercised deterministically. Synthetic instances have
+//! no process; health checks skip them and their usage is never refreshed.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use super::{Instance, InstanceManager, InstanceStatus};
+
+/// Description of a synthetic instance
+#[derive(Debug, Clone, Deserialize)]
+pub struct SyntheticInstance {
+    pub username: String,
+    pub status: InstanceStatus,
+    pub port: u16,
+    #[serde(default)]
+    pub memory_mb: u64,
+    #[serde(default)]
+    pub cpu_usage: f32,
+    #[serde(default)]
+    pub app_count: u32,
+}
+
+impl InstanceManager {
+    /// Insert a synthetic instance, replacing any instance of that user
+    ///
+    /// Nothing is written to disk or spawned.
+    pub async fn inject(&self, spec: SyntheticInstance) -> Instance {
+        let instance = Instance {
+            username: spec.username.clone(),
+            port: spec.port,
+            status: spec.status,
+            status_detail: Some("synthetic".to_string()),
+            pid: None,
+            memory_usage: spec.memory_mb * 1024 * 1024,
+            cpu_usage: spec.cpu_usage,
+            app_count: spec.app_count,
+            limits: self.default_limits(),
+            env_vars: HashMap::new(),
+            log_level: None,
+            tls_port: None,
+            health_path: super::default_health_path(),
+            custom_health_check: None,
+            readiness_probe: None,
+            started_at: None,
+            last_health_check: None,
+            restart_count: 0,
+            last_exit_reason: None,
+            quarantined: false,
+            metrics_token: None,
+        };
+
+        self.synthetic
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(spec.username.clone());
+        self.instances
+            .write()
+            .await
+            .insert(spec.username, instance.clone());
+        instance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::{EnvPolicy, ResourceLimits, UserPolicy};
+
+    #[tokio::test]
+    async fn test_inject_synthetic_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = InstanceManager::new(
+            dir.path().to_path_buf(),
+            dir.path().join("frame-server"),
+            ResourceLimits::default(),
+            EnvPolicy::default(),
+            UserPolicy::default(),
+        );
+
+        manager
+            .inject(SyntheticInstance {
+                username: "alice".to_string(),
+                status: InstanceStatus::Running,
+                port: 30001,
+                memory_mb: 128,
+                cpu_usage: 12.5,
+                app_count: 2,
+            })
+            .await;
+
+        let instance = manager.status("alice").await.unwrap();
+        assert_eq!(instance.status, InstanceStatus::Running);
+        assert_eq!(instance.memory_usage, 128 * 1024 * 1024);
+        assert!(manager.is_synthetic("alice"));
+        assert!(!manager.is_synthetic("bob"));
+        assert_eq!(manager.running_count().await, 1);
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+    }
+}
@@ -0,0 +1,181 @@
+//! Managed User Policy
+//!
+//! Decides which system users the manager will run Frame instances for.
+
+use anyhow::Result;
+use std::path::Path;
+
+/// Users never managed unless the deny list is overridden
+pub const DEFAULT_MANAGED_USERS_DENY: &[&str] = &["root", "nobody", "cpanel*"];
+
+/// Policy deciding which users get a Frame instance
+#[derive(Debug, Clone)]
+pub struct UserPolicy {
+    /// If non-empty, only users matching one of these glob patterns are managed
+    pub allow: Vec<String>,
+    /// Users matching any of these glob patterns are never managed
+    pub deny: Vec<String>,
+}
+
+impl Default for UserPolicy {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: DEFAULT_MANAGED_USERS_DENY
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl UserPolicy {
+    /// Check whether a user is managed; deny takes precedence over allow
+    pub fn allows(&self, username: &str) -> bool {
+        if self.deny.iter().any(|p| glob_match(p, username)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|p| glob_match(p, username))
+    }
+}
+
+/// Longest username cPanel accepts
+pub const MAX_USERNAME_LEN: usize = 16;
+
+/// Validate a username before it's used in a path under the instances
+/// directory (letters, digits, `_`, `-` and `.`, not starting with a dot)
+pub fn validate_username(username: &str) -> Result<()> {
+    let valid = !username.is_empty()
+        && username.len() <= MAX_USERNAME_LEN
+        && !username.starts_with('.')
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+
+    if !valid {
+        anyhow::bail!(
+            "Invalid username '{}': use up to {} letters, digits, '_', '-' or '.', not starting with '.'",
+            username,
+            MAX_USERNAME_LEN
+        );
+    }
+    Ok(())
+}
+
+/// Hosting package (plan) cPanel assigned to a user, if any
+///
+/// Read from the account file `<users_dir>/<username>`, which only root
+/// can change; the user-owned instance directory is never trusted for it.
+pub fn cpanel_plan(users_dir: &Path, username: &str) -> Result<Option<String>> {
+    validate_username(username)?;
+    let content = match std::fs::read_to_string(users_dir.join(username)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(parse_plan(&content))
+}
+
+/// The `PLAN=` value of a cPanel account file
+fn parse_plan(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("PLAN="))
+        .map(str::trim)
+        .filter(|plan| !plan.is_empty() && *plan != "undefined")
+        .map(str::to_string)
+}
+
+/// Match a name against a glob pattern supporting `*` and `?`
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` seen and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` absorb one more character
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("cpanel*", "cpanelphpmyadmin"));
+        assert!(glob_match("*test*", "betatester"));
+        assert!(glob_match("user?", "user1"));
+        assert!(!glob_match("user?", "user12"));
+        assert!(!glob_match("root", "rooted"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn test_deny_takes_precedence() {
+        let policy = UserPolicy {
+            allow: vec!["client*".to_string()],
+            deny: vec!["client-reseller".to_string()],
+        };
+        assert!(policy.allows("client42"));
+        assert!(!policy.allows("client-reseller"));
+        assert!(!policy.allows("alice"));
+
+        assert!(!UserPolicy::default().allows("root"));
+        assert!(UserPolicy::default().allows("alice"));
+    }
+
+    #[test]
+    fn test_parse_plan() {
+        let account = "DNS=example.com\nPLAN=gold\nOWNER=root\n";
+        assert_eq!(parse_plan(account).as_deref(), Some("gold"));
+        assert_eq!(parse_plan("PLAN=undefined\n"), None);
+        assert_eq!(parse_plan("PLAN=\n"), None);
+        assert_eq!(parse_plan("DNS=example.com\n"), None);
+    }
+
+    #[test]
+    fn test_validate_username() {
+        assert!(validate_username("alice").is_ok());
+        assert!(validate_username("bob_2.dev-x").is_ok());
+        assert!(validate_username("sixteencharsxxxx").is_ok());
+
+        for invalid in [
+            "",
+            "..",
+            "../",
+            "../alice",
+            "foo/bar",
+            "/etc",
+            ".hidden",
+            "a\\b",
+            "with space",
+            "seventeencharsxxx",
+        ] {
+            assert!(validate_username(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+}
@@ -2,14 +2,23 @@
 //!
 //! Core functionality for the Frame Service Manager daemon.
 
+// Synthetic test instances must never ship in a release build
+#[cfg(all(feature = "testing", not(debug_assertions)))]
+compile_error!("the `testing` feature is only allowed in debug builds");
+
 pub mod api;
 pub mod config;
+pub mod daemon;
+pub mod deploy;
 pub mod events;
 pub mod health;
 pub mod instance;
+pub mod logs;
 pub mod manager;
 pub mod metrics;
+pub mod operations;
 pub mod port;
+pub mod stats;
 
 pub use config::Config;
 pub use manager::FrameManager;
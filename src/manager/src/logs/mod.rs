@@ -0,0 +1,545 @@
+//! Log Streaming Module
+//!
+//! Follows instance log files, one at a time or fanned in to a single stream.
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use std::collections::{HashMap, HashSet};
+use std::io::SeekFrom;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration};
+
+use crate::instance::InstanceManager;
+
+mod rotation;
+
+pub use rotation::{RotationOutcome, RotationPolicy, ROTATION_INTERVAL};
+
+/// Maximum bytes read from a single log file per poll
+const MAX_READ_PER_POLL: u64 = 64 * 1024;
+
+/// Bytes read per step when searching backwards for the last lines of a log
+const TAIL_CHUNK: u64 = 8 * 1024;
+
+/// Most lines sent before following a single log
+pub const MAX_BACKFILL_LINES: usize = 1000;
+
+/// Log level used for filtering streamed lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// Canonical names of all levels, least severe first
+    pub const NAMES: [&'static str; 5] = ["trace", "debug", "info", "warn", "error"];
+
+    /// Parse a level name (trace, debug, info, warn, error)
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "trace" => Some(LogLevel::Trace),
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "error" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+
+    /// Canonical lowercase name of the level
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+
+    /// Detect the level of a log line from its first level-like word
+    pub fn detect(line: &str) -> Option<Self> {
+        line.split(|c: char| !c.is_ascii_alphabetic())
+            .filter(|word| word.len() >= 4 && word.chars().all(|c| c.is_ascii_uppercase()))
+            .find_map(Self::parse)
+    }
+}
+
+/// Filter applied to streamed log lines
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    /// Only include these users (all users if `None`)
+    pub users: Option<HashSet<String>>,
+    /// Minimum level to include (all lines if `None`)
+    pub min_level: Option<LogLevel>,
+}
+
+impl LogFilter {
+    /// Check whether a user's log should be followed
+    pub fn includes_user(&self, username: &str) -> bool {
+        self.users
+            .as_ref()
+            .map(|users| users.contains(username))
+            .unwrap_or(true)
+    }
+
+    /// Check whether a line passes the level filter
+    pub fn includes_line(&self, line: &str) -> bool {
+        match self.min_level {
+            Some(min) => LogLevel::detect(line).map(|l| l >= min).unwrap_or(false),
+            None => true,
+        }
+    }
+}
+
+/// Read position within a followed log file
+#[derive(Default)]
+struct FileCursor {
+    offset: u64,
+    partial: String,
+    /// Inode of the file the offset is in, to notice rotation
+    inode: Option<u64>,
+}
+
+/// Combined log tailer across all instances
+pub struct LogTailer {
+    /// Base directory for instance data
+    instances_dir: PathBuf,
+    /// Maximum number of log files followed at once
+    max_watchers: usize,
+    /// How often log files are polled for new data
+    poll_interval: Duration,
+}
+
+impl LogTailer {
+    /// Create a new log tailer
+    pub fn new(instances_dir: PathBuf, max_watchers: usize) -> Self {
+        Self {
+            instances_dir,
+            max_watchers: max_watchers.max(1),
+            poll_interval: Duration::from_millis(500),
+        }
+    }
+
+    /// Follow one instance's log, starting with its last `backfill` lines
+    ///
+    /// Backfill is capped at [`MAX_BACKFILL_LINES`]. A log that doesn't exist
+    /// yet is followed from its start once created. The background task ends
+    /// when the receiver is dropped, even while the log is quiet.
+    pub fn follow(&self, username: &str, backfill: usize) -> mpsc::Receiver<String> {
+        let (tx, rx) = mpsc::channel(1024);
+        let log_path = log_path(&self.instances_dir, username);
+        let poll_interval = self.poll_interval;
+
+        tokio::spawn(async move {
+            let mut cursor = match tail(&log_path, backfill.min(MAX_BACKFILL_LINES)).await {
+                Ok((lines, cursor)) => {
+                    for line in lines {
+                        if tx.send(line).await.is_err() {
+                            return;
+                        }
+                    }
+                    cursor
+                }
+                Err(_) => FileCursor::default(),
+            };
+
+            let mut ticker = interval(poll_interval);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = tx.closed() => return,
+                }
+
+                for line in read_new_lines(&log_path, &mut cursor).await {
+                    if tx.send(line).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        rx
+    }
+
+    /// Follow the logs of all instances matching the filter
+    ///
+    /// Lines are prefixed with the username. The instance list is refreshed on
+    /// every poll so instances created or removed during the stream are picked
+    /// up or dropped. The background task ends when the receiver is dropped.
+    pub fn follow_all(
+        &self,
+        instance_manager: Arc<InstanceManager>,
+        filter: LogFilter,
+    ) -> mpsc::Receiver<String> {
+        let (tx, rx) = mpsc::channel(1024);
+        let instances_dir = self.instances_dir.clone();
+        let max_watchers = self.max_watchers;
+        let poll_interval = self.poll_interval;
+
+        tokio::spawn(async move {
+            let mut cursors: HashMap<String, FileCursor> = HashMap::new();
+            let mut ticker = interval(poll_interval);
+            let mut warned_cap = false;
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = tx.closed() => return,
+                }
+
+                let mut usernames: Vec<String> = instance_manager
+                    .list()
+                    .await
+                    .into_iter()
+                    .map(|i| i.username)
+                    .filter(|u| filter.includes_user(u))
+                    .collect();
+                usernames.sort();
+
+                if usernames.len() > max_watchers {
+                    if !warned_cap {
+                        let notice = format!(
+                            "[frame-manager] following {} of {} instance logs (watcher limit reached)",
+                            max_watchers,
+                            usernames.len()
+                        );
+                        if tx.send(notice).await.is_err() {
+                            return;
+                        }
+                        warned_cap = true;
+                    }
+                    usernames.truncate(max_watchers);
+                }
+
+                // Drop cursors for instances that went away
+                cursors.retain(|u, _| usernames.contains(u));
+
+                for username in usernames {
+                    let log_path = log_path(&instances_dir, &username);
+
+                    let cursor = match cursors.get_mut(&username) {
+                        Some(cursor) => cursor,
+                        None => {
+                            // Start new watchers at the end of the file
+                            let meta = tokio::fs::metadata(&log_path).await.ok();
+                            cursors.insert(
+                                username.clone(),
+                                FileCursor {
+                                    offset: meta.as_ref().map(|m| m.len()).unwrap_or(0),
+                                    partial: String::new(),
+                                    inode: meta.map(|m| m.ino()),
+                                },
+                            );
+                            continue;
+                        }
+                    };
+
+                    for line in read_new_lines(&log_path, cursor).await {
+                        if !filter.includes_line(&line) {
+                            continue;
+                        }
+                        if tx.send(format!("[{}] {}", username, line)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        rx
+    }
+}
+
+/// Path of an instance's log file
+fn log_path(instances_dir: &Path, username: &str) -> PathBuf {
+    instances_dir.join(username).join("logs").join("frame.log")
+}
+
+/// Last `lines` lines of a log file, reading only as much of its end as needed
+///
+/// An unterminated last line is included.
+pub async fn last_lines(path: &Path, lines: usize) -> std::io::Result<Vec<String>> {
+    let (mut last, cursor) = tail(path, lines).await?;
+    if !cursor.partial.is_empty() {
+        last.push(cursor.partial);
+        if last.len() > lines {
+            last.remove(0);
+        }
+    }
+    Ok(last)
+}
+
+/// Read the last `lines` complete lines of a file from its end
+///
+/// Returns them with a cursor at the end of the file, holding any
+/// unterminated last line as partial.
+async fn tail(path: &Path, lines: usize) -> std::io::Result<(Vec<String>, FileCursor)> {
+    let (mut file, meta) = open_log(path).await?;
+    let (lines, mut cursor) = read_tail(&mut file, meta.len(), lines).await?;
+    cursor.inode = Some(meta.ino());
+    Ok((lines, cursor))
+}
+
+/// Open a log file for reading, with its metadata
+///
+/// Logs sit in the user's directory and are read as root, so a symlink in
+/// place of the file, or anything but a regular file, is refused.
+async fn open_log(path: &Path) -> std::io::Result<(tokio::fs::File, std::fs::Metadata)> {
+    let not_regular = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} is not a regular file", path.display()),
+        )
+    };
+    // Non-blocking so a FIFO can't stall the open
+    let file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags((OFlag::O_NOFOLLOW | OFlag::O_NONBLOCK).bits())
+        .open(path)
+        .await
+        .map_err(|e| match e.raw_os_error() {
+            Some(code) if code == Errno::ELOOP as i32 => not_regular(),
+            _ => e,
+        })?;
+    let meta = file.metadata().await?;
+    if !meta.is_file() {
+        return Err(not_regular());
+    }
+    Ok((file, meta))
+}
+
+/// [`tail`] over any seekable reader of `len` bytes
+async fn read_tail<R>(
+    reader: &mut R,
+    len: u64,
+    lines: usize,
+) -> std::io::Result<(Vec<String>, FileCursor)>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    // Read backwards until the chunks hold the wanted lines plus the end of
+    // the one before them, or the whole file
+    let mut start = len;
+    let mut chunks = Vec::new();
+    let mut newlines = 0;
+    while start > 0 && newlines <= lines {
+        let step = TAIL_CHUNK.min(start);
+        start -= step;
+        reader.seek(SeekFrom::Start(start)).await?;
+        let mut chunk = vec![0; step as usize];
+        reader.read_exact(&mut chunk).await?;
+        newlines += chunk.iter().filter(|&&b| b == b'\n').count();
+        chunks.push(chunk);
+    }
+    let buf: Vec<u8> = chunks.into_iter().rev().flatten().collect();
+
+    let text = String::from_utf8_lossy(&buf);
+    let mut complete: Vec<&str> = text.split('\n').collect();
+    // Whatever follows the last newline is an unterminated line (or empty)
+    let partial = complete.pop().unwrap_or_default();
+    // The first line is cut off unless the start of the file was reached
+    if start > 0 && !complete.is_empty() {
+        complete.remove(0);
+    }
+    let skip = complete.len().saturating_sub(lines);
+    let last = complete[skip..]
+        .iter()
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .collect();
+
+    let cursor = FileCursor {
+        offset: len,
+        partial: partial.to_string(),
+        inode: None,
+    };
+    Ok((last, cursor))
+}
+
+/// Read complete lines appended since the cursor's last position
+async fn read_new_lines(path: &std::path::Path, cursor: &mut FileCursor) -> Vec<String> {
+    let (mut file, meta) = match open_log(path).await {
+        Ok(opened) => opened,
+        Err(_) => return Vec::new(),
+    };
+    let (len, inode) = (meta.len(), meta.ino());
+
+    // File was truncated or rotated; start over
+    if len < cursor.offset || cursor.inode.is_some_and(|known| known != inode) {
+        cursor.offset = 0;
+        cursor.partial.clear();
+    }
+    cursor.inode = Some(inode);
+
+    if len == cursor.offset {
+        return Vec::new();
+    }
+
+    if file.seek(SeekFrom::Start(cursor.offset)).await.is_err() {
+        return Vec::new();
+    }
+
+    let mut buf = Vec::new();
+    let read = match file.take(MAX_READ_PER_POLL).read_to_end(&mut buf).await {
+        Ok(read) => read,
+        Err(_) => return Vec::new(),
+    };
+    cursor.offset += read as u64;
+    cursor.partial.push_str(&String::from_utf8_lossy(&buf));
+
+    let mut lines = Vec::new();
+    while let Some(pos) = cursor.partial.find('\n') {
+        let line: String = cursor.partial.drain(..=pos).collect();
+        lines.push(line.trim_end_matches(['\n', '\r']).to_string());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_detection() {
+        assert_eq!(
+            LogLevel::detect("2024-01-01T00:00:00Z ERROR request failed"),
+            Some(LogLevel::Error)
+        );
+        assert_eq!(
+            LogLevel::detect("[WARN] slow response"),
+            Some(LogLevel::Warn)
+        );
+        assert_eq!(LogLevel::detect("plain line with no level"), None);
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = LogFilter {
+            users: Some(["alice".to_string()].into_iter().collect()),
+            min_level: Some(LogLevel::Warn),
+        };
+
+        assert!(filter.includes_user("alice"));
+        assert!(!filter.includes_user("bob"));
+        assert!(filter.includes_line("ERROR boom"));
+        assert!(filter.includes_line("WARN careful"));
+        assert!(!filter.includes_line("INFO hello"));
+        assert!(!filter.includes_line("no level here"));
+    }
+
+    /// Reader counting the bytes read through it
+    struct CountingReader<R> {
+        inner: R,
+        read: usize,
+    }
+
+    impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let before = buf.filled().len();
+            let poll = std::pin::Pin::new(&mut self.inner).poll_read(cx, buf);
+            self.read += buf.filled().len() - before;
+            poll
+        }
+    }
+
+    impl<R: AsyncSeek + Unpin> AsyncSeek for CountingReader<R> {
+        fn start_seek(
+            mut self: std::pin::Pin<&mut Self>,
+            position: SeekFrom,
+        ) -> std::io::Result<()> {
+            std::pin::Pin::new(&mut self.inner).start_seek(position)
+        }
+
+        fn poll_complete(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<u64>> {
+            std::pin::Pin::new(&mut self.inner).poll_complete(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tail_reads_only_the_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frame.log");
+        let content: String = (0..200_000).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let mut reader = CountingReader {
+            inner: file,
+            read: 0,
+        };
+        let (lines, cursor) = read_tail(&mut reader, content.len() as u64, 3)
+            .await
+            .unwrap();
+        assert_eq!(lines, ["line 199997", "line 199998", "line 199999"]);
+        assert!(cursor.partial.is_empty());
+        assert_eq!(reader.read as u64, TAIL_CHUNK);
+
+        // Reading further back than one chunk, and past the start of the file
+        let lines = last_lines(&path, 5000).await.unwrap();
+        assert_eq!(lines.len(), 5000);
+        assert_eq!(lines[0], "line 195000");
+        assert_eq!(last_lines(&path, 300_000).await.unwrap().len(), 200_000);
+    }
+
+    #[tokio::test]
+    async fn test_symlinked_log_is_not_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = dir.path().join("shadow");
+        std::fs::write(&secret, "root:secret\n").unwrap();
+        let path = dir.path().join("frame.log");
+        std::os::unix::fs::symlink(&secret, &path).unwrap();
+
+        let err = last_lines(&path, 10).await.unwrap_err();
+        assert!(err.to_string().contains("is not a regular file"));
+        let mut cursor = FileCursor::default();
+        assert!(read_new_lines(&path, &mut cursor).await.is_empty());
+        assert_eq!(cursor.offset, 0);
+    }
+
+    async fn next(rx: &mut mpsc::Receiver<String>) -> String {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_follow_with_backfill() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = log_path(dir.path(), "alice");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "one\ntwo\nthree\nfo").unwrap();
+
+        assert_eq!(last_lines(&path, 2).await.unwrap(), ["three", "fo"]);
+
+        let mut tailer = LogTailer::new(dir.path().to_path_buf(), 1);
+        tailer.poll_interval = Duration::from_millis(10);
+        let mut rx = tailer.follow("alice", 2);
+        assert_eq!(next(&mut rx).await, "two");
+        assert_eq!(next(&mut rx).await, "three");
+
+        // The unterminated line is sent once it's complete
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"ur\nfive\n").unwrap();
+        assert_eq!(next(&mut rx).await, "four");
+        assert_eq!(next(&mut rx).await, "five");
+    }
+}
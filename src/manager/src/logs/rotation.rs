@@ -0,0 +1,260 @@
+//! Log Rotation
+//!
+//! Keeps instance logs within the `[logging]` limits: `frame.log` is copied
+//! to `frame.log.<timestamp>` and truncated once it grows past
+//! `max_file_size`, and rotated files older than `retention_days` are
+//! deleted. The running frame-server keeps its log open, so the file is
+//! truncated in place rather than renamed away from under it; lines written
+//! between the copy and the truncation are lost. Log followers notice the
+//! file shrinking and start over from its beginning.
+//!
+//! The logs directory belongs to the instance's user, who can swap any
+//! entry for a symlink while root works on it. Everything is therefore done
+//! relative to a descriptor of the directory: `frame.log` is only used if it
+//! opens as a regular file without following a link, and the rotated copy
+//! is always a file created fresh.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use nix::dir::Dir;
+use nix::errno::Errno;
+use nix::fcntl::{openat, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::{unlinkat, UnlinkatFlags};
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::config::LoggingConfig;
+
+/// Name of the log file written by frame-server
+const LOG_FILE: &str = "frame.log";
+
+/// Timestamp suffix of rotated log files
+const ROTATED_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// How often instance logs are checked for rotation
+pub const ROTATION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// When instance logs are rotated and cleaned up
+#[derive(Debug, Clone, Copy)]
+pub struct RotationPolicy {
+    /// Size in bytes past which `frame.log` is rotated (0 never rotates)
+    pub max_bytes: u64,
+    /// Age after which rotated logs are deleted (kept forever if `None`)
+    pub retention: Option<chrono::Duration>,
+}
+
+/// What a rotation pass did to one log directory
+#[derive(Debug, Default)]
+pub struct RotationOutcome {
+    /// Where `frame.log` was copied, if it was rotated
+    pub rotated: Option<PathBuf>,
+    /// Expired rotated logs deleted
+    pub removed: usize,
+}
+
+impl RotationPolicy {
+    /// Policy from the `[logging]` section
+    pub fn from_config(config: &LoggingConfig) -> Self {
+        Self {
+            max_bytes: config.max_file_size * 1024 * 1024,
+            retention: (config.retention_days > 0)
+                .then(|| chrono::Duration::days(config.retention_days.into())),
+        }
+    }
+
+    /// Rotate `frame.log` in `logs_dir` if it's too large, then delete
+    /// rotated logs past the retention
+    pub async fn apply(&self, logs_dir: &Path, now: DateTime<Utc>) -> io::Result<RotationOutcome> {
+        let policy = *self;
+        let logs_dir = logs_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || policy.apply_blocking(&logs_dir, now))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    fn apply_blocking(&self, logs_dir: &Path, now: DateTime<Utc>) -> io::Result<RotationOutcome> {
+        let mut outcome = RotationOutcome::default();
+        let flags = OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+        let mut dir = match Dir::open(logs_dir, flags, Mode::empty()) {
+            Ok(dir) => dir,
+            Err(Errno::ENOENT) => return Ok(outcome),
+            Err(e) => return Err(e.into()),
+        };
+
+        if self.max_bytes > 0 {
+            if let Some(mut log) = open_log(&dir)? {
+                let meta = log.metadata()?;
+                if meta.len() > self.max_bytes {
+                    let name = format!("{}.{}", LOG_FILE, now.format(ROTATED_FORMAT));
+                    // A log rotated within the same second is left alone until the next pass
+                    if let Some(mut rotated) = create_rotated(&dir, &name)? {
+                        // Readable by the user, like the log it was copied from
+                        std::os::unix::fs::fchown(&rotated, Some(meta.uid()), Some(meta.gid()))?;
+                        io::copy(&mut log, &mut rotated)?;
+                        log.set_len(0)?;
+                        outcome.rotated = Some(logs_dir.join(name));
+                    }
+                }
+            }
+        }
+
+        let Some(retention) = self.retention else {
+            return Ok(outcome);
+        };
+        let fd = dir.as_raw_fd();
+        let mut expired = Vec::new();
+        for entry in dir.iter() {
+            let entry = entry?;
+            let Some(rotated_at) = entry.file_name().to_str().ok().and_then(rotated_at) else {
+                continue;
+            };
+            if now - rotated_at > retention {
+                expired.push(entry.file_name().to_owned());
+            }
+        }
+        for name in expired {
+            unlinkat(Some(fd), name.as_c_str(), UnlinkatFlags::NoRemoveDir)?;
+            outcome.removed += 1;
+        }
+
+        Ok(outcome)
+    }
+}
+
+/// Open `frame.log` for reading and truncation; `None` if there is none
+///
+/// A symlink in its place or anything but a regular file is an error rather
+/// than something to copy from and truncate.
+fn open_log(dir: &Dir) -> io::Result<Option<File>> {
+    // Non-blocking so a FIFO can't stall the open
+    let flags = OFlag::O_RDWR | OFlag::O_NOFOLLOW | OFlag::O_NONBLOCK | OFlag::O_CLOEXEC;
+    let fd = match openat(Some(dir.as_raw_fd()), LOG_FILE, flags, Mode::empty()) {
+        Ok(fd) => fd,
+        Err(Errno::ENOENT) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    // SAFETY: openat just returned this descriptor and nothing else owns it
+    let file = unsafe { File::from_raw_fd(fd) };
+    if !file.metadata()?.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a regular file", LOG_FILE),
+        ));
+    }
+    Ok(Some(file))
+}
+
+/// Create the rotated copy `name`; `None` if something already has the name
+fn create_rotated(dir: &Dir, name: &str) -> io::Result<Option<File>> {
+    let flags =
+        OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    let mode = Mode::from_bits_truncate(0o600);
+    match openat(Some(dir.as_raw_fd()), name, flags, mode) {
+        // SAFETY: openat just returned this descriptor and nothing else owns it
+        Ok(fd) => Ok(Some(unsafe { File::from_raw_fd(fd) })),
+        Err(Errno::EEXIST) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// When a rotated log was rotated, from its name
+fn rotated_at(name: &str) -> Option<DateTime<Utc>> {
+    let suffix = name.strip_prefix(LOG_FILE)?.strip_prefix('.')?;
+    NaiveDateTime::parse_from_str(suffix, ROTATED_FORMAT)
+        .ok()
+        .map(|at| at.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn test_rotate_and_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = RotationPolicy {
+            max_bytes: 16,
+            retention: Some(chrono::Duration::days(7)),
+        };
+        let now = Utc::now();
+        let log_path = dir.path().join(LOG_FILE);
+
+        // Small enough to stay put
+        std::fs::write(&log_path, "short\n").unwrap();
+        let outcome = policy.apply(dir.path(), now).await.unwrap();
+        assert!(outcome.rotated.is_none());
+        assert!(log_path.exists());
+
+        // Past the size limit it's copied aside and emptied, while the
+        // server keeps writing through the handle it opened
+        let mut server = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&log_path)
+            .unwrap();
+        server.write_all(b"a line longer than the limit\n").unwrap();
+        let outcome = policy.apply(dir.path(), now).await.unwrap();
+        let rotated = outcome.rotated.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&rotated).unwrap(),
+            "short\na line longer than the limit\n"
+        );
+        server.write_all(b"after\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&log_path).unwrap(), "after\n");
+        assert_eq!(
+            rotated_at(rotated.file_name().unwrap().to_str().unwrap()),
+            Some(now.with_nanosecond(0).unwrap())
+        );
+
+        // Only rotated logs past the retention are deleted
+        let old = now - chrono::Duration::days(8);
+        let expired = dir
+            .path()
+            .join(format!("{}.{}", LOG_FILE, old.format(ROTATED_FORMAT)));
+        std::fs::write(&expired, "old\n").unwrap();
+        std::fs::write(dir.path().join("frame.log.bak"), "kept\n").unwrap();
+        let outcome = policy.apply(dir.path(), now).await.unwrap();
+        assert_eq!(outcome.removed, 1);
+        assert!(!expired.exists());
+        assert!(rotated.exists());
+        assert!(dir.path().join("frame.log.bak").exists());
+    }
+
+    #[tokio::test]
+    async fn test_symlinks_are_not_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs = dir.path().join("logs");
+        std::fs::create_dir(&logs).unwrap();
+        let policy = RotationPolicy {
+            max_bytes: 4,
+            retention: None,
+        };
+        let now = Utc::now();
+
+        // frame.log pointing at a file of root's is neither copied nor truncated
+        let target = dir.path().join("shadow");
+        std::fs::write(&target, "root:secret\n").unwrap();
+        std::os::unix::fs::symlink(&target, logs.join(LOG_FILE)).unwrap();
+        assert!(policy.apply(&logs, now).await.is_err());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "root:secret\n");
+        assert_eq!(std::fs::read_dir(&logs).unwrap().count(), 1);
+
+        // A link planted at the next rotated name isn't written through
+        std::fs::remove_file(logs.join(LOG_FILE)).unwrap();
+        std::fs::write(logs.join(LOG_FILE), "user controlled\n").unwrap();
+        let planted = dir.path().join("planted");
+        let rotated = logs.join(format!("{}.{}", LOG_FILE, now.format(ROTATED_FORMAT)));
+        std::os::unix::fs::symlink(&planted, &rotated).unwrap();
+        let outcome = policy.apply(&logs, now).await.unwrap();
+        assert!(outcome.rotated.is_none());
+        assert!(!planted.exists());
+        assert_eq!(
+            std::fs::read_to_string(logs.join(LOG_FILE)).unwrap(),
+            "user controlled\n"
+        );
+    }
+}
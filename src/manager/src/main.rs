@@ -10,7 +10,11 @@ use std::path::PathBuf;
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
-use frame_manager::{config::Config, manager::FrameManager};
+use frame_manager::{
+    config::{Config, ConfigValidation, CONFIG_VERSION},
+    daemon::{self, Pidfile},
+    manager::FrameManager,
+};
 
 /// Frame Service Manager for cPanel
 #[derive(Parser)]
@@ -25,6 +29,18 @@ struct Cli {
     #[arg(short, long, default_value = "info")]
     log_level: String,
 
+    /// Fork into the background instead of running in the foreground (systemd)
+    #[arg(long)]
+    daemonize: bool,
+
+    /// Pidfile locked while the daemon runs (prevents concurrent managers)
+    #[arg(long, default_value = "/var/frame/manager/frame-manager.pid")]
+    pidfile: PathBuf,
+
+    /// Log file used when daemonized
+    #[arg(long, default_value = "/var/log/frame/manager.log")]
+    log_file: PathBuf,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -57,12 +73,34 @@ enum Commands {
 
     /// Show statistics
     Stats {
-        /// Stat type (memory, cpu, instances)
+        /// Stat type (memory, cpu, disk, instances)
         stat_type: Option<String>,
     },
 
     /// Reload configuration
     Reload,
+
+    /// Configuration file maintenance
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Upgrade the configuration file to the current config version
+    Migrate {
+        /// Write the upgraded file back (the original is kept as .bak)
+        #[arg(long)]
+        write: bool,
+    },
+    /// Check a configuration file without applying it
+    Validate {
+        /// File to check (defaults to the --config file)
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -89,6 +127,11 @@ enum UserCommands {
     },
     /// List all user instances
     List,
+    /// Re-chown a user's instance directory after their UID/GID changed
+    FixOwnership {
+        /// Username
+        username: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -102,15 +145,24 @@ enum PortCommands {
     Release {
         /// Username
         username: String,
+        /// Succeed without error if no port is allocated
+        #[arg(long)]
+        if_present: bool,
     },
     /// List all port allocations
     List,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    // Daemonize before the runtime spawns any threads
+    let daemonize = cli.daemonize && matches!(cli.command, None | Some(Commands::Start));
+    if daemonize {
+        Pidfile::ensure_not_running(&cli.pidfile)?;
+        daemon::daemonize(&cli.log_file)?;
+    }
+
     // Initialize logging
     let log_level = match cli.log_level.to_lowercase().as_str() {
         "trace" => Level::TRACE,
@@ -121,29 +173,95 @@ async fn main() -> Result<()> {
         _ => Level::INFO,
     };
 
-    let subscriber = FmtSubscriber::builder()
+    FmtSubscriber::builder()
         .with_max_level(log_level)
         .with_target(true)
         .with_thread_ids(true)
         .with_file(true)
         .with_line_number(true)
+        .with_ansi(!daemonize)
         .init();
 
+    tokio::runtime::Runtime::new()?.block_on(run(cli))
+}
+
+async fn run(cli: Cli) -> Result<()> {
     info!("Frame Manager starting...");
     info!("Configuration file: {}", cli.config.display());
 
+    // Config maintenance works on the raw file and needs no running manager
+    if let Some(Commands::Config { action }) = &cli.command {
+        match action {
+            ConfigCommands::Migrate { write } => {
+                let migrated = Config::migrate_file(&cli.config, *write)?;
+                if !migrated.is_changed() {
+                    println!("Configuration is already at version {}", CONFIG_VERSION);
+                    return Ok(());
+                }
+                for change in &migrated.changes {
+                    println!("{}", change);
+                }
+                if *write {
+                    println!("Configuration upgraded to version {}", CONFIG_VERSION);
+                } else {
+                    println!("Run with --write to apply these changes");
+                }
+            }
+            ConfigCommands::Validate { file } => {
+                let file = file.as_deref().unwrap_or(&cli.config);
+                // Cross-checks need the state of the current configuration;
+                // if even that doesn't load, check the file on its own
+                let validation = match Config::load(&cli.config) {
+                    Ok(config) => {
+                        FrameManager::with_config_path(config, cli.config.clone())
+                            .await?
+                            .validate_config_file(file)
+                            .await
+                    }
+                    Err(_) => ConfigValidation::load(file).0,
+                };
+                println!("{}", serde_json::to_string_pretty(&validation)?);
+                if !validation.valid {
+                    anyhow::bail!("{} is not valid", file.display());
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // Lock the pidfile before touching any state, so a second daemon never
+    // gets as far as the port registry or the API socket
+    let _pidfile = match cli.command {
+        None | Some(Commands::Start) | Some(Commands::Restart) => {
+            Some(Pidfile::create(&cli.pidfile)?)
+        }
+        _ => None,
+    };
+
     // Load configuration
     let config = Config::load(&cli.config)?;
     info!("Configuration loaded successfully");
 
     // Create manager instance
-    let manager = FrameManager::new(config).await?;
+    let manager = FrameManager::with_config_path(config, cli.config.clone()).await?;
 
     // Handle commands
     match cli.command {
         None | Some(Commands::Start) => {
             info!("Starting Frame Manager daemon...");
-            manager.run().await?;
+
+            let run = manager.run();
+            tokio::pin!(run);
+            tokio::select! {
+                result = &mut run => result?,
+                _ = shutdown_signal() => {
+                    info!("Shutdown signal received");
+                    // Keep `run` polled so the API server can drain while stopping
+                    let (stopped, served) = tokio::join!(manager.stop(), run);
+                    stopped?;
+                    served?;
+                }
+            }
         }
         Some(Commands::Stop) => {
             info!("Stopping Frame Manager daemon...");
@@ -179,8 +297,17 @@ async fn main() -> Result<()> {
                 println!("{}", serde_json::to_string_pretty(&status)?);
             }
             UserCommands::List => {
-                let instances = manager.list_instances().await?;
-                println!("{}", serde_json::to_string_pretty(&instances)?);
+                let page = manager.list_instances(&Default::default()).await?;
+                println!("{}", serde_json::to_string_pretty(&page.instances)?);
+            }
+            UserCommands::FixOwnership { username } => {
+                match manager.fix_ownership(&username).await? {
+                    Some(change) => println!(
+                        "Changed ownership for user {} from {}:{} to {}:{}",
+                        username, change.old_uid, change.old_gid, change.new_uid, change.new_gid
+                    ),
+                    None => println!("Ownership already correct for user: {}", username),
+                }
             }
         },
         Some(Commands::Port { action }) => match action {
@@ -188,8 +315,15 @@ async fn main() -> Result<()> {
                 let port = manager.allocate_port(&username).await?;
                 println!("Allocated port {} for user: {}", port, username);
             }
-            PortCommands::Release { username } => {
-                manager.release_port(&username).await?;
+            PortCommands::Release {
+                username,
+                if_present,
+            } => {
+                if if_present {
+                    manager.release_port_if_present(&username).await?;
+                } else {
+                    manager.release_port(&username).await?;
+                }
                 println!("Released port for user: {}", username);
             }
             PortCommands::List => {
@@ -206,7 +340,27 @@ async fn main() -> Result<()> {
             manager.reload_config().await?;
             println!("Configuration reloaded");
         }
+        Some(Commands::Config { .. }) => unreachable!("handled before the manager starts"),
     }
 
     Ok(())
 }
+
+/// Wait for SIGTERM or SIGINT
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(e) => {
+            tracing::error!("Failed to install SIGTERM handler: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
@@ -2,20 +2,74 @@
 //!
 //! Coordinates all Frame manager components.
 
-use anyhow::Result;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures::future;
+use futures::stream::{self, StreamExt};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-
-use crate::api::handlers::{InstanceStatusResponse, ServiceStatus, SettingsUpdate, PackageUpdate};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
+
+use crate::api::auth::ApiAuth;
+use crate::api::handlers::{
+    BatchAction, GroupMemberResult, InstanceLimitsResponse, InstanceStatusResponse,
+    PackageMemberResult, PackageUpdate, ServiceStatus, SettingsSectionUpdate, SettingsUpdate,
+};
+use crate::api::listing::{InstanceListQuery, InstancePage};
 use crate::api::ApiServer;
-use crate::config::{Config, PackageConfig};
-use crate::events::{Event, EventEmitter};
-use crate::health::HealthMonitor;
-use crate::instance::{Instance, InstanceManager, ResourceLimits};
-use crate::metrics::MetricsCollector;
-use crate::port::PortAllocator;
+use crate::config::{
+    Config, ConfigValidation, DefaultsConfig, EffectiveConfig, GroupError, GroupsConfig,
+    PackageConfig, PackageLimits,
+};
+use crate::deploy::{self, DeployError, DeployResult};
+use crate::events::{
+    Event, EventEmitter, EventEnvelope, EventQuery, HookInfo, HookTestResult, WebhookSink,
+};
+use crate::health::{HealthCheck, HealthMonitor};
+use crate::instance::{
+    cpanel_plan, validate_username, FlapPolicy, ForceKillReport, InstanceManager, OwnershipChange,
+    RemoveOptions, RemoveReport, ResourceLimits, RunAs,
+};
+use crate::logs::{self, LogFilter, LogLevel, LogTailer, RotationPolicy};
+use crate::metrics::{load_average_1m, HostUsage, MetricsCollector, RequestReport, SelfMonitor};
+use crate::operations::{Operation, OperationHandle, OperationKind, OperationQueue};
+use crate::port::{is_port_in_use, PortAllocator};
+use crate::stats::{
+    CpuStats, DiskStats, InstanceCounts, InstanceCpu, InstanceDisk, InstanceMemory, InstancesStats,
+    MemoryStats, Stats,
+};
+
+/// Start attempts per instance during auto-start
+const AUTO_START_ATTEMPTS: u32 = 2;
+
+/// How long an auto-started instance has to pass its first health check
+const AUTO_START_HEALTH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Interval between health polls while waiting for an auto-started instance
+const AUTO_START_HEALTH_POLL: Duration = Duration::from_secs(1);
+
+/// Longest an auto-start wave waits for the load average to drop
+const AUTO_START_MAX_LOAD_WAIT: Duration = Duration::from_secs(600);
+
+/// Interval between load average checks while an auto-start wave waits
+const AUTO_START_LOAD_POLL: Duration = Duration::from_secs(5);
+
+/// How long a deployment candidate has to pass its health check
+const DEPLOY_HEALTH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum number of instances stopped concurrently when disabling the service
+const DISABLE_STOP_CONCURRENCY: usize = 4;
+
+/// Disk usage of every instance as last measured
+struct DiskUsageSnapshot {
+    taken: tokio::time::Instant,
+    /// Modification time of the config file when measured
+    config_modified: Option<std::time::SystemTime>,
+    used_mb: HashMap<String, u64>,
+}
 
 /// Main Frame Manager
 pub struct FrameManager {
@@ -23,6 +77,8 @@ pub struct FrameManager {
     config: Arc<RwLock<Config>>,
     /// Configuration file path
     config_path: PathBuf,
+    /// Directory of hosting package configs
+    packages_dir: PathBuf,
     /// Instance manager
     instance_manager: Arc<InstanceManager>,
     /// Port allocator
@@ -31,21 +87,56 @@ pub struct FrameManager {
     health_monitor: Arc<HealthMonitor>,
     /// Metrics collector
     metrics: Arc<RwLock<MetricsCollector>>,
+    /// Samples the manager's own resource usage for the status endpoint
+    status_monitor: Arc<Mutex<SelfMonitor>>,
+    /// Samples the manager's own resource usage for the metrics; separate
+    /// from `status_monitor` so neither resets the other's CPU baseline
+    metrics_monitor: Arc<Mutex<SelfMonitor>>,
+    /// When the manager was created, for its uptime
+    started: Instant,
     /// Event emitter
     events: Arc<EventEmitter>,
-    /// API server
-    api_server: Option<Arc<ApiServer>>,
+    /// API server, once `run` has started it
+    api_server: Arc<Mutex<Option<Arc<ApiServer>>>>,
+    /// Users with a deployment in progress
+    deploys_in_progress: Arc<Mutex<HashSet<String>>>,
+    /// Queue of instance operations requested through the API
+    operations: Arc<OperationQueue>,
+    /// Last disk usage measurement; walking is slow
+    disk_usage: Arc<Mutex<Option<DiskUsageSnapshot>>>,
     /// Running state
     running: Arc<RwLock<bool>>,
+    /// Startup finished and the API is bound; cleared again on stop
+    ready: AtomicBool,
 }
 
 impl FrameManager {
-    /// Create a new Frame manager
+    /// Create a new Frame manager reloading from `/etc/frame/frame.conf`
     pub async fn new(config: Config) -> Result<Arc<Self>> {
-        let config_path = PathBuf::from("/etc/frame/frame.conf");
-        let instances_dir = PathBuf::from("/var/frame/instances");
-        let ports_registry = PathBuf::from("/var/frame/manager/ports.json");
-        let frame_server_path = PathBuf::from("/usr/local/cpanel/3rdparty/bin/frame-server");
+        Self::with_config_path(config, PathBuf::from("/etc/frame/frame.conf")).await
+    }
+
+    /// Create a new Frame manager whose configuration was loaded from `config_path`
+    pub async fn with_config_path(config: Config, config_path: PathBuf) -> Result<Arc<Self>> {
+        Self::build(config, config_path, RunAs::Sudo).await
+    }
+
+    /// Create a Frame manager whose instances run as the manager's own user
+    #[cfg(test)]
+    pub(crate) async fn running_as_manager(config: Config) -> Result<Arc<Self>> {
+        Self::build(
+            config,
+            PathBuf::from("/etc/frame/frame.conf"),
+            RunAs::Manager,
+        )
+        .await
+    }
+
+    async fn build(config: Config, config_path: PathBuf, run_as: RunAs) -> Result<Arc<Self>> {
+        let instances_dir = PathBuf::from(&config.paths.instances_dir);
+        let ports_registry = PathBuf::from(&config.paths.registry_path);
+        let frame_server_path = PathBuf::from(&config.paths.frame_server_path);
+        let packages_dir = PathBuf::from(&config.paths.packages_dir);
 
         // Create default resource limits from config
         let default_limits = ResourceLimits::from_defaults(
@@ -56,41 +147,122 @@ impl FrameManager {
         );
 
         // Initialize components
-        let port_allocator = Arc::new(PortAllocator::new(
-            config.service.port_range_start,
-            config.service.port_range_end,
-            &ports_registry,
-        )?);
-
-        let instance_manager = Arc::new(InstanceManager::new(
-            instances_dir,
-            frame_server_path,
-            default_limits,
-        ));
-
-        let health_monitor = Arc::new(HealthMonitor::new(
-            config.service.health_check_interval,
-            Arc::clone(&instance_manager),
-        ));
+        let mut events = EventEmitter::new(PathBuf::from(&config.paths.hooks_dir))
+            .with_hook_events(&config.events.enabled_events)
+            .with_hook_timeout(std::time::Duration::from_secs(
+                config.events.hook_timeout_secs,
+            ))
+            .with_history_size(config.events.history_size);
+        if let Some(sink) = WebhookSink::from_config(&config.events)? {
+            events = events.with_webhook(sink);
+        }
+        let events = Arc::new(events);
+
+        let port_allocator = Arc::new(
+            PortAllocator::new(
+                config.service.port_range_start,
+                config.service.port_range_end,
+                &ports_registry,
+            )?
+            .with_reserved(vec![config.service.manager_port])
+            .with_released_port_reuse(config.service.reuse_released_ports)
+            .with_events(Arc::clone(&events)),
+        );
+
+        let instance_manager = Arc::new(
+            InstanceManager::new(
+                instances_dir,
+                frame_server_path,
+                default_limits,
+                config.security.env_policy(),
+                config.security.user_policy(),
+            )
+            .with_flap_detection(
+                FlapPolicy {
+                    threshold: config.service.flap_threshold,
+                    window: chrono::Duration::seconds(config.service.flap_window_secs as i64),
+                },
+                Arc::clone(&events),
+            )
+            .with_cgroup_backend(config.service.cgroup_backend()?)
+            .with_spawn_mode(config.service.spawn_mode()?)
+            .with_run_as(run_as)
+            .with_stop_grace(Duration::from_millis(config.service.stop_grace_ms))
+            .with_exit_policy(config.service.exit_policy()?)
+            .with_backups_dir(PathBuf::from(&config.service.backups_dir))
+            .with_state_dir(PathBuf::from(&config.paths.state_dir))
+            .with_max_concurrent_starts(config.service.max_concurrent_starts)
+            .with_readiness_probe(
+                Duration::from_millis(config.service.readiness_probe_timeout_ms),
+                config.service.readiness_probe_retries,
+            ),
+        );
 
         let metrics = Arc::new(RwLock::new(MetricsCollector::default()));
-        let events = Arc::new(EventEmitter::default());
+        let health_monitor = Arc::new(
+            HealthMonitor::new(
+                config.service.health_check_interval,
+                config.service.health_check_concurrency,
+                Duration::from_millis(config.service.health_check_timeout_ms),
+                config.service.health_check_tls_warn_days,
+                config.service.restart_policy(),
+                Arc::clone(&instance_manager),
+                Arc::clone(&events),
+            )
+            .with_http_accept_redirects(config.service.health_check_accept_redirects)
+            .with_disk_check_interval(config.service.disk_check_interval)
+            .with_metrics(Arc::clone(&metrics)),
+        );
+
+        let operations = Arc::new(OperationQueue::new(config.service.operation_queue_size));
 
         let manager = Arc::new(Self {
             config: Arc::new(RwLock::new(config)),
             config_path,
+            packages_dir,
             instance_manager,
             port_allocator,
             health_monitor,
             metrics,
+            status_monitor: Arc::new(Mutex::new(SelfMonitor::new())),
+            metrics_monitor: Arc::new(Mutex::new(SelfMonitor::new())),
+            started: Instant::now(),
             events,
-            api_server: None,
+            api_server: Arc::new(Mutex::new(None)),
+            deploys_in_progress: Arc::new(Mutex::new(HashSet::new())),
+            operations,
+            disk_usage: Arc::new(Mutex::new(None)),
             running: Arc::new(RwLock::new(false)),
+            ready: AtomicBool::new(false),
         });
 
         Ok(manager)
     }
 
+    /// Start the workers executing queued instance operations
+    ///
+    /// The workers only hold a weak reference: the manager owns the queue,
+    /// so a strong one would keep both alive forever.
+    pub(crate) async fn start_operation_workers(self: &Arc<Self>) {
+        let spawn_concurrency = self.config.read().await.service.spawn_concurrency;
+        let manager = Arc::downgrade(self);
+        self.operations
+            .start_workers(spawn_concurrency, move |kind, username| {
+                let manager = manager.upgrade();
+                async move {
+                    let Some(manager) = manager else {
+                        anyhow::bail!("Manager shut down before {:?} for {} ran", kind, username);
+                    };
+                    match kind {
+                        OperationKind::Start => manager.start_instance(&username).await,
+                        OperationKind::Stop => manager.stop_instance(&username).await,
+                        OperationKind::Restart => manager.restart_instance(&username).await,
+                    }
+                }
+            })
+            .await;
+    }
+
     /// Run the Frame manager (main loop)
     pub async fn run(self: &Arc<Self>) -> Result<()> {
         let mut running = self.running.write().await;
@@ -104,16 +276,37 @@ impl FrameManager {
 
         // Initialize instance manager
         self.instance_manager.init().await?;
+        self.port_allocator.check_reserved().await?;
+        self.reconcile_ports().await?;
+        self.port_allocator.check_capacity().await;
 
         // Start health monitor
         self.health_monitor.start().await;
 
+        // Start the workers serving queued instance operations
+        self.start_operation_workers().await;
+
+        // Refresh metrics in the background so scrapes only read the collector
+        let metrics_interval = self.config.read().await.service.metrics_interval;
+        let manager = Arc::clone(self);
+        tokio::spawn(async move {
+            manager
+                .collect_metrics(Duration::from_secs(metrics_interval))
+                .await
+        });
+
+        // Keep instance logs within the configured size and retention
+        let manager = Arc::clone(self);
+        tokio::spawn(async move { manager.rotate_logs(logs::ROTATION_INTERVAL).await });
+
         // Emit service started event
         self.events.emit(Event::ServiceStarted).await;
 
         // Auto-start instances if configured
         let config = self.config.read().await;
-        if config.service.auto_start {
+        if config.service.auto_start && !config.service.enabled {
+            tracing::info!("Frame service is disabled, skipping auto-start");
+        } else if config.service.auto_start {
             drop(config);
             self.auto_start_instances().await?;
         }
@@ -121,30 +314,32 @@ impl FrameManager {
         // Start API server
         let config = self.config.read().await;
         let api_port = config.service.manager_port;
+        let listener_options = config.service.listener_options();
         drop(config);
 
         tracing::info!("Frame Manager is running on port {}", api_port);
 
-        // Create and run API server (this blocks)
-        let api_server = ApiServer::new(api_port, Arc::clone(&self.clone()));
+        // Create and run API server (this blocks until `stop` drains it)
+        let api_server = Arc::new(
+            ApiServer::new(api_port, Arc::clone(self)).with_listener_options(listener_options),
+        );
+        *self.api_server.lock().await = Some(Arc::clone(&api_server));
         api_server.start().await?;
 
         Ok(())
     }
 
-    /// Clone the Arc for passing to API server
-    fn clone(&self) -> Arc<Self> {
-        Arc::new(Self {
-            config: Arc::clone(&self.config),
-            config_path: self.config_path.clone(),
-            instance_manager: Arc::clone(&self.instance_manager),
-            port_allocator: Arc::clone(&self.port_allocator),
-            health_monitor: Arc::clone(&self.health_monitor),
-            metrics: Arc::clone(&self.metrics),
-            events: Arc::clone(&self.events),
-            api_server: self.api_server.clone(),
-            running: Arc::clone(&self.running),
-        })
+    /// Whether the manager is ready to serve requests
+    ///
+    /// Only true between the API binding its listener at the end of startup
+    /// and the start of a stop.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// Mark the manager ready, once the API server is listening
+    pub(crate) fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Release);
     }
 
     /// Stop the Frame manager
@@ -155,21 +350,26 @@ impl FrameManager {
         }
         *running = false;
         drop(running);
+        self.set_ready(false);
 
         tracing::info!("Stopping Frame Manager...");
 
-        // Stop health monitor
+        // Stop taking requests first so nothing starts instances while they
+        // are torn down; requests already in flight are answered
+        let api_server = self.api_server.lock().await.take();
+        if let Some(api_server) = api_server {
+            api_server.stop().await;
+        }
+
+        // Then stop health monitoring so it doesn't restart stopping instances
         self.health_monitor.stop().await;
 
         // Stop all instances
         let instances = self.instance_manager.list().await;
         for instance in instances {
-            let _ = self.instance_manager.stop(&instance.username).await;
-        }
-
-        // Stop API server
-        if let Some(api_server) = &self.api_server {
-            api_server.stop().await;
+            if let Err(e) = self.instance_manager.stop(&instance.username).await {
+                tracing::warn!("Failed to stop instance for {}: {}", instance.username, e);
+            }
         }
 
         // Emit service stopped event
@@ -181,34 +381,173 @@ impl FrameManager {
     }
 
     /// Auto-start instances with auto_start enabled
+    ///
+    /// Each instance must pass a health check within `AUTO_START_HEALTH_TIMEOUT`
+    /// before it is considered started; failed instances are retried up to
+    /// `AUTO_START_ATTEMPTS` times and reported in a summary.
+    ///
+    /// With `auto_start_batch_size` set, instances are started concurrently
+    /// in waves, pausing between waves and while the system load is high.
     async fn auto_start_instances(&self) -> Result<()> {
         let instances = self.instance_manager.list().await;
+        let mut started = 0;
+        let mut failed: Vec<(String, String)> = Vec::new();
 
+        let mut usernames = Vec::new();
         for instance in instances {
+            if instance.quarantined {
+                tracing::info!(
+                    "Not auto-starting quarantined instance for {}",
+                    instance.username
+                );
+                continue;
+            }
+
             // Check if instance config has auto_start
-            let config_path = PathBuf::from("/var/frame/instances")
-                .join(&instance.username)
+            let config_path = self
+                .instance_manager
+                .instance_dir(&instance.username)
                 .join("config.json");
 
             if config_path.exists() {
                 let content = tokio::fs::read_to_string(&config_path).await?;
                 let config: serde_json::Value = serde_json::from_str(&content)?;
 
-                if config.get("auto_start").and_then(|v| v.as_bool()).unwrap_or(true) {
-                    if let Err(e) = self.start_instance(&instance.username).await {
-                        tracing::error!(
-                            "Failed to auto-start instance for {}: {}",
-                            instance.username,
-                            e
-                        );
-                    }
+                if config
+                    .get("auto_start")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(true)
+                {
+                    usernames.push(instance.username);
                 }
             }
         }
 
+        let config = self.config.read().await;
+        let (batch_size, batch_delay, max_load) = match config.service.auto_start_batch_size {
+            0 => (1, Duration::ZERO, 0.0),
+            size => (
+                size,
+                Duration::from_secs(config.service.auto_start_batch_delay_secs),
+                config.service.auto_start_max_load,
+            ),
+        };
+        drop(config);
+
+        let results = start_in_waves(&usernames, batch_size, batch_delay, max_load, |username| {
+            self.auto_start_instance(username)
+        })
+        .await;
+        for (username, result) in usernames.iter().zip(results) {
+            match result {
+                Ok(()) => started += 1,
+                Err(e) => {
+                    tracing::error!("Failed to auto-start instance for {}: {}", username, e);
+                    failed.push((username.clone(), e.to_string()));
+                }
+            }
+        }
+
+        if failed.is_empty() {
+            tracing::info!("Auto-start complete: {} instances healthy", started);
+        } else {
+            let users: Vec<&str> = failed.iter().map(|(u, _)| u.as_str()).collect();
+            tracing::warn!(
+                "Auto-start complete: {} instances healthy, {} failed ({})",
+                started,
+                failed.len(),
+                users.join(", ")
+            );
+        }
+
+        for (username, reason) in failed {
+            let mut labels = HashMap::new();
+            labels.insert("user".to_string(), username.clone());
+            self.metrics
+                .write()
+                .await
+                .inc_counter("frame_autostart_failures_total", labels);
+
+            self.events
+                .emit(Event::AutoStartFailed { username, reason })
+                .await;
+        }
+
         Ok(())
     }
 
+    /// Start a single instance and wait for it to become healthy, retrying on failure
+    async fn auto_start_instance(&self, username: &str) -> Result<()> {
+        let mut last_error = anyhow::anyhow!("Instance was not started");
+
+        for attempt in 1..=AUTO_START_ATTEMPTS {
+            if attempt > 1 {
+                tracing::info!(
+                    "Retrying auto-start for {} (attempt {}/{})",
+                    username,
+                    attempt,
+                    AUTO_START_ATTEMPTS
+                );
+                let _ = self.instance_manager.stop(username).await;
+            }
+
+            if let Err(e) = self.start_instance(username).await {
+                last_error = e;
+                continue;
+            }
+
+            self.instance_manager
+                .set_status_detail(
+                    username,
+                    Some(format!(
+                        "awaiting initial health check (attempt {}/{})",
+                        attempt, AUTO_START_ATTEMPTS
+                    )),
+                )
+                .await;
+
+            let result = self.wait_until_healthy(username).await;
+            self.instance_manager
+                .set_status_detail(username, None)
+                .await;
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Poll health checks until the instance passes or the timeout expires
+    async fn wait_until_healthy(&self, username: &str) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + AUTO_START_HEALTH_TIMEOUT;
+
+        loop {
+            let status = self.health_monitor.check_now(username).await?;
+            if status.healthy {
+                return Ok(());
+            }
+
+            if tokio::time::Instant::now() >= deadline {
+                let failed: Vec<String> = status
+                    .checks
+                    .iter()
+                    .filter(|c| !c.passed)
+                    .map(|c| c.message.clone())
+                    .collect();
+                anyhow::bail!(
+                    "Instance did not become healthy within {}s: {}",
+                    AUTO_START_HEALTH_TIMEOUT.as_secs(),
+                    failed.join("; ")
+                );
+            }
+
+            tokio::time::sleep(AUTO_START_HEALTH_POLL).await;
+        }
+    }
+
     /// Get service status
     pub async fn status(&self) -> Result<ServiceStatus> {
         let config = self.config.read().await;
@@ -220,10 +559,12 @@ impl FrameManager {
         let total_memory: u64 = instances.iter().map(|i| i.memory_usage).sum();
 
         Ok(ServiceStatus {
-            service_status: if *self.running.read().await {
-                "running".to_string()
-            } else {
+            service_status: if !*self.running.read().await {
                 "stopped".to_string()
+            } else if !self.is_enabled().await {
+                "disabled".to_string()
+            } else {
+                "running".to_string()
             },
             instances_running: running_count,
             instances_total: total_count,
@@ -232,11 +573,75 @@ impl FrameManager {
                 "{}-{}",
                 config.service.port_range_start, config.service.port_range_end
             ),
+            manager: self.status_monitor.lock().await.sample(),
         })
     }
 
+    /// Whether the global service kill-switch allows instances to run
+    pub async fn is_enabled(&self) -> bool {
+        self.config.read().await.service.enabled
+    }
+
+    async fn ensure_enabled(&self) -> Result<()> {
+        if !self.is_enabled().await {
+            anyhow::bail!("Frame service is disabled; enable it to start instances");
+        }
+        Ok(())
+    }
+
+    /// Turn the global service kill-switch on or off and persist it
+    ///
+    /// Disabling stops every instance that isn't stopped already and returns
+    /// the per-instance results; enabling only allows starts again.
+    pub async fn set_enabled(&self, enabled: bool) -> Result<BTreeMap<String, GroupMemberResult>> {
+        let mut values = serde_json::Map::new();
+        values.insert("enabled".to_string(), enabled.into());
+        self.update_settings_section("service", values).await?;
+        tracing::info!(
+            "Frame service {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        if enabled {
+            return Ok(BTreeMap::new());
+        }
+
+        let running: Vec<String> = self
+            .instance_manager
+            .list()
+            .await
+            .into_iter()
+            .filter(|i| i.status != crate::instance::InstanceStatus::Stopped)
+            .map(|i| i.username)
+            .collect();
+        let results = stream::iter(running)
+            .map(|username| async move {
+                let result = self.stop_instance(&username).await;
+                if let Err(e) = &result {
+                    tracing::warn!("Failed to stop {} while disabling: {}", username, e);
+                }
+                (
+                    username,
+                    GroupMemberResult {
+                        success: result.is_ok(),
+                        error: result.err().map(|e| e.to_string()),
+                    },
+                )
+            })
+            .buffer_unordered(DISABLE_STOP_CONCURRENCY)
+            .collect::<BTreeMap<_, _>>()
+            .await;
+
+        Ok(results)
+    }
+
     /// Start a user instance
     pub async fn start_instance(&self, username: &str) -> Result<()> {
+        validate_username(username)?;
+        self.ensure_enabled().await?;
+
+        // Refuse excluded users before allocating them a port
+        self.instance_manager.ensure_managed(username)?;
+
         // Allocate port
         let port = self.port_allocator.allocate(username).await?;
 
@@ -259,8 +664,116 @@ impl FrameManager {
         Ok(())
     }
 
+    /// Insert a synthetic instance for integration tests (`testing` feature)
+    #[cfg(feature = "testing")]
+    pub async fn inject_instance(
+        &self,
+        spec: crate::instance::SyntheticInstance,
+    ) -> InstanceStatusResponse {
+        let instance = self.instance_manager.inject(spec).await;
+        self.update_metrics().await;
+
+        InstanceStatusResponse::new(
+            &instance,
+            self.instance_manager.is_flapping(&instance.username),
+            self.last_health_check(&instance.username).await,
+        )
+    }
+
+    /// Force-kill a stuck instance, releasing its port once it is free
+    pub async fn force_kill_instance(&self, username: &str) -> Result<ForceKillReport> {
+        validate_username(username)?;
+        let report = self.instance_manager.force_kill(username).await?;
+        // Handing out a port something still listens on would break the
+        // next instance that gets it
+        if report.port_released {
+            self.port_allocator.release_if_present(username).await?;
+        } else {
+            tracing::warn!(
+                "Keeping the port of {} allocated, it is still in use",
+                username
+            );
+        }
+        self.update_metrics().await;
+        Ok(report)
+    }
+
+    /// Create an instance for a user, starting it if `auto_start` is set
+    ///
+    /// Fails with a [`CreateError`](crate::instance::CreateError) if the user already has an instance or
+    /// can't have one. The instance is kept if it fails to start.
+    pub async fn create_instance(
+        &self,
+        username: &str,
+        limits: Option<ResourceLimits>,
+        auto_start: bool,
+    ) -> Result<InstanceStatusResponse> {
+        self.instance_manager.create(username, limits).await?;
+
+        if auto_start {
+            self.start_instance(username).await?;
+        } else {
+            self.update_metrics().await;
+        }
+
+        self.instance_status(username).await
+    }
+
+    /// Remove a user instance and release its port
+    ///
+    /// Removing a user without an instance succeeds, so account cleanup can
+    /// safely be retried.
+    pub async fn remove_instance(
+        &self,
+        username: &str,
+        options: RemoveOptions,
+    ) -> Result<RemoveReport> {
+        validate_username(username)?;
+        let report = self.instance_manager.remove(username, options).await?;
+        self.port_allocator.release_if_present(username).await?;
+
+        self.events
+            .emit(Event::InstanceRemoved {
+                username: username.to_string(),
+                purged: report.purged,
+            })
+            .await;
+
+        // Update metrics
+        self.update_metrics().await;
+
+        Ok(report)
+    }
+
+    /// Freeze a running instance for debugging
+    pub async fn freeze_instance(&self, username: &str) -> Result<()> {
+        validate_username(username)?;
+        self.instance_manager.freeze(username).await?;
+        self.update_metrics().await;
+        Ok(())
+    }
+
+    /// Thaw a frozen instance
+    pub async fn thaw_instance(&self, username: &str) -> Result<()> {
+        validate_username(username)?;
+        self.instance_manager.thaw(username).await?;
+        self.update_metrics().await;
+        Ok(())
+    }
+
+    /// Re-chown a user's instance directory after a UID/GID change
+    ///
+    /// Returns the ownership change made, or `None` if nothing needed fixing.
+    pub async fn fix_ownership(&self, username: &str) -> Result<Option<OwnershipChange>> {
+        validate_username(username)?;
+        // Fail with the usual message for unknown instances
+        self.instance_manager.status(username).await?;
+        self.instance_manager.fix_ownership(username).await
+    }
+
     /// Stop a user instance
     pub async fn stop_instance(&self, username: &str) -> Result<()> {
+        validate_username(username)?;
         self.instance_manager.stop(username).await?;
 
         // Emit event
@@ -278,6 +791,9 @@ impl FrameManager {
 
     /// Restart a user instance
     pub async fn restart_instance(&self, username: &str) -> Result<()> {
+        validate_username(username)?;
+        self.ensure_enabled().await?;
+
         let port = self
             .port_allocator
             .get_port(username)
@@ -285,6 +801,10 @@ impl FrameManager {
             .ok_or_else(|| anyhow::anyhow!("No port allocated for user: {}", username))?;
 
         self.instance_manager.restart(username, port).await?;
+        self.metrics
+            .write()
+            .await
+            .record_restart(username, "manual");
 
         // Update metrics
         self.update_metrics().await;
@@ -292,48 +812,557 @@ impl FrameManager {
         Ok(())
     }
 
-    /// Restart all instances
+    /// Reload a user instance without a full restart
+    ///
+    /// Sends the configured reload signal when frame-server supports it,
+    /// otherwise falls back to a restart. Returns how the instance was reloaded.
+    pub async fn reload_instance(&self, username: &str) -> Result<&'static str> {
+        validate_username(username)?;
+        let (supported, signal) = {
+            let config = self.config.read().await;
+            (
+                config.service.instance_reload_supported,
+                config.service.reload_signal()?,
+            )
+        };
+
+        if !supported {
+            self.restart_instance(username).await?;
+            return Ok("restarted");
+        }
+
+        self.instance_manager.signal(username, signal).await?;
+        tracing::info!("Sent {} to instance for {} to reload it", signal, username);
+
+        Ok("signaled")
+    }
+
+    /// Set an instance's frame-server log level, restarting it if running
+    ///
+    /// `None` reverts to the server's default level.
+    pub async fn set_instance_log_level(&self, username: &str, level: Option<&str>) -> Result<()> {
+        validate_username(username)?;
+        let level = match level {
+            Some(name) => Some(
+                LogLevel::parse(name)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid log level: {}", name))?
+                    .as_str()
+                    .to_string(),
+            ),
+            None => None,
+        };
+
+        self.instance_manager
+            .set_log_level(username, level.clone())
+            .await?;
+        tracing::info!(
+            "Log level for {} set to {}",
+            username,
+            level.as_deref().unwrap_or("default")
+        );
+
+        let instance = self.instance_manager.status(username).await?;
+        if instance.status == crate::instance::InstanceStatus::Running {
+            self.restart_instance(username).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Restart all running instances
     pub async fn restart_all(&self) -> Result<()> {
-        let instances = self.instance_manager.list().await;
+        let running = self
+            .instance_manager
+            .list()
+            .await
+            .into_iter()
+            .filter(|i| i.status == crate::instance::InstanceStatus::Running)
+            .map(|i| i.username)
+            .collect();
+        self.run_action(running, BatchAction::Restart, "restart of all instances")
+            .await;
 
-        for instance in instances {
-            if instance.status == crate::instance::InstanceStatus::Running {
-                let _ = self.restart_instance(&instance.username).await;
+        Ok(())
+    }
+
+    /// Apply an action to the given instances, or to every instance if `None`
+    ///
+    /// Unknown or invalid usernames fail on their own without affecting the
+    /// rest of the batch.
+    pub async fn run_batch(
+        &self,
+        action: BatchAction,
+        usernames: Option<Vec<String>>,
+    ) -> BTreeMap<String, GroupMemberResult> {
+        let usernames: Vec<String> = match usernames {
+            Some(usernames) => {
+                // A user named twice would race with itself
+                let unique: BTreeSet<String> = usernames.into_iter().collect();
+                unique.into_iter().collect()
             }
+            None => self
+                .instance_manager
+                .list()
+                .await
+                .into_iter()
+                .map(|i| i.username)
+                .collect(),
+        };
+        tracing::info!("Running {:?} on {} instances", action, usernames.len());
+
+        self.run_action(usernames, action, "batch").await
+    }
+
+    /// Start every instance in a group
+    pub async fn start_group(&self, name: &str) -> Result<BTreeMap<String, GroupMemberResult>> {
+        self.run_group_action(name, BatchAction::Start).await
+    }
+
+    /// Stop every instance in a group
+    pub async fn stop_group(&self, name: &str) -> Result<BTreeMap<String, GroupMemberResult>> {
+        self.run_group_action(name, BatchAction::Stop).await
+    }
+
+    /// Restart every instance in a group
+    pub async fn restart_group(&self, name: &str) -> Result<BTreeMap<String, GroupMemberResult>> {
+        self.run_group_action(name, BatchAction::Restart).await
+    }
+
+    /// Apply an action to all members of a group
+    async fn run_group_action(
+        &self,
+        name: &str,
+        action: BatchAction,
+    ) -> Result<BTreeMap<String, GroupMemberResult>> {
+        let groups_path = PathBuf::from(&self.config.read().await.paths.groups_path);
+        let groups = GroupsConfig::load(&groups_path)?;
+        let members = groups
+            .members(name)
+            .ok_or_else(|| GroupError::Unknown(name.to_string()))?
+            .to_vec();
+
+        tracing::info!(
+            "Running {:?} on group {} ({} members)",
+            action,
+            name,
+            members.len()
+        );
+
+        Ok(self
+            .run_action(members, action, &format!("group {}", name))
+            .await)
+    }
+
+    /// Apply an action to instances through the operation queue
+    ///
+    /// Every instance is attempted; failures are reported per instance rather
+    /// than aborting the rest. `scope` names the operation in log messages.
+    async fn run_action(
+        &self,
+        usernames: Vec<String>,
+        action: BatchAction,
+        scope: &str,
+    ) -> BTreeMap<String, GroupMemberResult> {
+        let kind = match action {
+            BatchAction::Start => OperationKind::Start,
+            BatchAction::Stop => OperationKind::Stop,
+            BatchAction::Restart => OperationKind::Restart,
+        };
+
+        // Queued like single-instance operations, so neither can race the
+        // other on the same user; the workers bound the concurrency
+        let mut handles = Vec::new();
+        for username in usernames {
+            let handle = match validate_username(&username) {
+                Ok(()) => self.operations.enqueue(kind, &username).await,
+                Err(e) => Err(e),
+            };
+            handles.push((username, handle));
         }
 
-        Ok(())
+        future::join_all(handles.into_iter().map(|(username, handle)| async move {
+            let result = match handle {
+                Ok(handle) => handle.wait().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                tracing::warn!("{:?} failed for {} in {}: {}", action, username, scope, e);
+            }
+            (
+                username,
+                GroupMemberResult {
+                    success: result.is_ok(),
+                    error: result.err().map(|e| e.to_string()),
+                },
+            )
+        }))
+        .await
+        .into_iter()
+        .collect()
+    }
+
+    /// Deploy a new version of an app using a blue/green swap
+    ///
+    /// A candidate frame-server serving the new version is started on a
+    /// temporary port and health-checked. Once healthy the proxy is switched to
+    /// it and the old process is retired. On failure the old version keeps
+    /// serving and the candidate is torn down.
+    pub async fn deploy_app(
+        &self,
+        username: &str,
+        app_name: &str,
+        artifact: &std::path::Path,
+    ) -> Result<DeployResult> {
+        validate_username(username).map_err(|e| DeployError::Invalid(e.to_string()))?;
+        deploy::validate_app_name(app_name)?;
+        let instance = self
+            .instance_manager
+            .status(username)
+            .await
+            .map_err(|e| DeployError::NotFound(e.to_string()))?;
+        let instance_dir = self.instance_manager.instance_dir(username);
+
+        // Only accept artifacts staged inside the user's own instance
+        // directory; the release is built from the path relative to it
+        let artifact = tokio::fs::canonicalize(artifact).await.map_err(|e| {
+            DeployError::NotFound(format!("Artifact {} not found: {}", artifact.display(), e))
+        })?;
+        let artifact = artifact
+            .strip_prefix(tokio::fs::canonicalize(&instance_dir).await?)
+            .ok()
+            .filter(|rel| !rel.as_os_str().is_empty())
+            .ok_or_else(|| {
+                DeployError::Invalid(
+                    "Artifact must be located inside the user's instance directory".to_string(),
+                )
+            })?
+            .to_path_buf();
+
+        if !self
+            .deploys_in_progress
+            .lock()
+            .await
+            .insert(username.to_string())
+        {
+            anyhow::bail!("A deployment is already in progress for user: {}", username);
+        }
+
+        let result = self
+            .run_deploy(
+                username,
+                app_name,
+                &artifact,
+                &instance_dir,
+                instance.status,
+            )
+            .await;
+
+        self.deploys_in_progress.lock().await.remove(username);
+
+        let result = result?;
+        self.events
+            .emit(Event::AppDeployed {
+                username: username.to_string(),
+                app_name: app_name.to_string(),
+            })
+            .await;
+        self.update_metrics().await;
+
+        Ok(result)
+    }
+
+    /// Perform the steps of a deployment
+    async fn run_deploy(
+        &self,
+        username: &str,
+        app_name: &str,
+        artifact: &std::path::Path,
+        instance_dir: &std::path::Path,
+        status: crate::instance::InstanceStatus,
+    ) -> Result<DeployResult> {
+        let (release_id, release_dir) =
+            deploy::prepare_release(instance_dir, app_name, artifact).await?;
+        crate::instance::chown_to_user(&release_dir, username).await;
+
+        // Nothing is serving, so there's nothing to swap: install directly
+        if status != crate::instance::InstanceStatus::Running {
+            let port = self.port_allocator.get_port(username).await.unwrap_or(0);
+            deploy::install_app(instance_dir, app_name, &release_dir).await?;
+            crate::instance::chown_to_user(&instance_dir.join("apps").join(app_name), username)
+                .await;
+            deploy::prune_releases(instance_dir, None).await?;
+
+            return Ok(DeployResult {
+                username: username.to_string(),
+                app_name: app_name.to_string(),
+                release_id,
+                previous_port: port,
+                port,
+            });
+        }
+
+        let blue_port = self
+            .port_allocator
+            .get_port(username)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No port allocated for user: {}", username))?;
+        let candidate_key = deploy::candidate_key(username);
+        let green_port = self.port_allocator.allocate(&candidate_key).await?;
+
+        match self
+            .start_candidate(username, &release_dir, blue_port, green_port)
+            .await
+        {
+            Ok(green_pid) => {
+                // Traffic now flows to the candidate; make it the instance's process
+                self.port_allocator
+                    .reassign(&candidate_key, username)
+                    .await?;
+                let blue_pid = self
+                    .instance_manager
+                    .promote_candidate(username, green_port, green_pid)
+                    .await?;
+
+                if let Some(pid) = blue_pid {
+                    if let Err(e) = self.instance_manager.stop_process(pid).await {
+                        tracing::warn!(
+                            "Failed to stop previous process {} for {}: {}",
+                            pid,
+                            username,
+                            e
+                        );
+                    }
+                }
+
+                deploy::install_app(instance_dir, app_name, &release_dir).await?;
+                crate::instance::chown_to_user(&instance_dir.join("apps").join(app_name), username)
+                    .await;
+                deploy::prune_releases(instance_dir, Some(&release_id)).await?;
+
+                tracing::info!(
+                    "Deployed {} for {} (release {}), port {} -> {}",
+                    app_name,
+                    username,
+                    release_id,
+                    blue_port,
+                    green_port
+                );
+
+                Ok(DeployResult {
+                    username: username.to_string(),
+                    app_name: app_name.to_string(),
+                    release_id,
+                    previous_port: blue_port,
+                    port: green_port,
+                })
+            }
+            Err(e) => {
+                tracing::error!("Deployment of {} for {} failed: {}", app_name, username, e);
+                let _ = self.port_allocator.release_if_present(&candidate_key).await;
+                let _ = tokio::fs::remove_dir_all(&release_dir).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Start a candidate process, wait for it to be healthy and switch the proxy to it
+    ///
+    /// On failure the candidate process is stopped before returning.
+    async fn start_candidate(
+        &self,
+        username: &str,
+        release_dir: &std::path::Path,
+        blue_port: u16,
+        green_port: u16,
+    ) -> Result<u32> {
+        let health_path = self.instance_manager.status(username).await?.health_path;
+        let green_pid = self
+            .instance_manager
+            .spawn_candidate(username, green_port, release_dir)
+            .await?;
+
+        let result = async {
+            let deadline = tokio::time::Instant::now() + DEPLOY_HEALTH_TIMEOUT;
+            loop {
+                let check = HealthCheck::http(green_port, &health_path).execute().await;
+                if check.passed {
+                    break;
+                }
+                if tokio::time::Instant::now() >= deadline {
+                    anyhow::bail!("New version did not become healthy: {}", check.message);
+                }
+                tokio::time::sleep(AUTO_START_HEALTH_POLL).await;
+            }
+
+            let switch_command = self.config.read().await.proxy.switch_command.clone();
+            deploy::switch_proxy(&switch_command, username, blue_port, green_port).await
+        }
+        .await;
+
+        match result {
+            Ok(()) => Ok(green_pid),
+            Err(e) => {
+                let _ = self.instance_manager.stop_process(green_pid).await;
+                Err(e)
+            }
+        }
     }
 
     /// Get instance status
     pub async fn instance_status(&self, username: &str) -> Result<InstanceStatusResponse> {
+        validate_username(username)?;
         let instance = self.instance_manager.status(username).await?;
 
-        Ok(InstanceStatusResponse {
-            username: instance.username,
-            status: instance.status.to_string(),
-            port: instance.port,
-            memory_usage_mb: instance.memory_usage / 1024 / 1024,
-            cpu_usage: instance.cpu_usage,
-            app_count: instance.app_count,
-        })
+        Ok(InstanceStatusResponse::new(
+            &instance,
+            self.instance_manager.is_flapping(username),
+            self.last_health_check(username).await,
+        ))
     }
 
-    /// List all instances
-    pub async fn list_instances(&self) -> Result<Vec<InstanceStatusResponse>> {
-        let instances = self.instance_manager.list().await;
+    /// When the health monitor last checked a user's instance
+    async fn last_health_check(&self, username: &str) -> Option<DateTime<Utc>> {
+        self.health_monitor
+            .get_status(username)
+            .await
+            .map(|status| status.last_check)
+    }
 
-        Ok(instances
+    /// List instances, filtered, sorted and paged by `query`
+    pub async fn list_instances(&self, query: &InstanceListQuery) -> Result<InstancePage> {
+        let flapping = self.instance_manager.flapping();
+        let last_checks: HashMap<String, DateTime<Utc>> = self
+            .health_monitor
+            .get_all_statuses()
+            .await
             .into_iter()
-            .map(|i| InstanceStatusResponse {
-                username: i.username,
-                status: i.status.to_string(),
-                port: i.port,
-                memory_usage_mb: i.memory_usage / 1024 / 1024,
-                cpu_usage: i.cpu_usage,
-                app_count: i.app_count,
+            .map(|status| (status.username, status.last_check))
+            .collect();
+        let instances = self
+            .instance_manager
+            .summarize(|i| {
+                InstanceStatusResponse::new(
+                    i,
+                    flapping.contains(&i.username),
+                    last_checks.get(&i.username).copied(),
+                )
             })
-            .collect())
+            .await;
+
+        Ok(query.apply(instances))
+    }
+
+    /// Get the effective resource limits for every instance
+    ///
+    /// Each instance's config.json merged with the defaults, with the limits
+    /// of the package its cPanel account is on taking precedence.
+    pub async fn all_effective_limits(&self) -> Result<Vec<InstanceLimitsResponse>> {
+        let users_dir = PathBuf::from(&self.config.read().await.paths.cpanel_users_dir);
+        let mut instances = self.instance_manager.list().await;
+        instances.sort_by(|a, b| a.username.cmp(&b.username));
+
+        let mut packages: HashMap<String, Option<PackageLimits>> = HashMap::new();
+        let mut limits = Vec::with_capacity(instances.len());
+        for instance in instances {
+            let username = instance.username;
+            let mut effective = match self.instance_manager.configured_limits(&username).await {
+                Ok(effective) => effective,
+                Err(e) => {
+                    tracing::warn!("Failed to read the limits of {}: {}", username, e);
+                    instance.limits
+                }
+            };
+
+            let plan = cpanel_plan(&users_dir, &username).unwrap_or_else(|e| {
+                tracing::warn!("Failed to read the cPanel package of {}: {}", username, e);
+                None
+            });
+            if let Some(plan) = plan {
+                let package = match packages.get(&plan) {
+                    Some(package) => package.clone(),
+                    None => {
+                        let package = self.package_limits(&plan);
+                        packages.insert(plan, package.clone());
+                        package
+                    }
+                };
+                if let Some(package) = package {
+                    effective = with_package_limits(&package, effective);
+                }
+            }
+
+            limits.push(InstanceLimitsResponse {
+                username,
+                limits: effective,
+            });
+        }
+
+        Ok(limits)
+    }
+
+    /// Queue a start, stop or restart of a user's instance
+    pub async fn submit_operation(
+        &self,
+        kind: OperationKind,
+        username: &str,
+    ) -> Result<OperationHandle> {
+        validate_username(username)?;
+        self.operations.submit(kind, username).await
+    }
+
+    /// Look up a queued operation
+    pub async fn get_operation(&self, id: &str) -> Result<Operation> {
+        let id = uuid::Uuid::parse_str(id)
+            .map_err(|_| anyhow::anyhow!("Invalid operation id: {}", id))?;
+        self.operations
+            .get(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Operation not found: {}", id))
+    }
+
+    /// List recent queued operations
+    pub async fn list_operations(&self) -> Vec<Operation> {
+        self.operations.list().await
+    }
+
+    /// Move users off registry ports that something else is listening on
+    ///
+    /// Instances re-adopted as running hold their own ports; any other
+    /// process on an allocated port would keep the instance from binding it.
+    /// The proxy is switched to each moved user's new port.
+    async fn reconcile_ports(&self) -> Result<()> {
+        let holders: HashMap<String, u16> = self
+            .instance_manager
+            .list()
+            .await
+            .into_iter()
+            .filter(|i| i.status == crate::instance::InstanceStatus::Running)
+            .map(|i| (i.username, i.port))
+            .collect();
+        let reassigned = self.port_allocator.reconcile(&holders).await?;
+        if reassigned.is_empty() {
+            return Ok(());
+        }
+
+        let switch_command = self.config.read().await.proxy.switch_command.clone();
+        for change in reassigned {
+            if let Err(e) = deploy::switch_proxy(
+                &switch_command,
+                &change.username,
+                change.old_port,
+                change.new_port,
+            )
+            .await
+            {
+                tracing::warn!(
+                    "Failed to switch proxy of user {} to port {}: {}",
+                    change.username,
+                    change.new_port,
+                    e
+                );
+            }
+        }
+        Ok(())
     }
 
     /// Allocate a port for a user
@@ -346,6 +1375,11 @@ impl FrameManager {
         self.port_allocator.release(username).await
     }
 
+    /// Release a user's port if one is allocated
+    pub async fn release_port_if_present(&self, username: &str) -> Result<()> {
+        self.port_allocator.release_if_present(username).await
+    }
+
     /// List port allocations
     pub async fn list_ports(&self) -> Result<serde_json::Value> {
         let allocations = self.port_allocator.list_allocations().await;
@@ -359,8 +1393,10 @@ impl FrameManager {
 
     /// Get logs for a user
     pub async fn get_logs(&self, username: &str, lines: usize) -> Result<Vec<String>> {
-        let log_path = PathBuf::from("/var/frame/instances")
-            .join(username)
+        validate_username(username)?;
+        let log_path = self
+            .instance_manager
+            .instance_dir(username)
             .join("logs")
             .join("frame.log");
 
@@ -368,24 +1404,38 @@ impl FrameManager {
             return Ok(Vec::new());
         }
 
-        let content = tokio::fs::read_to_string(&log_path).await?;
-        let all_lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+        Ok(logs::last_lines(&log_path, lines).await?)
+    }
 
-        // Return last N lines
-        let start = if all_lines.len() > lines {
-            all_lines.len() - lines
-        } else {
-            0
-        };
+    /// Follow an instance's log, starting with its last `backfill` lines
+    pub async fn follow_logs(
+        &self,
+        username: &str,
+        backfill: usize,
+    ) -> Result<mpsc::Receiver<String>> {
+        validate_username(username)?;
+        self.instance_manager.status(username).await?;
+        let max_watchers = self.config.read().await.logging.stream_max_watchers;
+        let tailer = LogTailer::new(
+            self.instance_manager.instances_dir().to_path_buf(),
+            max_watchers,
+        );
+        Ok(tailer.follow(username, backfill))
+    }
 
-        Ok(all_lines[start..].to_vec())
+    /// Stream new log lines from all instances matching the filter
+    pub async fn stream_logs(&self, filter: LogFilter) -> mpsc::Receiver<String> {
+        let max_watchers = self.config.read().await.logging.stream_max_watchers;
+        let tailer = LogTailer::new(
+            self.instance_manager.instances_dir().to_path_buf(),
+            max_watchers,
+        );
+        tailer.follow_all(Arc::clone(&self.instance_manager), filter)
     }
 
     /// Get user's apps
     async fn get_user_apps(&self, username: &str) -> Result<Vec<String>> {
-        let apps_dir = PathBuf::from("/var/frame/instances")
-            .join(username)
-            .join("apps");
+        let apps_dir = self.instance_manager.instance_dir(username).join("apps");
 
         if !apps_dir.exists() {
             return Ok(Vec::new());
@@ -405,53 +1455,168 @@ impl FrameManager {
         Ok(apps)
     }
 
+    /// Replace the API token with a new random one and return it
+    ///
+    /// Only the token line of the config file is rewritten, and the old
+    /// token stops being accepted right away. Only possible once a token is
+    /// configured, so a caller can't lock everyone else out.
+    pub async fn rotate_api_token(&self) -> Result<String> {
+        let mut config = self.config.write().await;
+        if config.security.api_token.is_none() {
+            anyhow::bail!("No API token is configured to rotate");
+        }
+
+        let token = crate::api::auth::generate_token().context("Failed to generate API token")?;
+        Config::store_api_token(&self.config_path, &token)?;
+        config.security.api_token = Some(token.clone());
+        drop(config);
+
+        tracing::info!("API token rotated");
+        Ok(token)
+    }
+
     /// Get settings
     pub async fn get_settings(&self) -> Result<serde_json::Value> {
         let config = self.config.read().await;
         Ok(serde_json::to_value(&*config)?)
     }
 
-    /// Update settings
-    pub async fn update_settings(&self, update: SettingsUpdate) -> Result<()> {
-        let mut config = self.config.write().await;
+    /// Current API token requirements
+    pub async fn api_auth(&self) -> ApiAuth {
+        let config = self.config.read().await;
+        ApiAuth {
+            token: config.security.api_token.clone(),
+            public_reads: config.security.api_public_reads,
+        }
+    }
+
+    /// Every setting's effective value and whether it came from the file
+    pub async fn effective_config(&self) -> Result<EffectiveConfig> {
+        self.config.read().await.effective()
+    }
 
+    /// Get one section of the settings, or `None` for an unknown section
+    pub async fn get_settings_section(&self, section: &str) -> Result<Option<serde_json::Value>> {
+        if !Config::sections().any(|name| name == section) {
+            return Ok(None);
+        }
+        let mut settings = self.get_settings().await?;
+        Ok(settings.get_mut(section).map(serde_json::Value::take))
+    }
+
+    /// Update some keys of one settings section, in memory and on disk
+    ///
+    /// New `[defaults]` limits are applied to the instances that follow
+    /// them. Returns the updated section, and which of the changes wait for
+    /// the service to restart.
+    pub async fn update_settings_section(
+        &self,
+        section: &str,
+        values: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<SettingsSectionUpdate> {
+        let restart_keys: Vec<String> = values
+            .keys()
+            .filter(|key| Config::needs_restart(section, key))
+            .cloned()
+            .collect();
+        let defaults = {
+            let mut config = self.config.write().await;
+            *config = Config::update_section(&self.config_path, section, &values)?;
+            config.defaults.clone()
+        };
+        self.events.emit(Event::ConfigReloaded).await;
+
+        if section == "defaults" {
+            self.apply_default_limits(&defaults).await;
+        }
+
+        if restart_keys.is_empty() {
+            tracing::info!("Settings section [{}] updated", section);
+        } else {
+            tracing::info!(
+                "Settings section [{}] updated; {} take effect when the service restarts",
+                section,
+                restart_keys.join(", ")
+            );
+        }
+
+        let settings = self
+            .get_settings_section(section)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Unknown config section: {}", section))?;
+        Ok(SettingsSectionUpdate {
+            settings,
+            restart_required: !restart_keys.is_empty(),
+            restart_keys,
+        })
+    }
+
+    /// Apply `[defaults]` limits to the instances that follow them
+    async fn apply_default_limits(&self, defaults: &DefaultsConfig) {
+        let results = self
+            .instance_manager
+            .set_default_limits(ResourceLimits::from_defaults(
+                defaults.memory_limit,
+                defaults.cpu_limit,
+                defaults.max_apps,
+                defaults.disk_quota,
+            ))
+            .await;
+        for (username, result) in results {
+            if let Err(e) = result {
+                tracing::warn!("Failed to apply new defaults to {}: {}", username, e);
+            }
+        }
+    }
+
+    /// Update settings
+    ///
+    /// Only the given `[service]` keys are rewritten; every other setting,
+    /// section and comment in the file is kept.
+    pub async fn update_settings(&self, update: SettingsUpdate) -> Result<SettingsSectionUpdate> {
+        let mut values = serde_json::Map::new();
         if let Some(enabled) = update.enabled {
-            config.service.enabled = enabled;
+            values.insert("enabled".to_string(), enabled.into());
         }
         if let Some(auto_start) = update.auto_start {
-            config.service.auto_start = auto_start;
+            values.insert("auto_start".to_string(), auto_start.into());
         }
         if let Some(interval) = update.health_check_interval {
-            config.service.health_check_interval = interval;
+            values.insert("health_check_interval".to_string(), interval.into());
         }
 
-        // Save to file
-        let content = format!(
-            "[service]\nenabled = {}\nauto_start = {}\nhealth_check_interval = {}\n",
-            config.service.enabled,
-            config.service.auto_start,
-            config.service.health_check_interval
-        );
-
-        tokio::fs::write(&self.config_path, content).await?;
+        self.update_settings_section("service", values).await
+    }
 
-        // Emit event
-        drop(config);
-        self.events.emit(Event::ConfigReloaded).await;
+    /// Config file of a hosting package
+    fn package_path(&self, name: &str) -> Result<PathBuf> {
+        PackageConfig::validate_name(name)?;
+        Ok(self.packages_dir.join(format!("{}.conf", name)))
+    }
 
-        Ok(())
+    /// Limits of a hosting package, or `None` if it has no config
+    fn package_limits(&self, name: &str) -> Option<PackageLimits> {
+        let path = self.package_path(name).ok()?;
+        if !path.exists() {
+            return None;
+        }
+        match PackageConfig::load(&path) {
+            Ok(package) => Some(package.limits),
+            Err(e) => {
+                tracing::warn!("Failed to load package {}: {}", name, e);
+                None
+            }
+        }
     }
 
     /// List packages
     pub async fn list_packages(&self) -> Result<Vec<serde_json::Value>> {
-        let packages_dir = PathBuf::from("/etc/frame/packages");
-
-        if !packages_dir.exists() {
+        if !self.packages_dir.exists() {
             return Ok(Vec::new());
         }
 
         let mut packages = Vec::new();
-        let mut entries = tokio::fs::read_dir(&packages_dir).await?;
+        let mut entries = tokio::fs::read_dir(&self.packages_dir).await?;
 
         while let Some(entry) = entries.next_entry().await? {
             let path = entry.path();
@@ -466,8 +1631,11 @@ impl FrameManager {
     }
 
     /// Update package
+    ///
+    /// The new limits are applied to the package's members right away,
+    /// live for running instances.
     pub async fn update_package(&self, name: &str, update: PackageUpdate) -> Result<()> {
-        let package_path = PathBuf::from("/etc/frame/packages").join(format!("{}.conf", name));
+        let package_path = self.package_path(name)?;
 
         let mut content = String::new();
         content.push_str("[limits]\n");
@@ -486,92 +1654,377 @@ impl FrameManager {
         }
 
         tokio::fs::write(&package_path, content).await?;
+        self.apply_package_to_members(name).await?;
 
         Ok(())
     }
 
-    /// Reload configuration
-    pub async fn reload_config(&self) -> Result<()> {
-        let new_config = Config::load(&self.config_path)?;
+    /// Apply a package's current limits to every instance assigned to it
+    ///
+    /// Members are the instances whose cPanel account is on the package,
+    /// as recorded in `[paths] cpanel_users_dir`.
+    /// Running instances are updated live where possible; the rest pick up
+    /// the new limits on their next start.
+    pub async fn apply_package_to_members(
+        &self,
+        name: &str,
+    ) -> Result<BTreeMap<String, PackageMemberResult>> {
+        let package_path = self.package_path(name)?;
+        if !package_path.exists() {
+            anyhow::bail!("Unknown package: {}", name);
+        }
+        let package = PackageConfig::load(&package_path)?;
+
+        // Membership comes from cPanel's account files, which users can't edit
+        let users_dir = PathBuf::from(&self.config.read().await.paths.cpanel_users_dir);
+        let mut members = Vec::new();
+        for instance in self.instance_manager.list().await {
+            match cpanel_plan(&users_dir, &instance.username) {
+                Ok(plan) if plan.as_deref() == Some(name) => members.push(instance),
+                Ok(_) => {}
+                Err(e) => tracing::warn!(
+                    "Failed to read the cPanel package of {}: {}",
+                    instance.username,
+                    e
+                ),
+            }
+        }
+        members.sort_by(|a, b| a.username.cmp(&b.username));
+
+        tracing::info!("Applying package {} to {} members", name, members.len());
+
+        let mut results = BTreeMap::new();
+        for instance in members {
+            let limits = with_package_limits(&package.limits, instance.limits);
+
+            let result = self
+                .instance_manager
+                .set_limits(&instance.username, limits)
+                .await;
+            if let Err(e) = &result {
+                tracing::warn!(
+                    "Failed to apply package {} to {}: {}",
+                    name,
+                    instance.username,
+                    e
+                );
+            }
+            results.insert(
+                instance.username,
+                PackageMemberResult {
+                    success: result.is_ok(),
+                    applied: result.as_ref().ok().copied(),
+                    error: result.err().map(|e| e.to_string()),
+                },
+            );
+        }
+
+        Ok(results)
+    }
+
+    /// Check a configuration file without applying it
+    ///
+    /// Besides parsing and validating the file, checks it against the
+    /// running service: a changed manager port must be free, and the port
+    /// range must have room for the existing instances.
+    pub async fn validate_config_file(&self, path: &Path) -> ConfigValidation {
+        self.check_config_file(path).await.0
+    }
+
+    /// Validate a configuration file, returning it if it loaded
+    async fn check_config_file(&self, path: &Path) -> (ConfigValidation, Option<Config>) {
+        let (mut validation, config) = ConfigValidation::load(path);
+        let Some(config) = config else {
+            return (validation, None);
+        };
+
+        // The current port is held by this manager's own API server
+        let port = config.service.manager_port;
+        if port != self.config.read().await.service.manager_port && is_port_in_use(port) {
+            validation.problem(format!("manager_port {} is already in use", port));
+        }
+
+        let range_size = (u32::from(config.service.port_range_end) + 1)
+            .saturating_sub(u32::from(config.service.port_range_start));
+        let instances = self.instance_manager.total_count().await;
+        if (range_size as usize) < instances {
+            validation.problem(format!(
+                "port range {}-{} has {} ports, fewer than the {} existing instances",
+                config.service.port_range_start,
+                config.service.port_range_end,
+                range_size,
+                instances
+            ));
+        }
+
+        (validation, Some(config))
+    }
+
+    /// Configuration file read by `reload_config`
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    /// Reload configuration, returning the configuration now in effect
+    ///
+    /// The file is read once and swapped in whole, and only if it passes
+    /// `validate_config_file`; otherwise the current configuration is kept.
+    pub async fn reload_config(&self) -> Result<Config> {
+        let (validation, new_config) = self.check_config_file(&self.config_path).await;
+        let Some(new_config) = new_config.filter(|_| validation.valid) else {
+            anyhow::bail!(
+                "Configuration not reloaded: {}",
+                validation.problems.join("; ")
+            );
+        };
 
         let mut config = self.config.write().await;
-        *config = new_config;
+        *config = new_config.clone();
         drop(config);
 
+        self.apply_default_limits(&new_config.defaults).await;
         self.events.emit(Event::ConfigReloaded).await;
 
         tracing::info!("Configuration reloaded");
 
-        Ok(())
+        Ok(new_config)
     }
 
     /// Get statistics
-    pub async fn stats(&self, stat_type: Option<&str>) -> Result<serde_json::Value> {
+    pub async fn stats(&self, stat_type: Option<&str>) -> Result<Stats> {
         match stat_type {
             Some("memory") => {
-                let instances = self.instance_manager.list().await;
-                let memory_stats: Vec<serde_json::Value> = instances
-                    .iter()
-                    .map(|i| {
-                        serde_json::json!({
-                            "username": i.username,
-                            "memory_mb": i.memory_usage / 1024 / 1024,
-                            "limit_mb": i.limits.memory_mb
-                        })
+                let memory = self
+                    .instance_manager
+                    .summarize(|i| InstanceMemory {
+                        username: i.username.clone(),
+                        memory_mb: i.memory_usage / 1024 / 1024,
+                        limit_mb: i.limits.memory_mb,
                     })
-                    .collect();
-                Ok(serde_json::json!({"memory": memory_stats}))
+                    .await;
+                Ok(Stats::Memory(MemoryStats { memory }))
             }
             Some("cpu") => {
-                let instances = self.instance_manager.list().await;
-                let cpu_stats: Vec<serde_json::Value> = instances
-                    .iter()
-                    .map(|i| {
-                        serde_json::json!({
-                            "username": i.username,
-                            "cpu_percent": i.cpu_usage,
-                            "limit_percent": i.limits.cpu_percent
-                        })
+                let cpu = self
+                    .instance_manager
+                    .summarize(|i| InstanceCpu {
+                        username: i.username.clone(),
+                        cpu_percent: i.cpu_usage,
+                        limit_percent: i.limits.cpu_percent,
                     })
-                    .collect();
-                Ok(serde_json::json!({"cpu": cpu_stats}))
+                    .await;
+                Ok(Stats::Cpu(CpuStats { cpu }))
             }
             Some("instances") | None => {
                 let running = self.instance_manager.running_count().await;
                 let total = self.instance_manager.total_count().await;
-                let port_stats = self.port_allocator.stats().await;
 
-                Ok(serde_json::json!({
-                    "instances": {
-                        "running": running,
-                        "stopped": total - running,
-                        "total": total
+                Ok(Stats::Instances(InstancesStats {
+                    instances: InstanceCounts {
+                        running,
+                        stopped: total - running,
+                        total,
                     },
-                    "ports": port_stats
+                    ports: self.port_allocator.stats().await,
                 }))
             }
+            Some("disk") => Ok(Stats::Disk(self.disk_stats().await)),
             _ => anyhow::bail!("Unknown stat type: {}", stat_type.unwrap_or("none")),
         }
     }
 
+    /// Disk usage of every instance against its current quota
+    ///
+    /// The measured usage is reused for one health check interval, unless the
+    /// config file changed or an instance was added since. Quotas are always
+    /// read fresh.
+    async fn disk_stats(&self) -> DiskStats {
+        let max_age = Duration::from_secs(self.config.read().await.service.health_check_interval);
+        let config_modified = tokio::fs::metadata(&self.config_path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let quotas = self
+            .instance_manager
+            .summarize(|i| (i.username.clone(), i.limits.disk_quota_mb))
+            .await;
+
+        // Held while measuring so concurrent requests share one walk
+        let mut cached = self.disk_usage.lock().await;
+        let fresh = cached.as_ref().is_some_and(|snapshot| {
+            snapshot.taken.elapsed() < max_age
+                && snapshot.config_modified == config_modified
+                && quotas
+                    .iter()
+                    .all(|(username, _)| snapshot.used_mb.contains_key(username))
+        });
+        if !fresh {
+            let mut used_mb = HashMap::with_capacity(quotas.len());
+            for (username, _) in &quotas {
+                let used = self.instance_manager.disk_usage(username).await / 1024 / 1024;
+                used_mb.insert(username.clone(), used);
+            }
+            *cached = Some(DiskUsageSnapshot {
+                taken: tokio::time::Instant::now(),
+                config_modified,
+                used_mb,
+            });
+        }
+        let usage = &cached.as_ref().expect("measured above").used_mb;
+
+        let mut disk = Vec::with_capacity(quotas.len());
+        for (username, quota_mb) in quotas {
+            let used_mb = usage[&username];
+            disk.push(InstanceDisk {
+                username,
+                used_mb,
+                quota_mb,
+                percent: if quota_mb == 0 {
+                    0.0
+                } else {
+                    used_mb as f64 * 100.0 / quota_mb as f64
+                },
+            });
+        }
+
+        DiskStats { disk }
+    }
+
+    /// Recently emitted events matching a query, oldest first
+    pub fn recent_events(&self, query: &EventQuery) -> Result<Vec<EventEnvelope>> {
+        if let Some(event_type) = &query.event_type {
+            let names = EventEmitter::event_names();
+            if !names.contains(&event_type.as_str()) {
+                anyhow::bail!(
+                    "Unknown event type '{}' (expected one of: {})",
+                    event_type,
+                    names.join(", ")
+                );
+            }
+        }
+        Ok(self.events.history().query(query))
+    }
+
+    /// Subscribe to events as they're emitted
+    pub fn subscribe_events(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.events.subscribe()
+    }
+
+    /// List hook scripts per event type with their last execution result
+    pub fn list_hooks(&self) -> Vec<HookInfo> {
+        self.events.hooks().list()
+    }
+
+    /// Run the hooks for a synthetic event of the given type
+    pub async fn test_hook(
+        &self,
+        event_type: &str,
+        fields: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Vec<HookTestResult>> {
+        let event = Event::sample(event_type, fields)?;
+        self.events.hooks().test_fire(&event).await
+    }
+
+    /// Whether `presented` is the token an instance reports its metrics with
+    pub async fn metrics_token_matches(&self, username: &str, presented: &str) -> bool {
+        self.instance_manager
+            .metrics_token_matches(username, presented)
+            .await
+    }
+
+    /// Record request statistics reported by an instance
+    pub async fn record_requests(&self, username: &str, report: &RequestReport) -> Result<()> {
+        report.validate()?;
+        self.instance_manager.status(username).await?;
+        self.metrics.write().await.record_requests(username, report);
+        Ok(())
+    }
+
     /// Get Prometheus metrics
+    ///
+    /// Exports the collector as last refreshed by the background collection
+    /// (`metrics_interval`), so frequent scrapes don't recompute anything.
     pub async fn get_metrics(&self) -> Result<String> {
-        self.update_metrics().await;
-
         let metrics = self.metrics.read().await;
         Ok(metrics.export_prometheus())
     }
 
+    /// Refresh metrics every `interval` while the manager runs
+    async fn collect_metrics(&self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if !*self.running.read().await {
+                break;
+            }
+            self.update_metrics().await;
+        }
+    }
+
+    /// Rotate and clean up instance logs every `interval` while the manager runs
+    ///
+    /// The `[logging]` limits are read on every pass, so a reload applies them.
+    async fn rotate_logs(&self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if !*self.running.read().await {
+                break;
+            }
+
+            let policy = RotationPolicy::from_config(&self.config.read().await.logging);
+            for instance in self.instance_manager.list().await {
+                let username = &instance.username;
+                if self.instance_manager.is_synthetic(username) {
+                    continue;
+                }
+                let logs_dir = self.instance_manager.instance_dir(username).join("logs");
+                match policy.apply(&logs_dir, chrono::Utc::now()).await {
+                    Ok(outcome) => {
+                        if let Some(rotated) = outcome.rotated {
+                            tracing::info!("Rotated log for {} to {}", username, rotated.display());
+                        }
+                        if outcome.removed > 0 {
+                            tracing::debug!(
+                                "Removed {} expired logs for {}",
+                                outcome.removed,
+                                username
+                            );
+                        }
+                    }
+                    Err(e) => tracing::warn!("Failed to rotate logs for {}: {}", username, e),
+                }
+            }
+        }
+    }
+
     /// Update metrics
     async fn update_metrics(&self) {
-        let mut metrics = self.metrics.write().await;
-
-        let instances = self.instance_manager.list().await;
+        // Gather everything first; the collector is only locked to store it
+        let instances = self
+            .instance_manager
+            .summarize(|i| {
+                (
+                    i.username.clone(),
+                    i.status,
+                    i.memory_usage,
+                    i.cpu_usage,
+                    i.app_count,
+                )
+            })
+            .await;
         let running = instances
             .iter()
-            .filter(|i| i.status == crate::instance::InstanceStatus::Running)
+            .filter(|(_, status, ..)| *status == crate::instance::InstanceStatus::Running)
             .count();
         let stopped = instances.len() - running;
+        let port_stats = self.port_allocator.stats().await;
+        let usage = self.metrics_monitor.lock().await.sample();
+        let host = HostUsage::sample();
+
+        let mut metrics = self.metrics.write().await;
 
         metrics.set_gauge(
             "frame_instances_total",
@@ -582,25 +2035,20 @@ impl FrameManager {
         metrics.set_gauge("frame_instances_stopped", stopped as f64, HashMap::new());
 
         // Per-instance metrics
-        for instance in &instances {
+        for (username, _, memory_usage, cpu_usage, app_count) in instances {
             let mut labels = HashMap::new();
-            labels.insert("user".to_string(), instance.username.clone());
+            labels.insert("user".to_string(), username);
 
             metrics.set_gauge(
                 "frame_memory_usage_bytes",
-                instance.memory_usage as f64,
-                labels.clone(),
-            );
-            metrics.set_gauge(
-                "frame_cpu_usage_percent",
-                instance.cpu_usage as f64,
+                memory_usage as f64,
                 labels.clone(),
             );
-            metrics.set_gauge("frame_apps_total", instance.app_count as f64, labels);
+            metrics.set_gauge("frame_cpu_usage_percent", cpu_usage as f64, labels.clone());
+            metrics.set_gauge("frame_apps_total", app_count as f64, labels);
         }
 
         // Port metrics
-        let port_stats = self.port_allocator.stats().await;
         metrics.set_gauge(
             "frame_ports_allocated",
             port_stats.allocated as f64,
@@ -611,5 +2059,387 @@ impl FrameManager {
             port_stats.available as f64,
             HashMap::new(),
         );
+        metrics.set_gauge(
+            "frame_ports_released",
+            port_stats.released_pool as f64,
+            HashMap::new(),
+        );
+        metrics.set_gauge(
+            "frame_ports_largest_free_block",
+            port_stats.largest_free_block as f64,
+            HashMap::new(),
+        );
+        metrics.set_gauge(
+            "frame_ports_free_blocks",
+            port_stats.free_blocks as f64,
+            HashMap::new(),
+        );
+        metrics.set_gauge(
+            "frame_ports_fragmentation_ratio",
+            port_stats.fragmentation,
+            HashMap::new(),
+        );
+
+        // Manager process metrics
+        metrics.set_gauge(
+            "frame_manager_memory_bytes",
+            usage.memory_bytes as f64,
+            HashMap::new(),
+        );
+        metrics.set_gauge(
+            "frame_manager_cpu_percent",
+            usage.cpu_percent as f64,
+            HashMap::new(),
+        );
+        metrics.set_gauge(
+            "frame_manager_open_fds",
+            usage.open_fds as f64,
+            HashMap::new(),
+        );
+        metrics.set_gauge(
+            "frame_manager_tasks",
+            usage.tokio_tasks as f64,
+            HashMap::new(),
+        );
+        metrics.set_gauge(
+            "frame_manager_uptime_seconds",
+            self.started.elapsed().as_secs_f64(),
+            HashMap::new(),
+        );
+        metrics.record_host(&host);
+        metrics.set_gauge(
+            "frame_operations_queued",
+            self.operations.queued() as f64,
+            HashMap::new(),
+        );
+    }
+}
+
+/// Start instances in waves of `batch_size`, pausing `delay` between waves
+///
+/// Each wave first waits for the load average when `max_load` is set.
+/// Results come back in the order of `usernames`.
+async fn start_in_waves<'a, F, Fut>(
+    usernames: &'a [String],
+    batch_size: usize,
+    delay: Duration,
+    max_load: f64,
+    start: F,
+) -> Vec<Result<()>>
+where
+    F: Fn(&'a str) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let mut results = Vec::with_capacity(usernames.len());
+    for (wave, batch) in usernames.chunks(batch_size.max(1)).enumerate() {
+        if wave > 0 && !delay.is_zero() {
+            tracing::info!(
+                "Auto-start wave {} done, next wave in {}s",
+                wave,
+                delay.as_secs()
+            );
+            tokio::time::sleep(delay).await;
+        }
+        if max_load > 0.0 {
+            wait_for_load(max_load).await;
+        }
+
+        results
+            .extend(futures::future::join_all(batch.iter().map(|username| start(username))).await);
+    }
+    results
+}
+
+/// Wait until the 1-minute load average is at most `max_load`
+///
+/// Gives up after `AUTO_START_MAX_LOAD_WAIT` so a persistently loaded box
+/// still gets its instances started eventually.
+async fn wait_for_load(max_load: f64) {
+    let deadline = tokio::time::Instant::now() + AUTO_START_MAX_LOAD_WAIT;
+    let mut paused = false;
+
+    while let Some(load) = load_average_1m() {
+        if load <= max_load {
+            if paused {
+                tracing::info!("Load average down to {:.2}, resuming auto-start", load);
+            }
+            return;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            tracing::warn!(
+                "Load average still {:.2} after {}s, resuming auto-start anyway",
+                load,
+                AUTO_START_MAX_LOAD_WAIT.as_secs()
+            );
+            return;
+        }
+        if !paused {
+            tracing::info!(
+                "Load average {:.2} is above {:.2}, pausing auto-start",
+                load,
+                max_load
+            );
+            paused = true;
+        }
+        tokio::time::sleep(AUTO_START_LOAD_POLL).await;
+    }
+}
+
+/// `limits` with the ones a hosting package sets replaced by the package's
+fn with_package_limits(package: &PackageLimits, limits: ResourceLimits) -> ResourceLimits {
+    ResourceLimits {
+        memory_mb: package.memory_limit,
+        cpu_percent: package.cpu_limit,
+        max_apps: package.max_apps,
+        disk_quota_mb: package.disk_quota,
+        ..limits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::time::Instant;
+
+    fn test_config(dir: &Path) -> Config {
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let mut config = Config::default();
+        config.paths.instances_dir = path("instances");
+        config.paths.registry_path = path("ports.json");
+        config.paths.hooks_dir = path("hooks");
+        config.paths.packages_dir = path("packages");
+        config.paths.groups_path = path("groups.conf");
+        config.paths.cpanel_users_dir = path("cpanel-users");
+        config.paths.state_dir = path("state");
+        config
+    }
+
+    #[tokio::test]
+    async fn test_auto_start_waves_are_paced() {
+        let usernames: Vec<String> = ["alice", "bob", "carol"].map(String::from).into();
+        let delay = Duration::from_millis(100);
+
+        for batch_size in [1, 2] {
+            let started = Mutex::new(Vec::new());
+            let begin = Instant::now();
+            let results = start_in_waves(&usernames, batch_size, delay, 0.0, |username| {
+                started
+                    .lock()
+                    .unwrap()
+                    .push((username.to_string(), begin.elapsed()));
+                async { Ok(()) }
+            })
+            .await;
+            assert_eq!(results.len(), 3);
+
+            // Every wave after the first waits out the delay, single-instance
+            // waves included
+            let started = started.into_inner().unwrap();
+            let waves: Vec<Duration> = started
+                .iter()
+                .step_by(batch_size)
+                .map(|(_, at)| *at)
+                .collect();
+            for pair in waves.windows(2) {
+                assert!(pair[1] - pair[0] >= delay, "{:?}", started);
+            }
+            if batch_size == 2 {
+                assert!(started[1].1 < delay);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_switches_proxy() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stands in for the proxy switch command: records its arguments
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("switch-port");
+        let switched = dir.path().join("switched");
+        std::fs::write(
+            &script,
+            format!("#!/bin/sh\necho \"$@\" >> {}\n", switched.display()),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = test_config(dir.path());
+        config.service.port_range_start = 30311;
+        config.service.port_range_end = 30320;
+        config.proxy.switch_command = script.to_string_lossy().into_owned();
+        {
+            let mut registry =
+                crate::port::PortRegistry::load(&dir.path().join("ports.json")).unwrap();
+            registry.allocate("alice", 30311).unwrap();
+            registry.save().unwrap();
+        }
+        // Another service took alice's port
+        let _other_service = std::net::TcpListener::bind(("127.0.0.1", 30311)).unwrap();
+
+        let manager = FrameManager::new(config).await.unwrap();
+        manager.reconcile_ports().await.unwrap();
+
+        assert_eq!(manager.port_allocator.get_port("alice").await, Some(30312));
+        assert_eq!(
+            std::fs::read_to_string(&switched).unwrap(),
+            "alice 30311 30312\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_last_health_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FrameManager::new(test_config(dir.path())).await.unwrap();
+        manager.create_instance("alice", None, false).await.unwrap();
+        let status = manager.instance_status("alice").await.unwrap();
+        assert_eq!(status.last_health_check, None);
+
+        let checked = manager.health_monitor.check_now("alice").await.unwrap();
+        let status = manager.instance_status("alice").await.unwrap();
+        assert_eq!(status.last_health_check, Some(checked.last_check));
+        let page = manager
+            .list_instances(&InstanceListQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            page.instances[0].last_health_check,
+            Some(checked.last_check)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_actions_read_configured_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FrameManager::new(test_config(dir.path())).await.unwrap();
+        manager.start_operation_workers().await;
+        manager.create_instance("alice", None, false).await.unwrap();
+        manager.create_instance("bob", None, false).await.unwrap();
+        std::fs::write(dir.path().join("groups.conf"), "[web]\nmembers = alice\n").unwrap();
+
+        let results = manager.stop_group("web").await.unwrap();
+        assert_eq!(results.keys().collect::<Vec<_>>(), ["alice"]);
+        assert!(manager.stop_group("mail").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_package_members_come_from_cpanel_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FrameManager::new(test_config(dir.path())).await.unwrap();
+        for username in ["alice", "bob"] {
+            manager
+                .create_instance(username, None, false)
+                .await
+                .unwrap();
+        }
+        std::fs::create_dir_all(dir.path().join("packages")).unwrap();
+        std::fs::write(
+            dir.path().join("packages/gold.conf"),
+            "[limits]\nmemory_limit = 1024\n",
+        )
+        .unwrap();
+        let users_dir = dir.path().join("cpanel-users");
+        std::fs::create_dir_all(&users_dir).unwrap();
+        std::fs::write(users_dir.join("alice"), "DNS=alice.test\nPLAN=gold\n").unwrap();
+        std::fs::write(users_dir.join("bob"), "DNS=bob.test\nPLAN=basic\n").unwrap();
+        // Claiming the package in the user-owned config changes nothing
+        std::fs::write(
+            dir.path().join("instances/bob/config.json"),
+            r#"{"auto_start": true, "memory_limit": 512, "max_apps": 5, "env_vars": {}, "package": "gold"}"#,
+        )
+        .unwrap();
+
+        let results = manager.apply_package_to_members("gold").await.unwrap();
+        assert_eq!(results.keys().collect::<Vec<_>>(), ["alice"]);
+        let alice = manager.instance_manager.status("alice").await.unwrap();
+        assert_eq!(alice.limits.memory_mb, 1024);
+        let bob = manager.instance_manager.status("bob").await.unwrap();
+        assert_eq!(bob.limits.memory_mb, 512);
+    }
+
+    #[tokio::test]
+    async fn test_effective_limits_merge_package_and_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.defaults.cpu_limit = 30;
+        config.defaults.disk_quota = 2048;
+        let manager = FrameManager::new(config).await.unwrap();
+        for username in ["alice", "bob"] {
+            manager
+                .create_instance(username, None, false)
+                .await
+                .unwrap();
+        }
+        std::fs::create_dir_all(dir.path().join("packages")).unwrap();
+        std::fs::write(
+            dir.path().join("packages/gold.conf"),
+            "[limits]\nmemory_limit = 1024\ncpu_limit = 50\ndisk_quota = 4096\n",
+        )
+        .unwrap();
+        let users_dir = dir.path().join("cpanel-users");
+        std::fs::create_dir_all(&users_dir).unwrap();
+        std::fs::write(users_dir.join("alice"), "PLAN=gold\n").unwrap();
+        // bob's config sets a memory limit and leaves the rest to the defaults
+        std::fs::write(
+            dir.path().join("instances/bob/config.json"),
+            r#"{"auto_start": true, "memory_limit": 768, "max_apps": 3, "env_vars": {}}"#,
+        )
+        .unwrap();
+
+        let limits = manager.all_effective_limits().await.unwrap();
+        let usernames: Vec<&str> = limits.iter().map(|l| l.username.as_str()).collect();
+        assert_eq!(usernames, ["alice", "bob"]);
+        let (alice, bob) = (&limits[0].limits, &limits[1].limits);
+        assert_eq!(
+            (alice.memory_mb, alice.cpu_percent, alice.disk_quota_mb),
+            (1024, 50, 4096)
+        );
+        assert_eq!((bob.memory_mb, bob.max_apps), (768, 3));
+        assert_eq!((bob.cpu_percent, bob.disk_quota_mb), (30, 2048));
+    }
+
+    #[tokio::test]
+    async fn test_reload_instance_sends_the_reload_signal() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stands in for frame-server: notes each reload signal it gets
+        let dir = tempfile::tempdir().unwrap();
+        let server = dir.path().join("frame-server");
+        let reloads = dir.path().join("reloads");
+        std::fs::write(
+            &server,
+            format!(
+                "#!/bin/sh\ntrap 'echo reload >> {}' USR1\nwhile :; do sleep 0.05; done\n",
+                reloads.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut config = test_config(dir.path());
+        config.paths.frame_server_path = server.to_string_lossy().into_owned();
+        config.service.instance_reload_supported = true;
+        config.service.instance_reload_signal = "USR1".to_string();
+        let manager = FrameManager::running_as_manager(config).await.unwrap();
+        manager.create_instance("alice", None, true).await.unwrap();
+        let pid = manager.instance_manager.status("alice").await.unwrap().pid;
+
+        assert_eq!(manager.reload_instance("alice").await.unwrap(), "signaled");
+        for _ in 0..50 {
+            if reloads.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(std::fs::read_to_string(&reloads).unwrap(), "reload\n");
+
+        // A signal that would end the instance is refused before it's sent
+        manager.config.write().await.service.instance_reload_signal = "KILL".to_string();
+        assert!(manager.reload_instance("alice").await.is_err());
+        let alice = manager.instance_manager.status("alice").await.unwrap();
+        assert_eq!(alice.status, crate::instance::InstanceStatus::Running);
+        assert_eq!(alice.pid, pid);
+
+        manager.stop_instance("alice").await.unwrap();
     }
 }
@@ -0,0 +1,81 @@
+//! Host Metrics
+//!
+//! Samples host-wide memory and load from /proc, so capacity can be judged
+//! alongside the instance metrics. Other platforms report nothing.
+
+#[cfg(target_os = "linux")]
+use super::load_average_1m;
+
+/// Host-wide resource figures; `None` where they couldn't be read
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostUsage {
+    pub memory_total_bytes: Option<u64>,
+    pub memory_available_bytes: Option<u64>,
+    /// 1-minute load average
+    pub load1: Option<f64>,
+}
+
+impl HostUsage {
+    /// Take a sample
+    #[cfg(target_os = "linux")]
+    pub fn sample() -> Self {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").unwrap_or_default();
+        Self {
+            memory_total_bytes: meminfo_bytes(&meminfo, "MemTotal"),
+            memory_available_bytes: meminfo_bytes(&meminfo, "MemAvailable"),
+            load1: load_average_1m(),
+        }
+    }
+
+    /// Take a sample
+    #[cfg(not(target_os = "linux"))]
+    pub fn sample() -> Self {
+        Self::default()
+    }
+}
+
+/// A `/proc/meminfo` field in bytes (the file reports kB)
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn meminfo_bytes(meminfo: &str, field: &str) -> Option<u64> {
+    meminfo.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.strip_prefix(':')?;
+        let kb: u64 = value.trim().trim_end_matches("kB").trim().parse().ok()?;
+        Some(kb * 1024)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricsCollector;
+
+    #[test]
+    fn test_meminfo_fields() {
+        let meminfo = "MemTotal:       16318480 kB\nMemFree:         1021356 kB\n\
+                       MemAvailable:    9876543 kB\n";
+        assert_eq!(meminfo_bytes(meminfo, "MemTotal"), Some(16318480 * 1024));
+        assert_eq!(meminfo_bytes(meminfo, "MemAvailable"), Some(9876543 * 1024));
+        assert_eq!(meminfo_bytes(meminfo, "Mem"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_host_gauges_exported() {
+        let mut collector = MetricsCollector::default();
+        collector.record_host(&HostUsage::sample());
+        let output = collector.export_prometheus();
+        for gauge in [
+            "frame_host_memory_total_bytes",
+            "frame_host_memory_available_bytes",
+            "frame_host_load1",
+        ] {
+            assert!(
+                output
+                    .lines()
+                    .any(|line| line.starts_with(&format!("{} ", gauge))),
+                "{} missing",
+                gauge
+            );
+        }
+    }
+}
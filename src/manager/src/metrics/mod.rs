@@ -2,12 +2,64 @@
 //!
 //! Collects and exports metrics in Prometheus format.
 
+mod host;
 mod prometheus;
+mod self_usage;
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+pub use host::HostUsage;
 pub use prometheus::PrometheusExporter;
+pub(crate) use self_usage::{clock_ticks, load_average_1m, parse_cpu_ticks};
+pub use self_usage::{ManagerUsage, SelfMonitor};
+
+/// Default histogram bucket upper bounds, in seconds
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Most request durations accepted in one report
+pub const MAX_REPORTED_DURATIONS: usize = 10_000;
+
+/// Request statistics an instance reports about itself
+///
+/// The manager doesn't see app traffic, so instances POST this to
+/// `/frame/instances/:username/metrics` with their `FRAME_METRICS_TOKEN` as
+/// bearer token, with the values since their previous report:
+///
+/// ```json
+/// {"requests": 120, "durations_seconds": [0.012, 0.250, 0.031]}
+/// ```
+///
+/// `durations_seconds` may be a sample of the requests; `requests` defaults
+/// to the number of durations given.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RequestReport {
+    pub requests: Option<u64>,
+    #[serde(default)]
+    pub durations_seconds: Vec<f64>,
+}
+
+impl RequestReport {
+    /// Reject reports that can't be recorded
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.durations_seconds.len() > MAX_REPORTED_DURATIONS {
+            anyhow::bail!(
+                "At most {} durations can be reported at once",
+                MAX_REPORTED_DURATIONS
+            );
+        }
+        if let Some(duration) = self
+            .durations_seconds
+            .iter()
+            .find(|duration| !duration.is_finite() || **duration < 0.0)
+        {
+            anyhow::bail!("Invalid request duration: {}", duration);
+        }
+        Ok(())
+    }
+}
 
 /// Metrics collector
 pub struct MetricsCollector {
@@ -22,6 +74,12 @@ pub struct Metric {
     pub help: String,
     pub metric_type: MetricType,
     pub values: Vec<MetricValue>,
+    /// Bucket upper bounds of a histogram, ascending (`+Inf` is implicit)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buckets: Vec<f64>,
+    /// Observations of a histogram, per label set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub histograms: Vec<HistogramValue>,
 }
 
 /// Metric types
@@ -41,6 +99,16 @@ pub struct MetricValue {
     pub labels: HashMap<String, String>,
 }
 
+/// Observations of a histogram for one label set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistogramValue {
+    /// Observations per bucket (not cumulative), parallel to `Metric::buckets`
+    pub bucket_counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+    pub labels: HashMap<String, String>,
+}
+
 impl MetricsCollector {
     /// Create a new metrics collector
     pub fn new() -> Self {
@@ -50,7 +118,14 @@ impl MetricsCollector {
     }
 
     /// Register a new metric
+    ///
+    /// Histograms get [`DEFAULT_BUCKETS`]; use
+    /// [`register_histogram`](Self::register_histogram) for other bounds.
     pub fn register(&mut self, name: &str, help: &str, metric_type: MetricType) {
+        let buckets = match metric_type {
+            MetricType::Histogram => DEFAULT_BUCKETS.to_vec(),
+            _ => Vec::new(),
+        };
         self.metrics.insert(
             name.to_string(),
             Metric {
@@ -58,19 +133,28 @@ impl MetricsCollector {
                 help: help.to_string(),
                 metric_type,
                 values: Vec::new(),
+                buckets,
+                histograms: Vec::new(),
             },
         );
     }
 
+    /// Register a histogram with the given bucket upper bounds
+    pub fn register_histogram(&mut self, name: &str, help: &str, buckets: &[f64]) {
+        self.register(name, help, MetricType::Histogram);
+        if let Some(metric) = self.metrics.get_mut(name) {
+            let mut buckets = buckets.to_vec();
+            buckets.sort_by(f64::total_cmp);
+            buckets.dedup();
+            metric.buckets = buckets;
+        }
+    }
+
     /// Set a gauge value
     pub fn set_gauge(&mut self, name: &str, value: f64, labels: HashMap<String, String>) {
         if let Some(metric) = self.metrics.get_mut(name) {
             // Find existing value with same labels or add new
-            if let Some(existing) = metric
-                .values
-                .iter_mut()
-                .find(|v| v.labels == labels)
-            {
+            if let Some(existing) = metric.values.iter_mut().find(|v| v.labels == labels) {
                 existing.value = value;
             } else {
                 metric.values.push(MetricValue { value, labels });
@@ -86,11 +170,7 @@ impl MetricsCollector {
     /// Add to a counter
     pub fn add_counter(&mut self, name: &str, value: f64, labels: HashMap<String, String>) {
         if let Some(metric) = self.metrics.get_mut(name) {
-            if let Some(existing) = metric
-                .values
-                .iter_mut()
-                .find(|v| v.labels == labels)
-            {
+            if let Some(existing) = metric.values.iter_mut().find(|v| v.labels == labels) {
                 existing.value += value;
             } else {
                 metric.values.push(MetricValue { value, labels });
@@ -98,6 +178,79 @@ impl MetricsCollector {
         }
     }
 
+    /// Record an observation in a histogram
+    pub fn observe_histogram(&mut self, name: &str, value: f64, labels: HashMap<String, String>) {
+        let Some(metric) = self.metrics.get_mut(name) else {
+            return;
+        };
+        let index = match metric.histograms.iter().position(|h| h.labels == labels) {
+            Some(index) => index,
+            None => {
+                metric.histograms.push(HistogramValue {
+                    bucket_counts: vec![0; metric.buckets.len()],
+                    sum: 0.0,
+                    count: 0,
+                    labels,
+                });
+                metric.histograms.len() - 1
+            }
+        };
+
+        let histogram = &mut metric.histograms[index];
+        if let Some(bucket) = metric.buckets.iter().position(|bound| value <= *bound) {
+            histogram.bucket_counts[bucket] += 1;
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    /// Count an instance restart; `cause` is manual, health or crash
+    pub fn record_restart(&mut self, username: &str, cause: &str) {
+        let mut labels = HashMap::new();
+        labels.insert("user".to_string(), username.to_string());
+        labels.insert("cause".to_string(), cause.to_string());
+        self.inc_counter("frame_instance_restarts_total", labels);
+    }
+
+    /// Count an instance process that exited on its own
+    pub fn record_crash(&mut self, username: &str) {
+        let mut labels = HashMap::new();
+        labels.insert("user".to_string(), username.to_string());
+        self.inc_counter("frame_instance_crashes_total", labels);
+    }
+
+    /// Record requests reported by an instance
+    pub fn record_requests(&mut self, username: &str, report: &RequestReport) {
+        let mut labels = HashMap::new();
+        labels.insert("user".to_string(), username.to_string());
+        let requests = report
+            .requests
+            .unwrap_or(report.durations_seconds.len() as u64);
+        self.add_counter("frame_requests_total", requests as f64, labels.clone());
+        for duration in &report.durations_seconds {
+            self.observe_histogram("frame_request_duration_seconds", *duration, labels.clone());
+        }
+    }
+
+    /// Record host-wide memory and load, skipping figures that weren't read
+    pub fn record_host(&mut self, host: &HostUsage) {
+        for (name, value) in [
+            (
+                "frame_host_memory_total_bytes",
+                host.memory_total_bytes.map(|bytes| bytes as f64),
+            ),
+            (
+                "frame_host_memory_available_bytes",
+                host.memory_available_bytes.map(|bytes| bytes as f64),
+            ),
+            ("frame_host_load1", host.load1),
+        ] {
+            if let Some(value) = value {
+                self.set_gauge(name, value, HashMap::new());
+            }
+        }
+    }
+
     /// Get all metrics
     pub fn get_all(&self) -> &HashMap<String, Metric> {
         &self.metrics
@@ -107,6 +260,7 @@ impl MetricsCollector {
     pub fn clear(&mut self) {
         for metric in self.metrics.values_mut() {
             metric.values.clear();
+            metric.histograms.clear();
         }
     }
 
@@ -148,12 +302,12 @@ impl Default for MetricsCollector {
         );
         collector.register(
             "frame_requests_total",
-            "Total requests per instance",
+            "Requests reported by each instance, by user",
             MetricType::Counter,
         );
         collector.register(
             "frame_request_duration_seconds",
-            "Request duration histogram",
+            "Request durations reported by each instance, by user",
             MetricType::Histogram,
         );
         collector.register(
@@ -171,11 +325,91 @@ impl Default for MetricsCollector {
             "Number of available ports",
             MetricType::Gauge,
         );
+        collector.register(
+            "frame_ports_released",
+            "Released ports waiting in the reuse pool",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_ports_largest_free_block",
+            "Longest run of consecutive free ports",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_ports_free_blocks",
+            "Number of separate runs of free ports",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_ports_fragmentation_ratio",
+            "Share of free ports outside the largest free block",
+            MetricType::Gauge,
+        );
         collector.register(
             "frame_health_check_failures",
             "Number of health check failures",
             MetricType::Counter,
         );
+        collector.register(
+            "frame_instance_restarts_total",
+            "Instance restarts by user and cause (manual, health or crash)",
+            MetricType::Counter,
+        );
+        collector.register(
+            "frame_instance_crashes_total",
+            "Instance processes that exited on their own, by user",
+            MetricType::Counter,
+        );
+        collector.register(
+            "frame_autostart_failures_total",
+            "Instances that failed to become healthy during auto-start",
+            MetricType::Counter,
+        );
+        collector.register(
+            "frame_manager_memory_bytes",
+            "Resident memory of the manager process in bytes",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_manager_cpu_percent",
+            "CPU usage of the manager process as percentage",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_manager_open_fds",
+            "Open file descriptors held by the manager process",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_manager_tasks",
+            "Tasks alive in the manager's async runtime",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_manager_uptime_seconds",
+            "Seconds since the manager started",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_host_memory_total_bytes",
+            "Total memory of the host in bytes",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_host_memory_available_bytes",
+            "Memory available for new processes on the host in bytes",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_host_load1",
+            "1-minute load average of the host",
+            MetricType::Gauge,
+        );
+        collector.register(
+            "frame_operations_queued",
+            "Instance operations waiting for a worker",
+            MetricType::Gauge,
+        );
 
         collector
     }
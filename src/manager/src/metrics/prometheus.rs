@@ -1,4 +1,7 @@
 //! Prometheus Format Exporter
+//!
+//! Output is deterministic: families are sorted by name, series within a
+//! family by their labels, and labels within a series by name.
 
 use std::collections::HashMap;
 
@@ -12,7 +15,10 @@ impl PrometheusExporter {
     pub fn export(metrics: &HashMap<String, Metric>) -> String {
         let mut output = String::new();
 
-        for metric in metrics.values() {
+        let mut families: Vec<&Metric> = metrics.values().collect();
+        families.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for metric in families {
             // Add HELP line
             output.push_str(&format!("# HELP {} {}\n", metric.name, metric.help));
 
@@ -26,22 +32,46 @@ impl PrometheusExporter {
             output.push_str(&format!("# TYPE {} {}\n", metric.name, type_str));
 
             // Add values
-            for value in &metric.values {
-                if value.labels.is_empty() {
-                    output.push_str(&format!("{} {}\n", metric.name, value.value));
-                } else {
-                    let labels: Vec<String> = value
-                        .labels
-                        .iter()
-                        .map(|(k, v)| format!("{}=\"{}\"", k, Self::escape_label_value(v)))
-                        .collect();
+            let mut values: Vec<_> = metric.values.iter().collect();
+            values.sort_by_cached_key(|value| Self::sorted_labels(&value.labels));
+            for value in values {
+                output.push_str(&format!(
+                    "{}{} {}\n",
+                    metric.name,
+                    Self::format_labels(&value.labels, None),
+                    value.value
+                ));
+            }
+
+            // Histograms: cumulative `le` buckets, then sum and count
+            let mut histograms: Vec<_> = metric.histograms.iter().collect();
+            histograms.sort_by_cached_key(|histogram| Self::sorted_labels(&histogram.labels));
+            for histogram in histograms {
+                let mut cumulative = 0;
+                for (bound, count) in metric.buckets.iter().zip(&histogram.bucket_counts) {
+                    cumulative += count;
                     output.push_str(&format!(
-                        "{}{{{}}} {}\n",
+                        "{}_bucket{} {}\n",
                         metric.name,
-                        labels.join(","),
-                        value.value
+                        Self::format_labels(&histogram.labels, Some(&bound.to_string())),
+                        cumulative
                     ));
                 }
+                output.push_str(&format!(
+                    "{}_bucket{} {}\n",
+                    metric.name,
+                    Self::format_labels(&histogram.labels, Some("+Inf")),
+                    histogram.count
+                ));
+                let labels = Self::format_labels(&histogram.labels, None);
+                output.push_str(&format!(
+                    "{}_sum{} {}\n",
+                    metric.name, labels, histogram.sum
+                ));
+                output.push_str(&format!(
+                    "{}_count{} {}\n",
+                    metric.name, labels, histogram.count
+                ));
             }
 
             output.push('\n');
@@ -50,6 +80,32 @@ impl PrometheusExporter {
         output
     }
 
+    /// Render `{k="v",...}`, with an optional `le` label last; empty without labels
+    fn format_labels(labels: &HashMap<String, String>, le: Option<&str>) -> String {
+        let mut pairs: Vec<String> = Self::sorted_labels(labels)
+            .into_iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, Self::escape_label_value(v)))
+            .collect();
+        if let Some(le) = le {
+            pairs.push(format!("le=\"{}\"", le));
+        }
+        if pairs.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", pairs.join(","))
+        }
+    }
+
+    /// Label pairs ordered by name, which also orders series by label set
+    fn sorted_labels(labels: &HashMap<String, String>) -> Vec<(&str, &str)> {
+        let mut pairs: Vec<(&str, &str)> = labels
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        pairs.sort_unstable();
+        pairs
+    }
+
     /// Escape special characters in label values
     fn escape_label_value(s: &str) -> String {
         s.replace('\\', "\\\\")
@@ -61,7 +117,7 @@ impl PrometheusExporter {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::metrics::MetricValue;
+    use crate::metrics::{MetricValue, MetricsCollector};
 
     #[test]
     fn test_prometheus_export() {
@@ -72,6 +128,8 @@ mod tests {
             help: "A test gauge".to_string(),
             metric_type: MetricType::Gauge,
             values: Vec::new(),
+            buckets: Vec::new(),
+            histograms: Vec::new(),
         };
 
         gauge.values.push(MetricValue {
@@ -87,12 +145,83 @@ mod tests {
         });
 
         metrics.insert("test_gauge".to_string(), gauge);
+        metrics.insert(
+            "a_counter".to_string(),
+            Metric {
+                name: "a_counter".to_string(),
+                help: "Sorts first".to_string(),
+                metric_type: MetricType::Counter,
+                values: Vec::new(),
+                buckets: Vec::new(),
+                histograms: Vec::new(),
+            },
+        );
 
         let output = PrometheusExporter::export(&metrics);
 
-        assert!(output.contains("# HELP test_gauge A test gauge"));
-        assert!(output.contains("# TYPE test_gauge gauge"));
-        assert!(output.contains("test_gauge 42"));
-        assert!(output.contains("test_gauge{user=\"test_user\"} 100"));
+        assert_eq!(
+            output,
+            "# HELP a_counter Sorts first\n\
+             # TYPE a_counter counter\n\
+             \n\
+             # HELP test_gauge A test gauge\n\
+             # TYPE test_gauge gauge\n\
+             test_gauge 42\n\
+             test_gauge{user=\"test_user\"} 100\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn test_labeled_series_are_sorted() {
+        let mut collector = MetricsCollector::new();
+        collector.register("test_restarts_total", "Restarts", MetricType::Counter);
+        for (user, cause) in [
+            ("carol", "crash"),
+            ("alice", "manual"),
+            ("bob", "health"),
+            ("alice", "crash"),
+        ] {
+            let labels = HashMap::from([
+                ("user".to_string(), user.to_string()),
+                ("cause".to_string(), cause.to_string()),
+            ]);
+            collector.inc_counter("test_restarts_total", labels);
+        }
+
+        assert_eq!(
+            collector.export_prometheus(),
+            "# HELP test_restarts_total Restarts\n\
+             # TYPE test_restarts_total counter\n\
+             test_restarts_total{cause=\"crash\",user=\"alice\"} 1\n\
+             test_restarts_total{cause=\"crash\",user=\"carol\"} 1\n\
+             test_restarts_total{cause=\"health\",user=\"bob\"} 1\n\
+             test_restarts_total{cause=\"manual\",user=\"alice\"} 1\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn test_histogram_export() {
+        let mut collector = MetricsCollector::new();
+        collector.register_histogram(
+            "test_duration_seconds",
+            "A test histogram",
+            &[0.1, 0.5, 1.0],
+        );
+        for value in [0.25, 0.5, 0.5, 2.0] {
+            collector.observe_histogram("test_duration_seconds", value, HashMap::new());
+        }
+
+        let output = collector.export_prometheus();
+        assert!(output.contains("# TYPE test_duration_seconds histogram\n"));
+        assert!(output.contains(
+            "test_duration_seconds_bucket{le=\"0.1\"} 0\n\
+             test_duration_seconds_bucket{le=\"0.5\"} 3\n\
+             test_duration_seconds_bucket{le=\"1\"} 3\n\
+             test_duration_seconds_bucket{le=\"+Inf\"} 4\n\
+             test_duration_seconds_sum 3.25\n\
+             test_duration_seconds_count 4\n"
+        ));
     }
 }
@@ -0,0 +1,157 @@
+//! Manager Self-Monitoring
+//!
+//! Samples the manager process's own resource usage from /proc.
+
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// Resource usage of the manager process
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ManagerUsage {
+    pub memory_bytes: u64,
+    pub cpu_percent: f32,
+    pub open_fds: u64,
+    /// Tasks alive in the tokio runtime
+    pub tokio_tasks: usize,
+}
+
+/// Samples the manager's own usage, tracking CPU time between samples
+#[derive(Debug, Default)]
+pub struct SelfMonitor {
+    /// CPU ticks and time of the previous sample
+    last_cpu: Option<(u64, Instant)>,
+}
+
+impl SelfMonitor {
+    /// Create a new self monitor
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a usage sample
+    ///
+    /// CPU percent is averaged over the time since the previous sample and is
+    /// 0 on the first one.
+    pub fn sample(&mut self) -> ManagerUsage {
+        let now = Instant::now();
+        let cpu_ticks = read_cpu_ticks();
+
+        let cpu_percent = match (self.last_cpu, cpu_ticks) {
+            (Some((last_ticks, last_time)), Some(ticks)) => {
+                let elapsed = now.duration_since(last_time).as_secs_f32();
+                if elapsed > 0.0 {
+                    let cpu_secs = ticks.saturating_sub(last_ticks) as f32 / clock_ticks() as f32;
+                    cpu_secs / elapsed * 100.0
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        };
+        if let Some(ticks) = cpu_ticks {
+            self.last_cpu = Some((ticks, now));
+        }
+
+        ManagerUsage {
+            memory_bytes: read_rss_bytes().unwrap_or(0),
+            cpu_percent,
+            open_fds: std::fs::read_dir("/proc/self/fd")
+                .map(|entries| entries.count() as u64)
+                .unwrap_or(0),
+            tokio_tasks: tokio::runtime::Handle::try_current()
+                .map(|handle| handle.metrics().num_alive_tasks())
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// Resident set size of the current process
+fn read_rss_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    Some(parse_rss_pages(&statm)? * page_size())
+}
+
+/// Resident pages from a `/proc/<pid>/statm` line
+fn parse_rss_pages(statm: &str) -> Option<u64> {
+    statm.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// User plus system CPU time of the current process in clock ticks
+fn read_cpu_ticks() -> Option<u64> {
+    parse_cpu_ticks(&std::fs::read_to_string("/proc/self/stat").ok()?)
+}
+
+/// User plus system CPU time in clock ticks from a `/proc/<pid>/stat` line
+pub(crate) fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    // The command name may contain spaces; fields are counted after its closing paren
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(utime + stime)
+}
+
+fn page_size() -> u64 {
+    nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
+        .ok()
+        .flatten()
+        .map(|v| v as u64)
+        .unwrap_or(4096)
+}
+
+/// System 1-minute load average
+pub(crate) fn load_average_1m() -> Option<f64> {
+    parse_load_average(&std::fs::read_to_string("/proc/loadavg").ok()?)
+}
+
+/// 1-minute load average from `/proc/loadavg`
+fn parse_load_average(loadavg: &str) -> Option<f64> {
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+/// Clock ticks per second (`_SC_CLK_TCK`)
+pub(crate) fn clock_ticks() -> u64 {
+    nix::unistd::sysconf(nix::unistd::SysconfVar::CLK_TCK)
+        .ok()
+        .flatten()
+        .map(|v| v as u64)
+        .filter(|v| *v > 0)
+        .unwrap_or(100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_ticks() {
+        let stat = "4242 (frame-manager) S 1 4242 4242 0 -1 4194560 1523 0 0 0 \
+                    371 129 0 0 20 0 9 0 12345 123456789 2048 18446744073709551615";
+        assert_eq!(parse_cpu_ticks(stat), Some(500));
+
+        // The command name may contain spaces and parentheses
+        let stat = "7 (tokio (rt) w) R 1 7 7 0 -1 0 0 0 0 0 12 3 0 0 20 0 1 0 1 1 1";
+        assert_eq!(parse_cpu_ticks(stat), Some(15));
+
+        assert_eq!(parse_cpu_ticks("7 (short) R 1 7"), None);
+        assert_eq!(parse_cpu_ticks(""), None);
+    }
+
+    #[test]
+    fn test_parse_rss_pages() {
+        assert_eq!(
+            parse_rss_pages("30845 2304 1536 298 0 3840 0\n"),
+            Some(2304)
+        );
+        assert_eq!(parse_rss_pages("30845"), None);
+        assert_eq!(parse_rss_pages("30845 x 1536"), None);
+    }
+
+    #[test]
+    fn test_parse_load_average() {
+        assert_eq!(
+            parse_load_average("0.52 0.58 0.59 2/1187 31337\n"),
+            Some(0.52)
+        );
+        assert_eq!(parse_load_average(""), None);
+    }
+}
@@ -0,0 +1,407 @@
+//! Instance Operation Queue
+//!
+//! Start/stop/restart requests from the API are queued and executed by a
+//! small worker pool, so a burst of requests (e.g. WHM bulk provisioning)
+//! can't spawn every instance at once. Operations on the same user run one
+//! at a time, in the order they were queued. Each queued operation is
+//! tracked in a registry until it has been finished for a while.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+use uuid::Uuid;
+
+/// Finished operations kept in the registry for status queries
+const MAX_FINISHED_OPERATIONS: usize = 1000;
+
+/// Instance operation that can be queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationKind {
+    Start,
+    Stop,
+    Restart,
+}
+
+/// Progress of a queued operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// A queued operation and its outcome
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Operation {
+    pub id: Uuid,
+    pub kind: OperationKind,
+    pub username: String,
+    pub status: OperationStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub queued_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Handle to a submitted operation
+pub struct OperationHandle {
+    pub id: Uuid,
+    done: oneshot::Receiver<Result<()>>,
+}
+
+impl OperationHandle {
+    /// Wait for the operation to finish
+    pub async fn wait(self) -> Result<()> {
+        match self.done.await {
+            Ok(result) => result,
+            Err(_) => anyhow::bail!("Operation {} was dropped before it finished", self.id),
+        }
+    }
+}
+
+/// Operation waiting in the queue
+struct Job {
+    id: Uuid,
+    kind: OperationKind,
+    username: String,
+    /// Carries the error itself so callers can still downcast it
+    done: oneshot::Sender<Result<()>>,
+}
+
+/// Per user, the id of the last operation taken and a receiver resolving
+/// once it finishes
+type UserTails = HashMap<String, (Uuid, oneshot::Receiver<()>)>;
+
+/// Operations by id, with finished ones evicted oldest first
+#[derive(Default)]
+struct Registry {
+    operations: HashMap<Uuid, Operation>,
+    finished: VecDeque<Uuid>,
+}
+
+/// Bounded operation queue served by a worker pool
+pub struct OperationQueue {
+    sender: mpsc::Sender<Job>,
+    /// Taken by `start_workers`
+    receiver: Mutex<Option<mpsc::Receiver<Job>>>,
+    registry: Arc<RwLock<Registry>>,
+}
+
+impl OperationQueue {
+    /// Create a queue holding at most `capacity` waiting operations
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        Self {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            registry: Arc::new(RwLock::new(Registry::default())),
+        }
+    }
+
+    /// Start `workers` tasks executing queued operations with `execute`
+    ///
+    /// Does nothing if the workers were already started.
+    pub async fn start_workers<F, Fut>(&self, workers: usize, execute: F)
+    where
+        F: Fn(OperationKind, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        let Some(receiver) = self.receiver.lock().await.take() else {
+            return;
+        };
+        let receiver = Arc::new(Mutex::new(receiver));
+        let execute = Arc::new(execute);
+        // Latest operation taken for each user, so two workers never
+        // operate on the same instance at once
+        let users: Arc<Mutex<UserTails>> = Arc::default();
+
+        for _ in 0..workers.max(1) {
+            let receiver = Arc::clone(&receiver);
+            let registry = Arc::clone(&self.registry);
+            let execute = Arc::clone(&execute);
+            let users = Arc::clone(&users);
+
+            tokio::spawn(async move {
+                loop {
+                    // Hold the receiver lock only while waiting for the next job,
+                    // and line up behind the user's previous operation before
+                    // letting it go so operations on one user keep their order
+                    let (job, previous, turn_done) = {
+                        let mut receiver = receiver.lock().await;
+                        let Some(job) = receiver.recv().await else {
+                            break;
+                        };
+                        let (turn_done, turn) = oneshot::channel::<()>();
+                        let previous = users
+                            .lock()
+                            .await
+                            .insert(job.username.clone(), (job.id, turn));
+                        (job, previous, turn_done)
+                    };
+                    if let Some((_, previous)) = previous {
+                        // Resolves once the previous operation drops its sender
+                        let _ = previous.await;
+                    }
+
+                    Self::update(&registry, job.id, |op| {
+                        op.status = OperationStatus::Running;
+                        op.started_at = Some(Utc::now());
+                    })
+                    .await;
+
+                    let result = execute(job.kind, job.username.clone()).await;
+                    if let Err(e) = &result {
+                        tracing::warn!("Queued {:?} for {} failed: {}", job.kind, job.username, e);
+                    }
+
+                    let outcome = result.as_ref().copied().map_err(ToString::to_string);
+                    Self::finish(&registry, job.id, outcome).await;
+                    {
+                        let mut users = users.lock().await;
+                        if users
+                            .get(&job.username)
+                            .is_some_and(|(id, _)| *id == job.id)
+                        {
+                            users.remove(&job.username);
+                        }
+                    }
+                    drop(turn_done);
+                    // The submitter may not be waiting (async requests)
+                    let _ = job.done.send(result);
+                }
+            });
+        }
+
+        tracing::info!("Operation queue started with {} workers", workers.max(1));
+    }
+
+    /// Queue an operation, failing immediately if the queue is full
+    pub async fn submit(&self, kind: OperationKind, username: &str) -> Result<OperationHandle> {
+        let (job, handle) = self.register(kind, username).await;
+        let id = job.id;
+        if let Err(e) = self.sender.try_send(job) {
+            self.registry.write().await.operations.remove(&id);
+            match e {
+                mpsc::error::TrySendError::Full(_) => {
+                    anyhow::bail!("Operation queue is full, try again later")
+                }
+                mpsc::error::TrySendError::Closed(_) => anyhow::bail!("Operation queue is closed"),
+            }
+        }
+
+        Ok(handle)
+    }
+
+    /// Queue an operation, waiting for room if the queue is full
+    pub async fn enqueue(&self, kind: OperationKind, username: &str) -> Result<OperationHandle> {
+        let (job, handle) = self.register(kind, username).await;
+        let id = job.id;
+        if self.sender.send(job).await.is_err() {
+            self.registry.write().await.operations.remove(&id);
+            anyhow::bail!("Operation queue is closed");
+        }
+
+        Ok(handle)
+    }
+
+    /// Track a new operation and build the job for it
+    async fn register(&self, kind: OperationKind, username: &str) -> (Job, OperationHandle) {
+        let id = Uuid::new_v4();
+        let (done, done_rx) = oneshot::channel();
+
+        // Register first so a worker picking the job up always finds it
+        self.registry.write().await.operations.insert(
+            id,
+            Operation {
+                id,
+                kind,
+                username: username.to_string(),
+                status: OperationStatus::Queued,
+                error: None,
+                queued_at: Utc::now(),
+                started_at: None,
+                finished_at: None,
+            },
+        );
+
+        let job = Job {
+            id,
+            kind,
+            username: username.to_string(),
+            done,
+        };
+        (job, OperationHandle { id, done: done_rx })
+    }
+
+    /// Look up an operation by id
+    pub async fn get(&self, id: Uuid) -> Option<Operation> {
+        self.registry.read().await.operations.get(&id).cloned()
+    }
+
+    /// All tracked operations, oldest first
+    pub async fn list(&self) -> Vec<Operation> {
+        let mut operations: Vec<Operation> = self
+            .registry
+            .read()
+            .await
+            .operations
+            .values()
+            .cloned()
+            .collect();
+        operations.sort_by_key(|op| op.queued_at);
+        operations
+    }
+
+    /// Number of operations waiting for a worker
+    pub fn queued(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    async fn update(registry: &RwLock<Registry>, id: Uuid, f: impl FnOnce(&mut Operation)) {
+        if let Some(op) = registry.write().await.operations.get_mut(&id) {
+            f(op);
+        }
+    }
+
+    async fn finish(registry: &RwLock<Registry>, id: Uuid, result: Result<(), String>) {
+        let mut registry = registry.write().await;
+        let Some(op) = registry.operations.get_mut(&id) else {
+            return;
+        };
+        op.finished_at = Some(Utc::now());
+        match result {
+            Ok(()) => op.status = OperationStatus::Succeeded,
+            Err(e) => {
+                op.status = OperationStatus::Failed;
+                op.error = Some(e);
+            }
+        }
+
+        registry.finished.push_back(id);
+        while registry.finished.len() > MAX_FINISHED_OPERATIONS {
+            if let Some(oldest) = registry.finished.pop_front() {
+                registry.operations.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_queue_limits_concurrency() {
+        let queue = OperationQueue::new(16);
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let (a, p) = (Arc::clone(&active), Arc::clone(&peak));
+        queue
+            .start_workers(2, move |_, username| {
+                let (active, peak) = (Arc::clone(&a), Arc::clone(&p));
+                async move {
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    if username == "bad" {
+                        anyhow::bail!("spawn failed");
+                    }
+                    Ok(())
+                }
+            })
+            .await;
+
+        let mut handles = Vec::new();
+        for user in ["a", "b", "c", "d", "bad"] {
+            handles.push(queue.submit(OperationKind::Start, user).await.unwrap());
+        }
+        let ids: Vec<Uuid> = handles.iter().map(|h| h.id).collect();
+        let results: Vec<bool> = futures::future::join_all(handles.into_iter().map(|h| h.wait()))
+            .await
+            .iter()
+            .map(Result::is_ok)
+            .collect();
+
+        assert_eq!(results, [true, true, true, true, false]);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+
+        let failed = queue.get(ids[4]).await.unwrap();
+        assert_eq!(failed.status, OperationStatus::Failed);
+        assert_eq!(failed.error.as_deref(), Some("spawn failed"));
+    }
+
+    #[tokio::test]
+    async fn test_queue_rejects_when_full() {
+        // No workers: nothing drains the queue
+        let queue = OperationQueue::new(1);
+        queue.submit(OperationKind::Stop, "a").await.unwrap();
+        assert!(queue.submit(OperationKind::Stop, "b").await.is_err());
+        assert_eq!(queue.list().await.len(), 1);
+        assert_eq!(queue.queued(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_queue_serializes_user_operations() {
+        let queue = OperationQueue::new(16);
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let l = Arc::clone(&log);
+        queue
+            .start_workers(4, move |kind, username| {
+                let log = Arc::clone(&l);
+                async move {
+                    log.lock()
+                        .unwrap()
+                        .push(format!("{} {:?} begin", username, kind));
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    log.lock()
+                        .unwrap()
+                        .push(format!("{} {:?} end", username, kind));
+                    Ok(())
+                }
+            })
+            .await;
+
+        let mut handles = Vec::new();
+        for kind in [
+            OperationKind::Stop,
+            OperationKind::Start,
+            OperationKind::Restart,
+        ] {
+            handles.push(queue.submit(kind, "alice").await.unwrap());
+        }
+        handles.push(queue.enqueue(OperationKind::Start, "bob").await.unwrap());
+        for handle in handles {
+            handle.wait().await.unwrap();
+        }
+
+        // Other users still run alongside
+        let log = log.lock().unwrap().clone();
+        let alice: Vec<&String> = log.iter().filter(|l| l.starts_with("alice")).collect();
+        assert_eq!(
+            alice,
+            [
+                "alice Stop begin",
+                "alice Stop end",
+                "alice Start begin",
+                "alice Start end",
+                "alice Restart begin",
+                "alice Restart end",
+            ]
+        );
+        let bob_begin = log.iter().position(|l| l == "bob Start begin").unwrap();
+        assert!(bob_begin < log.iter().position(|l| l == "alice Stop end").unwrap());
+    }
+}
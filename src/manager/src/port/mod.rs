@@ -4,14 +4,44 @@
 
 mod registry;
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
-pub use registry::PortRegistry;
+use crate::events::{Event, EventEmitter};
+
+pub use registry::{PortRegistry, RegistryLock};
+
+/// Allocation level (percent of range) at which capacity warnings are logged
+const CAPACITY_WARNING_PERCENT: usize = 90;
+
+/// Attempts to write the registry before falling back to in-memory state
+const SAVE_ATTEMPTS: u32 = 3;
+
+/// Delay before the first save retry, doubled for each further attempt
+const SAVE_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Port allocation failure callers are expected to handle
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PortError {
+    /// Every port in the range is allocated, reserved or in use
+    #[error("No available ports in range {range_start}-{range_end}")]
+    Exhausted { range_start: u16, range_end: u16 },
+}
+
+impl PortError {
+    /// Error code reported in API responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Exhausted { .. } => "ports_exhausted",
+        }
+    }
+}
 
 /// Port allocation manager
 pub struct PortAllocator {
@@ -21,6 +51,15 @@ pub struct PortAllocator {
     range_end: u16,
     /// Registry for persistent storage
     registry: Arc<RwLock<PortRegistry>>,
+    /// Ports that must never be allocated to an instance (e.g. the manager API)
+    reserved: Vec<u16>,
+    /// Hand out released ports again before fresh ones
+    reuse_released: bool,
+    /// Emitter for capacity alerts
+    events: Option<Arc<EventEmitter>>,
+    /// Whether the range was last seen near capacity, so alerts fire once
+    /// per crossing
+    near_capacity: AtomicBool,
 }
 
 /// Port allocation entry
@@ -31,6 +70,14 @@ pub struct PortAllocation {
     pub allocated_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// An allocation moved to a new port by [`PortAllocator::reconcile`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortReassignment {
+    pub username: String,
+    pub old_port: u16,
+    pub new_port: u16,
+}
+
 impl PortAllocator {
     /// Create a new port allocator
     pub fn new(range_start: u16, range_end: u16, registry_path: &Path) -> Result<Self> {
@@ -40,41 +87,345 @@ impl PortAllocator {
             range_start,
             range_end,
             registry: Arc::new(RwLock::new(registry)),
+            reserved: Vec::new(),
+            reuse_released: true,
+            events: None,
+            near_capacity: AtomicBool::new(false),
         })
     }
 
+    /// Emit `ResourceLimitReached` when the range nears or hits exhaustion
+    pub fn with_events(mut self, events: Arc<EventEmitter>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Set ports that must never be allocated to an instance
+    pub fn with_reserved(mut self, reserved: Vec<u16>) -> Self {
+        self.reserved = reserved;
+        self
+    }
+
+    /// Set whether released ports are handed out again
+    ///
+    /// When disabled, released ports stay in the registry for auditing but
+    /// every allocation takes a port that was never used before.
+    pub fn with_released_port_reuse(mut self, reuse: bool) -> Self {
+        self.reuse_released = reuse;
+        self
+    }
+
+    /// Fail if a reserved port is allocated to a user in the registry
+    ///
+    /// This can happen when the port range or manager port changed after the
+    /// allocation was made; the instance would fight the manager for the port.
+    pub async fn check_reserved(&self) -> Result<()> {
+        let registry = self.registry.read().await;
+        for (username, port) in &registry.allocated {
+            if self.reserved.contains(port) {
+                anyhow::bail!(
+                    "Port {} is reserved but allocated to user {} in the port registry; \
+                     release it with `frame-manager port release {}`",
+                    port,
+                    username,
+                    username
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Move allocations whose port is taken by something else to free ports
+    ///
+    /// `holders` maps users to the port their running instance listens on;
+    /// an allocated port that is in use is only left alone if its own user
+    /// holds it. Anything else (another service, another instance, a port
+    /// reassigned by hand) would keep the instance from binding, so the user
+    /// gets a new port and a `PortReassigned` event is emitted.
+    pub async fn reconcile(&self, holders: &HashMap<String, u16>) -> Result<Vec<PortReassignment>> {
+        let mut registry = self.registry.write().await;
+        let lock = begin_update(&mut registry);
+        let mut allocations: Vec<(String, u16)> = registry
+            .allocated
+            .iter()
+            .map(|(username, port)| (username.clone(), *port))
+            .collect();
+        allocations.sort();
+
+        let mut reassigned = Vec::new();
+        for (username, old_port) in allocations {
+            if holders.get(&username) == Some(&old_port) || !is_port_in_use(old_port) {
+                continue;
+            }
+            let new_port = match self.find_available_port(&registry) {
+                Ok(port) => port,
+                Err(e) => {
+                    tracing::error!(
+                        "Port {} of user {} is in use by another process, \
+                         and it can't be moved: {}",
+                        old_port,
+                        username,
+                        e
+                    );
+                    continue;
+                }
+            };
+            registry.release_if_present(&username);
+            registry.allocate(&username, new_port)?;
+            registry.reserve(new_port);
+            tracing::warn!(
+                "Port {} of user {} is in use by another process, reassigned port {}",
+                old_port,
+                username,
+                new_port
+            );
+            reassigned.push(PortReassignment {
+                username,
+                old_port,
+                new_port,
+            });
+        }
+        let saved = reassigned.is_empty() || persist(&mut registry, lock.as_ref());
+        drop(lock);
+        drop(registry);
+        if !saved {
+            self.retry_save().await;
+        }
+
+        if let Some(events) = &self.events {
+            for change in &reassigned {
+                events
+                    .emit(Event::PortReassigned {
+                        username: change.username.clone(),
+                        old_port: change.old_port,
+                        new_port: change.new_port,
+                        reason: "port in use by another process".to_string(),
+                    })
+                    .await;
+            }
+        }
+
+        Ok(reassigned)
+    }
+
+    /// Retry writing a registry whose save failed, with backoff
+    ///
+    /// The registry is only locked for each attempt, never across the waits
+    /// between them, so allocations and lookups go ahead in the meantime.
+    /// Stops early once another update has saved it.
+    async fn retry_save(&self) {
+        let mut delay = SAVE_RETRY_DELAY;
+
+        for attempt in 2..=SAVE_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+
+            let mut registry = self.registry.write().await;
+            if !registry.is_dirty() {
+                return;
+            }
+            match registry.save() {
+                Ok(()) => {
+                    tracing::info!("Port registry saved after earlier write failures");
+                    registry.set_dirty(false);
+                    return;
+                }
+                Err(e) if attempt < SAVE_ATTEMPTS => {
+                    tracing::warn!("Port registry save attempt {} failed: {:#}", attempt, e);
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Port registry could not be saved after {} attempts, continuing with \
+                         in-memory state until the next successful save: {:#}",
+                        SAVE_ATTEMPTS,
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Whether a port may be handed out to an instance
+    fn is_allocatable(&self, port: u16) -> bool {
+        (self.range_start..=self.range_end).contains(&port) && !self.reserved.contains(&port)
+    }
+
     /// Allocate a port for a user
     pub async fn allocate(&self, username: &str) -> Result<u16> {
         let mut registry = self.registry.write().await;
+        let lock = begin_update(&mut registry);
 
         // Check if user already has a port
         if let Some(port) = registry.get_port(username) {
             return Ok(port);
         }
 
-        // Try to reuse a released port first
-        if let Some(port) = registry.pop_released() {
-            registry.allocate(username, port)?;
-            registry.save()?;
-            return Ok(port);
+        // Try to reuse a released port first, otherwise find next available port.
+        // Released ports from an older range, now reserved, just handed out, or
+        // still bound by some process are discarded. Without reuse the pool is
+        // left alone as an audit trail.
+        let mut port = None;
+        if self.reuse_released {
+            while let Some(released) = registry.pop_released() {
+                if self.is_allocatable(released)
+                    && !registry.is_pending(released)
+                    && !is_port_in_use(released)
+                {
+                    port = Some(released);
+                    break;
+                }
+            }
         }
-
-        // Find next available port
-        let port = self.find_available_port(&registry)?;
+        let port = match port {
+            Some(port) => port,
+            None => match self.find_available_port(&registry) {
+                Ok(port) => port,
+                Err(e) => {
+                    let allocated = registry.allocated_count();
+                    drop(registry);
+                    // Past the warning level too; no separate alert for it
+                    self.near_capacity.store(true, Ordering::Relaxed);
+                    self.alert_capacity(username, allocated).await;
+                    return Err(e.into());
+                }
+            },
+        };
         registry.allocate(username, port)?;
-        registry.save()?;
+        registry.reserve(port);
+        let saved = persist(&mut registry, lock.as_ref());
+
+        let allocated = registry.allocated_count();
+        drop(lock);
+        drop(registry);
+        if !saved {
+            self.retry_save().await;
+        }
+        if self.crossed_capacity_warning(allocated) {
+            self.warn_capacity(allocated);
+            self.alert_capacity(username, allocated).await;
+        }
 
         Ok(port)
     }
 
+    /// Log a warning if the port range is close to exhaustion
+    pub async fn check_capacity(&self) {
+        let allocated = self.registry.read().await.allocated_count();
+        if self.is_near_capacity(allocated) {
+            self.warn_capacity(allocated);
+        }
+    }
+
+    /// Number of ports in the range
+    ///
+    /// Computed in `usize`: a range covering every port has 65536 of them,
+    /// one more than fits in a `u16`.
+    fn range_size(&self) -> usize {
+        usize::from(self.range_end) - usize::from(self.range_start) + 1
+    }
+
+    /// Whether `allocated` ports put the range at the warning level
+    fn is_near_capacity(&self, allocated: usize) -> bool {
+        allocated * 100 >= self.range_size() * CAPACITY_WARNING_PERCENT
+    }
+
+    /// Whether `allocated` ports just took the range to the warning level
+    ///
+    /// True once per crossing, so allocations above the level don't repeat
+    /// the warning until the range drops below it again.
+    fn crossed_capacity_warning(&self, allocated: usize) -> bool {
+        if self.is_near_capacity(allocated) {
+            !self.near_capacity.swap(true, Ordering::Relaxed)
+        } else {
+            self.near_capacity.store(false, Ordering::Relaxed);
+            false
+        }
+    }
+
+    fn warn_capacity(&self, allocated: usize) {
+        tracing::warn!(
+            "Port range {}-{} is nearly exhausted: {} of {} ports allocated",
+            self.range_start,
+            self.range_end,
+            allocated,
+            self.range_size()
+        );
+    }
+
+    /// Emit `ResourceLimitReached` for the port range
+    ///
+    /// Sent when an allocation crosses the warning level, and on every
+    /// allocation that fails because the range ran out.
+    async fn alert_capacity(&self, username: &str, allocated: usize) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        events
+            .emit(Event::ResourceLimitReached {
+                username: username.to_string(),
+                resource: "ports".to_string(),
+                current: allocated as u64,
+                limit: self.range_size() as u64,
+            })
+            .await;
+    }
+
     /// Release a user's port allocation
     pub async fn release(&self, username: &str) -> Result<()> {
         let mut registry = self.registry.write().await;
+        let lock = begin_update(&mut registry);
         registry.release(username)?;
-        registry.save()?;
+        let saved = persist(&mut registry, lock.as_ref());
+        drop(lock);
+        drop(registry);
+        if !saved {
+            self.retry_save().await;
+        }
+        Ok(())
+    }
+
+    /// Release a user's port allocation if one exists
+    ///
+    /// Unlike [`release`](Self::release) this succeeds when nothing is allocated,
+    /// so tearing down an instance that never started doesn't report an error.
+    pub async fn release_if_present(&self, username: &str) -> Result<()> {
+        let mut registry = self.registry.write().await;
+        let lock = begin_update(&mut registry);
+        let saved = match registry.release_if_present(username) {
+            Some(port) => {
+                tracing::debug!("Released port {} for user {}", port, username);
+                persist(&mut registry, lock.as_ref())
+            }
+            None => {
+                tracing::debug!(
+                    "No port allocated for user {}, nothing to release",
+                    username
+                );
+                true
+            }
+        };
+        drop(lock);
+        drop(registry);
+        if !saved {
+            self.retry_save().await;
+        }
         Ok(())
     }
 
+    /// Move the port allocated to `from` over to `to`, releasing `to`'s old port
+    pub async fn reassign(&self, from: &str, to: &str) -> Result<u16> {
+        let mut registry = self.registry.write().await;
+        let lock = begin_update(&mut registry);
+        let port = registry.reassign(from, to)?;
+        let saved = persist(&mut registry, lock.as_ref());
+        drop(lock);
+        drop(registry);
+        if !saved {
+            self.retry_save().await;
+        }
+        Ok(port)
+    }
+
     /// Get port for a user
     pub async fn get_port(&self, username: &str) -> Option<u16> {
         let registry = self.registry.read().await;
@@ -97,9 +448,20 @@ impl PortAllocator {
         !registry.allocated.values().any(|&p| p == port)
     }
 
+    /// Whether a released port must not be handed out again
+    fn is_retired(&self, registry: &PortRegistry, port: u16) -> bool {
+        !self.reuse_released && registry.released.contains(&port)
+    }
+
     /// Find an available port
-    fn find_available_port(&self, registry: &PortRegistry) -> Result<u16> {
+    fn find_available_port(&self, registry: &PortRegistry) -> Result<u16, PortError> {
         for port in self.range_start..=self.range_end {
+            if self.reserved.contains(&port)
+                || self.is_retired(registry, port)
+                || registry.is_pending(port)
+            {
+                continue;
+            }
             if !registry.allocated.values().any(|&p| p == port) {
                 // Also check if port is in use on the system
                 if !is_port_in_use(port) {
@@ -108,27 +470,79 @@ impl PortAllocator {
             }
         }
 
-        anyhow::bail!(
-            "No available ports in range {}-{}",
-            self.range_start,
-            self.range_end
-        )
+        Err(PortError::Exhausted {
+            range_start: self.range_start,
+            range_end: self.range_end,
+        })
     }
 
     /// Get statistics
     pub async fn stats(&self) -> PortStats {
         let registry = self.registry.read().await;
-        let total = (self.range_end - self.range_start + 1) as usize;
+        let total = self.range_size();
         let allocated = registry.allocated.len();
         let released = registry.released.len();
+        let taken: HashSet<u16> = registry.allocated.values().copied().collect();
+
+        // Runs of consecutive ports that could still be handed out
+        let mut free = 0;
+        let mut free_blocks = 0;
+        let mut largest_free_block = 0;
+        let mut run = 0;
+        for port in self.range_start..=self.range_end {
+            if self.is_allocatable(port)
+                && !taken.contains(&port)
+                && !self.is_retired(&registry, port)
+            {
+                if run == 0 {
+                    free_blocks += 1;
+                }
+                free += 1;
+                run += 1;
+                largest_free_block = largest_free_block.max(run);
+            } else {
+                run = 0;
+            }
+        }
+        let in_range = || {
+            taken
+                .iter()
+                .copied()
+                .filter(|port| (self.range_start..=self.range_end).contains(port))
+        };
+        let allocation_span = match (in_range().min(), in_range().max()) {
+            (Some(low), Some(high)) => (high - low) as usize + 1,
+            _ => 0,
+        };
+
+        // Released ports that won't be reissued are as good as allocated
+        let retired = if self.reuse_released {
+            0
+        } else {
+            registry
+                .released
+                .iter()
+                .filter(|port| (self.range_start..=self.range_end).contains(*port))
+                .count()
+        };
 
         PortStats {
             range_start: self.range_start,
             range_end: self.range_end,
             total,
             allocated,
-            available: total - allocated,
+            available: total.saturating_sub(allocated + retired),
             released_pool: released,
+            reuse_released_ports: self.reuse_released,
+            free_blocks,
+            largest_free_block,
+            allocation_span,
+            fragmentation: if free == 0 {
+                0.0
+            } else {
+                1.0 - largest_free_block as f64 / free as f64
+            },
+            unsaved_changes: registry.is_dirty(),
         }
     }
 }
@@ -142,10 +556,63 @@ pub struct PortStats {
     pub allocated: usize,
     pub available: usize,
     pub released_pool: usize,
+    /// Whether ports in the released pool are handed out again
+    pub reuse_released_ports: bool,
+    /// Number of separate runs of free ports in the range
+    pub free_blocks: usize,
+    /// Longest run of consecutive free ports
+    pub largest_free_block: usize,
+    /// Distance from the lowest to the highest allocated port, inclusive
+    pub allocation_span: usize,
+    /// Share of free ports outside the largest free block (0 = contiguous)
+    pub fragmentation: f64,
+    /// Allocations exist only in memory because the registry file couldn't be written
+    pub unsaved_changes: bool,
+}
+
+/// Lock the registry file and pick up changes saved by other processes
+///
+/// Without the lock the change is still made, and saved like any other.
+fn begin_update(registry: &mut PortRegistry) -> Option<RegistryLock> {
+    match registry.begin_update() {
+        Ok(lock) => Some(lock),
+        Err(e) => {
+            tracing::warn!("Port registry could not be locked for an update: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Write the registry to disk under the update's lock
+///
+/// A registry that can't be written is kept in memory and marked dirty
+/// instead of failing the operation; the in-memory state remains
+/// authoritative until a later save succeeds. Returns whether it was
+/// written, so the caller can `retry_save` once it has let go of the
+/// registry.
+fn persist(registry: &mut PortRegistry, lock: Option<&RegistryLock>) -> bool {
+    let saved = match lock {
+        Some(lock) => registry.save_locked(lock),
+        None => registry.save(),
+    };
+    match saved {
+        Ok(()) => {
+            if registry.is_dirty() {
+                tracing::info!("Port registry saved after earlier write failures");
+            }
+            registry.set_dirty(false);
+            true
+        }
+        Err(e) => {
+            tracing::warn!("Port registry save attempt 1 failed: {:#}", e);
+            registry.set_dirty(true);
+            false
+        }
+    }
 }
 
 /// Check if a port is in use on the system
-fn is_port_in_use(port: u16) -> bool {
+pub fn is_port_in_use(port: u16) -> bool {
     use std::net::TcpListener;
     TcpListener::bind(("127.0.0.1", port)).is_err()
 }
@@ -164,7 +631,7 @@ mod tests {
 
         // Allocate port for user1
         let port1 = allocator.allocate("user1").await.unwrap();
-        assert!(port1 >= 30001 && port1 <= 30100);
+        assert!((30001..=30100).contains(&port1));
 
         // Same user should get same port
         let port1_again = allocator.allocate("user1").await.unwrap();
@@ -178,4 +645,241 @@ mod tests {
         allocator.release("user1").await.unwrap();
         assert!(allocator.get_port("user1").await.is_none());
     }
+
+    #[tokio::test]
+    async fn test_fragmentation_stats() {
+        let dir = tempdir().unwrap();
+        let registry_path = dir.path().join("ports.json");
+
+        let allocator = PortAllocator::new(30001, 30010, &registry_path).unwrap();
+        {
+            let mut registry = allocator.registry.write().await;
+            registry.allocate("user1", 30003).unwrap();
+            registry.allocate("user2", 30004).unwrap();
+            registry.allocate("user3", 30008).unwrap();
+            registry.save().unwrap();
+        }
+        allocator.release("user2").await.unwrap();
+
+        // Free: 30001-30002, 30004-30007, 30009-30010
+        let stats = allocator.stats().await;
+        assert_eq!(stats.free_blocks, 3);
+        assert_eq!(stats.largest_free_block, 4);
+        assert_eq!(stats.allocation_span, 6);
+        assert_eq!(stats.released_pool, 1);
+        assert!((stats.fragmentation - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[tokio::test]
+    async fn test_full_port_range() {
+        let dir = tempdir().unwrap();
+        let allocator = PortAllocator::new(0, u16::MAX, &dir.path().join("ports.json")).unwrap();
+
+        allocator.check_capacity().await;
+        assert_eq!(allocator.stats().await.total, 65536);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_allocations_are_unique() {
+        let dir = tempdir().unwrap();
+        let registry_path = dir.path().join("ports.json");
+        let allocator = Arc::new(PortAllocator::new(30001, 30100, &registry_path).unwrap());
+
+        // Released ports are in the pool while the allocations race
+        for user in ["old1", "old2"] {
+            allocator.allocate(user).await.unwrap();
+            allocator.release(user).await.unwrap();
+        }
+
+        let handles: Vec<_> = (0..50)
+            .map(|i| {
+                let allocator = Arc::clone(&allocator);
+                tokio::spawn(async move { allocator.allocate(&format!("user{}", i)).await })
+            })
+            .collect();
+        let mut ports = HashSet::new();
+        for handle in handles {
+            assert!(ports.insert(handle.await.unwrap().unwrap()));
+        }
+
+        // Just-released ports stay held back rather than being reissued
+        assert!(!ports.contains(&30001));
+        assert!(!ports.contains(&30002));
+    }
+
+    #[tokio::test]
+    async fn test_no_released_port_reuse() {
+        let dir = tempdir().unwrap();
+        let registry_path = dir.path().join("ports.json");
+
+        let allocator = PortAllocator::new(30001, 30003, &registry_path)
+            .unwrap()
+            .with_released_port_reuse(false);
+        assert_eq!(allocator.allocate("user1").await.unwrap(), 30001);
+        allocator.release("user1").await.unwrap();
+
+        // The released port is kept for auditing but never handed out again
+        assert_eq!(allocator.allocate("user2").await.unwrap(), 30002);
+        let stats = allocator.stats().await;
+        assert_eq!(stats.released_pool, 1);
+        assert_eq!(stats.available, 1);
+        assert!(!stats.reuse_released_ports);
+
+        assert_eq!(allocator.allocate("user3").await.unwrap(), 30003);
+        assert!(allocator.allocate("user4").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_allocation_survives_unwritable_registry() {
+        let dir = tempdir().unwrap();
+        // A file where the registry directory should be makes every save fail
+        let blocker = dir.path().join("manager");
+        std::fs::write(&blocker, "").unwrap();
+
+        let allocator = PortAllocator::new(30001, 30100, &blocker.join("ports.json")).unwrap();
+        let port = allocator.allocate("user1").await.unwrap();
+        assert_eq!(allocator.get_port("user1").await, Some(port));
+        assert!(allocator.stats().await.unsaved_changes);
+
+        // Once the path is writable the next change saves everything
+        std::fs::remove_file(&blocker).unwrap();
+        allocator.allocate("user2").await.unwrap();
+        assert!(!allocator.stats().await.unsaved_changes);
+
+        let registry = PortRegistry::load(&blocker.join("ports.json")).unwrap();
+        assert_eq!(registry.get_port("user1"), Some(port));
+    }
+
+    #[tokio::test]
+    async fn test_save_retries_leave_registry_unlocked() {
+        let dir = tempdir().unwrap();
+        let blocker = dir.path().join("manager");
+        std::fs::write(&blocker, "").unwrap();
+        let allocator =
+            Arc::new(PortAllocator::new(30001, 30100, &blocker.join("ports.json")).unwrap());
+
+        // The allocation backs off between save attempts for 150ms in all
+        let allocation = tokio::spawn({
+            let allocator = Arc::clone(&allocator);
+            async move { allocator.allocate("user1").await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let started = std::time::Instant::now();
+        assert!(allocator.get_port("user1").await.is_some());
+        assert!(allocator.stats().await.unsaved_changes);
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert!(!allocation.is_finished());
+        allocation.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reserved_ports() {
+        let dir = tempdir().unwrap();
+        let registry_path = dir.path().join("ports.json");
+
+        // A registry from an older range that handed out what is now the manager port
+        {
+            let mut registry = PortRegistry::load(&registry_path).unwrap();
+            registry.allocate("olduser", 30000).unwrap();
+            registry.save().unwrap();
+        }
+
+        let allocator = PortAllocator::new(30000, 30100, &registry_path)
+            .unwrap()
+            .with_reserved(vec![30000]);
+        assert!(allocator.check_reserved().await.is_err());
+
+        // Once released, the reserved port is never handed out again
+        allocator.release("olduser").await.unwrap();
+        assert!(allocator.check_reserved().await.is_ok());
+        assert_eq!(allocator.allocate("user1").await.unwrap(), 30001);
+    }
+
+    #[test]
+    fn test_capacity_warning_once_per_crossing() {
+        let dir = tempdir().unwrap();
+        let allocator = PortAllocator::new(30001, 30010, &dir.path().join("ports.json")).unwrap();
+
+        assert!(!allocator.crossed_capacity_warning(8));
+        assert!(allocator.crossed_capacity_warning(9));
+        assert!(!allocator.crossed_capacity_warning(10));
+        assert!(!allocator.crossed_capacity_warning(9));
+        assert!(!allocator.crossed_capacity_warning(5));
+        assert!(allocator.crossed_capacity_warning(9));
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_range() {
+        let dir = tempdir().unwrap();
+        let events = Arc::new(EventEmitter::new(dir.path().join("hooks")));
+        let mut received = events.subscribe();
+        let allocator = PortAllocator::new(30001, 30002, &dir.path().join("ports.json"))
+            .unwrap()
+            .with_events(Arc::clone(&events));
+
+        allocator.allocate("user1").await.unwrap();
+        allocator.allocate("user2").await.unwrap();
+        let err = allocator.allocate("user3").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PortError>(),
+            Some(&PortError::Exhausted {
+                range_start: 30001,
+                range_end: 30002
+            })
+        );
+
+        // One alert for crossing the warning level, one for running out
+        for _ in 0..2 {
+            let envelope = received.try_recv().unwrap();
+            assert!(matches!(
+                envelope.event,
+                Event::ResourceLimitReached { ref resource, current: 2, limit: 2, .. }
+                    if resource == "ports"
+            ));
+        }
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_conflicting_registry() {
+        let dir = tempdir().unwrap();
+        let registry_path = dir.path().join("ports.json");
+        // Another service took user1's port; user2's instance holds its own
+        let _other_service = std::net::TcpListener::bind(("127.0.0.1", 30201)).unwrap();
+        let _user2_instance = std::net::TcpListener::bind(("127.0.0.1", 30202)).unwrap();
+        {
+            let mut registry = PortRegistry::load(&registry_path).unwrap();
+            registry.allocate("user1", 30201).unwrap();
+            registry.allocate("user2", 30202).unwrap();
+            registry.allocate("user3", 30203).unwrap();
+            registry.save().unwrap();
+        }
+
+        let events = Arc::new(EventEmitter::new(dir.path().join("hooks")));
+        let mut received = events.subscribe();
+        let allocator = PortAllocator::new(30201, 30210, &registry_path)
+            .unwrap()
+            .with_events(Arc::clone(&events));
+        let holders = HashMap::from([("user2".to_string(), 30202)]);
+        let reassigned = allocator.reconcile(&holders).await.unwrap();
+
+        assert_eq!(
+            reassigned,
+            vec![PortReassignment {
+                username: "user1".to_string(),
+                old_port: 30201,
+                new_port: 30204,
+            }]
+        );
+        assert_eq!(allocator.get_port("user2").await, Some(30202));
+        assert_eq!(allocator.get_port("user3").await, Some(30203));
+        let registry = PortRegistry::load(&registry_path).unwrap();
+        assert_eq!(registry.get_port("user1"), Some(30204));
+        assert!(matches!(
+            received.try_recv().unwrap().event,
+            Event::PortReassigned { ref username, old_port: 30201, new_port: 30204, .. }
+                if username == "user1"
+        ));
+    }
 }
@@ -1,10 +1,17 @@
 //! Port Registry - Persistent storage for port allocations
 
 use anyhow::{Context, Result};
+use nix::fcntl::{Flock, FlockArg};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long a handed-out port is held back from being handed out again,
+/// covering the gap until its instance has bound it
+const PENDING_TTL: Duration = Duration::from_secs(30);
 
 /// Persistent port registry
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +28,14 @@ pub struct PortRegistry {
 
     /// Released ports available for reuse
     pub released: Vec<u16>,
+
+    /// In-memory state has changes that couldn't be written to disk
+    #[serde(skip)]
+    dirty: bool,
+
+    /// Ports handed out recently, with when (never persisted)
+    #[serde(skip)]
+    pending: HashMap<u16, Instant>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +57,7 @@ impl PortRegistry {
     /// Load registry from file or create new
     pub fn load(path: &Path) -> Result<Self> {
         if path.exists() {
+            let _lock = lock(path, FlockArg::LockShared)?;
             let content = fs::read_to_string(path)
                 .with_context(|| format!("Failed to read port registry: {}", path.display()))?;
 
@@ -56,28 +72,98 @@ impl PortRegistry {
                 range: PortRange::default(),
                 allocated: HashMap::new(),
                 released: Vec::new(),
+                dirty: false,
+                pending: HashMap::new(),
             })
         }
     }
 
+    /// Lock the registry file for a load+modify+save
+    ///
+    /// Another process (e.g. the CLI) may have saved since this registry was
+    /// loaded, so its allocations are read back in first, unless this one
+    /// holds changes it couldn't write yet. Modify, then `save_locked` before
+    /// dropping the lock.
+    pub fn begin_update(&mut self) -> Result<RegistryLock> {
+        self.create_parent()?;
+        let lock = RegistryLock {
+            _flock: lock(&self.path, FlockArg::LockExclusive)?,
+        };
+        if !self.dirty && self.path.exists() {
+            let content = fs::read_to_string(&self.path).with_context(|| {
+                format!("Failed to read port registry: {}", self.path.display())
+            })?;
+            let saved: PortRegistry = serde_json::from_str(&content)
+                .with_context(|| "Failed to parse port registry JSON")?;
+            self.range = saved.range;
+            self.allocated = saved.allocated;
+            self.released = saved.released;
+        }
+        Ok(lock)
+    }
+
     /// Save registry to file
     pub fn save(&self) -> Result<()> {
-        // Ensure parent directory exists
+        self.create_parent()?;
+        // Another process (e.g. the CLI) may save at the same time
+        let _lock = lock(&self.path, FlockArg::LockExclusive)?;
+        self.write()
+    }
+
+    /// Save registry to file under a lock from `begin_update`
+    pub fn save_locked(&self, _lock: &RegistryLock) -> Result<()> {
+        self.write()
+    }
+
+    fn create_parent(&self) -> Result<()> {
         if let Some(parent) = self.path.parent() {
-            fs::create_dir_all(parent).with_context(|| {
-                format!("Failed to create directory: {}", parent.display())
-            })?;
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
         }
+        Ok(())
+    }
 
+    fn write(&self) -> Result<()> {
         let content = serde_json::to_string_pretty(self)
             .with_context(|| "Failed to serialize port registry")?;
 
-        fs::write(&self.path, content)
-            .with_context(|| format!("Failed to write port registry: {}", self.path.display()))?;
+        // Write to a temp file private to this process, flushed to disk, and
+        // rename it into place, so a crash or failed write never leaves a
+        // truncated registry behind
+        let tmp_path = self
+            .path
+            .with_extension(format!("json.{}.tmp", std::process::id()));
+        let written = File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            // Make the rename itself survive a crash
+            .and_then(|_| match self.path.parent() {
+                Some(parent) => File::open(parent)?.sync_all(),
+                None => Ok(()),
+            });
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e).with_context(|| {
+                format!("Failed to write port registry: {}", self.path.display())
+            });
+        }
 
         Ok(())
     }
 
+    /// Whether in-memory changes are waiting to be written to disk
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Record whether the last save attempt failed
+    pub fn set_dirty(&mut self, dirty: bool) {
+        self.dirty = dirty;
+    }
+
     /// Get port for a user
     pub fn get_port(&self, username: &str) -> Option<u16> {
         self.allocated.get(username).copied()
@@ -112,6 +198,52 @@ impl PortRegistry {
         }
     }
 
+    /// Release a user's port if one is allocated
+    ///
+    /// Returns the released port, or `None` if the user had no allocation.
+    pub fn release_if_present(&mut self, username: &str) -> Option<u16> {
+        let port = self.allocated.remove(username)?;
+        if !self.released.contains(&port) {
+            self.released.push(port);
+        }
+        Some(port)
+    }
+
+    /// Move the port allocated to `from` over to `to`
+    ///
+    /// Any port `to` previously held is released. Returns the moved port.
+    pub fn reassign(&mut self, from: &str, to: &str) -> Result<u16> {
+        let port = self
+            .allocated
+            .remove(from)
+            .ok_or_else(|| anyhow::anyhow!("No port allocated for: {}", from))?;
+
+        if let Some(previous) = self.allocated.insert(to.to_string(), port) {
+            if !self.released.contains(&previous) {
+                self.released.push(previous);
+            }
+        }
+
+        Ok(port)
+    }
+
+    /// Hold back a port that was just handed out
+    ///
+    /// Even if its allocation is released right away, the port isn't handed
+    /// out again until `PENDING_TTL` passes, since the first instance may
+    /// still bind it.
+    pub fn reserve(&mut self, port: u16) {
+        self.pending.retain(|_, at| at.elapsed() < PENDING_TTL);
+        self.pending.insert(port, Instant::now());
+    }
+
+    /// Whether a port was handed out less than `PENDING_TTL` ago
+    pub fn is_pending(&self, port: u16) -> bool {
+        self.pending
+            .get(&port)
+            .is_some_and(|at| at.elapsed() < PENDING_TTL)
+    }
+
     /// Pop a released port for reuse
     pub fn pop_released(&mut self) -> Option<u16> {
         self.released.pop()
@@ -128,6 +260,27 @@ impl PortRegistry {
     }
 }
 
+/// Exclusive hold on the registry file, from `PortRegistry::begin_update`
+pub struct RegistryLock {
+    _flock: Flock<File>,
+}
+
+/// Take an advisory lock on the registry's lock file, waiting for it
+///
+/// The registry file itself is replaced on every save, so the lock lives in
+/// a separate file next to it.
+fn lock(path: &Path, arg: FlockArg) -> Result<Flock<File>> {
+    let lock_path = path.with_extension("json.lock");
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open lock file: {}", lock_path.display()))?;
+    Flock::lock(file, arg)
+        .map_err(|(_, e)| anyhow::anyhow!("Failed to lock port registry {}: {}", path.display(), e))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -155,6 +308,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_interrupted_save_keeps_registry() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ports.json");
+        let mut registry = PortRegistry::load(&path).unwrap();
+        registry.allocate("user1", 30001).unwrap();
+        registry.save().unwrap();
+
+        // A truncated temp file left by a crashed writer is never read...
+        let tmp_path = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&tmp_path, "{\"range\": {\"start\": 300").unwrap();
+        assert_eq!(
+            PortRegistry::load(&path).unwrap().get_port("user1"),
+            Some(30001)
+        );
+        // ...and is simply replaced by the next save
+        registry.allocate("user2", 30002).unwrap();
+        registry.save().unwrap();
+        assert!(!tmp_path.exists());
+        assert_eq!(PortRegistry::load(&path).unwrap().allocated_count(), 2);
+
+        // A write that fails midway leaves the previous registry in place
+        fs::create_dir(&tmp_path).unwrap();
+        registry.allocate("user3", 30003).unwrap();
+        assert!(registry.save().is_err());
+        let loaded = PortRegistry::load(&path).unwrap();
+        assert_eq!(loaded.allocated_count(), 2);
+        assert!(loaded.get_port("user3").is_none());
+    }
+
     #[test]
     fn test_port_release_and_reuse() {
         let dir = tempdir().unwrap();
@@ -168,4 +351,46 @@ mod tests {
         assert!(registry.get_port("user1").is_none());
         assert_eq!(registry.pop_released(), Some(30001));
     }
+
+    #[test]
+    fn test_release_if_present() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ports.json");
+
+        let mut registry = PortRegistry::load(&path).unwrap();
+
+        registry.allocate("user1", 30001).unwrap();
+        assert_eq!(registry.release_if_present("user1"), Some(30001));
+
+        // Releasing again is a no-op rather than an error
+        assert_eq!(registry.release_if_present("user1"), None);
+        assert!(registry.release("user1").is_err());
+        assert_eq!(registry.released_count(), 1);
+    }
+
+    #[test]
+    fn test_update_keeps_concurrent_changes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ports.json");
+
+        // Two processes load the same registry...
+        let mut manager = PortRegistry::load(&path).unwrap();
+        let mut cli = PortRegistry::load(&path).unwrap();
+
+        // ...and each allocates a port, one after the other
+        let lock = cli.begin_update().unwrap();
+        cli.allocate("user1", 30001).unwrap();
+        cli.save_locked(&lock).unwrap();
+        drop(lock);
+
+        let lock = manager.begin_update().unwrap();
+        assert!(manager.allocate("user2", 30001).is_err());
+        manager.allocate("user2", 30002).unwrap();
+        manager.save_locked(&lock).unwrap();
+        drop(lock);
+
+        let loaded = PortRegistry::load(&path).unwrap();
+        assert_eq!(loaded.get_port("user1"), Some(30001));
+        assert_eq!(loaded.get_port("user2"), Some(30002));
+    }
 }
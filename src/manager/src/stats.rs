@@ -0,0 +1,108 @@
+//! Statistics Reports
+//!
+//! Typed results of `FrameManager::stats`, one shape per stat type, so API
+//! and CLI consumers can rely on (and deserialize) a documented schema.
+
+use serde::{Deserialize, Serialize};
+
+use crate::port::PortStats;
+
+/// Any statistics report; serializes as the inner report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Stats {
+    Memory(MemoryStats),
+    Cpu(CpuStats),
+    Instances(InstancesStats),
+    Disk(DiskStats),
+}
+
+/// Memory usage per instance (`stats memory`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryStats {
+    pub memory: Vec<InstanceMemory>,
+}
+
+/// Memory usage of one instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceMemory {
+    pub username: String,
+    /// Resident memory in MB
+    pub memory_mb: u64,
+    pub limit_mb: u64,
+}
+
+/// CPU usage per instance (`stats cpu`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpuStats {
+    pub cpu: Vec<InstanceCpu>,
+}
+
+/// CPU usage of one instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceCpu {
+    pub username: String,
+    pub cpu_percent: f32,
+    pub limit_percent: u8,
+}
+
+/// Disk usage per instance against its quota (`stats disk`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskStats {
+    pub disk: Vec<InstanceDisk>,
+}
+
+/// Disk usage of one instance's apps and data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceDisk {
+    pub username: String,
+    pub used_mb: u64,
+    pub quota_mb: u64,
+    /// Share of the quota in use (0 when there is no quota)
+    pub percent: f64,
+}
+
+/// Instance counts and port pool state (`stats instances`, the default)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstancesStats {
+    pub instances: InstanceCounts,
+    pub ports: PortStats,
+}
+
+/// Number of instances by state
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct InstanceCounts {
+    pub running: usize,
+    pub stopped: usize,
+    pub total: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_stats_round_trip() {
+        let reports = [
+            json!({"memory": [{"username": "alice", "memory_mb": 120, "limit_mb": 512}]}),
+            json!({"cpu": [{"username": "alice", "cpu_percent": 12.5, "limit_percent": 25}]}),
+        ];
+        for report in reports {
+            let stats: Stats = serde_json::from_value(report.clone()).unwrap();
+            assert_eq!(serde_json::to_value(&stats).unwrap(), report);
+        }
+
+        let stats: Stats = serde_json::from_value(json!({
+            "instances": {"running": 2, "stopped": 1, "total": 3},
+            "ports": {
+                "range_start": 30001, "range_end": 30010, "total": 10,
+                "allocated": 3, "available": 7, "released_pool": 0, "reuse_released_ports": true,
+                "free_blocks": 1, "largest_free_block": 7, "allocation_span": 3,
+                "fragmentation": 0.0, "unsaved_changes": false
+            }
+        }))
+        .unwrap();
+        assert!(matches!(stats, Stats::Instances(s) if s.instances.running == 2));
+    }
+}
@@ -0,0 +1,48 @@
+//! cgroup Enforcement
+//!
+//! Creates real control groups under /sys/fs/cgroup/frame, so it only runs
+//! with `--features cgroup-tests` as root on a host with cgroup v2.
+
+#![cfg(all(feature = "cgroup-tests", target_os = "linux"))]
+
+use frame_manager::instance::{CgroupController, ResourceLimits};
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn test_process_joins_cgroup_before_exec() {
+    let username = "frame-cgroup-test";
+    let limits = ResourceLimits {
+        memory_mb: 64,
+        cpu_percent: 10,
+        ..ResourceLimits::default()
+    };
+    let cgroup = CgroupController::prepare(username, &limits).unwrap();
+    let dir = Path::new("/sys/fs/cgroup/frame").join(username);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("memory.max"))
+            .unwrap()
+            .trim(),
+        (64 * 1024 * 1024).to_string()
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("cpu.max")).unwrap().trim(),
+        "10000 100000"
+    );
+
+    // The shell reports its own cgroup, so it was already inside at exec
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "cat /proc/self/cgroup; exec sleep 30"])
+        .stdout(std::process::Stdio::piped());
+    cgroup.join_on_spawn(&mut cmd).unwrap();
+    let mut child = cmd.spawn().unwrap();
+    let procs = std::fs::read_to_string(dir.join("cgroup.procs")).unwrap();
+    assert!(procs.lines().any(|line| line == child.id().to_string()));
+
+    child.kill().unwrap();
+    let output = child.wait_with_output().unwrap();
+    let own_cgroup = String::from_utf8(output.stdout).unwrap();
+    assert!(own_cgroup.trim().ends_with(&format!("/frame/{}", username)));
+    cgroup.remove().unwrap();
+    assert!(!dir.exists());
+}
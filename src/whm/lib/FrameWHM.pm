@@ -8,6 +8,8 @@ use warnings;
 
 use JSON;
 use HTTP::Tiny;
+use lib '/usr/local/cpanel';
+use FrameAuth ();
 
 our $MANAGER_API = 'http://127.0.0.1:30000';
 
@@ -40,6 +42,7 @@ sub _request {
     my $options = {
         headers => { 'Content-Type' => 'application/json' },
     };
+    FrameAuth::add_auth_header($options->{headers});
 
     if ($data && ($method eq 'POST' || $method eq 'PUT')) {
         $options->{content} = encode_json($data);